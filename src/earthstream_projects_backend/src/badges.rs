@@ -0,0 +1,72 @@
+use candid::{CandidType, Nat, Principal};
+use ic_cdk::api::call::RejectionCode;
+use serde::Deserialize;
+use sha2::{Digest, Sha256};
+
+use crate::ledger::Account;
+
+// Minting is not part of ICRC-7 itself; the configured collection is expected to
+// let this canister call
+//   mint : (MintArg) -> (variant { Ok : nat; Err : text })
+// to return the minted token id, and to refuse a token id that already exists.
+// Token ids are derived from (owner, project), so a supporter's badge always has
+// the same id, and a mint whose reply was lost can be checked with icrc7_owner_of.
+
+pub enum MintError {
+    Failed(String),  // Nothing was minted
+    Unknown(String),  // The token may or may not have been minted
+}
+
+// Subset of ICRC-3 Value used for token metadata
+#[derive(CandidType, Deserialize)]
+pub enum Value {
+    Text(String),
+    Nat(Nat),
+}
+
+#[derive(CandidType, Deserialize)]
+struct MintArg {
+    token_id: Nat,
+    owner: Account,
+    metadata: Vec<(String, Value)>,
+}
+
+/// The id of `owner`'s badge for the project: the first 16 bytes of a SHA-256 over
+/// both, which ids picked by anyone else are very unlikely to collide with.
+pub fn token_id(owner: &Principal, project_id: &str) -> Nat {
+    let mut hasher = Sha256::new();
+    hasher.update(b"earthstream-badge:");
+    hasher.update(owner.as_slice());
+    hasher.update(project_id.as_bytes());
+    let hash = hasher.finalize();
+    let mut bytes = [0u8; 16];
+    bytes.copy_from_slice(&hash[..16]);
+    Nat::from(u128::from_be_bytes(bytes))
+}
+
+pub async fn mint(collection: Principal, token_id: Nat, owner: Principal, metadata: Vec<(String, Value)>) -> Result<Nat, MintError> {
+    let arg = MintArg {
+        token_id,
+        owner: Account { owner, subaccount: None },
+        metadata,
+    };
+    let (result,): (Result<Nat, String>,) = ic_cdk::call(collection, "mint", (arg,))
+        .await
+        .map_err(|(code, e)| {
+            let message = format!("Badge collection call failed: {}", e);
+            // Only these say the call was never executed or rolled back
+            match code {
+                RejectionCode::DestinationInvalid | RejectionCode::CanisterReject | RejectionCode::CanisterError => MintError::Failed(message),
+                _ => MintError::Unknown(message),
+            }
+        })?;
+    result.map_err(|e| MintError::Failed(format!("Badge mint failed: {}", e)))
+}
+
+/// The token's owner per ICRC-7, None if it hasn't been minted.
+pub async fn owner_of(collection: Principal, token_id: Nat) -> Result<Option<Account>, String> {
+    let (owners,): (Vec<Option<Account>>,) = ic_cdk::call(collection, "icrc7_owner_of", (vec![token_id],))
+        .await
+        .map_err(|(_, e)| format!("Badge collection call failed: {}", e))?;
+    Ok(owners.into_iter().next().flatten())
+}
@@ -0,0 +1,140 @@
+use canbench_rs::{bench, bench_fn, BenchResult};
+use candid::Principal;
+
+use crate::{
+    encode_geohash, geo_index, get_projects_by_status, get_projects_by_votes, insert_project, new_project,
+    search_projects, set_vote_count, GatewayType, Location, ProjectCategory, ProjectData, ProjectImages,
+    ProjectStatus, STATE,
+};
+
+// Instruction counts for the hot list queries, run with `canbench` from the crate
+// directory. Each benchmark loads its synthetic projects before the measured call.
+
+const BASE_TIMESTAMP: u64 = 1_700_000_000_000_000_000;
+const TAGS: [&str; 8] = ["forest", "river", "birds", "coral", "soil", "air", "wetland", "urban"];
+const CATEGORIES: [ProjectCategory; 4] = [
+    ProjectCategory::Reforestation,
+    ProjectCategory::Water,
+    ProjectCategory::Wildlife,
+    ProjectCategory::AirQuality,
+];
+
+// Spread over the globe; one in ten is a draft, one in ten pending and one in ten archived
+fn load_projects(count: usize) {
+    STATE.with(|state| {
+        let mut state = state.borrow_mut();
+        for i in 0..count {
+            let lat = ((i * 7_919) % 17_000) as f64 / 100.0 - 85.0;
+            let lng = ((i * 104_729) % 36_000) as f64 / 100.0 - 180.0;
+            let data = ProjectData {
+                name: format!("Synthetic project {}", i),
+                description: format!("Monitoring {} habitat number {}", TAGS[i % TAGS.len()], i),
+                category: CATEGORIES[i % CATEGORIES.len()].clone(),
+                gateway_type: if i % 2 == 0 { GatewayType::Wifi } else { GatewayType::GSM },
+                images: ProjectImages {
+                    background: format!("https://example.org/images/{}.jpg", i),
+                    gallery: vec![],
+                    pins: None,
+                },
+                location: Location {
+                    lat,
+                    lng,
+                    address: "Synthetic".to_string(),
+                    geohash: encode_geohash(lat, lng).expect("coordinates are in range"),
+                },
+                project_discord: None,
+                private_discord: String::new(),
+                sensors_required: (i % 20) as u32,
+                video: None,
+                tags: vec![TAGS[i % TAGS.len()].to_string(), TAGS[(i / 3) % TAGS.len()].to_string()],
+            };
+            let status = match i % 10 {
+                0 => ProjectStatus::Draft,
+                1 => ProjectStatus::PendingReview,
+                2 => ProjectStatus::Archived,
+                _ => ProjectStatus::Approved,
+            };
+            let owner = Principal::from_slice(&(i as u64).to_be_bytes());
+            let project = new_project(&state, data, owner, status, BASE_TIMESTAMP + i as u64);
+            let id = insert_project(&mut state, project);
+            set_vote_count(&mut state, &id, (i % 997) as u64);
+        }
+    });
+}
+
+fn bench_with(count: usize, query: impl FnOnce()) -> BenchResult {
+    load_projects(count);
+    bench_fn(query)
+}
+
+fn search() {
+    search_projects("forest river".to_string(), Some(1), Some(20));
+}
+
+fn geo_find() {
+    let center = encode_geohash(48.85, 2.35).expect("coordinates are in range");
+    geo_index::find(center, 500.0);
+}
+
+fn by_status() {
+    get_projects_by_status(ProjectStatus::Approved, Some(5), Some(20));
+}
+
+// The homepage query, served from the leaderboard
+fn by_votes() {
+    get_projects_by_votes(None, None, Some(1), Some(20));
+}
+
+fn by_votes_filtered() {
+    get_projects_by_votes(Some(100), Some(500), Some(1), Some(20));
+}
+
+#[bench(raw)]
+fn search_projects_10k() -> BenchResult {
+    bench_with(10_000, search)
+}
+
+#[bench(raw)]
+fn search_projects_100k() -> BenchResult {
+    bench_with(100_000, search)
+}
+
+#[bench(raw)]
+fn geo_find_10k() -> BenchResult {
+    bench_with(10_000, geo_find)
+}
+
+#[bench(raw)]
+fn geo_find_100k() -> BenchResult {
+    bench_with(100_000, geo_find)
+}
+
+#[bench(raw)]
+fn projects_by_status_10k() -> BenchResult {
+    bench_with(10_000, by_status)
+}
+
+#[bench(raw)]
+fn projects_by_status_100k() -> BenchResult {
+    bench_with(100_000, by_status)
+}
+
+#[bench(raw)]
+fn projects_by_votes_10k() -> BenchResult {
+    bench_with(10_000, by_votes)
+}
+
+#[bench(raw)]
+fn projects_by_votes_100k() -> BenchResult {
+    bench_with(100_000, by_votes)
+}
+
+#[bench(raw)]
+fn projects_by_votes_filtered_10k() -> BenchResult {
+    bench_with(10_000, by_votes_filtered)
+}
+
+#[bench(raw)]
+fn projects_by_votes_filtered_100k() -> BenchResult {
+    bench_with(100_000, by_votes_filtered)
+}
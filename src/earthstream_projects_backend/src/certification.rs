@@ -0,0 +1,59 @@
+use candid::CandidType;
+use digest::Digest;
+use ic_cdk::api::set_certified_data;
+use ic_certified_map::{labeled, labeled_hash, AsHashTree, Hash, RbTree};
+use serde::Serialize;
+use sha2::Sha256;
+use std::cell::RefCell;
+
+// Certified tree layout:
+//   "projects" -> project id -> SHA-256 of the Candid-encoded PublicProject
+// Only the hashes are kept; records are encoded again when read.
+const PROJECTS_LABEL: &[u8] = b"projects";
+
+thread_local! {
+    static TREE: RefCell<RbTree<String, Hash>> = const { RefCell::new(RbTree::new()) };
+}
+
+/// The encoding a project's certified hash is taken over.
+pub fn record(project: &impl CandidType) -> Option<Vec<u8>> {
+    candid::encode_one(project).ok()
+}
+
+pub fn hash(record: &[u8]) -> Hash {
+    Sha256::digest(record).into()
+}
+
+/// Sets or, for None, removes the given entries and updates the canister's
+/// certified data. Must run in an update context, e.g. a timer.
+pub fn update(hashes: Vec<(String, Option<Hash>)>) {
+    if hashes.is_empty() {
+        return;
+    }
+    TREE.with(|tree| {
+        let mut tree = tree.borrow_mut();
+        for (id, hash) in hashes {
+            match hash {
+                Some(hash) => tree.insert(id, hash),
+                None => tree.delete(id.as_bytes()),
+            }
+        }
+        set_certified_data(&labeled_hash(PROJECTS_LABEL, &tree.root_hash()));
+    });
+}
+
+/// A CBOR witness for the project, if `record` is the encoding that was certified.
+pub fn witness(id: &str, record: &[u8]) -> Option<Vec<u8>> {
+    TREE.with(|tree| {
+        let tree = tree.borrow();
+        if tree.get(id.as_bytes()) != Some(&hash(record)) {
+            return None;
+        }
+        let mut serializer = serde_cbor::Serializer::new(Vec::new());
+        serializer.self_describe().ok()?;
+        labeled(PROJECTS_LABEL, tree.witness(id.as_bytes()))
+            .serialize(&mut serializer)
+            .ok()?;
+        Some(serializer.into_inner())
+    })
+}
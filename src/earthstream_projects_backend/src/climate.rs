@@ -0,0 +1,105 @@
+use candid::CandidType;
+use ic_cdk::api::management_canister::http_request::{
+    http_request, CanisterHttpRequestArgument, HttpMethod, HttpResponse, TransformArgs, TransformContext,
+};
+use serde::{Deserialize, Serialize};
+use std::cell::RefCell;
+use std::collections::HashMap;
+
+// The configured API must answer in Open-Meteo's format, e.g.
+//   https://api.open-meteo.com/v1/forecast?latitude={lat}&longitude={lng}&current=temperature_2m,precipitation
+// returns { "current": { "time": "...", "temperature_2m": 21.3, "precipitation": 0.4, ... }, ... }
+
+pub const CELL_PRECISION: usize = 5;  // ~5km geohash cells; projects in a cell share one lookup
+const MAX_RESPONSE_BYTES: u64 = 16 * 1024;
+const OUTCALL_CYCLES: u128 = 50_000_000_000;
+
+#[derive(CandidType, Serialize, Deserialize, Clone, Debug)]
+pub struct Climate {
+    pub temperature_c: f64,
+    pub precipitation_mm: f64,
+    pub observed_at: String,  // As reported by the API, ISO 8601
+    pub fetched_at: u64,
+}
+
+#[derive(Deserialize)]
+struct ApiResponse {
+    current: Current,
+}
+
+#[derive(Serialize, Deserialize)]
+struct Current {
+    time: String,
+    temperature_2m: f64,
+    precipitation: f64,
+}
+
+thread_local! {
+    static CACHE: RefCell<HashMap<String, Climate>> = RefCell::default();  // geohash cell -> latest climate
+}
+
+pub fn validate_url_template(template: &str) -> Result<(), String> {
+    if !template.starts_with("https://") || !template.contains("{lat}") || !template.contains("{lng}") {
+        return Err("climate_api_url must be an https URL containing {lat} and {lng}".to_string());
+    }
+    Ok(())
+}
+
+pub fn cell(geohash: &str) -> String {
+    geohash.chars().take(CELL_PRECISION).collect()
+}
+
+pub fn get(cell: &str) -> Option<Climate> {
+    CACHE.with(|cache| cache.borrow().get(cell).cloned())
+}
+
+pub fn fetched_at(cell: &str) -> Option<u64> {
+    CACHE.with(|cache| cache.borrow().get(cell).map(|c| c.fetched_at))
+}
+
+/// Fetches the current climate at the coordinates and caches it for the cell.
+pub async fn refresh(template: &str, cell: String, lat: f64, lng: f64, now: u64) -> Result<(), String> {
+    let url = template
+        .replace("{lat}", &format!("{:.4}", lat))
+        .replace("{lng}", &format!("{:.4}", lng));
+    let request = CanisterHttpRequestArgument {
+        url,
+        method: HttpMethod::GET,
+        body: None,
+        max_response_bytes: Some(MAX_RESPONSE_BYTES),
+        transform: Some(TransformContext::from_name("transform_climate_response".to_string(), vec![])),
+        headers: vec![],
+    };
+
+    let (response,) = http_request(request, OUTCALL_CYCLES)
+        .await
+        .map_err(|(_, e)| e)?;
+    if response.status != 200u64 {
+        return Err(format!("Climate API returned {}", response.status));
+    }
+    let current: Current = serde_json::from_slice(&response.body)
+        .map_err(|e| format!("Unexpected climate response: {}", e))?;
+
+    CACHE.with(|cache| {
+        cache.borrow_mut().insert(cell, Climate {
+            temperature_c: current.temperature_2m,
+            precipitation_mm: current.precipitation,
+            observed_at: current.time,
+            fetched_at: now,
+        })
+    });
+    Ok(())
+}
+
+/// Reduces the response to the fields we read so replicas agree on it.
+pub fn transform(args: TransformArgs) -> HttpResponse {
+    let body = serde_json::from_slice::<ApiResponse>(&args.response.body)
+        .ok()
+        .and_then(|response| serde_json::to_vec(&response.current).ok())
+        .unwrap_or_default();
+    HttpResponse {
+        status: args.response.status,
+        headers: vec![],
+        body,
+    }
+}
@@ -0,0 +1,51 @@
+use crate::rfc3339;
+
+// Atom 1.0 (RFC 4287) documents served over http_request
+
+pub const CONTENT_TYPE: &str = "application/atom+xml; charset=utf-8";
+
+pub struct Entry {
+    pub id: String,
+    pub title: String,
+    pub link: String,
+    pub updated: u64,
+    pub summary: String,
+}
+
+fn escape(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&quot;"),
+            '\'' => escaped.push_str("&apos;"),
+            // Control characters other than tab and newlines are not allowed in XML 1.0
+            c if c.is_control() && !matches!(c, '\t' | '\n' | '\r') => {}
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+/// Renders the feed; `updated` is the newest entry's time, or `fallback_updated`
+/// when there are no entries.
+pub fn atom(id: &str, title: &str, link: &str, entries: &[Entry], fallback_updated: u64) -> String {
+    let updated = entries.iter().map(|e| e.updated).max().unwrap_or(fallback_updated);
+    let mut xml = format!(
+        "<?xml version=\"1.0\" encoding=\"utf-8\"?>\n\
+         <feed xmlns=\"http://www.w3.org/2005/Atom\">\n\
+         <id>{}</id>\n<title>{}</title>\n<link rel=\"self\" href=\"{}\"/>\n<updated>{}</updated>\n\
+         <author><name>Earthstream</name></author>\n",
+        escape(id), escape(title), escape(link), rfc3339(updated)
+    );
+    for entry in entries {
+        xml.push_str(&format!(
+            "<entry>\n<id>{}</id>\n<title>{}</title>\n<link href=\"{}\"/>\n<updated>{}</updated>\n<summary>{}</summary>\n</entry>\n",
+            escape(&entry.id), escape(&entry.title), escape(&entry.link), rfc3339(entry.updated), escape(&entry.summary)
+        ));
+    }
+    xml.push_str("</feed>\n");
+    xml
+}
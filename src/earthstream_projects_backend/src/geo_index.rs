@@ -171,6 +171,19 @@ pub fn index(geohash: String, id: String) {
 
 }
 
+pub fn remove(id: &str) {
+    GEO_INDEX.with(|geo_index|{
+        let mut index_mut = geo_index.borrow_mut();
+        for v in index_mut.values_mut(){
+            v.retain(|s| s != id);
+        }
+        index_mut.retain(|_, v| !v.is_empty());
+    });
+    GEO_HASH_LOOKUP.with(|geo_hash_lookup|{
+        geo_hash_lookup.borrow_mut().remove(id);
+    })
+}
+
 pub fn view_index() -> Vec<String>{
     let mut empty_vec: Vec<String> = Vec::new();
     GEO_INDEX.with(|geo_index|{
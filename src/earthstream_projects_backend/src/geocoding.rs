@@ -0,0 +1,103 @@
+use ic_cdk::api::management_canister::http_request::{
+    http_request, CanisterHttpRequestArgument, HttpHeader, HttpMethod, HttpResponse, TransformArgs,
+    TransformContext,
+};
+use serde::{Deserialize, Serialize};
+use std::cell::RefCell;
+use std::collections::HashMap;
+
+// The configured API must answer in Nominatim's reverse geocoding format, e.g.
+//   https://nominatim.openstreetmap.org/reverse?format=jsonv2&lat={lat}&lon={lng}
+// returns { "display_name": "...", "address": { "country_code": "ke", ... }, ... }
+
+const CELL_PRECISION: usize = 7;  // ~150m geohash cells share a cached result
+const MAX_RESPONSE_BYTES: u64 = 16 * 1024;
+const OUTCALL_CYCLES: u128 = 50_000_000_000;
+const USER_AGENT: &str = "earthstream-projects";  // Nominatim rejects requests without one
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct Place {
+    pub address: String,
+    pub country_code: Option<String>,  // ISO 3166-1 alpha-2, upper case
+}
+
+#[derive(Deserialize)]
+struct ApiResponse {
+    display_name: String,
+    address: Option<ApiAddress>,
+}
+
+#[derive(Deserialize)]
+struct ApiAddress {
+    country_code: Option<String>,
+}
+
+thread_local! {
+    static CACHE: RefCell<HashMap<String, Place>> = RefCell::default();  // geohash cell -> place
+}
+
+pub fn validate_url_template(template: &str) -> Result<(), String> {
+    if !template.starts_with("https://") || !template.contains("{lat}") || !template.contains("{lng}") {
+        return Err("geocoding_api_url must be an https URL containing {lat} and {lng}".to_string());
+    }
+    Ok(())
+}
+
+fn cell(geohash: &str) -> String {
+    geohash.chars().take(CELL_PRECISION).collect()
+}
+
+pub fn cached(geohash: &str) -> Option<Place> {
+    CACHE.with(|cache| cache.borrow().get(&cell(geohash)).cloned())
+}
+
+/// Looks up the place at the coordinates and caches it for the geohash's cell.
+pub async fn reverse(template: &str, geohash: &str, lat: f64, lng: f64) -> Result<Place, String> {
+    let url = template
+        .replace("{lat}", &format!("{:.6}", lat))
+        .replace("{lng}", &format!("{:.6}", lng));
+    let request = CanisterHttpRequestArgument {
+        url,
+        method: HttpMethod::GET,
+        body: None,
+        max_response_bytes: Some(MAX_RESPONSE_BYTES),
+        transform: Some(TransformContext::from_name("transform_geocoding_response".to_string(), vec![])),
+        headers: vec![HttpHeader {
+            name: "User-Agent".to_string(),
+            value: USER_AGENT.to_string(),
+        }],
+    };
+
+    let (response,) = http_request(request, OUTCALL_CYCLES)
+        .await
+        .map_err(|(_, e)| e)?;
+    if response.status != 200u64 {
+        return Err(format!("Geocoding API returned {}", response.status));
+    }
+    let place: Place = serde_json::from_slice(&response.body)
+        .map_err(|e| format!("Unexpected geocoding response: {}", e))?;
+
+    CACHE.with(|cache| cache.borrow_mut().insert(cell(geohash), place.clone()));
+    Ok(place)
+}
+
+/// Reduces the response to a Place so replicas agree on it.
+pub fn transform(args: TransformArgs) -> HttpResponse {
+    let body = serde_json::from_slice::<ApiResponse>(&args.response.body)
+        .ok()
+        .and_then(|response| {
+            let place = Place {
+                address: response.display_name,
+                country_code: response.address
+                    .and_then(|a| a.country_code)
+                    .map(|code| code.to_uppercase()),
+            };
+            serde_json::to_vec(&place).ok()
+        })
+        .unwrap_or_default();
+    HttpResponse {
+        status: args.response.status,
+        headers: vec![],
+        body,
+    }
+}
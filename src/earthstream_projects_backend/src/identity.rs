@@ -0,0 +1,286 @@
+use candid::{CandidType, Principal};
+use k256::ecdsa::{RecoveryId, Signature, VerifyingKey};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use sha3::Keccak256;
+use std::cell::RefCell;
+use std::collections::HashMap;
+
+use crate::rfc3339;
+
+// Supporters prove control of a wallet by signing a Sign-In with Ethereum (EIP-4361)
+// message with personal_sign (EIP-191). The message names their principal, so a
+// signature cannot be replayed to link the wallet to anyone else.
+
+const CHALLENGE_TTL_NANOS: u64 = 10 * 60 * 1_000_000_000;
+const MAX_IDENTITIES_PER_PRINCIPAL: usize = 10;
+
+#[derive(CandidType, Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum ExternalChain {
+    Ethereum,
+}
+
+impl ExternalChain {
+    fn chain_id(&self) -> u64 {
+        match self {
+            ExternalChain::Ethereum => 1,
+        }
+    }
+}
+
+#[derive(CandidType, Serialize, Deserialize, Clone, Debug)]
+pub struct ExternalIdentity {
+    pub chain: ExternalChain,
+    pub address: String,  // EIP-55 checksummed
+    pub linked_at: u64,
+}
+
+struct Challenge {
+    chain: ExternalChain,
+    address: String,
+    message: String,
+    expires_at: u64,
+}
+
+thread_local! {
+    static IDENTITIES: RefCell<HashMap<Principal, Vec<ExternalIdentity>>> = RefCell::default();
+    static LINKED: RefCell<HashMap<(ExternalChain, String), Principal>> = RefCell::default();  // (chain, checksummed address) -> owner
+    static CHALLENGES: RefCell<HashMap<Principal, Challenge>> = RefCell::default();
+    static NONCE_COUNTER: RefCell<u64> = const { RefCell::new(0) };
+}
+
+fn keccak256(data: &[u8]) -> [u8; 32] {
+    Keccak256::digest(data).into()
+}
+
+/// Parses a 0x-prefixed 20-byte address and returns its EIP-55 checksummed form.
+/// Mixed-case input must already carry a valid checksum.
+pub fn checksum_address(address: &str) -> Result<String, String> {
+    let hex_part = address.strip_prefix("0x")
+        .filter(|h| h.len() == 40 && h.chars().all(|c| c.is_ascii_hexdigit()))
+        .ok_or("Address must be 0x followed by 40 hex characters")?;
+    let lower = hex_part.to_ascii_lowercase();
+    let hash = keccak256(lower.as_bytes());
+    let checksummed: String = lower.chars().enumerate()
+        .map(|(i, c)| {
+            let nibble = (hash[i / 2] >> if i % 2 == 0 { 4 } else { 0 }) & 0x0f;
+            if c.is_ascii_alphabetic() && nibble >= 8 { c.to_ascii_uppercase() } else { c }
+        })
+        .collect();
+
+    let mixed_case = hex_part != lower && hex_part != hex_part.to_ascii_uppercase();
+    if mixed_case && hex_part != checksummed {
+        return Err("Address checksum is invalid".to_string());
+    }
+    Ok(format!("0x{}", checksummed))
+}
+
+// Ethereum address of the key that produced a personal_sign signature over the message
+fn recover_address(message: &str, signature: &str) -> Result<String, String> {
+    let bytes = hex::decode(signature.strip_prefix("0x").unwrap_or(signature))
+        .map_err(|_| "Signature must be hex encoded".to_string())?;
+    if bytes.len() != 65 {
+        return Err("Signature must be 65 bytes".to_string());
+    }
+    let v = match bytes[64] {
+        0 | 1 => bytes[64],
+        27 | 28 => bytes[64] - 27,
+        _ => return Err("Signature has an invalid recovery id".to_string()),
+    };
+    let mut signature = Signature::from_slice(&bytes[..64])
+        .map_err(|_| "Malformed signature".to_string())?;
+    let mut recovery_id = RecoveryId::from_byte(v).ok_or("Signature has an invalid recovery id")?;
+    // Wallets may emit high-s signatures, which k256 rejects; flip to the equivalent low-s form
+    if let Some(normalized) = signature.normalize_s() {
+        signature = normalized;
+        recovery_id = RecoveryId::new(!recovery_id.is_y_odd(), recovery_id.is_x_reduced());
+    }
+
+    let prefixed = format!("\x19Ethereum Signed Message:\n{}{}", message.len(), message);
+    let key = VerifyingKey::recover_from_prehash(&keccak256(prefixed.as_bytes()), &signature, recovery_id)
+        .map_err(|_| "Signature does not match".to_string())?;
+    let point = key.to_encoded_point(false);
+    let hash = keccak256(&point.as_bytes()[1..]);
+    checksum_address(&format!("0x{}", hex::encode(&hash[12..])))
+}
+
+fn next_nonce(principal: &Principal, now: u64) -> String {
+    let counter = NONCE_COUNTER.with(|counter| {
+        let mut counter = counter.borrow_mut();
+        *counter += 1;
+        *counter
+    });
+    let mut hasher = Sha256::new();
+    hasher.update(principal.as_slice());
+    hasher.update(now.to_be_bytes());
+    hasher.update(counter.to_be_bytes());
+    hex::encode(&hasher.finalize()[..8])
+}
+
+/// Issues the SIWE message the wallet must sign, replacing any earlier challenge
+/// of the principal.
+pub fn challenge(principal: Principal, chain: ExternalChain, address: &str, domain: &str, now: u64) -> Result<String, String> {
+    let address = checksum_address(address)?;
+    let expires_at = now + CHALLENGE_TTL_NANOS;
+    let message = format!(
+        "{domain} wants you to sign in with your Ethereum account:\n{address}\n\n\
+         Link this wallet to Internet Computer principal {principal}.\n\n\
+         URI: https://{domain}\nVersion: 1\nChain ID: {chain_id}\nNonce: {nonce}\n\
+         Issued At: {issued_at}\nExpiration Time: {expires}",
+        chain_id = chain.chain_id(),
+        nonce = next_nonce(&principal, now),
+        issued_at = rfc3339(now),
+        expires = rfc3339(expires_at),
+    );
+    CHALLENGES.with(|challenges| {
+        let mut challenges = challenges.borrow_mut();
+        challenges.retain(|_, c| c.expires_at > now);
+        challenges.insert(principal, Challenge {
+            chain,
+            address,
+            message: message.clone(),
+            expires_at,
+        })
+    });
+    Ok(message)
+}
+
+/// Verifies the signature over the principal's pending challenge and links the
+/// address. The challenge is used up either way once it matches chain and address.
+pub fn link(principal: Principal, chain: ExternalChain, address: &str, signature: &str, now: u64) -> Result<ExternalIdentity, String> {
+    let address = checksum_address(address)?;
+    let challenge = CHALLENGES.with(|challenges| {
+        let mut challenges = challenges.borrow_mut();
+        let matches = challenges.get(&principal).is_some_and(|c| c.chain == chain && c.address == address);
+        if matches { challenges.remove(&principal) } else { None }
+    }).ok_or("Request a link message for this address first")?;
+    if now > challenge.expires_at {
+        return Err("Link message has expired, request a new one".to_string());
+    }
+    if recover_address(&challenge.message, signature)? != address {
+        return Err("Signature was not made by this address".to_string());
+    }
+
+    let key = (chain, address.clone());
+    if let Some(owner) = LINKED.with(|linked| linked.borrow().get(&key).copied()) {
+        return Err(if owner == principal {
+            "Address is already linked to your account".to_string()
+        } else {
+            "Address is linked to another account".to_string()
+        });
+    }
+    let identity = ExternalIdentity { chain, address, linked_at: now };
+    IDENTITIES.with(|identities| {
+        let mut identities = identities.borrow_mut();
+        let linked = identities.entry(principal).or_default();
+        if linked.len() >= MAX_IDENTITIES_PER_PRINCIPAL {
+            return Err(format!("At most {} addresses can be linked", MAX_IDENTITIES_PER_PRINCIPAL));
+        }
+        linked.push(identity.clone());
+        Ok(())
+    })?;
+    LINKED.with(|linked| linked.borrow_mut().insert(key, principal));
+    Ok(identity)
+}
+
+pub fn unlink(principal: Principal, chain: ExternalChain, address: &str) -> Result<(), String> {
+    let address = checksum_address(address)?;
+    let removed = IDENTITIES.with(|identities| {
+        let mut identities = identities.borrow_mut();
+        let linked = identities.get_mut(&principal)?;
+        let before = linked.len();
+        linked.retain(|i| !(i.chain == chain && i.address == address));
+        let removed = linked.len() < before;
+        if linked.is_empty() {
+            identities.remove(&principal);
+        }
+        Some(removed)
+    });
+    if removed != Some(true) {
+        return Err("Address is not linked to your account".to_string());
+    }
+    LINKED.with(|linked| linked.borrow_mut().remove(&(chain, address)));
+    Ok(())
+}
+
+pub fn identities(principal: &Principal) -> Vec<ExternalIdentity> {
+    IDENTITIES.with(|identities| identities.borrow().get(principal).cloned().unwrap_or_default())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use k256::ecdsa::SigningKey;
+
+    // Hardhat's first development account
+    const PRIVATE_KEY: &str = "ac0974bec39a17e36ba4a6b4d238ff944bacb478cbed5efcae784d7bf4f2ff80";
+    const ADDRESS: &str = "0xf39Fd6e51aad88F6F4ce6aB8827279cffFb92266";
+
+    fn personal_sign(message: &str) -> (Signature, RecoveryId) {
+        let key = SigningKey::from_slice(&hex::decode(PRIVATE_KEY).unwrap()).unwrap();
+        let prefixed = format!("\x19Ethereum Signed Message:\n{}{}", message.len(), message);
+        key.sign_prehash_recoverable(&keccak256(prefixed.as_bytes())).unwrap()
+    }
+
+    fn encode(signature: &Signature, v: u8) -> String {
+        let mut bytes = signature.to_bytes().to_vec();
+        bytes.push(v);
+        format!("0x{}", hex::encode(bytes))
+    }
+
+    #[test]
+    fn checksum_address_matches_eip55_vectors() {
+        for expected in [
+            "0x52908400098527886E0F7030069857D2E4169EE7",
+            "0x8617E340B3D01FA5F11F306F4090FD50E238070D",
+            "0xde709f2102306220921060314715629080e2fb77",
+            "0x27b1fdb04752bbc536007a920d24acb045561c26",
+            "0x5aAeb6053F3E94C9b9A09f33669435E7Ef1BeAed",
+            "0xfB6916095ca1df60bB79Ce92cE3Ea74c37c5d359",
+            "0xdbF03B407c01E7cD3CBea99509d93f8DDDC8C6FB",
+            "0xD1220A0cf47c7B9Be7A2E6BA89F429762e7b9aDb",
+        ] {
+            assert_eq!(checksum_address(&expected.to_lowercase()).unwrap(), expected);
+            assert_eq!(checksum_address(expected).unwrap(), expected);
+        }
+        assert!(checksum_address("0x5aAeb6053F3E94C9b9A09f33669435E7Ef1BeAeD").is_err());
+        assert!(checksum_address("5aAeb6053F3E94C9b9A09f33669435E7Ef1BeAed").is_err());
+    }
+
+    #[test]
+    fn recover_address_accepts_each_recovery_id_encoding() {
+        let message = "earthstream.example wants you to sign in";
+        let (signature, recovery_id) = personal_sign(message);
+        let v = recovery_id.to_byte();
+        assert_eq!(recover_address(message, &encode(&signature, v)).unwrap(), ADDRESS);
+        assert_eq!(recover_address(message, &encode(&signature, v + 27)).unwrap(), ADDRESS);
+        assert_ne!(recover_address(message, &encode(&signature, (v ^ 1) + 27)).unwrap(), ADDRESS);
+        assert_ne!(recover_address("another message", &encode(&signature, v + 27)).unwrap(), ADDRESS);
+        assert!(recover_address(message, &encode(&signature, 29)).is_err());
+    }
+
+    #[test]
+    fn recover_address_accepts_high_s_signatures() {
+        let message = "earthstream.example wants you to sign in";
+        let (signature, recovery_id) = personal_sign(message);
+        let (r, s) = signature.split_scalars();
+        let high_s = Signature::from_scalars(r, -*s).unwrap();
+        assert!(high_s.normalize_s().is_some());
+        // Negating s mirrors the recovered point, so the recovery id flips with it
+        let v = (recovery_id.to_byte() ^ 1) + 27;
+        assert_eq!(recover_address(message, &encode(&high_s, v)).unwrap(), ADDRESS);
+    }
+
+    #[test]
+    fn challenge_drops_expired_challenges() {
+        let first = Principal::from_slice(&[1]);
+        let second = Principal::from_slice(&[2]);
+        challenge(first, ExternalChain::Ethereum, ADDRESS, "earthstream.example", 1).unwrap();
+        challenge(second, ExternalChain::Ethereum, ADDRESS, "earthstream.example", 2 + CHALLENGE_TTL_NANOS).unwrap();
+        CHALLENGES.with(|challenges| {
+            let challenges = challenges.borrow();
+            assert!(!challenges.contains_key(&first));
+            assert!(challenges.contains_key(&second));
+        });
+    }
+}
@@ -0,0 +1,213 @@
+use candid::CandidType;
+use digest::Digest;
+use serde::{Deserialize, Serialize};
+use sha2::Sha256;
+use std::cell::RefCell;
+use std::collections::{BTreeMap, BTreeSet};
+
+pub const MAX_CHUNK_SIZE: usize = 1_900_000; // stay below the 2 MiB ingress message limit
+pub const MAX_IMAGE_SIZE: usize = 1_500_000; // served in a single query reply, which is capped at 2 MiB
+pub const MAX_PROJECT_BYTES: u64 = 32 * 1024 * 1024;
+pub const IMAGE_PATH_PREFIX: &str = "/images/";
+const UPLOAD_EXPIRY_NANOS: u64 = 60 * 60 * 1_000_000_000; // Abandoned uploads stop counting after an hour
+// Raster formats only; SVG can carry scripts and is served from the canister's origin
+const CONTENT_TYPES: [&str; 5] = ["image/png", "image/jpeg", "image/gif", "image/webp", "image/avif"];
+
+#[derive(CandidType, Serialize, Deserialize, Clone, Debug)]
+pub struct StoredImage {
+    pub project_ids: BTreeSet<String>, // Every project that uploaded it; each is charged for it
+    pub content_type: String,
+    #[serde(with = "serde_bytes")]
+    pub data: Vec<u8>,
+    pub uploaded_at: u64,
+}
+
+#[derive(Default)]
+struct PendingUpload {
+    started_at: u64,
+    chunks: BTreeMap<u32, Vec<u8>>,
+}
+
+type Images = BTreeMap<String, StoredImage>; // content hash -> image
+type PendingUploads = BTreeMap<(String, String), PendingUpload>; // (project_id, upload_id) -> chunks
+type ProjectUsage = BTreeMap<String, u64>; // project_id -> stored bytes
+
+thread_local! {
+    static IMAGES: RefCell<Images> = RefCell::default();
+    static PENDING_UPLOADS: RefCell<PendingUploads> = RefCell::default();
+    static PROJECT_USAGE: RefCell<ProjectUsage> = RefCell::default();
+}
+
+fn hash_content(data: &[u8]) -> String {
+    let mut hasher = Sha256::new();
+    hasher.update(data);
+    format!("{:x}", hasher.finalize())
+}
+
+fn pending_size(project_id: &str) -> u64 {
+    PENDING_UPLOADS.with(|uploads| {
+        uploads.borrow()
+            .iter()
+            .filter(|((p, _), _)| p == project_id)
+            .flat_map(|(_, upload)| upload.chunks.values())
+            .map(|chunk| chunk.len() as u64)
+            .sum()
+    })
+}
+
+pub fn project_usage(project_id: &str) -> u64 {
+    PROJECT_USAGE.with(|usage| usage.borrow().get(project_id).copied().unwrap_or(0))
+}
+
+fn prune_expired_uploads(now: u64) {
+    PENDING_UPLOADS.with(|uploads| {
+        uploads.borrow_mut().retain(|_, upload| now.saturating_sub(upload.started_at) < UPLOAD_EXPIRY_NANOS)
+    });
+}
+
+pub fn add_chunk(project_id: &str, upload_id: &str, chunk_index: u32, data: Vec<u8>, now: u64) -> Result<(), String> {
+    if data.is_empty() {
+        return Err("Chunk is empty".to_string());
+    }
+    if data.len() > MAX_CHUNK_SIZE {
+        return Err(format!("Chunk exceeds maximum size of {} bytes", MAX_CHUNK_SIZE));
+    }
+    prune_expired_uploads(now);
+
+    let upload_size: usize = PENDING_UPLOADS.with(|uploads| {
+        uploads.borrow()
+            .get(&(project_id.to_string(), upload_id.to_string()))
+            .map(|upload| upload.chunks.iter().filter(|(i, _)| **i != chunk_index).map(|(_, c)| c.len()).sum())
+            .unwrap_or(0)
+    });
+    if upload_size + data.len() > MAX_IMAGE_SIZE {
+        return Err(format!("Image exceeds maximum size of {} bytes", MAX_IMAGE_SIZE));
+    }
+    if project_usage(project_id) + pending_size(project_id) + data.len() as u64 > MAX_PROJECT_BYTES {
+        return Err(format!("Project image storage limit of {} bytes reached", MAX_PROJECT_BYTES));
+    }
+
+    PENDING_UPLOADS.with(|uploads| {
+        uploads.borrow_mut()
+            .entry((project_id.to_string(), upload_id.to_string()))
+            .or_insert_with(|| PendingUpload { started_at: now, chunks: BTreeMap::new() })
+            .chunks
+            .insert(chunk_index, data);
+    });
+    Ok(())
+}
+
+/// Assembles the chunks of an upload, stores the image under its SHA-256
+/// hash and returns the path it is served from.
+pub fn commit(project_id: &str, upload_id: &str, content_type: String, timestamp: u64) -> Result<String, String> {
+    if !CONTENT_TYPES.contains(&content_type.as_str()) {
+        return Err(format!("Content type must be one of {}", CONTENT_TYPES.join(", ")));
+    }
+    prune_expired_uploads(timestamp);
+
+    let key = (project_id.to_string(), upload_id.to_string());
+    let upload = PENDING_UPLOADS.with(|uploads| uploads.borrow_mut().remove(&key))
+        .ok_or("Upload not found")?;
+
+    // Chunks must be numbered 0..n without gaps
+    if upload.chunks.keys().enumerate().any(|(expected, index)| *index as usize != expected) {
+        PENDING_UPLOADS.with(|uploads| uploads.borrow_mut().insert(key, upload));
+        return Err("Upload is missing chunks".to_string());
+    }
+
+    let data: Vec<u8> = upload.chunks.into_values().flatten().collect();
+    let hash = hash_content(&data);
+    let size = data.len() as u64;
+
+    // Identical content is stored once, but every project referencing it is charged
+    let is_new = IMAGES.with(|images| {
+        let mut images = images.borrow_mut();
+        let image = images.entry(hash.clone()).or_insert_with(|| StoredImage {
+            project_ids: BTreeSet::new(),
+            content_type,
+            data,
+            uploaded_at: timestamp,
+        });
+        image.project_ids.insert(project_id.to_string())
+    });
+
+    if is_new {
+        PROJECT_USAGE.with(|usage| {
+            *usage.borrow_mut().entry(project_id.to_string()).or_insert(0) += size;
+        });
+    }
+
+    Ok(format!("{}{}", IMAGE_PATH_PREFIX, hash))
+}
+
+pub fn get(hash: &str) -> Option<StoredImage> {
+    IMAGES.with(|images| images.borrow().get(hash).cloned())
+}
+
+/// Drops the project's reference; the blob itself goes once no project references it.
+pub fn delete(hash: &str, project_id: &str) -> Result<(), String> {
+    let size = IMAGES.with(|images| {
+        let mut images = images.borrow_mut();
+        let image = images.get_mut(hash)?;
+        if !image.project_ids.remove(project_id) {
+            return None;
+        }
+        let size = image.data.len() as u64;
+        if image.project_ids.is_empty() {
+            images.remove(hash);
+        }
+        Some(size)
+    }).ok_or("Image not found")?;
+
+    PROJECT_USAGE.with(|usage| {
+        if let Some(bytes) = usage.borrow_mut().get_mut(project_id) {
+            *bytes = bytes.saturating_sub(size);
+        }
+    });
+    Ok(())
+}
+
+/// Hash of an image served from this canister, given its path or a project image reference.
+pub fn hash_of(reference: &str) -> Option<&str> {
+    reference.strip_prefix(IMAGE_PATH_PREFIX)
+}
+
+const IPFS_SCHEME: &str = "ipfs://";
+const ARWEAVE_SCHEME: &str = "ar://";
+const BASE58_ALPHABET: &str = "123456789ABCDEFGHJKLMNPQRSTUVWXYZabcdefghijkmnopqrstuvwxyz";
+const MAX_REFERENCE_PATH_LENGTH: usize = 200;
+
+/// ipfs:// and ar:// references point at immutable content rather than a mutable URL.
+pub fn is_content_addressed(reference: &str) -> bool {
+    reference.starts_with(IPFS_SCHEME) || reference.starts_with(ARWEAVE_SCHEME)
+}
+
+// CIDv0 (base58btc "Qm...") or CIDv1 in the default base32 multibase ("b...")
+fn is_cid(cid: &str) -> bool {
+    if let Some(rest) = cid.strip_prefix("Qm") {
+        return cid.len() == 46 && rest.chars().all(|c| BASE58_ALPHABET.contains(c));
+    }
+    match cid.strip_prefix('b') {
+        Some(rest) => (50..=100).contains(&cid.len()) && rest.chars().all(|c| matches!(c, 'a'..='z' | '2'..='7')),
+        None => false,
+    }
+}
+
+/// Checks the format of ipfs://<cid>[/path] and ar://<transaction id> references;
+/// other image references are accepted as before.
+pub fn validate_reference(reference: &str) -> Result<(), String> {
+    if let Some(rest) = reference.strip_prefix(IPFS_SCHEME) {
+        let (cid, path) = rest.split_once('/').unwrap_or((rest, ""));
+        if !is_cid(cid) {
+            return Err(format!("{} is not a valid IPFS CID", cid));
+        }
+        if path.len() > MAX_REFERENCE_PATH_LENGTH || path.chars().any(|c| c.is_whitespace() || c.is_control()) {
+            return Err("IPFS path is too long or contains whitespace".to_string());
+        }
+    } else if let Some(id) = reference.strip_prefix(ARWEAVE_SCHEME) {
+        if id.len() != 43 || !id.chars().all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_') {
+            return Err(format!("{} is not a valid Arweave transaction id", id));
+        }
+    }
+    Ok(())
+}
@@ -0,0 +1,51 @@
+use candid::CandidType;
+use serde::Deserialize;
+use std::cell::RefCell;
+use std::collections::BTreeMap;
+use std::time::Duration;
+
+const NANOS_PER_SEC: u64 = 1_000_000_000;
+
+#[derive(CandidType, Deserialize, Clone, Debug)]
+pub struct ScheduledJob {
+    pub name: String,
+    pub interval_seconds: u64,
+    pub last_run_at: Option<u64>,
+    pub next_run_at: u64,
+    pub run_count: u64,
+}
+
+thread_local! {
+    // Timers don't survive upgrades, so neither does this; start_timers rebuilds it
+    static JOBS: RefCell<BTreeMap<&'static str, ScheduledJob>> = RefCell::default();
+}
+
+/// Runs `job` every `interval_secs` and records each run for `list`.
+pub fn schedule(name: &'static str, interval_secs: u64, job: fn()) {
+    let now = ic_cdk::api::time();
+    JOBS.with(|jobs| {
+        jobs.borrow_mut().insert(name, ScheduledJob {
+            name: name.to_string(),
+            interval_seconds: interval_secs,
+            last_run_at: None,
+            next_run_at: now + interval_secs * NANOS_PER_SEC,
+            run_count: 0,
+        })
+    });
+
+    ic_cdk_timers::set_timer_interval(Duration::from_secs(interval_secs), move || {
+        let now = ic_cdk::api::time();
+        JOBS.with(|jobs| {
+            if let Some(entry) = jobs.borrow_mut().get_mut(name) {
+                entry.last_run_at = Some(now);
+                entry.next_run_at = now + entry.interval_seconds * NANOS_PER_SEC;
+                entry.run_count += 1;
+            }
+        });
+        job();
+    });
+}
+
+pub fn list() -> Vec<ScheduledJob> {
+    JOBS.with(|jobs| jobs.borrow().values().cloned().collect())
+}
@@ -0,0 +1,302 @@
+use candid::{CandidType, Nat, Principal};
+use digest::Digest;
+use serde::{Deserialize, Serialize};
+use serde_bytes::ByteBuf;
+use sha2::{Sha224, Sha256};
+
+// Minimal subset of the ICRC-1 ledger interface; fields we don't use are
+// skipped when decoding. The ICP ledger has no get_transactions, so its
+// blocks are read through query_blocks instead.
+
+pub const ICP_LEDGER: &str = "ryjl3-tyaaa-aaaaa-aaaba-cai";
+
+#[derive(CandidType, Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct Account {
+    pub owner: Principal,
+    pub subaccount: Option<ByteBuf>,
+}
+
+#[derive(CandidType, Deserialize)]
+struct GetTransactionsRequest {
+    start: Nat,
+    length: Nat,
+}
+
+#[derive(CandidType, Deserialize)]
+struct GetTransactionsResponse {
+    transactions: Vec<Transaction>,
+}
+
+#[derive(CandidType, Deserialize)]
+struct Transaction {
+    kind: String,
+    transfer: Option<Transfer>,
+    timestamp: u64,
+}
+
+#[derive(CandidType, Deserialize)]
+struct Transfer {
+    from: Account,
+    to: Account,
+    amount: Nat,
+}
+
+#[derive(CandidType, Deserialize)]
+struct QueryBlocksRequest {
+    start: u64,
+    length: u64,
+}
+
+#[derive(CandidType, Deserialize)]
+struct QueryBlocksResponse {
+    first_block_index: u64,  // Of `blocks`; later than the request when the start was archived
+    blocks: Vec<IcpBlock>,
+}
+
+#[derive(CandidType, Deserialize)]
+struct IcpBlock {
+    transaction: IcpTransaction,
+    timestamp: IcpTimestamp,
+}
+
+#[derive(CandidType, Deserialize)]
+struct IcpTimestamp {
+    timestamp_nanos: u64,
+}
+
+#[derive(CandidType, Deserialize)]
+struct IcpTransaction {
+    operation: Option<IcpOperation>,  // None for operations other than transfers
+}
+
+#[derive(CandidType, Deserialize)]
+enum IcpOperation {
+    Transfer { from: ByteBuf, to: ByteBuf, amount: IcpTokens },
+}
+
+#[derive(CandidType, Deserialize)]
+struct IcpTokens {
+    e8s: u64,
+}
+
+#[derive(CandidType, Deserialize)]
+struct TransferArg {
+    from_subaccount: Option<ByteBuf>,
+    to: Account,
+    amount: Nat,
+    fee: Option<Nat>,
+    memo: Option<ByteBuf>,
+    created_at_time: Option<u64>,
+}
+
+#[derive(CandidType, Deserialize, Debug)]
+enum TransferError {
+    BadFee { expected_fee: Nat },
+    BadBurn { min_burn_amount: Nat },
+    InsufficientFunds { balance: Nat },
+    TooOld,
+    CreatedInFuture { ledger_time: u64 },
+    Duplicate { duplicate_of: Nat },
+    TemporarilyUnavailable,
+    GenericError { error_code: Nat, message: String },
+}
+
+// ICP blocks name account identifiers rather than accounts
+enum Party {
+    Account(Account),
+    AccountIdentifier(Vec<u8>),
+}
+
+impl Party {
+    fn is(&self, account: &Account) -> bool {
+        match self {
+            Party::Account(own) => own == account,
+            Party::AccountIdentifier(id) => *id == account_identifier(account),
+        }
+    }
+}
+
+pub struct VerifiedTransfer {
+    from: Party,
+    to: Party,
+    pub amount: Nat,
+    pub timestamp: u64,
+}
+
+impl VerifiedTransfer {
+    /// Whether `owner` sent it; on the ICP ledger only from the default subaccount.
+    pub fn is_from(&self, owner: Principal) -> bool {
+        match &self.from {
+            Party::Account(account) => account.owner == owner,
+            party => party.is(&Account { owner, subaccount: None }),
+        }
+    }
+
+    pub fn is_to(&self, account: &Account) -> bool {
+        self.to.is(account)
+    }
+
+    /// None on the ICP ledger, whose blocks don't name the sender's principal.
+    pub fn sender(&self) -> Option<Principal> {
+        match &self.from {
+            Party::Account(account) => Some(account.owner),
+            Party::AccountIdentifier(_) => None,
+        }
+    }
+}
+
+fn is_icp_ledger(ledger: Principal) -> bool {
+    Principal::from_text(ICP_LEDGER).is_ok_and(|icp| icp == ledger)
+}
+
+fn crc32(bytes: &[u8]) -> u32 {
+    let mut crc = !0u32;
+    for byte in bytes {
+        crc ^= *byte as u32;
+        for _ in 0..8 {
+            crc = if crc & 1 == 1 { (crc >> 1) ^ 0xEDB8_8320 } else { crc >> 1 };
+        }
+    }
+    !crc
+}
+
+/// ICP ledger account identifier: CRC-32 of the SHA-224 hash, then the hash.
+fn account_identifier(account: &Account) -> Vec<u8> {
+    let mut hasher = Sha224::new();
+    hasher.update(b"\x0Aaccount-id");
+    hasher.update(account.owner.as_slice());
+    match &account.subaccount {
+        Some(subaccount) => hasher.update(subaccount),
+        None => hasher.update([0u8; 32]),
+    }
+    let hash = hasher.finalize();
+    let mut id = crc32(&hash).to_be_bytes().to_vec();
+    id.extend_from_slice(&hash);
+    id
+}
+
+/// Memo tying a transfer to the record it pays out, so a retry with the same
+/// created_at_time is deduplicated by the ledger.
+pub fn memo(kind: &[u8], id: u64) -> ByteBuf {
+    let mut memo = kind.to_vec();
+    memo.extend_from_slice(&id.to_be_bytes());
+    ByteBuf::from(memo)
+}
+
+/// Subaccount of this canister that collects donations for a project.
+pub fn project_subaccount(project_id: &str) -> ByteBuf {
+    let mut hasher = Sha256::new();
+    hasher.update(b"donation:");
+    hasher.update(project_id.as_bytes());
+    ByteBuf::from(hasher.finalize().to_vec())
+}
+
+/// Subaccount of this canister that holds payments for featuring bookings.
+pub fn featuring_subaccount() -> ByteBuf {
+    ByteBuf::from(Sha256::digest(b"featuring").to_vec())
+}
+
+/// Subaccount of this canister that holds a pledge's escrowed payment.
+pub fn escrow_subaccount(pledge_id: u64) -> ByteBuf {
+    let mut hasher = Sha256::new();
+    hasher.update(b"escrow:");
+    hasher.update(pledge_id.to_be_bytes());
+    ByteBuf::from(hasher.finalize().to_vec())
+}
+
+/// Sends `amount` minus the ledger fee from one of this canister's subaccounts and
+/// returns the block index. Nothing is sent if the amount doesn't cover the fee.
+/// Retries must reuse `memo` and `created_at_time`; a transfer the ledger already
+/// made returns the original block.
+pub async fn transfer(
+    ledger: Principal,
+    from_subaccount: ByteBuf,
+    to: Account,
+    amount: Nat,
+    memo: ByteBuf,
+    created_at_time: u64,
+) -> Result<Nat, String> {
+    let (fee,): (Nat,) = ic_cdk::call(ledger, "icrc1_fee", ())
+        .await
+        .map_err(|(_, e)| format!("Ledger call failed: {}", e))?;
+    if amount <= fee {
+        return Err("Amount does not cover the ledger fee".to_string());
+    }
+
+    let arg = TransferArg {
+        from_subaccount: Some(from_subaccount),
+        to,
+        amount: amount - fee.clone(),
+        fee: Some(fee),
+        memo: Some(memo),
+        created_at_time: Some(created_at_time),
+    };
+    let (result,): (Result<Nat, TransferError>,) = ic_cdk::call(ledger, "icrc1_transfer", (arg,))
+        .await
+        .map_err(|(_, e)| format!("Ledger call failed: {}", e))?;
+    match result {
+        Err(TransferError::Duplicate { duplicate_of }) => Ok(duplicate_of),
+        result => result.map_err(|e| format!("Ledger rejected the transfer: {:?}", e)),
+    }
+}
+
+pub async fn balance_of(ledger: Principal, account: Account) -> Result<Nat, String> {
+    let (balance,): (Nat,) = ic_cdk::call(ledger, "icrc1_balance_of", (account,))
+        .await
+        .map_err(|(_, e)| format!("Ledger call failed: {}", e))?;
+    Ok(balance)
+}
+
+/// Looks up a block on the ledger and returns it if it is a transfer. Blocks that
+/// have been moved to an archive canister are not followed.
+pub async fn get_transfer(ledger: Principal, block_index: u64) -> Result<VerifiedTransfer, String> {
+    if is_icp_ledger(ledger) {
+        return get_icp_transfer(ledger, block_index).await;
+    }
+    let request = GetTransactionsRequest {
+        start: Nat::from(block_index),
+        length: Nat::from(1u64),
+    };
+    let (response,): (GetTransactionsResponse,) = ic_cdk::call(ledger, "get_transactions", (request,))
+        .await
+        .map_err(|(_, e)| format!("Ledger call failed: {}", e))?;
+
+    let transaction = response.transactions
+        .into_iter()
+        .next()
+        .ok_or("Block not found on the ledger; it may have been archived")?;
+    if transaction.kind != "transfer" {
+        return Err("Block is not a transfer".to_string());
+    }
+    let transfer = transaction.transfer.ok_or("Block is not a transfer")?;
+
+    Ok(VerifiedTransfer {
+        from: Party::Account(transfer.from),
+        to: Party::Account(transfer.to),
+        amount: transfer.amount,
+        timestamp: transaction.timestamp,
+    })
+}
+
+async fn get_icp_transfer(ledger: Principal, block_index: u64) -> Result<VerifiedTransfer, String> {
+    let request = QueryBlocksRequest { start: block_index, length: 1 };
+    let (response,): (QueryBlocksResponse,) = ic_cdk::call(ledger, "query_blocks", (request,))
+        .await
+        .map_err(|(_, e)| format!("Ledger call failed: {}", e))?;
+
+    let block = response.blocks
+        .into_iter()
+        .next()
+        .filter(|_| response.first_block_index == block_index)
+        .ok_or("Block not found on the ledger; it may have been archived")?;
+    let Some(IcpOperation::Transfer { from, to, amount }) = block.transaction.operation else {
+        return Err("Block is not a transfer".to_string());
+    };
+
+    Ok(VerifiedTransfer {
+        from: Party::AccountIdentifier(from.into_vec()),
+        to: Party::AccountIdentifier(to.into_vec()),
+        amount: Nat::from(amount.e8s),
+        timestamp: block.timestamp.timestamp_nanos,
+    })
+}
@@ -1,829 +1,9946 @@
-use candid::{CandidType, Principal};
+use candid::{CandidType, Nat, Principal};
 use ic_cdk::caller;
 use ic_cdk_macros::*;
 use serde::{Deserialize, Serialize};
+use serde_bytes::ByteBuf;
 use std::cell::RefCell;
-use std::collections::{HashMap, BTreeMap};
-use std::cmp::min;
+use std::collections::{HashMap, HashSet, BTreeMap, BTreeSet, VecDeque};
+use std::ops::{Bound, Deref};
 
+mod badges;
+#[cfg(feature = "canbench-rs")]
+mod benches;
+mod certification;
+mod climate;
+mod feeds;
+#[allow(
+    clippy::clone_on_copy,
+    clippy::manual_unwrap_or_default,
+    clippy::map_entry,
+    clippy::ptr_arg,
+    clippy::single_match,
+    clippy::unnecessary_lazy_evaluations,
+    clippy::vec_init_then_push
+)]
 mod geo_index;
+mod geocoding;
+mod heap;
+mod identity;
+mod image_store;
+mod jobs;
+mod owner_webhooks;
+mod partner;
+mod profiles;
+mod ledger;
+mod rate_limit;
+mod registry;
+mod relay;
+mod sensor_types;
+mod shards;
+mod subscriptions;
+mod telemetry;
+mod upload_grants;
+mod vote_archive;
+mod webhooks;
+mod word_filter;
 
-#[derive(CandidType, Serialize, Deserialize, Clone, Debug, PartialEq)]
+use ledger::Account;
+use identity::{ExternalChain, ExternalIdentity};
+use owner_webhooks::{OwnerWebhook, WebhookEvent};
+use profiles::Profile;
+use subscriptions::{EventPayload, EventType};
+use rate_limit::{RateLimit, RateLimitedMethod};
+use relay::{RelayEvent, RelayTemplate};
+use sensor_types::SensorType;
+use telemetry::{Aggregate, Reading};
+use vote_archive::{AppendError, ProjectVote};
+use word_filter::{FilterAction, WordFilter};
+
+const DEFAULT_PAGE_LIMIT: u32 = 20;
+const HTTP_PROJECTS_PATH: &str = "/projects";
+const HTTP_JSON_MAX_AGE_SECS: u64 = 60;
+const HTTP_FEEDS_PATH: &str = "/feeds";
+const MAX_FEED_ENTRIES: usize = 50;
+const MAX_PROJECT_UPDATES: usize = 50;  // Per project, oldest dropped first
+const MAX_NOTIFICATIONS: usize = 200;  // Per principal, oldest dropped first
+const MAX_FOLLOWER_NOTIFICATIONS_PER_MESSAGE: usize = 500;  // Larger audiences are finished by deliver_follower_notifications
+const MAX_PAGE_LIMIT: u32 = 100;
+const LEADERBOARD_SIZE: usize = 100;  // Top listed projects by votes kept ready for the homepage
+const MAX_RESPONSE_BYTES: usize = 1_500_000;  // Replies over 2 MiB are rejected; leaves room for the rest of the response
+const SUMMARY_OVERHEAD_BYTES: usize = 64;  // Field hashes, length prefixes and fixed-size fields of a ProjectSummary
+const MAX_TAGS_PER_PROJECT: u32 = 10;
+const MAX_GALLERY_IMAGES: usize = 30;
+const FEATURED_SLOTS: u32 = 6;
+const DUPLICATE_RADIUS_KM: f64 = 1.0;
+const DUPLICATE_NAME_SIMILARITY: f64 = 0.8;
+const REPORT_ESCALATION_THRESHOLD: u32 = 5;
+const MAX_REPORT_REASON_LENGTH: usize = 1000;
+const MAX_ADMIN_NOTE_LENGTH: usize = 2000;
+const MAX_APPEAL_STATEMENT_LENGTH: usize = 2000;
+const MAX_RETIREMENT_REASON_LENGTH: usize = 500;
+const MAX_DISPUTE_REASON_LENGTH: usize = 1000;
+const UPLOAD_TOKEN_BYTES: usize = 16;
+const WEBHOOK_SECRET_BYTES: usize = 32;
+const NANOS_PER_DAY: u64 = 86_400_000_000_000;
+const STALE_AFTER_DAYS: u64 = 180;  // ~6 months without activity
+const STALE_SUSPENSION_GRACE_DAYS: u64 = 30;
+const MAX_STALE_DAYS: u64 = 10 * 365;  // Upper bound for stale_after_days and the grace period
+const MAX_VOTE_COOLDOWN_SECONDS: u64 = 30 * 24 * 60 * 60;
+const STALE_CHECK_INTERVAL_SECS: u64 = 24 * 60 * 60;
+const FEATURE_SCHEDULE_INTERVAL_SECS: u64 = 5 * 60;
+const TELEMETRY_PRUNE_INTERVAL_SECS: u64 = 60 * 60;
+const RAW_RETENTION_DAYS: u32 = 90;
+const MAX_RETENTION_DAYS: u32 = 10 * 365;
+const SUPER_ADMIN_TRANSFER_EXPIRY_NANOS: u64 = 7 * NANOS_PER_DAY;
+const MAX_ADMIN_INVITE_NANOS: u64 = 30 * NANOS_PER_DAY;
+const ADMIN_APPROVAL_THRESHOLD: u32 = 2;  // Co-signatures needed for sensitive actions
+const ADMIN_PROPOSAL_EXPIRY_NANOS: u64 = 3 * NANOS_PER_DAY;
+const MAX_BULK_STATUS_UPDATES: usize = 100;
+const MAX_BULK_GET_IDS: usize = 100;
+const REJECTION_REVERSAL_APPROVALS: usize = 2;  // Distinct admins needed to approve a rejected project
+const REVIEW_CLAIM_EXPIRY_NANOS: u64 = 2 * NANOS_PER_DAY;  // Abandoned claims return to the queue
+const DASHBOARD_DAYS: u64 = 30;
+const DASHBOARD_WEEKS: u64 = 12;
+const DEVICE_ONLINE_NANOS: u64 = 15 * 60 * 1_000_000_000;  // Seen within 15 minutes
+const DEVICE_STALE_NANOS: u64 = 2 * 60 * 60 * 1_000_000_000;  // Seen within 2 hours
+const ACTIVE_SENSOR_NANOS: u64 = NANOS_PER_DAY;  // Sensors that sent data within this window count as active
+const DEPLOYMENT_REFRESH_INTERVAL_SECS: u64 = 15 * 60;
+const INCIDENT_CHECK_INTERVAL_SECS: u64 = 5 * 60;
+const INCIDENT_AFTER_MINUTES: u64 = 30;
+const MAX_INCIDENT_AFTER_MINUTES: u64 = 7 * 24 * 60;
+const MAX_HEAP_BYTES: u64 = 4 * 1024 * 1024 * 1024;  // 32-bit wasm heap limit
+const HEAP_HIGH_WATER_MARK_BYTES: u64 = 3 * 1024 * 1024 * 1024;  // Leaves room for upgrades and in-flight messages
+const UPTIME_HISTORY_NANOS: u64 = 7 * NANOS_PER_DAY;  // Longest uptime window
+const MAX_CALIBRATION_COEFFICIENTS: usize = 16;
+const MAX_CALIBRATION_INTERVAL_DAYS: u32 = 10 * 365;
+const COVERAGE_CELL_PRECISION: usize = 7;  // ~150m x 150m geohash cells
+const MAX_ALERT_RULES_PER_PROJECT: usize = 20;
+const MAX_ALERTS_PER_PROJECT: usize = 500;
+const MAX_ALERT_WINDOW_SECONDS: u64 = 30 * 24 * 60 * 60;  // Upper bound for alert durations and cooldowns
+const CLAIM_CODE_EXPIRY_NANOS: u64 = 180 * NANOS_PER_DAY;  // Long enough for hardware to ship
+const CLAIM_CODE_BYTES: usize = 10;
+const MAX_READING_CLOCK_SKEW_NANOS: u64 = 5 * 60 * 1_000_000_000;
+const MAX_READINGS_QUERY: u32 = 500;
+const EXPORT_CHUNK_READINGS: usize = 1000;
+const MAX_CARRIER_LENGTH: usize = 64;
+const MAX_DEVICE_IMPORT: usize = 500;
+const TOP_DONORS: usize = 10;
+const MAX_FEATURING_BOOKING_DAYS: u64 = 30;
+const MAX_SPONSORSHIPS_PER_PROJECT: usize = 3;  // Per sponsor, so one principal can't take every slot
+const MAX_UNLINKED_SPONSORSHIPS: usize = 10;  // Per sponsor across projects; linking frees a slot
+const MAX_OPEN_PLEDGED_SENSORS: u32 = 10;  // Per backer per project, so one backer can't take all the open demand
+const MAX_OPEN_PLEDGES: usize = 20;  // Per backer across projects
+const VOTE_MILESTONES: [u64; 6] = [10, 50, 100, 500, 1000, 5000];
+const WEBHOOK_DELIVERY_INTERVAL_SECS: u64 = 60;
+const EVENT_DELIVERY_INTERVAL_SECS: u64 = 10;
+const FOLLOWER_FANOUT_INTERVAL_SECS: u64 = 30;
+const TRENDING_REFRESH_INTERVAL_SECS: u64 = 10 * 60;
+const CERTIFICATION_INTERVAL_SECS: u64 = 30;  // Certified reads lag live state by at most this
+const CLIMATE_REFRESH_INTERVAL_SECS: u64 = 60 * 60;
+const CLIMATE_MAX_AGE_NANOS: u64 = 6 * 60 * 60 * 1_000_000_000;
+const MAX_CLIMATE_FETCHES_PER_RUN: usize = 20;  // Bounds outcall cycles spent per run
+const DONATION_RECONCILE_INTERVAL_SECS: u64 = 60 * 60;
+const MAX_RECONCILIATIONS_PER_RUN: usize = 50;
+const ANALYTICS_PUSH_INTERVAL_SECS: u64 = 15 * 60;
+const ANALYTICS_EVENT: &str = "analytics";
+const VOTE_ARCHIVE_INTERVAL_SECS: u64 = 24 * 60 * 60;
+const VOTE_ROUND_DAYS: u64 = 7;  // Votes are archived a whole round at a time
+const VOTE_ARCHIVE_AFTER_ROUNDS: u32 = 26;
+const MAX_VOTES_PER_ARCHIVE_BATCH: usize = 2000;
+const GEOCODE_INTERVAL_SECS: u64 = 60;
+const RATE_LIMIT_PRUNE_INTERVAL_SECS: u64 = 60 * 60;
+const CLAIM_CODE_PRUNE_INTERVAL_SECS: u64 = 24 * 60 * 60;
+const MAX_GEOCODES_PER_RUN: usize = 5;  // Keeps within public geocoders' rate limits
+const MAX_GEOCODE_ATTEMPTS: u32 = 6;  // Failed lookups are retried after 2, 4, 8... minutes, then left for the owner
+const TRENDING_WINDOW_NANOS: u64 = 7 * NANOS_PER_DAY;  // Votes this recent count towards trending
+
+#[derive(CandidType, Serialize, Deserialize, Clone, Debug, PartialEq, Eq, Hash)]
 pub enum ProjectStatus {
     PendingReview,
     Approved,
     Rejected,
-    Suspended
+    Suspended,
+    Archived,
+    Draft
 }
 
-#[derive(CandidType, Serialize, Deserialize, Clone, Debug, PartialEq)]
+#[derive(CandidType, Serialize, Deserialize, Clone, Debug, PartialEq, Eq, Hash)]
 pub enum GatewayType {
     Wifi,
     GSM
 }
 
-#[derive(CandidType, Serialize, Deserialize, Clone, Debug)]
-pub struct ProjectImages {
-    background: String,
-    gallery: Vec<String>
+#[derive(CandidType, Serialize, Deserialize, Clone, Copy, Debug, PartialEq)]
+pub enum Role {
+    SuperAdmin,
+    Reviewer,
+    Moderator,
+    Curator,
+    Finance
 }
 
-#[derive(CandidType, Serialize, Deserialize, Clone, Debug)]
-pub struct Location {
-    lat: f64,
-    lng: f64,
-    address: String,
-    geohash: String,
+// Narrow capabilities for off-chain tooling (bots, pipelines) that isn't a human admin
+#[derive(CandidType, Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum ServiceScope {
+    ReadPrivate,
+    PostTelemetry,
+    ManageImagePins,
 }
 
 #[derive(CandidType, Serialize, Deserialize, Clone, Debug)]
-pub struct Project {
-    id: String,
+pub struct ServicePrincipal {
     name: String,
-    description: String,
-    gateway_type: GatewayType,
-    images: ProjectImages,
-    location: Location,
-    project_discord: Option<String>,
-    private_discord: String,
-    sensors_required: u32,
-    video: Option<String>,
-    status: ProjectStatus,
-    owner: Principal,
-    created_at: u64,
-    vote_count: u64,  // Cache for quick access to vote count
-    featured: bool,
-    featured_at: Option<u64>,
-    tags: Vec<String>,
+    scopes: Vec<ServiceScope>,
+    registered_by: Principal,
+    registered_at: u64,
+}
+
+// Capabilities checked by individual entry points; see `role_permits`
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Permission {
+    ManageAdmins,
+    ReviewProjects,
+    ModerateContent,
+    FeatureProjects,
+    ManageTemplates,
+    ManageSensors,
+    ViewPrivate,
+    SuspendProjects,
+    PostTelemetry,
+    ManageImagePins,
+    ManageEscrow,
+}
+
+#[derive(CandidType, Serialize, Deserialize, Clone, Debug, PartialEq, Eq, Hash)]
+pub enum ProjectCategory {
+    Reforestation,
+    Bioacoustics,
+    Water,
+    Wildlife,
+    Soil,
+    AirQuality,
+    Marine,
+    Agriculture,
+    Other
+}
+
+#[derive(CandidType, Serialize, Deserialize, Clone, Copy, Debug, PartialEq)]
+pub enum PowerSource {
+    Mains,
+    Solar,
+    Battery,
+}
+
+#[derive(CandidType, Serialize, Deserialize, Clone, Copy, Debug, PartialEq)]
+pub enum LoraBand {
+    EU868,
+    US915,
+    AU915,
+    AS923,
+    IN865,
+    KR920,
+}
+
+// Unknown capabilities are left as None
+#[derive(CandidType, Serialize, Deserialize, Clone, Debug, Default)]
+pub struct GatewayCapabilities {
+    backhaul_kbps: Option<u32>,
+    carrier: Option<String>,  // SIM carrier, for GSM gateways
+    power_source: Option<PowerSource>,
+    max_sensors: Option<u32>,  // Sensors the gateway can serve at once
+    lora_band: Option<LoraBand>,
+}
+
+impl GatewayCapabilities {
+    fn validate(&self) -> Result<(), String> {
+        if self.backhaul_kbps == Some(0) {
+            return Err("Backhaul bandwidth must be positive".to_string());
+        }
+        if self.max_sensors == Some(0) {
+            return Err("Max sensors must be positive".to_string());
+        }
+        if self.carrier.as_ref().is_some_and(|c| c.trim().is_empty() || c.len() > MAX_CARRIER_LENGTH) {
+            return Err(format!("Carrier must be 1 to {} characters", MAX_CARRIER_LENGTH));
+        }
+        Ok(())
+    }
+}
+
+// Every set field must match
+#[derive(CandidType, Serialize, Deserialize, Clone, Debug, Default)]
+pub struct GatewayFilter {
+    gateway_type: Option<GatewayType>,
+    min_backhaul_kbps: Option<u32>,
+    power_source: Option<PowerSource>,
+    lora_band: Option<LoraBand>,
+    min_sensor_capacity: Option<u32>,
+    unassigned: Option<bool>,  // true for gateways not linked to a project
 }
 
 #[derive(CandidType, Serialize, Deserialize, Clone, Debug)]
-pub struct Vote {
-    voter: Principal,
-    timestamp: u64,
+pub enum DeviceImport {
+    Sensor { serial: String, sensor_type: Option<String>, public_key: Option<ByteBuf> },
+    Gateway { data: GatewayData, public_key: Option<ByteBuf> },
 }
 
 #[derive(CandidType, Serialize, Deserialize, Clone, Debug)]
-pub struct ProjectsResponse {
-    projects: Vec<Project>,
+pub struct GatewaysResponse {
+    gateways: Vec<GatewayWithStatus>,
     total: u64,
     page: u32,
     pages: u32,
+    has_next: bool,
+    has_prev: bool,
 }
 
 #[derive(CandidType, Serialize, Deserialize, Clone, Debug)]
-pub struct ProjectData {
-    name: String,
-    description: String,
+pub struct GatewayData {
+    id: String,
     gateway_type: GatewayType,
-    images: ProjectImages,
     location: Location,
-    project_discord: Option<String>,
-    private_discord: String,
-    sensors_required: u32,
-    video: Option<String>,
-    tags: Vec<String>,
+    project_id: Option<String>,
+    capabilities: GatewayCapabilities,
 }
 
-struct State {
-    projects: HashMap<String, Project>,
-    admins: HashMap<Principal, bool>,  // bool for is_super_admin
-    owner_projects: HashMap<Principal, Vec<String>>,
-    date_index: BTreeMap<u64, String>,
-    project_votes: HashMap<String, HashMap<Principal, Vote>>,
-    vote_index: HashMap<Principal, Vec<String>>,  // User's voted projects
-    featured_projects: BTreeMap<u64, String>,  // timestamp -> project_id
-    tag_index: HashMap<String, Vec<String>>,   // tag -> project_ids
+#[derive(CandidType, Serialize, Deserialize, Clone, Debug)]
+pub struct Gateway {
+    id: String,
+    gateway_type: GatewayType,
+    location: Location,
+    owner: Principal,
+    project_id: Option<String>,
+    registered_at: u64,
+    last_seen: Option<u64>,
+    public_key: Option<ByteBuf>,  // ed25519 key that must sign every submitted batch
+    capabilities: GatewayCapabilities,
 }
 
-impl Default for State {
-    fn default() -> Self {
-        Self {
-            projects: HashMap::new(),
-            admins: HashMap::new(),
-            owner_projects: HashMap::new(),
-            date_index: BTreeMap::new(),
-            project_votes: HashMap::new(),
-            vote_index: HashMap::new(),
-            featured_projects: BTreeMap::new(),
-            tag_index: HashMap::new(),
-        }
-    }
+#[derive(CandidType, Serialize, Deserialize, Clone, Debug)]
+pub struct Sensor {
+    serial: String,
+    owner: Principal,
+    project_id: Option<String>,
+    registered_at: u64,
+    assigned_at: Option<u64>,
+    last_seen: Option<u64>,
+    sensor_type: Option<String>,  // Id in the sensor type catalogue
+    location: Option<Location>,  // Where the sensor is deployed, if reported
+    relayed_by: Option<String>,  // Gateway that submitted the sensor's latest readings
+    relayed_at: Option<u64>,
+    retired: Option<SensorRetirement>,  // Retired sensors keep their history but accept no new data
+    public_key: Option<ByteBuf>,  // ed25519 key that must sign each of the sensor's readings
+    firmware_version: Option<String>,
+    calibration: Option<Calibration>,
 }
 
-thread_local! {
-    static STATE: RefCell<State> = RefCell::new(State::default());
+// Status is derived from last_seen when read rather than stored
+#[derive(CandidType, Serialize, Deserialize, Clone, Debug)]
+pub struct GatewayWithStatus {
+    gateway: Gateway,
+    status: DeviceStatus,
 }
 
-// Helper functions
-fn index_text(text: &str) -> Vec<String> {
-    text.to_lowercase()
-        .split_whitespace()
-        .map(|s| s.to_string())
-        .collect()
+#[derive(CandidType, Serialize, Deserialize, Clone, Debug)]
+pub struct SensorWithStatus {
+    sensor: Sensor,
+    status: DeviceStatus,
 }
 
-fn caller_is_super_admin() -> bool {
-    let caller = caller();
-    STATE.with(|state| {
-        state.borrow()
-            .admins
-            .get(&caller)
-            .copied()
-            .unwrap_or(false)
-    })
+#[derive(CandidType, Serialize, Deserialize, Clone, Debug)]
+pub struct SensorRetirement {
+    reason: String,
+    retired_by: Principal,
+    retired_at: u64,
 }
 
-fn caller_is_admin() -> bool {
-    let caller = caller();
-    STATE.with(|state| state.borrow().admins.contains_key(&caller))
+#[derive(CandidType, Serialize, Deserialize, Clone, Debug)]
+pub struct Calibration {
+    calibrated_at: u64,
+    coefficients: Vec<f64>,  // Sensor-specific, e.g. [offset, gain]
+    interval_days: u32,  // How long the calibration stays valid
 }
 
-fn generate_project_id(name: &str, owner: &Principal, timestamp: u64) -> String {
-    use sha2::{Sha256, Digest};
-    let mut hasher = Sha256::new();
-    hasher.update(name.as_bytes());
-    hasher.update(owner.to_string().as_bytes());
-    hasher.update(timestamp.to_string().as_bytes());
-    format!("{:x}", hasher.finalize())
+#[derive(CandidType, Serialize, Deserialize, Clone, Copy, Debug, PartialEq)]
+pub enum Comparator {
+    Below,
+    Above,
 }
 
-fn paginate<T: Clone>(items: Vec<T>, page: Option<u32>, limit: Option<u32>) -> (Vec<T>, u64, u32) {
-    let limit = limit.unwrap_or(20) as usize;
-    let page = page.unwrap_or(1) as usize;
-    let total_items = items.len();
-    let total_pages = (total_items + limit - 1) / limit;
-    let start = (page - 1) * limit;
-    let end = min(start + limit, total_items);
-    
-    (
-        items[start..end].to_vec(),
-        total_items as u64,  // Convert to u64 here
-        total_pages as u32
-    )
+#[derive(CandidType, Serialize, Deserialize, Clone, Debug)]
+pub struct AlertRuleData {
+    metric: String,
+    comparator: Comparator,
+    threshold: f64,
+    duration_seconds: u64,  // How long the condition must hold before alerting; 0 alerts immediately
+    cooldown_seconds: u64,  // Minimum gap between alerts for the same sensor
+    notify: bool,  // Also push alerts to the owner's notification inbox
 }
 
-// Admin Management
-#[update]
-fn create_super_admin() -> Result<(), String> {
-    let caller = caller();
-    if caller == Principal::anonymous() {
-        return Err("Anonymous principals cannot be admins".to_string());
-    }
+#[derive(CandidType, Serialize, Deserialize, Clone, Debug)]
+pub struct AlertRule {
+    id: u64,
+    project_id: String,
+    rule: AlertRuleData,
+    created_by: Principal,
+    created_at: u64,
+}
 
-    STATE.with(|state| {
-        let mut state = state.borrow_mut();
-        if state.admins.is_empty() {
-            state.admins.insert(caller, true);
-            Ok(())
-        } else {
-            Err("Super admin already exists".to_string())
-        }
-    })
+#[derive(CandidType, Serialize, Deserialize, Clone, Debug)]
+pub struct Alert {
+    rule_id: u64,
+    project_id: String,
+    sensor: String,
+    metric: String,
+    value: f64,
+    breach_started_at: u64,
+    triggered_at: u64,
 }
 
-#[update]
-fn add_admin(principal: Principal) -> Result<(), String> {
-    if !caller_is_super_admin() {
-        return Err("Only super admin can add admins".to_string());
-    }
-    
-    if principal == Principal::anonymous() {
-        return Err("Cannot add anonymous principal as admin".to_string());
-    }
+#[derive(Default)]
+struct AlertTracker {
+    breach_started_at: Option<u64>,
+    last_alerted_at: Option<u64>,
+}
 
-    STATE.with(|state| {
-        let mut state = state.borrow_mut();
-        state.admins.insert(principal, false);
-        Ok(())
-    })
+#[derive(CandidType, Serialize, Deserialize, Clone, Copy, Debug, PartialEq)]
+pub enum BadgeReason {
+    Voted,
+    Sponsored,
 }
 
-#[update]
-fn remove_admin(principal: Principal) -> Result<(), String> {
-    if !caller_is_super_admin() {
-        return Err("Only super admin can remove admins".to_string());
+impl BadgeReason {
+    // Written into permanent token metadata; matches the values of badges already minted
+    fn metadata_value(&self) -> &'static str {
+        match self {
+            BadgeReason::Voted => "Voted",
+            BadgeReason::Sponsored => "Sponsored",
+        }
     }
+}
 
-    STATE.with(|state| {
-        let mut state = state.borrow_mut();
-        if state.admins.get(&principal) == Some(&true) {
-            return Err("Cannot remove super admin".to_string());
-        }
-        state.admins.remove(&principal);
-        Ok(())
-    })
+// Receipt for a supporter badge minted on the badge collection
+#[derive(CandidType, Serialize, Deserialize, Clone, Debug)]
+pub struct BadgeReceipt {
+    token_id: Nat,
+    project_id: String,
+    owner: Principal,
+    reason: BadgeReason,
+    minted_at: u64,
 }
 
-// Project Management
-#[update]
-fn create_project(project_data: ProjectData) -> Result<String, String> {
-    let caller = caller();
-    if caller == Principal::anonymous() {
-        return Err("Anonymous principals cannot create projects".to_string());
-    }
+// Price per started day of paid featuring on one ledger, e.g. ICP or ckBTC
+#[derive(CandidType, Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct FeaturingPrice {
+    ledger: Principal,
+    price_per_day: Nat,
+}
 
-    let timestamp = ic_cdk::api::time();
-    let project_id = generate_project_id(&project_data.name, &caller, timestamp);
+#[derive(CandidType, Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub enum BookingStatus {
+    Booked,
+    Refunding,  // Revoked by an admin; refund_amount is being returned to the payer
+    Refunded { block_index: Nat },
+    RefundFailed { error: String },
+}
 
-    let project = Project {
-        id: project_id.clone(),
-        name: project_data.name,
-        description: project_data.description,
-        gateway_type: project_data.gateway_type,
-        images: project_data.images,
-        location: project_data.location.clone(),
-        project_discord: project_data.project_discord,
-        private_discord: project_data.private_discord,
-        sensors_required: project_data.sensors_required,
-        video: project_data.video,
-        status: ProjectStatus::PendingReview,
-        owner: caller,
-        created_at: timestamp,
-        vote_count: 0,
-        featured: false,
-        featured_at: None,
-        tags: project_data.tags.clone(),
-    };
+// A featuring slot paid for by the project owner
+#[derive(CandidType, Serialize, Deserialize, Clone, Debug)]
+pub struct FeaturingBooking {
+    id: u64,
+    project_id: String,
+    payer: Principal,
+    ledger: Principal,
+    block_index: u64,
+    amount: Nat,
+    starts_at: u64,
+    ends_at: u64,
+    booked_at: u64,
+    status: BookingStatus,
+    refund_amount: Option<Nat>,  // Unused share of the payment, set when revoked
+    refund_created_at: Option<u64>,  // Reused by refund retries so the ledger deduplicates them
+}
 
-    STATE.with(|state| {
-        let mut state = state.borrow_mut();
-        
-        // Store project
-        state.projects.insert(project_id.clone(), project);
-        
-        // Update owner index
-        state.owner_projects
-            .entry(caller)
-            .or_insert_with(Vec::new)
-            .push(project_id.clone());
-        
-        // Update date index
-        state.date_index.insert(timestamp, project_id.clone());
-        
-        // Index location
-        geo_index::index(project_data.location.geohash, project_id.clone());
-        for tag in &project_data.tags {
-            state.tag_index
-                .entry(tag.to_lowercase())
-                .or_insert_with(Vec::new)
-                .push(project_id.clone());
-        }
+#[derive(CandidType, Serialize, Deserialize, Clone, Debug)]
+pub struct Donation {
+    ledger: Principal,
+    block_index: u64,  // Ledger block of the transfer; each (ledger, block) is credited once
+    project_id: String,
+    donor: Principal,
+    amount: Nat,
+    donated_at: u64,  // Ledger timestamp
+    recorded_at: u64,
+}
 
-    });
+#[derive(CandidType, Serialize, Deserialize, Clone, Debug)]
+pub struct DonorTotal {
+    donor: Principal,
+    donor_name: Option<String>,
+    total: Nat,
+}
 
-    Ok(project_id)
+#[derive(CandidType, Serialize, Deserialize, Clone, Debug)]
+pub struct ProjectDonations {
+    project_id: String,
+    total: Nat,
+    donation_count: u64,
+    top_donors: Vec<DonorTotal>,
 }
 
-#[update]
-fn update_project(id: String, project_data: ProjectData) -> Result<(), String> {
-    let caller = caller();
-    
-    STATE.with(|state| {
-        let mut state = state.borrow_mut();
-        
-        let project = state.projects.get_mut(&id)
-            .ok_or("Project not found")?;
-        
-        if project.owner != caller {
-            return Err("Only project owner can update".to_string());
-        }
+#[derive(CandidType, Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub enum WithdrawalStatus {
+    Pending,
+    Completed { block_index: Nat },
+    Failed { error: String },  // Nothing was sent; the owner can withdraw again
+}
 
-        // Update fields
-        project.name = project_data.name;
-        project.description = project_data.description;
-        project.gateway_type = project_data.gateway_type;
-        project.images = project_data.images;
-        project.location = project_data.location.clone();
-        project.project_discord = project_data.project_discord;
-        project.private_discord = project_data.private_discord;
-        project.sensors_required = project_data.sensors_required;
-        project.video = project_data.video;
+// The owner moving a project's donation balance out of its donation account
+#[derive(CandidType, Serialize, Deserialize, Clone, Debug)]
+pub struct DonationWithdrawal {
+    id: u64,
+    project_id: String,
+    ledger: Principal,
+    to: Account,
+    amount: Nat,  // Taken from the donation account, ledger fee included; 0 until the balance is read
+    requested_by: Principal,
+    created_at: u64,
+    status: WithdrawalStatus,
+}
 
-        // Update geohash index
-        geo_index::index(project_data.location.geohash, id);
-        
-        Ok(())
-    })
+// Ledger balance of a project's donation account as of the last reconciliation
+#[derive(CandidType, Serialize, Deserialize, Clone, Debug)]
+pub struct FundingReconciliation {
+    ledger_balance: Nat,
+    recorded_total: Nat,  // Credited donations less withdrawals when the balance was read
+    reconciled_at: u64,
 }
 
-#[update]
-fn update_project_status(id: String, status: ProjectStatus) -> Result<(), String> {
-    if !caller_is_admin() {
-        return Err("Only admins can update project status".to_string());
-    }
+#[derive(CandidType, Serialize, Deserialize, Clone, Debug)]
+pub struct VerifiedFunding {
+    project_id: String,
+    verified_total: Option<Nat>,  // On-ledger balance; None until first reconciled
+    recorded_total: Nat,  // Credited through notify_donation less withdrawals, as of now
+    last_reconciled_at: Option<u64>,
+    in_sync: Option<bool>,  // Whether the balance matched the credited donations when read
+}
 
-    STATE.with(|state| {
-        let mut state = state.borrow_mut();
-        let project = state.projects.get_mut(&id)
-            .ok_or("Project not found")?;
-        project.status = status;
-        Ok(())
-    })
+#[derive(CandidType, Serialize, Deserialize, Clone, Copy, Debug, PartialEq)]
+pub enum PledgeStatus {
+    Open,
+    Fulfilled,  // Hardware has shipped
+    Cancelled,
 }
 
-#[update]
-fn feature_project(project_id: String) -> Result<(), String> {
-    if !caller_is_admin() {
-        return Err("Only admins can feature projects".to_string());
-    }
+// A backer committing to supply sensors towards a project's sensors_required
+#[derive(CandidType, Serialize, Deserialize, Clone, Debug)]
+pub struct Pledge {
+    id: u64,
+    project_id: String,
+    backer: Principal,
+    count: u32,
+    pledged_at: u64,
+    status: PledgeStatus,
+    resolved_at: Option<u64>,
+    resolved_by: Option<Principal>,
+    escrow: Option<PledgeEscrow>,  // Set once the backer has funded the pledge
+}
 
-    let timestamp = ic_cdk::api::time();
+#[derive(CandidType, Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub enum EscrowStatus {
+    Held,
+    Disputed { raised_by: Principal, reason: String },
+    Releasing,  // Owner confirmed receipt; being sent to the procurement principal
+    Released { block_index: Nat },
+    Refunding,
+    Refunded { block_index: Nat },
+    TransferFailed { refund: bool, error: String },  // Retried by finance admins
+}
 
-    STATE.with(|state| {
-        let mut state = state.borrow_mut();
-        
-        // First check if project exists and is not already featured
-        if let Some(project) = state.projects.get(&project_id) {
-            if project.featured {
-                return Err("Project is already featured".to_string());
-            }
-        } else {
-            return Err("Project not found".to_string());
-        }
-        
-        // Then update the project
-        if let Some(project) = state.projects.get_mut(&project_id) {
-            project.featured = true;
-            project.featured_at = Some(timestamp);
-        }
-        
-        // Finally update the featured projects index
-        state.featured_projects.insert(timestamp, project_id);
-        
-        Ok(())
-    })
+// ckBTC a backer has paid into the pledge's escrow subaccount
+#[derive(CandidType, Serialize, Deserialize, Clone, Debug)]
+pub struct PledgeEscrow {
+    ledger: Principal,
+    block_index: u64,
+    amount: Nat,
+    funded_at: u64,
+    status: EscrowStatus,
+    transfer_created_at: Option<u64>,  // Of the payout; reused by retries so the ledger deduplicates them
 }
 
-#[update]
-fn unfeature_project(project_id: String) -> Result<(), String> {
-    if !caller_is_admin() {
-        return Err("Only admins can unfeature projects".to_string());
-    }
+#[derive(CandidType, Serialize, Deserialize, Clone, Debug)]
+pub struct ProjectDemand {
+    project_id: String,
+    name: String,
+    category: ProjectCategory,
+    sensors_required: u32,
+    sensors_assigned: u32,
+    pledged_open: u32,
+    pledged_fulfilled: u32,
+    open_demand: u32,  // Sensors nobody has supplied or pledged yet
+}
 
-    STATE.with(|state| {
-        let mut state = state.borrow_mut();
-        
-        // First get the featured_at timestamp and check if project is featured
-        let featured_at = if let Some(project) = state.projects.get(&project_id) {
-            if !project.featured {
+#[derive(CandidType, Serialize, Deserialize, Clone, Debug)]
+pub struct DemandResponse {
+    total_open_demand: u64,  // Across every approved project, not just this page
+    total_pledged_open: u64,
+    projects: Vec<ProjectDemand>,
+    total: u64,
+    page: u32,
+    pages: u32,
+    has_next: bool,
+    has_prev: bool,
+}
+
+// One sponsor paying for one sensor on a project
+#[derive(CandidType, Serialize, Deserialize, Clone, Debug)]
+pub struct Sponsorship {
+    id: u64,
+    project_id: String,
+    sponsor: Principal,
+    created_at: u64,
+    serial: Option<String>,  // Set once the sponsored hardware is deployed
+    linked_at: Option<u64>,
+}
+
+#[derive(CandidType, Serialize, Deserialize, Clone, Debug)]
+pub struct SensorAssignment {
+    project_id: String,
+    assigned_at: u64,
+    unassigned_at: Option<u64>,  // None while the sensor is still deployed there
+}
+
+#[derive(CandidType, Serialize, Deserialize, Clone, Debug)]
+pub struct ClaimCode {
+    serial: String,
+    created_by: Principal,
+    created_at: u64,
+    expires_at: u64,
+}
+
+#[derive(CandidType, Serialize, Deserialize, Clone, Debug)]
+pub struct CalibrationDue {
+    serial: String,
+    calibrated_at: Option<u64>,
+    due_at: Option<u64>,  // None for sensors that were never calibrated
+}
+
+#[derive(CandidType, Serialize, Deserialize, Clone, Copy, Debug, PartialEq)]
+pub enum DeviceStatus {
+    Online,
+    Stale,
+    Offline,
+}
+
+#[derive(CandidType, Serialize, Deserialize, Clone, Copy, Debug, PartialEq)]
+pub enum DeviceKind {
+    Gateway,
+    Sensor,
+}
+
+#[derive(CandidType, Serialize, Deserialize, Clone, Copy, Debug)]
+pub enum UptimeWindow {
+    Day,
+    Week,
+}
+
+#[derive(CandidType, Serialize, Deserialize, Clone, Debug)]
+pub struct GatewayUptime {
+    gateway_id: String,
+    window_start: u64,
+    window_end: u64,
+    uptime_percentage: f64,
+}
+
+#[derive(CandidType, Serialize, Deserialize, Clone, Debug)]
+pub struct ProjectUptime {
+    project_id: String,
+    average_uptime_percentage: Option<f64>,  // None when the project has no gateways
+    gateways: Vec<GatewayUptime>,
+}
+
+// Opened when a gateway has been silent for incident_after_minutes, closed on its next heartbeat or batch
+#[derive(CandidType, Serialize, Deserialize, Clone, Debug)]
+pub struct Incident {
+    id: u64,
+    gateway_id: String,
+    project_id: Option<String>,  // Project the gateway was linked to when the incident opened
+    last_seen_at: Option<u64>,  // None if the gateway never reported
+    opened_at: u64,
+    closed_at: Option<u64>,
+}
+
+#[derive(CandidType, Serialize, Deserialize, Clone, Debug)]
+pub enum IncidentScope {
+    Gateway(String),
+    Project(String),
+}
+
+#[derive(CandidType, Serialize, Deserialize, Clone, Debug)]
+pub struct IncidentsResponse {
+    incidents: Vec<Incident>,
+    total: u64,
+    page: u32,
+    pages: u32,
+    has_next: bool,
+    has_prev: bool,
+}
+
+#[derive(CandidType, Serialize, Deserialize, Clone, Debug)]
+pub struct DeviceHealth {
+    id: String,
+    kind: DeviceKind,
+    last_seen: Option<u64>,
+    status: DeviceStatus,
+}
+
+#[derive(CandidType, Serialize, Deserialize, Clone, Copy, Debug, PartialEq)]
+pub enum ExportFormat {
+    Csv,
+    LineProtocol,  // InfluxDB line protocol
+}
+
+#[derive(CandidType, Serialize, Deserialize, Clone, Debug)]
+pub struct ReadingsExport {
+    data: String,
+    next_from: Option<u64>,  // Pass as `from` to fetch the next chunk; None when complete
+}
+
+#[derive(CandidType, Serialize, Deserialize, Clone, Debug)]
+pub struct SensorRoute {
+    serial: String,
+    sensor_status: DeviceStatus,
+    gateway_id: Option<String>,  // None until the sensor's data has been relayed
+    gateway: Option<GatewayWithStatus>,  // None if the gateway has since been removed
+    relayed_at: Option<u64>,
+}
+
+#[derive(CandidType, Serialize, Deserialize, Clone, Debug)]
+pub struct DevicePosition {
+    id: String,
+    kind: DeviceKind,
+    location: Location,
+}
+
+#[derive(CandidType, Serialize, Deserialize, Clone, Debug)]
+pub struct ProjectCoverage {
+    project_id: String,
+    nominal_location: Location,  // Location given on the project itself
+    devices: Vec<DevicePosition>,
+    cells: Vec<String>,  // Geohash cells at COVERAGE_CELL_PRECISION holding at least one device
+}
+
+// Images may be web URLs, stored images (/images/<hash>), ipfs://<cid> or ar://<id>
+#[derive(CandidType, Serialize, Deserialize, Clone, Debug)]
+pub struct ProjectImages {
+    background: String,
+    gallery: Vec<String>,
+    pins: Option<Vec<ImagePin>>,  // Set by the pinning tool for ipfs:// and ar:// references; ignored on submission
+}
+
+#[derive(CandidType, Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub enum PinStatus {
+    Pending,
+    Pinned,
+    Failed,
+}
+
+#[derive(CandidType, Serialize, Deserialize, Clone, Debug)]
+pub struct ImagePin {
+    reference: String,
+    status: PinStatus,
+    updated_at: u64,
+}
+
+#[derive(CandidType, Serialize, Deserialize, Clone, Debug)]
+pub struct Location {
+    lat: f64,
+    lng: f64,
+    address: String,
+    geohash: String,
+}
+
+#[derive(CandidType, Serialize, Deserialize, Clone, Debug)]
+pub struct Project {
+    id: String,
+    slug: String,
+    name: String,
+    description: String,
+    category: ProjectCategory,
+    gateway_type: GatewayType,
+    images: ProjectImages,
+    location: Location,
+    country_code: Option<String>,  // ISO 3166-1 alpha-2, from reverse geocoding
+    project_discord: Option<String>,
+    private_discord: String,
+    sensors_required: u32,
+    sensors_assigned: u32,
+    sensors_sponsored: u32,  // Active sponsorships, cached like vote_count
+    active_sensor_count: u32,  // Assigned sensors with data in the last ACTIVE_SENSOR_NANOS
+    last_data_at: Option<u64>,  // Latest telemetry ingestion for any of the project's sensors
+    fully_equipped_at: Option<u64>,
+    video: Option<String>,
+    status: ProjectStatus,
+    owner: Principal,
+    created_at: u64,
+    status_updated_at: u64,
+    last_activity_at: u64,
+    stale_since: Option<u64>,
+    vote_count: u64,  // Cache for quick access to vote count
+    follower_count: u64,
+    featured: bool,
+    featured_at: Option<u64>,
+    tags: Vec<String>,
+    verified: bool,
+    verified_by: Option<Principal>,
+    verified_at: Option<u64>,
+}
+
+// Public view of a project; omits owner-only fields such as `private_discord`
+#[derive(CandidType, Serialize, Deserialize, Clone, Debug)]
+pub struct PublicProject {
+    id: String,
+    slug: String,
+    name: String,
+    description: String,
+    category: ProjectCategory,
+    gateway_type: GatewayType,
+    images: ProjectImages,
+    location: Location,
+    country_code: Option<String>,
+    project_discord: Option<String>,
+    sensors_required: u32,
+    sensors_assigned: u32,
+    fulfillment_percentage: f64,
+    sensors_sponsored: u32,
+    funding_percentage: f64,
+    active_sensor_count: u32,
+    last_data_at: Option<u64>,
+    fully_equipped_at: Option<u64>,
+    video: Option<String>,
+    status: ProjectStatus,
+    owner: Principal,
+    owner_name: Option<String>,  // From the owner's profile, if they set one
+    created_at: u64,
+    last_activity_at: u64,
+    stale_since: Option<u64>,
+    vote_count: u64,
+    follower_count: u64,
+    featured: bool,
+    featured_at: Option<u64>,
+    tags: Vec<String>,
+    verified: bool,
+    verified_by: Option<Principal>,
+    verified_at: Option<u64>,
+}
+
+impl From<&Project> for PublicProject {
+    fn from(project: &Project) -> Self {
+        Self {
+            id: project.id.clone(),
+            slug: project.slug.clone(),
+            name: project.name.clone(),
+            description: project.description.clone(),
+            category: project.category.clone(),
+            gateway_type: project.gateway_type.clone(),
+            images: project.images.clone(),
+            location: project.location.clone(),
+            country_code: project.country_code.clone(),
+            project_discord: project.project_discord.clone(),
+            sensors_required: project.sensors_required,
+            sensors_assigned: project.sensors_assigned,
+            fulfillment_percentage: fulfillment_percentage(project),
+            sensors_sponsored: project.sensors_sponsored,
+            funding_percentage: funding_percentage(project),
+            active_sensor_count: project.active_sensor_count,
+            last_data_at: project.last_data_at,
+            fully_equipped_at: project.fully_equipped_at,
+            video: project.video.clone(),
+            status: project.status.clone(),
+            owner: project.owner,
+            owner_name: profiles::display_name(&project.owner),
+            created_at: project.created_at,
+            last_activity_at: project.last_activity_at,
+            stale_since: project.stale_since,
+            vote_count: project.vote_count,
+            follower_count: project.follower_count,
+            featured: project.featured,
+            featured_at: project.featured_at,
+            tags: project.tags.clone(),
+            verified: project.verified,
+            verified_by: project.verified_by,
+            verified_at: project.verified_at,
+        }
+    }
+}
+
+#[derive(CandidType, Serialize, Deserialize, Clone, Debug)]
+pub struct NotFound {
+    id: String,
+}
+
+// What list endpoints return; fetch the full record with get_project
+#[derive(CandidType, Serialize, Deserialize, Clone, Debug)]
+pub struct ProjectSummary {
+    id: String,
+    name: String,
+    thumbnail: String,  // The background image
+    status: ProjectStatus,
+    vote_count: u64,
+    geohash: String,
+    tags: Vec<String>,
+}
+
+impl From<&Project> for ProjectSummary {
+    fn from(project: &Project) -> Self {
+        Self {
+            id: project.id.clone(),
+            name: project.name.clone(),
+            thumbnail: project.images.background.clone(),
+            status: project.status.clone(),
+            vote_count: project.vote_count,
+            geohash: project.location.geohash.clone(),
+            tags: project.tags.clone(),
+        }
+    }
+}
+
+#[derive(CandidType, Serialize, Deserialize, Clone, Debug)]
+pub struct Vote {
+    voter: Principal,
+    timestamp: u64,
+}
+
+impl From<&Project> for ProjectData {
+    fn from(project: &Project) -> Self {
+        Self {
+            name: project.name.clone(),
+            description: project.description.clone(),
+            category: project.category.clone(),
+            gateway_type: project.gateway_type.clone(),
+            images: project.images.clone(),
+            location: project.location.clone(),
+            project_discord: project.project_discord.clone(),
+            private_discord: project.private_discord.clone(),
+            sensors_required: project.sensors_required,
+            video: project.video.clone(),
+            tags: project.tags.clone(),
+        }
+    }
+}
+
+#[derive(CandidType, Serialize, Deserialize, Clone, Debug)]
+pub struct ProjectTemplate {
+    id: u64,
+    name: String,
+    description: String,
+    data: ProjectData,
+    created_by: Principal,
+    created_at: u64,
+}
+
+#[derive(CandidType, Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub enum VerificationStatus {
+    Pending,
+    Approved,
+    Denied
+}
+
+#[derive(CandidType, Serialize, Deserialize, Clone, Debug)]
+pub struct VerificationEvidence {
+    documents: Vec<String>,  // Registration or permit documents
+    photos: Vec<String>,     // Site photos
+    notes: String,
+}
+
+#[derive(CandidType, Serialize, Deserialize, Clone, Debug)]
+pub struct VerificationRequest {
+    project_id: String,
+    requested_by: Principal,
+    evidence: VerificationEvidence,
+    submitted_at: u64,
+    status: VerificationStatus,
+    reviewed_by: Option<Principal>,
+    reviewed_at: Option<u64>,
+    reason: Option<String>,
+}
+
+#[derive(CandidType, Serialize, Deserialize, Clone, Debug)]
+pub struct ReviewClaim {
+    reviewer: Principal,
+    claimed_at: u64,
+    assigned_by: Option<Principal>,  // Set when a super admin assigned the review; assignments don't expire
+}
+
+#[derive(CandidType, Serialize, Deserialize, Clone, Debug)]
+pub struct ReviewQueueItem {
+    project: PublicProject,
+    submitted_at: u64,
+    claim: Option<ReviewClaim>,
+}
+
+#[derive(CandidType, Serialize, Deserialize, Clone, Debug)]
+pub struct ReviewQueueResponse {
+    items: Vec<ReviewQueueItem>,
+    total: u64,
+    page: u32,
+    pages: u32,
+    has_next: bool,
+    has_prev: bool,
+}
+
+#[derive(CandidType, Serialize, Deserialize, Clone, Debug)]
+pub struct ReviewDecision {
+    project_id: String,
+    reviewer: Principal,
+    status: ProjectStatus,
+    submitted_at: u64,
+    decided_at: u64,
+}
+
+#[derive(CandidType, Serialize, Deserialize, Clone, Debug)]
+pub struct AnalyticsPushStatus {
+    enabled: bool,
+    endpoint: Option<String>,
+    last_pushed_at: Option<u64>,
+    last_delivery: Option<webhooks::DeliveryRecord>,  // None once dropped from the delivery history
+}
+
+#[derive(CandidType, Serialize, Deserialize, Clone, Debug)]
+pub struct PeriodCount {
+    period_start: u64,
+    count: u64,
+}
+
+#[derive(CandidType, Serialize, Deserialize, Clone, Debug)]
+pub struct WeeklyDecisions {
+    week_start: u64,
+    approvals: u64,
+    rejections: u64,
+}
+
+#[derive(CandidType, Serialize, Deserialize, Clone, Debug)]
+pub struct AdminActivity {
+    principal: Principal,
+    role: Option<Role>,
+    window_start: u64,
+    reviews_completed: u64,
+    approvals: u64,
+    rejections: u64,
+    average_decision_time: Option<u64>,  // Nanoseconds from submission to decision
+    features_granted: u64,
+    notes_written: u64,
+}
+
+#[derive(CandidType, Serialize, Deserialize, Clone, Debug)]
+pub struct AdminDashboard {
+    pending_review: u64,
+    unclaimed_reviews: u64,
+    pending_verifications: u64,
+    reported_projects: u64,
+    stale_projects: u64,
+    average_review_turnaround: Option<u64>,  // Nanoseconds from submission to decision
+    decisions_per_week: Vec<WeeklyDecisions>,
+    new_projects_per_day: Vec<PeriodCount>,
+    votes_per_day: Vec<PeriodCount>,
+}
+
+#[derive(CandidType, Serialize, Deserialize, Clone, Debug)]
+pub enum RedactableField {
+    Name,
+    Description,
+    BackgroundImage,
+    GalleryImage(u32),  // Index into images.gallery
+    ProjectDiscord,
+    PrivateDiscord,
+    Video,
+}
+
+#[derive(CandidType, Serialize, Deserialize, Clone, Debug)]
+pub enum AuditEvent {
+    ReviewerAssigned { project_id: String, reviewer: Principal, previous: Option<Principal> },
+    ReviewerUnassigned { project_id: String, reviewer: Principal },
+    AdminInvited { principal: Principal, role: Role, expires_at: u64 },
+    AdminInviteAccepted { role: Role },
+    // The removed content is deliberately not kept
+    FieldRedacted { project_id: String, field: RedactableField },
+    ProjectFeatured { project_id: String, starts_at: u64, ends_at: Option<u64> },
+    ServiceRegistered { principal: Principal, scopes: Vec<ServiceScope> },
+    ServiceRevoked { principal: Principal },
+    GovernanceExecuted { action: GovernanceAction },
+}
+
+// Actions token holders can take through the governance canister's proposals
+#[derive(CandidType, Serialize, Deserialize, Clone, Debug)]
+pub enum GovernanceAction {
+    FeatureProject { project_id: String, starts_at: Option<u64>, ends_at: Option<u64> },
+    UnfeatureProject { project_id: String },
+    SetVoteCooldown { seconds: u64 },
+    SetFeaturedSlots { slots: u32 },
+    SetVotingEnabled { enabled: bool },
+}
+
+#[derive(CandidType, Serialize, Deserialize, Clone, Debug)]
+pub struct AuditEntry {
+    id: u64,
+    actor: Principal,
+    event: AuditEvent,
+    timestamp: u64,
+}
+
+#[derive(CandidType, Serialize, Deserialize, Clone, Debug)]
+pub struct AuditLogResponse {
+    entries: Vec<AuditEntry>,
+    total: u64,
+    page: u32,
+    pages: u32,
+    has_next: bool,
+    has_prev: bool,
+}
+
+#[derive(CandidType, Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub enum AppealOutcome {
+    Upheld,
+    Overturned,
+}
+
+#[derive(CandidType, Serialize, Deserialize, Clone, Debug)]
+pub struct AppealResolution {
+    outcome: AppealOutcome,
+    reason: String,
+    resolved_by: Principal,
+    resolved_at: u64,
+}
+
+#[derive(CandidType, Serialize, Deserialize, Clone, Debug)]
+pub struct Appeal {
+    id: u64,
+    project_id: String,
+    owner: Principal,
+    appealed_status: ProjectStatus,
+    decided_at: u64,  // status_updated_at of the decision being appealed
+    statement: String,
+    filed_at: u64,
+    resolution: Option<AppealResolution>,
+}
+
+#[derive(CandidType, Serialize, Deserialize, Clone, Debug)]
+pub struct AppealsResponse {
+    appeals: Vec<Appeal>,
+    total: u64,
+    page: u32,
+    pages: u32,
+    has_next: bool,
+    has_prev: bool,
+}
+
+#[derive(CandidType, Serialize, Deserialize, Clone, Debug)]
+pub struct AdminNote {
+    author: Principal,
+    note: String,
+    created_at: u64,
+}
+
+#[derive(CandidType, Serialize, Deserialize, Clone, Debug)]
+pub struct BlockEntry {
+    reason: String,
+    blocked_by: Principal,
+    blocked_at: u64,
+}
+
+// Write paths that can be switched off independently; reads stay live
+#[derive(CandidType, Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum Subsystem {
+    ProjectCreation,
+    Voting,
+    Reporting,
+    ImageUploads,
+    Donations,
+}
+
+#[derive(CandidType, Serialize, Deserialize, Clone, Debug)]
+pub struct Notification {
+    id: u64,
+    project_id: Option<String>,
+    message: String,
+    created_at: u64,
+    read: bool,
+}
+
+#[derive(CandidType, Serialize, Deserialize, Clone, Debug)]
+pub struct NotificationsResponse {
+    notifications: Vec<Notification>,  // Newest first
+    total: u64,
+    page: u32,
+    pages: u32,
+    has_next: bool,
+    has_prev: bool,
+}
+
+// A follower notification whose recipients didn't fit in the message that sent it
+struct FollowerFanout {
+    project_id: String,
+    message: String,
+    timestamp: u64,
+    recipients: Vec<Principal>,
+}
+
+#[derive(CandidType, Serialize, Deserialize, Clone, Debug)]
+pub struct Report {
+    reporter: Principal,
+    reason: String,
+    timestamp: u64,
+}
+
+#[derive(CandidType, Serialize, Deserialize, Clone, Debug)]
+pub struct ReportedProject {
+    project: PublicProject,
+    report_count: u64,
+    reports: Vec<Report>,
+}
+
+#[derive(CandidType, Serialize, Deserialize, Clone, Debug)]
+pub struct ReportedProjectsResponse {
+    reported: Vec<ReportedProject>,
+    total: u64,
+    page: u32,
+    pages: u32,
+    has_next: bool,
+    has_prev: bool,
+}
+
+#[derive(CandidType, Serialize, Deserialize, Clone, Copy, Debug, PartialEq)]
+pub enum ProjectSort {
+    Newest,
+    MostVotes,
+    MostActive,  // Projects producing data first
+}
+
+#[derive(CandidType, Serialize, Deserialize, Clone, Debug)]
+pub struct CertifiedProject {
+    project: PublicProject,
+    record: ByteBuf,  // Candid encoding of `project`; its SHA-256 is the leaf at ["projects", id]
+    certificate: ByteBuf,
+    witness: ByteBuf,  // CBOR hash tree
+}
+
+// A truncated page holds fewer than `limit` projects because the rest would not fit
+// in one reply; pass next_offset back as `offset` to continue right after them
+#[derive(CandidType, Serialize, Deserialize, Clone, Debug)]
+pub struct ProjectsResponse {
+    projects: Vec<ProjectSummary>,
+    total: u64,
+    page: u32,
+    pages: u32,
+    has_next: bool,
+    has_prev: bool,
+    truncated: bool,
+    next_offset: Option<u64>,  // Position of the first project not returned; None at the end
+}
+
+#[derive(CandidType, Serialize, Deserialize, Clone, Debug)]
+pub struct ProjectCursorResponse {
+    projects: Vec<ProjectSummary>,
+    next_cursor: Option<(u64, String)>,  // (created_at, id) of the last project; None when there are no more
+    truncated: bool,  // Fewer than `limit` projects fit in the reply; next_cursor continues after them
+}
+
+#[derive(CandidType, Serialize, Deserialize, Clone, Debug)]
+pub struct ProjectData {
+    name: String,
+    description: String,
+    category: ProjectCategory,
+    gateway_type: GatewayType,
+    images: ProjectImages,
+    location: Location,
+    project_discord: Option<String>,
+    private_discord: String,
+    sensors_required: u32,
+    video: Option<String>,
+    tags: Vec<String>,
+}
+
+#[derive(CandidType, Serialize, Deserialize, Clone, Debug)]
+pub struct FeatureWindow {
+    project_id: String,
+    starts_at: u64,
+    ends_at: Option<u64>,
+    scheduled_by: Principal,
+    active: bool,  // Whether the project is currently on the featured list
+}
+
+// Runtime-tunable settings; the constants above are the defaults
+#[derive(CandidType, Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct Config {
+    default_page_limit: u32,
+    max_page_limit: u32,
+    max_tags_per_project: u32,
+    vote_cooldown_seconds: u64,  // Minimum gap between votes cast by one principal
+    featured_slots: u32,
+    report_escalation_threshold: u32,
+    duplicate_radius_km: f64,
+    duplicate_name_similarity: f64,
+    stale_after_days: u64,
+    stale_suspension_grace_days: u64,
+    admin_approval_threshold: u32,
+    terms_version: u32,  // 0 means no terms need to be accepted
+    terms_hash: String,  // Hash of the published terms document for terms_version
+    raw_retention_days: u32,  // Default for projects without a retention override
+    hourly_retention_days: Option<u32>,  // None keeps hourly aggregates forever
+    require_signed_telemetry: bool,  // Reject batches from gateways without a public key
+    incident_after_minutes: u64,  // Silence after which a gateway incident is opened or a sensor reported offline
+    donation_ledger: Option<Principal>,  // ICRC-1 ledger donations are made on; None disables donations
+    badge_collection: Option<Principal>,  // ICRC-7 collection supporter badges are minted on; None disables badges
+    admin_discord_webhook: Option<String>,  // Receives every project's status, feature and milestone events
+    governance_canister: Option<Principal>,  // May call execute_proposal; None disables governance
+    featuring_prices: Vec<FeaturingPrice>,  // Ledgers paid featuring accepts; empty disables it
+    climate_api_url: Option<String>,  // URL template with {lat} and {lng}; None disables climate enrichment
+    vote_archive: Option<Principal>,  // Canister older votes are moved to; None keeps every vote here
+    vote_archive_after_rounds: u32,  // Completed rounds of VOTE_ROUND_DAYS kept here
+    region_shards: Vec<shards::RegionShard>,  // Regions whose projects live on sibling canisters
+    router: Option<Principal>,  // Set on a shard: the router allowed to call create_project_for
+    geocoding_api_url: Option<String>,  // URL template with {lat} and {lng}; None leaves empty addresses empty
+    siwe_domain: Option<String>,  // Host named in wallet link messages; None uses this canister's icp0.io host
+    escrow_ledger: Option<Principal>,  // ckBTC ledger pledges are escrowed on; None disables escrow
+    procurement_principal: Option<Principal>,  // Receives escrowed funds once hardware is received
+    asset_canister: Option<Principal>,  // Asset canister owners upload images to with a grant; None disables grants
+    analytics_endpoint: Option<String>,  // Receives platform metrics while the push is switched on
+    notification_relay_url: Option<String>,  // HTTPS endpoint RelayEvents are posted to; None disables the relay
+    relay_templates: Vec<RelayTemplate>,  // Overrides of the default event messages
+    heap_high_water_mark_bytes: u64,  // New projects, images and telemetry are rejected above this heap size
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Self {
+            default_page_limit: DEFAULT_PAGE_LIMIT,
+            max_page_limit: MAX_PAGE_LIMIT,
+            max_tags_per_project: MAX_TAGS_PER_PROJECT,
+            vote_cooldown_seconds: 0,
+            featured_slots: FEATURED_SLOTS,
+            report_escalation_threshold: REPORT_ESCALATION_THRESHOLD,
+            duplicate_radius_km: DUPLICATE_RADIUS_KM,
+            duplicate_name_similarity: DUPLICATE_NAME_SIMILARITY,
+            stale_after_days: STALE_AFTER_DAYS,
+            stale_suspension_grace_days: STALE_SUSPENSION_GRACE_DAYS,
+            admin_approval_threshold: ADMIN_APPROVAL_THRESHOLD,
+            terms_version: 0,
+            terms_hash: String::new(),
+            raw_retention_days: RAW_RETENTION_DAYS,
+            hourly_retention_days: None,
+            require_signed_telemetry: true,
+            incident_after_minutes: INCIDENT_AFTER_MINUTES,
+            donation_ledger: None,
+            badge_collection: None,
+            admin_discord_webhook: None,
+            governance_canister: None,
+            featuring_prices: vec![],
+            climate_api_url: None,
+            vote_archive: None,
+            vote_archive_after_rounds: VOTE_ARCHIVE_AFTER_ROUNDS,
+            region_shards: vec![],
+            router: None,
+            geocoding_api_url: None,
+            siwe_domain: None,
+            escrow_ledger: None,
+            procurement_principal: None,
+            asset_canister: None,
+            analytics_endpoint: None,
+            notification_relay_url: None,
+            relay_templates: vec![],
+            heap_high_water_mark_bytes: HEAP_HIGH_WATER_MARK_BYTES,
+        }
+    }
+}
+
+impl Config {
+    fn validate(&self) -> Result<(), String> {
+        if self.default_page_limit == 0 || self.default_page_limit > self.max_page_limit {
+            return Err("default_page_limit must be between 1 and max_page_limit".to_string());
+        }
+        if self.featured_slots == 0 {
+            return Err("featured_slots must be at least 1".to_string());
+        }
+        if self.report_escalation_threshold == 0 {
+            return Err("report_escalation_threshold must be at least 1".to_string());
+        }
+        if self.vote_cooldown_seconds > MAX_VOTE_COOLDOWN_SECONDS {
+            return Err(format!("vote_cooldown_seconds cannot exceed {}", MAX_VOTE_COOLDOWN_SECONDS));
+        }
+        if self.stale_after_days > MAX_STALE_DAYS || self.stale_suspension_grace_days > MAX_STALE_DAYS {
+            return Err(format!("stale_after_days and stale_suspension_grace_days cannot exceed {}", MAX_STALE_DAYS));
+        }
+        if !(0.0..=1.0).contains(&self.duplicate_name_similarity) {
+            return Err("duplicate_name_similarity must be between 0 and 1".to_string());
+        }
+        if self.duplicate_radius_km <= 0.0 {
+            return Err("duplicate_radius_km must be positive".to_string());
+        }
+        if self.admin_approval_threshold == 0 {
+            return Err("admin_approval_threshold must be at least 1".to_string());
+        }
+        if self.admin_discord_webhook.as_ref().is_some_and(|url| !webhooks::is_webhook_url(url)) {
+            return Err(format!("admin_discord_webhook must start with {}", webhooks::WEBHOOK_URL_PREFIX));
+        }
+        if self.notification_relay_url.as_ref().is_some_and(|url| !url.starts_with("https://")) {
+            return Err("notification_relay_url must be an https URL".to_string());
+        }
+        relay::validate_templates(&self.relay_templates)?;
+        shards::validate(&self.region_shards)?;
+        if let Some(template) = &self.geocoding_api_url {
+            geocoding::validate_url_template(template)?;
+        }
+        if let Some(domain) = &self.siwe_domain {
+            if domain.is_empty() || !domain.chars().all(|c| c.is_ascii_alphanumeric() || c == '.' || c == '-' || c == ':') {
+                return Err("siwe_domain must be a host name, optionally with a port".to_string());
+            }
+        }
+        if let Some(template) = &self.climate_api_url {
+            climate::validate_url_template(template)?;
+        }
+        for (i, price) in self.featuring_prices.iter().enumerate() {
+            if price.price_per_day == 0u64 {
+                return Err("featuring_prices must be positive".to_string());
+            }
+            if self.featuring_prices[..i].iter().any(|p| p.ledger == price.ledger) {
+                return Err("featuring_prices may list each ledger once".to_string());
+            }
+        }
+        if self.analytics_endpoint.as_ref().is_some_and(|url| !url.starts_with("https://")) {
+            return Err("analytics_endpoint must be an https URL".to_string());
+        }
+        if self.escrow_ledger.is_some() && self.procurement_principal.is_none() {
+            return Err("procurement_principal is required when escrow_ledger is set".to_string());
+        }
+        // Dashboards count votes over the last DASHBOARD_DAYS from local state
+        if (self.vote_archive_after_rounds as u64) * VOTE_ROUND_DAYS < DASHBOARD_DAYS {
+            return Err(format!("vote_archive_after_rounds must be at least {}", DASHBOARD_DAYS.div_ceil(VOTE_ROUND_DAYS)));
+        }
+        if self.incident_after_minutes == 0 || self.incident_after_minutes > MAX_INCIDENT_AFTER_MINUTES {
+            return Err(format!("incident_after_minutes must be between 1 and {}", MAX_INCIDENT_AFTER_MINUTES));
+        }
+        if self.heap_high_water_mark_bytes == 0 || self.heap_high_water_mark_bytes > MAX_HEAP_BYTES {
+            return Err(format!("heap_high_water_mark_bytes must be between 1 and {}", MAX_HEAP_BYTES));
+        }
+        if self.terms_version > 0 && self.terms_hash.is_empty() {
+            return Err("terms_hash is required when terms_version is set".to_string());
+        }
+        self.retention().validate()
+    }
+
+    fn retention(&self) -> RetentionPolicy {
+        RetentionPolicy {
+            raw_days: self.raw_retention_days,
+            hourly_days: self.hourly_retention_days,
+        }
+    }
+}
+
+#[derive(CandidType, Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct RetentionPolicy {
+    raw_days: u32,
+    hourly_days: Option<u32>,  // None keeps hourly aggregates forever
+}
+
+impl RetentionPolicy {
+    fn validate(&self) -> Result<(), String> {
+        if self.raw_days == 0 {
+            return Err("Raw readings must be kept for at least one day".to_string());
+        }
+        if self.raw_days > MAX_RETENTION_DAYS || self.hourly_days.is_some_and(|days| days > MAX_RETENTION_DAYS) {
+            return Err(format!("Retention cannot exceed {} days; leave hourly_days unset to keep aggregates forever", MAX_RETENTION_DAYS));
+        }
+        if self.hourly_days.is_some_and(|days| days < self.raw_days) {
+            return Err("Hourly aggregates cannot expire before raw readings".to_string());
+        }
+        Ok(())
+    }
+}
+
+#[derive(CandidType, Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub enum AdminAction {
+    RemoveAdmin(Principal),
+    DeleteProject(String),
+    UpdateConfig(Box<Config>),
+}
+
+#[derive(CandidType, Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub enum ProposalStatus {
+    Pending,
+    Executed,
+    Expired,
+    Failed(String),
+}
+
+#[derive(CandidType, Serialize, Deserialize, Clone, Debug)]
+pub struct AdminProposal {
+    id: u64,
+    action: AdminAction,
+    proposer: Principal,
+    approvals: Vec<Principal>,
+    required_approvals: u32,
+    created_at: u64,
+    expires_at: u64,
+    status: ProposalStatus,
+}
+
+#[derive(CandidType, Serialize, Deserialize, Clone, Debug)]
+pub struct SuperAdminTransfer {
+    from: Principal,
+    to: Principal,
+    proposed_at: u64,
+    expires_at: u64,
+}
+
+#[derive(CandidType, Serialize, Deserialize, Clone, Debug)]
+pub struct TermsAcceptance {
+    version: u32,
+    accepted_at: u64,
+}
+
+#[derive(CandidType, Serialize, Deserialize, Clone, Debug)]
+pub struct AdminInvite {
+    principal: Principal,
+    role: Role,
+    invited_by: Principal,
+    invited_at: u64,
+    expires_at: u64,
+}
+
+#[derive(CandidType, Deserialize, Clone, Debug)]
+pub struct HttpRequest {
+    method: String,
+    url: String,
+    headers: Vec<(String, String)>,
+    #[serde(with = "serde_bytes")]
+    body: Vec<u8>,
+}
+
+#[derive(CandidType, Serialize, Clone, Debug)]
+pub struct HttpResponse {
+    status_code: u16,
+    headers: Vec<(String, String)>,
+    #[serde(with = "serde_bytes")]
+    body: Vec<u8>,
+}
+
+// A public event in a project's life, as sent to its followers
+#[derive(Clone, Debug)]
+struct ProjectUpdate {
+    sequence: u64,  // Per project, counting from 0; stays with the update as older ones are dropped
+    message: String,
+    timestamp: u64,
+}
+
+// Projects by id. Changes go through here so they are queued for re-certification.
+#[derive(Default)]
+struct ProjectStore {
+    projects: HashMap<String, Project>,
+    changed: BTreeSet<String>,  // Ids changed since the last certify_projects run
+}
+
+impl Deref for ProjectStore {
+    type Target = HashMap<String, Project>;
+
+    fn deref(&self) -> &Self::Target {
+        &self.projects
+    }
+}
+
+impl ProjectStore {
+    fn get_mut(&mut self, id: &str) -> Option<&mut Project> {
+        let project = self.projects.get_mut(id)?;
+        self.changed.insert(id.to_string());
+        Some(project)
+    }
+
+    fn insert(&mut self, id: String, project: Project) -> Option<Project> {
+        self.changed.insert(id.clone());
+        self.projects.insert(id, project)
+    }
+
+    fn remove(&mut self, id: &str) -> Option<Project> {
+        self.changed.insert(id.to_string());
+        self.projects.remove(id)
+    }
+
+    fn take_changed(&mut self) -> BTreeSet<String> {
+        std::mem::take(&mut self.changed)
+    }
+}
+
+#[derive(Default)]
+struct State {
+    projects: ProjectStore,
+    admins: HashMap<Principal, Role>,
+    pending_super_admin_transfer: Option<SuperAdminTransfer>,
+    admin_invites: HashMap<Principal, AdminInvite>,  // Invitee -> outstanding invite
+    service_principals: HashMap<Principal, ServicePrincipal>,
+    terms_acceptances: HashMap<Principal, TermsAcceptance>,  // Latest version each principal accepted
+    admin_proposals: BTreeMap<u64, AdminProposal>,
+    next_proposal_id: u64,
+    owner_projects: HashMap<Principal, Vec<String>>,
+    date_index: BTreeSet<(u64, String)>,  // (created_at, project_id); ids keep same-time projects apart
+    status_index: HashMap<ProjectStatus, BTreeSet<(u64, String)>>,  // status -> (created_at, project_id)
+    project_votes: HashMap<String, HashMap<Principal, Vote>>,  // Votes not yet moved to the vote archive
+    archived_vote_counts: HashMap<String, u64>,  // project_id -> votes held by the vote archive
+    archiving_votes: bool,  // A batch is on its way to the archive
+    unconfirmed_vote_batch: Vec<ProjectVote>,  // Sent to the archive without a reply; sent again next run
+    archived_votes: HashSet<u64>,  // vote_archive::vote_key of votes moved to or on their way to the archive
+    vote_index: HashMap<Principal, Vec<String>>,  // User's voted projects, excluding archived votes
+    vote_rank: BTreeSet<(u64, String)>,  // (vote_count, project_id) for vote-ranked listings
+    leaderboard: Vec<(u64, String)>,  // Top LEADERBOARD_SIZE listed entries of vote_rank, highest first
+    featured_projects: Vec<String>,  // Unique project_ids in display order; no timestamp keys to collide
+    trending_projects: Vec<String>,  // Listed project_ids by recent votes, rebuilt by refresh_trending
+    feature_windows: HashMap<String, FeatureWindow>,  // Scheduled or time-limited features
+    tag_index: HashMap<String, Vec<String>>,   // tag -> project_ids
+    listed_tag_counts: HashMap<String, u64>,  // tag -> listed projects carrying it; tags at 0 are dropped
+    tag_rank: BTreeSet<(std::cmp::Reverse<u64>, String)>,  // listed_tag_counts, most used first
+    search_index: HashMap<String, HashSet<String>>,  // index_text term of name or description -> project_ids
+    category_index: HashMap<ProjectCategory, Vec<String>>,  // category -> project_ids
+    country_index: HashMap<String, Vec<String>>,  // country code -> project_ids
+    gateway_index: HashMap<GatewayType, Vec<String>>,  // gateway type -> project_ids
+    geocode_queue: VecDeque<String>,  // Projects whose address is to be looked up
+    geocode_retries: HashMap<String, (u32, u64)>,  // project_id -> (failed lookups, not retried before)
+    project_reports: HashMap<String, HashMap<Principal, Report>>,  // One report per principal per project
+    notifications: HashMap<Principal, VecDeque<Notification>>,  // Per-principal inbox, oldest first
+    follower_fanout: VecDeque<FollowerFanout>,  // Follower notifications still to be delivered
+    next_notification_id: u64,
+    follower_index: HashMap<String, HashSet<Principal>>,  // project_id -> followers
+    project_updates: HashMap<String, VecDeque<ProjectUpdate>>,  // project_id -> what followers were told, oldest first
+    followed_index: HashMap<Principal, Vec<String>>,  // User's followed projects
+    slug_index: HashMap<String, String>,  // slug -> project_id
+    gateways: HashMap<String, Gateway>,
+    gateway_online_intervals: HashMap<String, Vec<(u64, u64)>>,  // Merged (start, end) spans credited by heartbeats
+    project_gateways: HashMap<String, Vec<String>>,  // project_id -> gateway ids
+    gateway_sensors: HashMap<String, BTreeSet<String>>,  // gateway id -> serials it last relayed
+    gateway_sequences: HashMap<String, u64>,  // gateway id -> highest signed batch sequence accepted
+    incidents: BTreeMap<u64, Incident>,
+    next_incident_id: u64,
+    open_incidents: HashMap<String, u64>,  // gateway id -> its open incident
+    sensors: HashMap<String, Sensor>,  // serial -> sensor
+    project_sensors: HashMap<String, Vec<String>>,  // project_id -> sensor serials
+    sensor_history: HashMap<String, Vec<SensorAssignment>>,  // serial -> provenance, oldest first
+    alert_rules: BTreeMap<u64, AlertRule>,
+    alert_rule_index: HashMap<(String, String), Vec<u64>>,  // (project_id, metric) -> rule ids
+    next_alert_rule_id: u64,
+    alert_trackers: HashMap<(u64, String), AlertTracker>,  // (rule_id, serial) -> breach state
+    alerts: HashMap<String, Vec<Alert>>,  // project_id -> alerts, oldest first
+    retention_overrides: HashMap<String, RetentionPolicy>,  // project_id -> policy replacing the config default
+    offline_sensors: HashSet<String>,  // Reported offline, cleared when they are next seen
+    discord_opt_in: HashSet<String>,  // Projects posting events to the webhook in private_discord
+    badges: HashMap<(Principal, String), BadgeReceipt>,  // One badge per supporter per project
+    pending_badges: HashSet<(Principal, String)>,  // Mints awaiting the collection's reply
+    unconfirmed_badges: HashSet<(Principal, String)>,  // Mints whose outcome is unknown; checked before minting again
+    donations: BTreeMap<(Principal, u64), Donation>,  // (ledger, block index) -> donation
+    funding_reconciliations: HashMap<String, FundingReconciliation>,  // project_id -> latest reconciliation
+    pending_donation_blocks: HashSet<(Principal, u64)>,  // (ledger, block) being verified with the ledger
+    donation_withdrawals: BTreeMap<u64, DonationWithdrawal>,
+    next_withdrawal_id: u64,
+    removed_donation_owners: HashMap<String, Principal>,  // Removed projects with donations -> owner, who can still withdraw
+    featuring_bookings: BTreeMap<u64, FeaturingBooking>,
+    next_booking_id: u64,
+    featuring_blocks: HashSet<(Principal, u64)>,  // (ledger, block) used or being verified for bookings
+    pledges: BTreeMap<u64, Pledge>,
+    pledge_totals: HashMap<String, (u32, u32)>,  // project_id -> sensors in (open, fulfilled) pledges
+    next_pledge_id: u64,
+    escrow_blocks: HashSet<u64>,  // Escrow ledger blocks used or being verified for pledges
+    sponsorships: BTreeMap<u64, Sponsorship>,
+    next_sponsorship_id: u64,
+    claim_codes: HashMap<String, ClaimCode>,  // SHA-256 of the code -> claim; codes themselves are never stored
+    templates: BTreeMap<u64, ProjectTemplate>,
+    next_template_id: u64,
+    verification_requests: HashMap<String, VerificationRequest>,  // Latest request per project
+    review_claims: HashMap<String, ReviewClaim>,  // project_id -> reviewer working on it
+    review_decisions: Vec<ReviewDecision>,  // Approve/reject outcomes of pending reviews
+    rejection_reversals: HashMap<String, HashSet<Principal>>,  // Rejected project_id -> admins approving it
+    previously_rejected: HashSet<String>,  // Rejected and not approved since, whatever the current status
+    last_vote_at: HashMap<Principal, u64>,  // For the vote cooldown
+    blocklist: HashMap<Principal, BlockEntry>,
+    disabled_subsystems: HashSet<Subsystem>,  // Circuit breakers tripped by admins
+    analytics_push_enabled: bool,
+    last_analytics_push: Option<(u64, u64)>,  // (pushed_at, delivery id)
+    admin_notes: HashMap<String, Vec<AdminNote>>,  // project_id -> internal notes, never public
+    appeals: BTreeMap<u64, Appeal>,
+    next_appeal_id: u64,
+    audit_log: Vec<AuditEntry>,  // Append-only, id is the position in the log
+}
+
+thread_local! {
+    static STATE: RefCell<State> = RefCell::new(State::default());
+    static CONFIG: RefCell<Config> = RefCell::new(Config::default());
+}
+
+fn config() -> Config {
+    CONFIG.with(|config| config.borrow().clone())
+}
+
+// Helper functions
+fn index_text(text: &str) -> Vec<String> {
+    text.to_lowercase()
+        .split_whitespace()
+        .map(|s| s.to_string())
+        .collect()
+}
+
+// Nanoseconds since the epoch as an RFC 3339 UTC timestamp
+fn rfc3339(nanos: u64) -> String {
+    let secs = nanos / 1_000_000_000;
+    let (days, rem) = (secs / 86_400, secs % 86_400);
+    // Civil date from days since 1970-01-01 (Howard Hinnant's algorithm)
+    let z = days as i64 + 719_468;
+    let era = z.div_euclid(146_097);
+    let doe = z - era * 146_097;
+    let yoe = (doe - doe / 1460 + doe / 36_524 - doe / 146_096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + if month <= 2 { 1 } else { 0 };
+    format!(
+        "{:04}-{:02}-{:02}T{:02}:{:02}:{:02}Z",
+        year, month, day, rem / 3600, rem % 3600 / 60, rem % 60
+    )
+}
+
+// Guard applied to every update entry point
+fn caller_is_not_blocked() -> Result<(), String> {
+    let caller = caller();
+    STATE.with(|state| {
+        if state.borrow().blocklist.contains_key(&caller) {
+            Err("Caller is blocked".to_string())
+        } else {
+            Ok(())
+        }
+    })
+}
+
+fn ensure_enabled(subsystem: Subsystem) -> Result<(), String> {
+    STATE.with(|state| {
+        if state.borrow().disabled_subsystems.contains(&subsystem) {
+            Err(format!("{:?} is temporarily disabled", subsystem))
+        } else {
+            Ok(())
+        }
+    })
+}
+
+// Refuses writes that grow state once live heap data passes the configured mark
+fn ensure_heap_headroom() -> Result<(), String> {
+    let (used, limit) = (heap::allocated_bytes(), config().heap_high_water_mark_bytes);
+    if used >= limit {
+        return Err(format!("Canister memory is nearly full ({} of {} bytes); new data is not accepted until space is freed",
+            used, limit));
+    }
+    Ok(())
+}
+
+fn validate_images(images: &ProjectImages) -> Result<(), String> {
+    if images.gallery.len() > MAX_GALLERY_IMAGES {
+        return Err(format!("Galleries can have at most {} images", MAX_GALLERY_IMAGES));
+    }
+    std::iter::once(&images.background)
+        .chain(&images.gallery)
+        .try_for_each(|reference| image_store::validate_reference(reference))
+}
+
+// Pins recorded for references the images still use
+fn retained_pins(previous: &ProjectImages, images: &ProjectImages) -> Option<Vec<ImagePin>> {
+    let pins: Vec<ImagePin> = previous.pins.iter()
+        .flatten()
+        .filter(|pin| images.background == pin.reference || images.gallery.contains(&pin.reference))
+        .cloned()
+        .collect();
+    Some(pins).filter(|pins| !pins.is_empty())
+}
+
+// Rejects text containing blocked terms and returns any terms that should be flagged
+fn screen_text(texts: &[&str]) -> Result<Vec<String>, String> {
+    let matches = word_filter::scan(texts);
+    if !matches.blocked.is_empty() {
+        return Err(format!("Submission contains blocked terms: {}", matches.blocked.join(", ")));
+    }
+    Ok(matches.flagged)
+}
+
+// Files a report from the canister itself so flagged content lands in the moderation queue
+fn flag_for_review(state: &mut State, project_id: &str, terms: &[String], timestamp: u64) {
+    let report = Report {
+        reporter: ic_cdk::id(),
+        reason: format!("Automatically flagged for: {}", terms.join(", ")),
+        timestamp,
+    };
+    state.project_reports
+        .entry(project_id.to_string())
+        .or_default()
+        .insert(report.reporter, report);
+
+    if let Some(project) = state.projects.get_mut(project_id) {
+        if project.status == ProjectStatus::Approved {
+            project.status = ProjectStatus::PendingReview;
+            on_status_changed(state, project_id, timestamp);
+        }
+    }
+}
+
+fn ensure_terms_accepted(principal: Principal) -> Result<(), String> {
+    let terms_version = config().terms_version;
+    if terms_version == 0 {
+        return Ok(());
+    }
+    STATE.with(|state| {
+        match state.borrow().terms_acceptances.get(&principal) {
+            Some(acceptance) if acceptance.version >= terms_version => Ok(()),
+            _ => Err(format!("Terms of service version {} must be accepted first", terms_version)),
+        }
+    })
+}
+
+fn role_permits(role: Role, permission: Permission) -> bool {
+    match role {
+        Role::SuperAdmin => true,
+        Role::Reviewer => matches!(permission, Permission::ReviewProjects | Permission::ViewPrivate),
+        Role::Moderator => matches!(
+            permission,
+            Permission::ModerateContent | Permission::ManageSensors | Permission::ViewPrivate | Permission::ManageImagePins
+        ),
+        Role::Curator => matches!(permission, Permission::FeatureProjects | Permission::ManageTemplates),
+        Role::Finance => permission == Permission::ManageEscrow,
+    }
+}
+
+// Suspensions (and lifting them) are SuperAdmin-only; archiving and drafting go
+// through their own entry points
+fn status_change_permission(from: &ProjectStatus, to: &ProjectStatus) -> Permission {
+    if *from == ProjectStatus::Suspended || *to == ProjectStatus::Suspended {
+        return Permission::SuspendProjects;
+    }
+    match to {
+        ProjectStatus::Approved | ProjectStatus::Rejected | ProjectStatus::PendingReview => Permission::ReviewProjects,
+        ProjectStatus::Archived | ProjectStatus::Draft => Permission::ManageAdmins,
+        ProjectStatus::Suspended => Permission::SuspendProjects,
+    }
+}
+
+fn caller_role() -> Option<Role> {
+    let caller = caller();
+    STATE.with(|state| state.borrow().admins.get(&caller).copied())
+}
+
+fn scope_permits(scope: ServiceScope, permission: Permission) -> bool {
+    match scope {
+        ServiceScope::ReadPrivate => permission == Permission::ViewPrivate,
+        ServiceScope::PostTelemetry => permission == Permission::PostTelemetry,
+        ServiceScope::ManageImagePins => permission == Permission::ManageImagePins,
+    }
+}
+
+fn caller_has_permission(permission: Permission) -> bool {
+    if caller_role().is_some_and(|role| role_permits(role, permission)) {
+        return true;
+    }
+    let caller = caller();
+    STATE.with(|state| {
+        state.borrow()
+            .service_principals
+            .get(&caller)
+            .is_some_and(|service| service.scopes.iter().any(|scope| scope_permits(*scope, permission)))
+    })
+}
+
+// Archived projects are kept for the record and drafts are private to their
+// owner; neither shows up in discovery listings
+fn is_listed(project: &Project) -> bool {
+    is_listed_status(&project.status)
+}
+
+fn is_listed_status(status: &ProjectStatus) -> bool {
+    !matches!(status, ProjectStatus::Archived | ProjectStatus::Draft)
+}
+
+// Lookups by id hide drafts from everyone but their owner and admins
+fn is_visible_to(project: &Project, caller: &Principal, can_view_drafts: bool) -> bool {
+    project.status != ProjectStatus::Draft || project.owner == *caller || can_view_drafts
+}
+
+fn listed_count(state: &State) -> usize {
+    state.status_index
+        .iter()
+        .filter(|(status, _)| is_listed_status(status))
+        .map(|(_, ids)| ids.len())
+        .sum()
+}
+
+// Listed projects via status_index, oldest first within each status
+fn listed_projects(state: &State) -> impl Iterator<Item = &Project> {
+    state.status_index
+        .iter()
+        .filter(|(status, _)| is_listed_status(status))
+        .flat_map(|(_, ids)| ids.iter())
+        .filter_map(|(_, id)| state.projects.get(id))
+}
+
+fn normalize_name(name: &str) -> String {
+    name.to_lowercase()
+        .split(|c: char| !c.is_alphanumeric())
+        .filter(|s| !s.is_empty())
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+// Levenshtein ratio between two normalized names, 1.0 meaning identical
+fn name_similarity(a: &str, b: &str) -> f64 {
+    let a: Vec<char> = normalize_name(a).chars().collect();
+    let b: Vec<char> = normalize_name(b).chars().collect();
+    let longest = a.len().max(b.len());
+    if longest == 0 {
+        return 1.0;
+    }
+
+    let mut previous: Vec<usize> = (0..=b.len()).collect();
+    for (i, ca) in a.iter().enumerate() {
+        let mut current = vec![i + 1; b.len() + 1];
+        for (j, cb) in b.iter().enumerate() {
+            let cost = if ca == cb { 0 } else { 1 };
+            current[j + 1] = (previous[j] + cost)
+                .min(previous[j + 1] + 1)
+                .min(current[j] + 1);
+        }
+        previous = current;
+    }
+
+    1.0 - previous[b.len()] as f64 / longest as f64
+}
+
+// Projects nearby with a name close enough to be a resubmission or copy
+fn find_duplicate_projects(state: &State, name: &str, geohash: &str, exclude: Option<&str>) -> Vec<String> {
+    let config = config();
+    geo_index::find(geohash.to_string(), config.duplicate_radius_km)
+        .into_iter()
+        .filter(|id| Some(id.as_str()) != exclude)
+        .filter(|id| {
+            state.projects
+                .get(id)
+                .map(|p| {
+                    p.status != ProjectStatus::Draft
+                        && name_similarity(&p.name, name) >= config.duplicate_name_similarity
+                })
+                .unwrap_or(false)
+        })
+        .collect()
+}
+
+fn fulfillment_percentage(project: &Project) -> f64 {
+    if project.sensors_required == 0 {
+        return 100.0;
+    }
+    (project.sensors_assigned as f64 / project.sensors_required as f64 * 100.0).min(100.0)
+}
+
+// Shipped hardware is counted until it has been assigned, so it isn't procured twice
+fn project_demand(state: &State, project: &Project) -> ProjectDemand {
+    let (pledged_open, pledged_fulfilled) = state.pledge_totals.get(&project.id).copied().unwrap_or_default();
+    let covered = project.sensors_assigned.max(pledged_fulfilled).saturating_add(pledged_open);
+
+    ProjectDemand {
+        project_id: project.id.clone(),
+        name: project.name.clone(),
+        category: project.category.clone(),
+        sensors_required: project.sensors_required,
+        sensors_assigned: project.sensors_assigned,
+        pledged_open,
+        pledged_fulfilled,
+        open_demand: project.sensors_required.saturating_sub(covered),
+    }
+}
+
+fn shift_pledge_totals(state: &mut State, project_id: &str, status: PledgeStatus, count: u32, add: bool) {
+    let totals = state.pledge_totals.entry(project_id.to_string()).or_default();
+    let total = match status {
+        PledgeStatus::Open => &mut totals.0,
+        PledgeStatus::Fulfilled => &mut totals.1,
+        PledgeStatus::Cancelled => return,
+    };
+    *total = if add { total.saturating_add(count) } else { total.saturating_sub(count) };
+}
+
+// Every pledge status change goes through here to keep pledge_totals current
+fn set_pledge_status(state: &mut State, pledge_id: u64, status: PledgeStatus) {
+    let Some(pledge) = state.pledges.get_mut(&pledge_id) else {
+        return;
+    };
+    let previous = std::mem::replace(&mut pledge.status, status);
+    let (project_id, count) = (pledge.project_id.clone(), pledge.count);
+    shift_pledge_totals(state, &project_id, previous, count, false);
+    shift_pledge_totals(state, &project_id, status, count, true);
+}
+
+fn funding_percentage(project: &Project) -> f64 {
+    if project.sensors_required == 0 {
+        return 100.0;
+    }
+    (project.sensors_sponsored as f64 / project.sensors_required as f64 * 100.0).min(100.0)
+}
+
+// Keeps the fully-equipped marker in sync whenever assigned or required counts change
+fn on_sensor_count_changed(project: &mut Project, timestamp: u64) {
+    let fully_equipped = project.sensors_assigned >= project.sensors_required;
+    if fully_equipped && project.fully_equipped_at.is_none() {
+        project.fully_equipped_at = Some(timestamp);
+    } else if !fully_equipped {
+        project.fully_equipped_at = None;
+    }
+}
+
+// Owner edits, sensor changes and device traffic all count as signs of life
+fn record_activity(project: &mut Project, timestamp: u64) {
+    project.last_activity_at = timestamp;
+    project.stale_since = None;
+}
+
+// Registry sensors count towards sensors_assigned while linked to a project
+fn link_sensor(state: &mut State, serial: &str, project_id: &str, timestamp: u64) -> Result<(), String> {
+    if state.sensors.get(serial).is_some_and(|s| s.retired.is_some()) {
+        return Err("Sensor has been retired".to_string());
+    }
+    let project = state.projects.get_mut(project_id)
+        .ok_or("Project not found")?;
+    project.sensors_assigned = project.sensors_assigned.saturating_add(1);
+    on_sensor_count_changed(project, timestamp);
+    record_activity(project, timestamp);
+
+    if let Some(sensor) = state.sensors.get_mut(serial) {
+        sensor.project_id = Some(project_id.to_string());
+        sensor.assigned_at = Some(timestamp);
+    }
+    state.project_sensors.entry(project_id.to_string()).or_default().push(serial.to_string());
+    state.sensor_history.entry(serial.to_string()).or_default().push(SensorAssignment {
+        project_id: project_id.to_string(),
+        assigned_at: timestamp,
+        unassigned_at: None,
+    });
+    refresh_active_sensors(state, project_id, timestamp);
+    Ok(())
+}
+
+fn refresh_active_sensors(state: &mut State, project_id: &str, now: u64) {
+    let active = state.project_sensors
+        .get(project_id)
+        .into_iter()
+        .flatten()
+        .filter_map(|serial| state.sensors.get(serial))
+        .filter(|s| s.relayed_at.is_some_and(|at| now.saturating_sub(at) < ACTIVE_SENSOR_NANOS))
+        .count() as u32;
+    if let Some(project) = state.projects.get_mut(project_id) {
+        project.active_sensor_count = active;
+    }
+}
+
+fn has_voted(state: &State, voter: &Principal, project_id: &str) -> bool {
+    state.vote_index.get(voter).is_some_and(|ids| ids.iter().any(|id| id == project_id))
+        || state.archived_votes.contains(&vote_archive::vote_key(voter, project_id))
+}
+
+fn cast_vote(state: &mut State, voter: Principal, project_id: &str, timestamp: u64) -> Result<(), String> {
+    // Verify project exists and is still open for votes
+    match state.projects.get(project_id) {
+        None => return Err("Project not found".to_string()),
+        Some(project) if !is_listed(project) => {
+            return Err("Project is not open for votes".to_string());
+        }
+        Some(_) => {}
+    }
+    // Archived votes have left project_votes, so check the archive keys too
+    if has_voted(state, &voter, project_id) {
+        return Err("Already voted".to_string());
+    }
+
+    let cooldown = config().vote_cooldown_seconds.saturating_mul(1_000_000_000);
+    if let Some(last_vote_at) = state.last_vote_at.get(&voter) {
+        if timestamp.saturating_sub(*last_vote_at) < cooldown {
+            return Err("Voting too frequently, please wait before voting again".to_string());
+        }
+    }
+    state.last_vote_at.insert(voter, timestamp);
+
+    // Add vote
+    state.project_votes
+        .entry(project_id.to_string())
+        .or_default()
+        .insert(voter, Vote { voter, timestamp });
+
+    // Update vote index
+    state.vote_index
+        .entry(voter)
+        .or_default()
+        .push(project_id.to_string());
+
+    let vote_count = state.projects[project_id].vote_count + 1;
+    set_vote_count(state, project_id, vote_count);
+    Ok(())
+}
+
+fn close_sensor_assignment(state: &mut State, serial: &str, timestamp: u64) {
+    if let Some(current) = state.sensor_history.get_mut(serial).and_then(|h| h.last_mut()) {
+        if current.unassigned_at.is_none() {
+            current.unassigned_at = Some(timestamp);
+        }
+    }
+}
+
+fn unlink_sensor(state: &mut State, serial: &str, timestamp: u64) -> Option<String> {
+    let sensor = state.sensors.get_mut(serial)?;
+    let project_id = sensor.project_id.take()?;
+    sensor.assigned_at = None;
+
+    if let Some(serials) = state.project_sensors.get_mut(&project_id) {
+        serials.retain(|s| s != serial);
+    }
+    close_sensor_assignment(state, serial, timestamp);
+    if let Some(project) = state.projects.get_mut(&project_id) {
+        project.sensors_assigned = project.sensors_assigned.saturating_sub(1);
+        on_sensor_count_changed(project, timestamp);
+        record_activity(project, timestamp);
+    }
+    refresh_active_sensors(state, &project_id, timestamp);
+    Some(project_id)
+}
+
+// Readings must be in timestamp order so sustained breaches are measured correctly
+fn evaluate_alerts(state: &mut State, readings: &[Reading], now: u64) {
+    if state.alert_rules.is_empty() {
+        return;
+    }
+
+    for reading in readings.iter().filter(|r| r.anomaly.is_none()) {
+        let Some(project_id) = state.sensors.get(&reading.sensor).and_then(|s| s.project_id.clone()) else {
+            continue;
+        };
+        let rules: Vec<AlertRule> = state.alert_rule_index
+            .get(&(project_id.clone(), reading.metric.clone()))
+            .into_iter()
+            .flatten()
+            .filter_map(|id| state.alert_rules.get(id))
+            .cloned()
+            .collect();
+
+        for rule in rules {
+            let breached = match rule.rule.comparator {
+                Comparator::Below => reading.value < rule.rule.threshold,
+                Comparator::Above => reading.value > rule.rule.threshold,
+            };
+            let tracker = state.alert_trackers.entry((rule.id, reading.sensor.clone())).or_default();
+            if !breached {
+                tracker.breach_started_at = None;
+                continue;
+            }
+
+            let started = *tracker.breach_started_at.get_or_insert(reading.timestamp);
+            let sustained = reading.timestamp.saturating_sub(started) >= rule.rule.duration_seconds.saturating_mul(1_000_000_000);
+            let cooled_down = tracker.last_alerted_at
+                .is_none_or(|last| reading.timestamp.saturating_sub(last) >= rule.rule.cooldown_seconds.saturating_mul(1_000_000_000));
+            if !sustained || !cooled_down {
+                continue;
+            }
+            tracker.last_alerted_at = Some(reading.timestamp);
+
+            let alerts = state.alerts.entry(project_id.clone()).or_default();
+            if alerts.len() >= MAX_ALERTS_PER_PROJECT {
+                alerts.remove(0);
+            }
+            alerts.push(Alert {
+                rule_id: rule.id,
+                project_id: project_id.clone(),
+                sensor: reading.sensor.clone(),
+                metric: reading.metric.clone(),
+                value: reading.value,
+                breach_started_at: started,
+                triggered_at: now,
+            });
+            owner_webhooks::dispatch(&project_id, WebhookEvent::SensorAlert, serde_json::json!({
+                "rule_id": rule.id,
+                "sensor": reading.sensor,
+                "metric": reading.metric,
+                "value": reading.value,
+                "breach_started_at": started,
+            }), now);
+
+            if rule.rule.notify {
+                if let Some(owner) = state.projects.get(&project_id).map(|p| p.owner) {
+                    let message = format!(
+                        "Sensor {} reported {} {} ({:?} {})",
+                        reading.sensor, reading.metric, reading.value, rule.rule.comparator, rule.rule.threshold
+                    );
+                    notify(state, owner, Some(project_id.clone()), message, now);
+                }
+            }
+        }
+    }
+}
+
+fn hash_claim_code(code: &str) -> String {
+    use sha2::{Sha256, Digest};
+    let mut hasher = Sha256::new();
+    hasher.update(code.trim().to_uppercase().as_bytes());
+    format!("{:x}", hasher.finalize())
+}
+
+fn device_status(last_seen: Option<u64>, now: u64) -> DeviceStatus {
+    match last_seen.map(|seen| now.saturating_sub(seen)) {
+        Some(age) if age <= DEVICE_ONLINE_NANOS => DeviceStatus::Online,
+        Some(age) if age <= DEVICE_STALE_NANOS => DeviceStatus::Stale,
+        _ => DeviceStatus::Offline,
+    }
+}
+
+// Each heartbeat credits the gateway as up until DEVICE_ONLINE_NANOS later;
+// overlapping credits are merged into one interval
+fn mark_gateway_seen(state: &mut State, gateway_id: &str, now: u64) {
+    let Some(gateway) = state.gateways.get_mut(gateway_id) else {
+        return;
+    };
+    gateway.last_seen = Some(now);
+
+    if let Some(incident_id) = state.open_incidents.remove(gateway_id) {
+        if let Some(incident) = state.incidents.get_mut(&incident_id) {
+            incident.closed_at = Some(now);
+        }
+    }
+
+    let intervals = state.gateway_online_intervals.entry(gateway_id.to_string()).or_default();
+    let credited_until = now + DEVICE_ONLINE_NANOS;
+    match intervals.last_mut() {
+        Some((_, end)) if now <= *end => *end = (*end).max(credited_until),
+        _ => intervals.push((now, credited_until)),
+    }
+    let cutoff = now.saturating_sub(UPTIME_HISTORY_NANOS);
+    intervals.retain(|(_, end)| *end >= cutoff);
+}
+
+fn gateway_uptime(state: &State, gateway: &Gateway, window: UptimeWindow, now: u64) -> GatewayUptime {
+    let length = match window {
+        UptimeWindow::Day => NANOS_PER_DAY,
+        UptimeWindow::Week => 7 * NANOS_PER_DAY,
+    };
+    let window_start = now.saturating_sub(length).max(gateway.registered_at);
+    let covered: u64 = state.gateway_online_intervals
+        .get(&gateway.id)
+        .into_iter()
+        .flatten()
+        .map(|(start, end)| (*end).min(now).saturating_sub((*start).max(window_start)))
+        .sum();
+    let span = now.saturating_sub(window_start);
+
+    GatewayUptime {
+        gateway_id: gateway.id.clone(),
+        window_start,
+        window_end: now,
+        uptime_percentage: if span == 0 { 0.0 } else { (covered as f64 / span as f64 * 100.0).min(100.0) },
+    }
+}
+
+fn gateway_with_status(gateway: &Gateway, now: u64) -> GatewayWithStatus {
+    GatewayWithStatus { gateway: gateway.clone(), status: device_status(gateway.last_seen, now) }
+}
+
+fn new_sensor(serial: &str, owner: Principal, timestamp: u64) -> Sensor {
+    Sensor {
+        serial: serial.to_string(),
+        owner,
+        project_id: None,
+        registered_at: timestamp,
+        assigned_at: None,
+        last_seen: None,
+        sensor_type: None,
+        location: None,
+        relayed_by: None,
+        relayed_at: None,
+        retired: None,
+        public_key: None,
+        firmware_version: None,
+        calibration: None,
+    }
+}
+
+fn add_gateway(state: &mut State, data: GatewayData, owner: Principal, can_override: bool, timestamp: u64) -> Result<(), String> {
+    if data.id.trim().is_empty() {
+        return Err("Gateway id is required".to_string());
+    }
+    data.capabilities.validate()?;
+    let geohash = encode_geohash(data.location.lat, data.location.lng)?;
+    if state.gateways.contains_key(&data.id) {
+        return Err("Gateway is already registered".to_string());
+    }
+    if let Some(project_id) = &data.project_id {
+        let project = state.projects.get_mut(project_id)
+            .ok_or("Project not found")?;
+        if project.owner != owner && !can_override {
+            return Err("Only project owner or admins can link gateways".to_string());
+        }
+        record_activity(project, timestamp);
+        state.project_gateways.entry(project_id.clone()).or_default().push(data.id.clone());
+    }
+
+    let location = Location { geohash, ..data.location };
+    geo_index::index(location.geohash.clone(), gateway_geo_key(&data.id));
+    state.gateways.insert(data.id.clone(), Gateway {
+        id: data.id,
+        gateway_type: data.gateway_type,
+        location,
+        owner,
+        project_id: data.project_id,
+        registered_at: timestamp,
+        last_seen: None,
+        public_key: None,
+        capabilities: data.capabilities,
+    });
+    Ok(())
+}
+
+fn import_device(state: &mut State, device: DeviceImport, owner: Principal, timestamp: u64) -> Result<(), String> {
+    match device {
+        DeviceImport::Sensor { serial, sensor_type, public_key } => {
+            if serial.trim().is_empty() {
+                return Err("Sensor serial is required".to_string());
+            }
+            if state.sensors.contains_key(&serial) {
+                return Err(format!("Sensor {} is already registered", serial));
+            }
+            if sensor_type.as_ref().is_some_and(|t| !sensor_types::exists(t)) {
+                return Err("Unknown sensor type".to_string());
+            }
+            if let Some(key) = &public_key {
+                telemetry::validate_public_key(key)?;
+            }
+            let sensor = Sensor { sensor_type, public_key, ..new_sensor(&serial, owner, timestamp) };
+            state.sensors.insert(serial, sensor);
+            Ok(())
+        }
+        DeviceImport::Gateway { data, public_key } => {
+            if let Some(key) = &public_key {
+                telemetry::validate_public_key(key)?;
+            }
+            let id = data.id.clone();
+            add_gateway(state, data, owner, true, timestamp)?;
+            if let Some(gateway) = state.gateways.get_mut(&id) {
+                gateway.public_key = public_key;
+            }
+            Ok(())
+        }
+    }
+}
+
+fn sensor_with_status(sensor: &Sensor, now: u64) -> SensorWithStatus {
+    SensorWithStatus { sensor: sensor.clone(), status: device_status(sensor.last_seen, now) }
+}
+
+fn notify(state: &mut State, recipient: Principal, project_id: Option<String>, message: String, timestamp: u64) {
+    let id = state.next_notification_id;
+    state.next_notification_id += 1;
+    let inbox = state.notifications.entry(recipient).or_default();
+    if inbox.len() >= MAX_NOTIFICATIONS {
+        inbox.pop_front();
+    }
+    inbox.push_back(Notification {
+        id,
+        project_id,
+        message,
+        created_at: timestamp,
+        read: false,
+    });
+}
+
+fn notify_followers(state: &mut State, project_id: &str, message: String, timestamp: u64) {
+    let updates = state.project_updates.entry(project_id.to_string()).or_default();
+    if updates.len() >= MAX_PROJECT_UPDATES {
+        updates.pop_front();
+    }
+    let sequence = updates.back().map_or(0, |last| last.sequence + 1);
+    updates.push_back(ProjectUpdate { sequence, message: message.clone(), timestamp });
+
+    let mut followers: Vec<Principal> = state.follower_index
+        .get(project_id)
+        .map(|followers| followers.iter().copied().collect())
+        .unwrap_or_default();
+    let rest = followers.split_off(followers.len().min(MAX_FOLLOWER_NOTIFICATIONS_PER_MESSAGE));
+    for follower in followers {
+        notify(state, follower, Some(project_id.to_string()), message.clone(), timestamp);
+    }
+    if !rest.is_empty() {
+        state.follower_fanout.push_back(FollowerFanout {
+            project_id: project_id.to_string(),
+            message,
+            timestamp,
+            recipients: rest,
+        });
+    }
+}
+
+// Delivers queued follower notifications, at most MAX_FOLLOWER_NOTIFICATIONS_PER_MESSAGE per run
+fn deliver_follower_notifications() {
+    STATE.with(|state| {
+        let mut state = state.borrow_mut();
+        let mut budget = MAX_FOLLOWER_NOTIFICATIONS_PER_MESSAGE;
+        while budget > 0 {
+            let Some(mut fanout) = state.follower_fanout.pop_front() else {
+                break;
+            };
+            let rest = fanout.recipients.split_off(fanout.recipients.len().min(budget));
+            budget -= fanout.recipients.len();
+            for follower in &fanout.recipients {
+                notify(&mut state, *follower, Some(fanout.project_id.clone()), fanout.message.clone(), fanout.timestamp);
+            }
+            if !rest.is_empty() {
+                fanout.recipients = rest;
+                state.follower_fanout.push_front(fanout);
+            }
+        }
+    });
+}
+
+// Hook for every project status transition
+fn on_status_changed(state: &mut State, project_id: &str, timestamp: u64) {
+    let Some(project) = state.projects.get_mut(project_id) else {
+        return;
+    };
+    project.status_updated_at = timestamp;
+    let key = (project.created_at, project_id.to_string());
+    let status = project.status.clone();
+    let tags: BTreeSet<String> = project.tags.iter().map(|t| t.to_lowercase()).collect();
+    let was_listed = state.status_index.iter().any(|(indexed, ids)| is_listed_status(indexed) && ids.contains(&key));
+    if was_listed != is_listed_status(&status) {
+        for tag in &tags {
+            shift_listed_tag_count(state, tag, !was_listed);
+        }
+    }
+    // Callers have already overwritten the old status, so clear the key from every other bucket
+    for (indexed, ids) in state.status_index.iter_mut() {
+        if *indexed != status {
+            ids.remove(&key);
+        }
+    }
+    state.status_index.entry(status.clone()).or_default().insert(key);
+    update_leaderboard(state, project_id);
+
+    if status != ProjectStatus::PendingReview {
+        state.review_claims.remove(project_id);
+    }
+    state.rejection_reversals.remove(project_id);
+    // Suspended and rejected projects lose their featured slot, and paid bookings are refunded
+    if matches!(status, ProjectStatus::Suspended | ProjectStatus::Rejected) {
+        state.feature_windows.remove(project_id);
+        clear_featured(state, project_id);
+        revoke_bookings(state, project_id, timestamp);
+    }
+    match status {
+        ProjectStatus::Rejected => {
+            state.previously_rejected.insert(project_id.to_string());
+        }
+        ProjectStatus::Approved => {
+            state.previously_rejected.remove(project_id);
+        }
+        _ => {}
+    }
+
+    let project = &state.projects[project_id];
+    subscriptions::emit(EventPayload::StatusChanged {
+        project_id: project_id.to_string(),
+        status: project.status.clone(),
+    }, timestamp);
+    owner_webhooks::dispatch(project_id, WebhookEvent::StatusChanged, serde_json::json!({
+        "status": partner::status_name(&project.status),
+    }), timestamp);
+    if project.status == ProjectStatus::Approved {
+        relay_event(RelayEvent::ProjectApproved, project_id, &[("project", &project.name)], timestamp);
+    }
+    let message = format!("Project \"{}\" is now {:?}", project.name, project.status);
+    post_to_discord(state, project_id, &message, timestamp);
+    notify_followers(state, project_id, message, timestamp);
+}
+
+// Forwards the event to the notification relay, if one is configured
+fn relay_event(event: RelayEvent, project_id: &str, values: &[(&str, &str)], timestamp: u64) {
+    let config = config();
+    if let Some(url) = config.notification_relay_url {
+        let message = relay::render(event, &config.relay_templates, values);
+        webhooks::enqueue(&url, event.name(), relay::body(event, project_id, &message, timestamp), timestamp);
+    }
+}
+
+// Queues the message for the project's webhook if it opted in, and for the admin channel
+fn post_to_discord(state: &State, project_id: &str, message: &str, timestamp: u64) {
+    if state.discord_opt_in.contains(project_id) {
+        if let Some(project) = state.projects.get(project_id) {
+            if webhooks::is_webhook_url(&project.private_discord) {
+                webhooks::enqueue_discord(&project.private_discord, message, timestamp);
+            }
+        }
+    }
+    if let Some(url) = config().admin_discord_webhook {
+        webhooks::enqueue_discord(&url, message, timestamp);
+    }
+}
+
+fn slugify(name: &str) -> String {
+    let slug = name.to_lowercase()
+        .split(|c: char| !c.is_ascii_alphanumeric())
+        .filter(|s| !s.is_empty())
+        .collect::<Vec<_>>()
+        .join("-");
+    if slug.is_empty() {
+        "project".to_string()
+    } else {
+        slug
+    }
+}
+
+// Appends -2, -3, ... until the slug is free
+fn generate_unique_slug(state: &State, name: &str) -> String {
+    let base = slugify(name);
+    let mut slug = base.clone();
+    let mut suffix = 2;
+    while state.slug_index.contains_key(&slug) {
+        slug = format!("{}-{}", base, suffix);
+        suffix += 1;
+    }
+    slug
+}
+
+fn new_project(state: &State, project_data: ProjectData, owner: Principal, status: ProjectStatus, timestamp: u64) -> Project {
+    let mut project = Project {
+        id: generate_unique_project_id(state, &project_data.name, &owner, timestamp),
+        slug: generate_unique_slug(state, &project_data.name),
+        name: project_data.name,
+        description: project_data.description,
+        category: project_data.category,
+        gateway_type: project_data.gateway_type,
+        images: ProjectImages { pins: None, ..project_data.images },
+        location: project_data.location,
+        country_code: None,
+        project_discord: project_data.project_discord,
+        private_discord: project_data.private_discord,
+        sensors_required: project_data.sensors_required,
+        sensors_assigned: 0,
+        sensors_sponsored: 0,
+        active_sensor_count: 0,
+        last_data_at: None,
+        fully_equipped_at: None,
+        video: project_data.video,
+        status,
+        owner,
+        created_at: timestamp,
+        status_updated_at: timestamp,
+        last_activity_at: timestamp,
+        stale_since: None,
+        vote_count: 0,
+        follower_count: 0,
+        featured: false,
+        featured_at: None,
+        tags: project_data.tags,
+        verified: false,
+        verified_by: None,
+        verified_at: None,
+    };
+    on_sensor_count_changed(&mut project, timestamp);
+    project
+}
+
+// Stores a new project and adds it to every index
+fn insert_project(state: &mut State, project: Project) -> String {
+    let project_id = project.id.clone();
+
+    // Update slug index
+    state.slug_index.insert(project.slug.clone(), project_id.clone());
+    
+    // Update owner index
+    state.owner_projects
+        .entry(project.owner)
+        .or_default()
+        .push(project_id.clone());
+    
+    // Update date index
+    state.date_index.insert((project.created_at, project_id.clone()));
+    state.status_index
+        .entry(project.status.clone())
+        .or_default()
+        .insert((project.created_at, project_id.clone()));
+    
+    // Index location
+    geo_index::index(project.location.geohash.clone(), project_id.clone());
+    index_tags(state, &project_id, &project.tags, is_listed(&project));
+
+    // Update category index
+    state.category_index
+        .entry(project.category.clone())
+        .or_default()
+        .push(project_id.clone());
+    state.gateway_index
+        .entry(project.gateway_type.clone())
+        .or_default()
+        .push(project_id.clone());
+    state.vote_rank.insert((project.vote_count, project_id.clone()));
+
+    if project.location.address.trim().is_empty() {
+        state.geocode_queue.push_back(project_id.clone());
+    }
+
+    subscriptions::emit(EventPayload::ProjectCreated {
+        project_id: project_id.clone(),
+        owner: project.owner,
+        status: project.status.clone(),
+    }, project.created_at);
+
+    // Store project
+    state.projects.insert(project_id.clone(), project);
+    update_leaderboard(state, &project_id);
+    update_search_index(state, &project_id, &HashSet::new());
+
+    project_id
+}
+
+fn project_search_terms(project: &Project) -> HashSet<String> {
+    index_text(&project.name).into_iter().chain(index_text(&project.description)).collect()
+}
+
+// Brings search_index in line with the project's current name and description,
+// given the terms it was indexed under before (empty for new projects)
+fn update_search_index(state: &mut State, project_id: &str, previous: &HashSet<String>) {
+    let current = state.projects.get(project_id).map(project_search_terms).unwrap_or_default();
+    for term in previous.difference(&current) {
+        if let Some(ids) = state.search_index.get_mut(term) {
+            ids.remove(project_id);
+            if ids.is_empty() {
+                state.search_index.remove(term);
+            }
+        }
+    }
+    for term in current.difference(previous) {
+        state.search_index.entry(term.clone()).or_default().insert(project_id.to_string());
+    }
+}
+
+// Tags are indexed case-insensitively, one posting per project per tag.
+// Postings of listed projects are also counted in listed_tag_counts.
+fn index_tags(state: &mut State, project_id: &str, tags: &[String], listed: bool) {
+    for tag in tags {
+        let key = tag.to_lowercase();
+        let ids = state.tag_index.entry(key.clone()).or_default();
+        if !ids.iter().any(|id| id == project_id) {
+            ids.push(project_id.to_string());
+            if listed {
+                shift_listed_tag_count(state, &key, true);
+            }
+        }
+    }
+}
+
+fn unindex_tags(state: &mut State, project_id: &str, tags: &[String], listed: bool) {
+    for tag in tags {
+        let key = tag.to_lowercase();
+        if let Some(ids) = state.tag_index.get_mut(&key) {
+            let before = ids.len();
+            ids.retain(|id| id != project_id);
+            let removed = ids.len() < before;
+            if ids.is_empty() {
+                state.tag_index.remove(&key);
+            }
+            if removed && listed {
+                shift_listed_tag_count(state, &key, false);
+            }
+        }
+    }
+}
+
+// Moves a tag's listed count up or down by one, keeping tag_rank in step
+fn shift_listed_tag_count(state: &mut State, key: &str, up: bool) {
+    let count = state.listed_tag_counts.get(key).copied().unwrap_or(0);
+    let next = if up { count + 1 } else { count.saturating_sub(1) };
+    state.tag_rank.remove(&(std::cmp::Reverse(count), key.to_string()));
+    if next == 0 {
+        state.listed_tag_counts.remove(key);
+    } else {
+        state.listed_tag_counts.insert(key.to_string(), next);
+        state.tag_rank.insert((std::cmp::Reverse(next), key.to_string()));
+    }
+}
+
+fn set_tags(state: &mut State, project_id: &str, tags: Vec<String>) {
+    let Some(project) = state.projects.get_mut(project_id) else {
+        return;
+    };
+    let listed = is_listed(project);
+    let previous = std::mem::replace(&mut project.tags, tags.clone());
+    unindex_tags(state, project_id, &previous, listed);
+    index_tags(state, project_id, &tags, listed);
+}
+
+fn validate_tags(tags: &[String]) -> Result<(), String> {
+    let max_tags = config().max_tags_per_project;
+    if tags.len() > max_tags as usize {
+        return Err(format!("Projects can have at most {} tags", max_tags));
+    }
+    Ok(())
+}
+
+// Updates the cached vote count and its place in vote_rank
+fn set_vote_count(state: &mut State, project_id: &str, vote_count: u64) {
+    let Some(project) = state.projects.get_mut(project_id) else {
+        return;
+    };
+    let previous = std::mem::replace(&mut project.vote_count, vote_count);
+    state.vote_rank.remove(&(previous, project_id.to_string()));
+    state.vote_rank.insert((vote_count, project_id.to_string()));
+    update_leaderboard(state, project_id);
+}
+
+// Re-places the project after its votes or listing changed, then tops the board back up
+// from vote_rank. Every listed project off the board ranks below its last entry.
+fn update_leaderboard(state: &mut State, project_id: &str) {
+    state.leaderboard.retain(|(_, id)| id != project_id);
+
+    // Refill first: a project that lost votes may now rank below projects off the board
+    let missing = LEADERBOARD_SIZE - state.leaderboard.len();
+    if missing > 0 {
+        let upper = match state.leaderboard.last() {
+            Some(last) => Bound::Excluded(last.clone()),
+            None => Bound::Unbounded,
+        };
+        let refill: Vec<(u64, String)> = state.vote_rank
+            .range((Bound::Unbounded, upper))
+            .rev()
+            .filter(|(_, id)| state.projects.get(id).is_some_and(is_listed))
+            .take(missing)
+            .cloned()
+            .collect();
+        state.leaderboard.extend(refill);
+    }
+
+    // Unless the refill already placed it, the project gets on only if it beats the last entry
+    if let Some(project) = state.projects.get(project_id).filter(|p| is_listed(p)) {
+        let key = (project.vote_count, project_id.to_string());
+        let position = state.leaderboard.partition_point(|entry| *entry > key);
+        if position < LEADERBOARD_SIZE && state.leaderboard.get(position) != Some(&key) {
+            state.leaderboard.insert(position, key);
+            state.leaderboard.truncate(LEADERBOARD_SIZE);
+        }
+    }
+}
+
+fn set_country(state: &mut State, project_id: &str, country_code: Option<String>) {
+    let Some(project) = state.projects.get_mut(project_id) else {
+        return;
+    };
+    let previous = std::mem::replace(&mut project.country_code, country_code.clone());
+    if previous == country_code {
+        return;
+    }
+    if let Some(ids) = previous.and_then(|code| state.country_index.get_mut(&code)) {
+        ids.retain(|id| id != project_id);
+    }
+    if let Some(code) = country_code {
+        state.country_index.entry(code).or_default().push(project_id.to_string());
+    }
+}
+
+// Removes a project and every index entry pointing at it
+fn remove_project(state: &mut State, project_id: &str) -> Option<Project> {
+    let project = state.projects.remove(project_id)?;
+
+    state.slug_index.remove(&project.slug);
+    if let Some(ids) = state.owner_projects.get_mut(&project.owner) {
+        ids.retain(|id| id != project_id);
+    }
+    state.date_index.remove(&(project.created_at, project_id.to_string()));
+    if let Some(ids) = state.status_index.get_mut(&project.status) {
+        ids.remove(&(project.created_at, project_id.to_string()));
+    }
+    state.featured_projects.retain(|id| id != project_id);
+    state.feature_windows.remove(project_id);
+    unindex_tags(state, project_id, &project.tags, is_listed(&project));
+    update_search_index(state, project_id, &project_search_terms(&project));
+    if let Some(ids) = state.category_index.get_mut(&project.category) {
+        ids.retain(|id| id != project_id);
+    }
+    if let Some(ids) = state.gateway_index.get_mut(&project.gateway_type) {
+        ids.retain(|id| id != project_id);
+    }
+    state.vote_rank.remove(&(project.vote_count, project_id.to_string()));
+    update_leaderboard(state, project_id);
+    if let Some(votes) = state.project_votes.remove(project_id) {
+        for voter in votes.keys() {
+            if let Some(ids) = state.vote_index.get_mut(voter) {
+                ids.retain(|id| id != project_id);
+            }
+        }
+    }
+    if let Some(followers) = state.follower_index.remove(project_id) {
+        for follower in followers {
+            if let Some(ids) = state.followed_index.get_mut(&follower) {
+                ids.retain(|id| id != project_id);
+            }
+        }
+    }
+    state.project_reports.remove(project_id);
+    state.verification_requests.remove(project_id);
+    state.review_claims.remove(project_id);
+    state.rejection_reversals.remove(project_id);
+    state.previously_rejected.remove(project_id);
+    state.admin_notes.remove(project_id);
+    state.appeals.retain(|_, appeal| appeal.project_id != project_id);
+    let rule_ids: HashSet<u64> = state.alert_rules.values()
+        .filter(|rule| rule.project_id == project_id)
+        .map(|rule| rule.id)
+        .collect();
+    state.alert_rules.retain(|id, _| !rule_ids.contains(id));
+    state.alert_rule_index.retain(|(id, _), _| id != project_id);
+    state.alert_trackers.retain(|(id, _), _| !rule_ids.contains(id));
+    state.alerts.remove(project_id);
+    state.retention_overrides.remove(project_id);
+    state.sponsorships.retain(|_, sponsorship| sponsorship.project_id != project_id);
+    state.pledges.retain(|_, pledge| pledge.project_id != project_id);
+    state.pledge_totals.remove(project_id);
+    state.discord_opt_in.remove(project_id);
+    state.funding_reconciliations.remove(project_id);
+    if state.donations.values().any(|d| d.project_id == project_id) {
+        state.removed_donation_owners.insert(project_id.to_string(), project.owner);
+    }
+    state.project_updates.remove(project_id);
+    owner_webhooks::remove(project_id);
+    if let Some(ids) = project.country_code.as_ref().and_then(|code| state.country_index.get_mut(code)) {
+        ids.retain(|id| id != project_id);
+    }
+    let timestamp = ic_cdk::api::time();
+    for serial in state.project_sensors.remove(project_id).unwrap_or_default() {
+        if let Some(sensor) = state.sensors.get_mut(&serial) {
+            sensor.project_id = None;
+            sensor.assigned_at = None;
+        }
+        close_sensor_assignment(state, &serial, timestamp);
+    }
+    for gateway_id in state.project_gateways.remove(project_id).unwrap_or_default() {
+        if let Some(gateway) = state.gateways.get_mut(&gateway_id) {
+            gateway.project_id = None;
+        }
+    }
+    geo_index::remove(project_id);
+
+    Some(project)
+}
+
+// Devices share the geo index with projects, so their ids are namespaced
+fn gateway_geo_key(gateway_id: &str) -> String {
+    format!("gateway:{}", gateway_id)
+}
+
+fn sensor_geo_key(serial: &str) -> String {
+    format!("sensor:{}", serial)
+}
+
+fn encode_geohash(lat: f64, lng: f64) -> Result<String, String> {
+    geohash::encode(geohash::Coord { x: lng, y: lat }, 9)
+        .map_err(|_| "Invalid coordinates".to_string())
+}
+
+// Clients may send any geohash; the stored one always matches the coordinates
+fn set_geohash(location: &mut Location) -> Result<(), String> {
+    location.geohash = encode_geohash(location.lat, location.lng)?;
+    Ok(())
+}
+
+fn generate_project_id(name: &str, owner: &Principal, timestamp: u64, salt: u32) -> String {
+    use sha2::{Sha256, Digest};
+    let mut hasher = Sha256::new();
+    hasher.update(name.as_bytes());
+    hasher.update(owner.to_string().as_bytes());
+    hasher.update(timestamp.to_string().as_bytes());
+    if salt > 0 {
+        hasher.update(salt.to_be_bytes());
+    }
+    format!("{:x}", hasher.finalize())
+}
+
+// Same name, owner and time, e.g. one project cloned twice in a message, get different salts
+fn generate_unique_project_id(state: &State, name: &str, owner: &Principal, timestamp: u64) -> String {
+    (0..)
+        .map(|salt| generate_project_id(name, owner, timestamp, salt))
+        .find(|id| !state.projects.contains_key(id))
+        .expect("ids are unique for some salt")
+}
+
+// A page of listings; only the projects on the page are converted
+fn projects_page(projects: Vec<&Project>, page: Option<u32>, limit: Option<u32>, offset: Option<u64>) -> Result<ProjectsResponse, String> {
+    let total = projects.len();
+    Ok(projects_response(paginate_prefix(projects, total, page, limit, offset)?))
+}
+
+fn projects_response(mut paged: Page<&Project>) -> ProjectsResponse {
+    let truncated = fit_response(&mut paged.items);
+    let next = paged.start + paged.items.len();
+    ProjectsResponse {
+        next_offset: (next < paged.total as usize).then_some(next as u64),
+        projects: paged.items.into_iter().map(ProjectSummary::from).collect(),
+        total: paged.total,
+        page: paged.page,
+        pages: paged.pages,
+        has_next: paged.has_next,
+        has_prev: paged.has_prev,
+        truncated,
+    }
+}
+
+// Upper bound on the Candid size of the project's summary
+fn summary_size(project: &Project) -> usize {
+    SUMMARY_OVERHEAD_BYTES
+        + project.id.len()
+        + project.name.len()
+        + project.images.background.len()
+        + project.location.geohash.len()
+        + project.tags.iter().map(|tag| tag.len() + 4).sum::<usize>()
+}
+
+// Drops projects from the end until their summaries fit in one reply; true if any were dropped
+fn fit_response(projects: &mut Vec<&Project>) -> bool {
+    let mut size = 0;
+    let fits = projects.iter()
+        .take_while(|p| {
+            size += summary_size(p);
+            size <= MAX_RESPONSE_BYTES
+        })
+        .count();
+    let truncated = fits < projects.len();
+    projects.truncate(fits);
+    truncated
+}
+
+// For listings without pages: at most `limit` (capped at max_page_limit), and no more
+// than fit in one reply
+fn capped_summaries(mut projects: Vec<&Project>, limit: Option<u32>) -> Result<Vec<ProjectSummary>, String> {
+    projects.truncate(page_limit(limit, config().default_page_limit)?);
+    fit_response(&mut projects);
+    Ok(projects.into_iter().map(ProjectSummary::from).collect())
+}
+
+struct Page<T> {
+    items: Vec<T>,
+    start: usize,  // Position of the first item in the whole listing
+    total: u64,
+    page: u32,
+    pages: u32,
+    has_next: bool,
+    has_prev: bool,
+}
+
+impl<T> Page<T> {
+    // Converts only the items on the page, e.g. to clone a page of references
+    fn map<U>(self, f: impl FnMut(T) -> U) -> Page<U> {
+        Page {
+            items: self.items.into_iter().map(f).collect(),
+            start: self.start,
+            total: self.total,
+            page: self.page,
+            pages: self.pages,
+            has_next: self.has_next,
+            has_prev: self.has_prev,
+        }
+    }
+}
+
+// Every listing takes its limit through here: None means `default`, 0 is an error and
+// larger limits are capped at `max`
+fn capped_limit(limit: Option<u32>, default: u32, max: u32) -> Result<usize, String> {
+    match limit {
+        Some(0) => Err("limit must be at least 1".to_string()),
+        limit => Ok(limit.unwrap_or(default).min(max).max(1) as usize),
+    }
+}
+
+fn page_limit(limit: Option<u32>, default: u32) -> Result<usize, String> {
+    capped_limit(limit, default, config().max_page_limit)
+}
+
+// Pages are numbered from 1; page 0 reads as page 1 and pages past the end are empty
+fn page_window(page: Option<u32>, limit: Option<u32>) -> Result<(u32, usize), String> {
+    let page = page.unwrap_or(1).max(1);
+    Ok((page, page_limit(limit, config().default_page_limit)?))
+}
+
+fn paginate<T>(items: Vec<T>, page: Option<u32>, limit: Option<u32>) -> Result<Page<T>, String> {
+    let total = items.len();
+    paginate_prefix(items, total, page, limit, None)
+}
+
+// As paginate, when `items` are only the first entries of a listing of `total`;
+// callers make sure the requested page lies within them. An offset, e.g. a previous
+// next_offset, starts the page there instead and takes precedence over `page`.
+fn paginate_prefix<T>(items: Vec<T>, total: usize, page: Option<u32>, limit: Option<u32>, offset: Option<u64>) -> Result<Page<T>, String> {
+    let (page, limit) = page_window(page, limit)?;
+    let start = match offset {
+        Some(offset) => usize::try_from(offset).unwrap_or(usize::MAX),
+        None => (page as usize - 1).saturating_mul(limit),
+    };
+    let pages = total.div_ceil(limit);
+    let page = match offset {
+        Some(_) => u32::try_from(start / limit + 1).unwrap_or(u32::MAX),
+        None => page,
+    };
+
+    Ok(Page {
+        items: items.into_iter().skip(start).take(limit).collect(),
+        start,
+        total: total as u64,
+        page,
+        pages: pages as u32,
+        has_next: start.saturating_add(limit) < total,
+        has_prev: start > 0 && pages > 0,
+    })
+}
+
+// Admin Management
+#[update(guard = "caller_is_not_blocked")]
+fn create_super_admin() -> Result<(), String> {
+    let caller = caller();
+    if caller == Principal::anonymous() {
+        return Err("Anonymous principals cannot be admins".to_string());
+    }
+
+    STATE.with(|state| {
+        let mut state = state.borrow_mut();
+        if state.admins.is_empty() {
+            state.admins.insert(caller, Role::SuperAdmin);
+            Ok(())
+        } else {
+            Err("Super admin already exists".to_string())
+        }
+    })
+}
+
+#[update(guard = "caller_is_not_blocked")]
+fn set_role(principal: Principal, role: Role) -> Result<(), String> {
+    if !caller_has_permission(Permission::ManageAdmins) {
+        return Err("Only super admin can assign roles".to_string());
+    }
+    
+    if principal == Principal::anonymous() {
+        return Err("Cannot add anonymous principal as admin".to_string());
+    }
+    if role == Role::SuperAdmin {
+        return Err("There can only be one super admin".to_string());
+    }
+
+    // New admins come in through invite_admin so a mistyped principal never holds a role
+    STATE.with(|state| {
+        let mut state = state.borrow_mut();
+        match state.admins.get(&principal) {
+            None => return Err("Principal is not an admin, use invite_admin".to_string()),
+            Some(Role::SuperAdmin) => return Err("Cannot change the super admin's role".to_string()),
+            Some(_) => {}
+        }
+        state.admins.insert(principal, role);
+        Ok(())
+    })
+}
+
+// Admin Invitations
+#[update(guard = "caller_is_not_blocked")]
+fn invite_admin(principal: Principal, role: Role, expires_at: u64) -> Result<(), String> {
+    if !caller_has_permission(Permission::ManageAdmins) {
+        return Err("Only super admin can invite admins".to_string());
+    }
+    if principal == Principal::anonymous() {
+        return Err("Cannot invite anonymous principal".to_string());
+    }
+    if role == Role::SuperAdmin {
+        return Err("There can only be one super admin".to_string());
+    }
+
+    let caller = caller();
+    let timestamp = ic_cdk::api::time();
+    if expires_at <= timestamp {
+        return Err("Invite expiry must be in the future".to_string());
+    }
+    if expires_at - timestamp > MAX_ADMIN_INVITE_NANOS {
+        return Err("Invites cannot be valid for more than 30 days".to_string());
+    }
+
+    STATE.with(|state| {
+        let mut state = state.borrow_mut();
+        if state.admins.contains_key(&principal) {
+            return Err("Principal is already an admin".to_string());
+        }
+        state.admin_invites.insert(principal, AdminInvite {
+            principal,
+            role,
+            invited_by: caller,
+            invited_at: timestamp,
+            expires_at,
+        });
+        audit(&mut state, caller, AuditEvent::AdminInvited { principal, role, expires_at }, timestamp);
+        Ok(())
+    })
+}
+
+#[update(guard = "caller_is_not_blocked")]
+fn revoke_admin_invite(principal: Principal) -> Result<(), String> {
+    if !caller_has_permission(Permission::ManageAdmins) {
+        return Err("Only super admin can revoke invites".to_string());
+    }
+
+    STATE.with(|state| {
+        state.borrow_mut()
+            .admin_invites
+            .remove(&principal)
+            .map(|_| ())
+            .ok_or("No invite pending for principal".to_string())
+    })
+}
+
+#[update(guard = "caller_is_not_blocked")]
+fn accept_admin_invite() -> Result<Role, String> {
+    let caller = caller();
+    let timestamp = ic_cdk::api::time();
+
+    STATE.with(|state| {
+        let mut state = state.borrow_mut();
+        let invite = state.admin_invites.remove(&caller)
+            .ok_or("No invite pending for caller")?;
+        if timestamp > invite.expires_at {
+            return Err("Invite has expired".to_string());
+        }
+        if state.admins.contains_key(&caller) {
+            return Err("Caller is already an admin".to_string());
+        }
+
+        state.admins.insert(caller, invite.role);
+        audit(&mut state, caller, AuditEvent::AdminInviteAccepted { role: invite.role }, timestamp);
+        Ok(invite.role)
+    })
+}
+
+#[query]
+fn get_admin_invites() -> Result<Vec<AdminInvite>, String> {
+    if !caller_has_permission(Permission::ManageAdmins) {
+        return Err("Only super admin can view invites".to_string());
+    }
+
+    let timestamp = ic_cdk::api::time();
+    STATE.with(|state| {
+        Ok(state.borrow()
+            .admin_invites
+            .values()
+            .filter(|invite| invite.expires_at >= timestamp)
+            .cloned()
+            .collect())
+    })
+}
+
+#[query]
+fn get_my_admin_invite() -> Option<AdminInvite> {
+    let caller = caller();
+    let timestamp = ic_cdk::api::time();
+    STATE.with(|state| {
+        state.borrow()
+            .admin_invites
+            .get(&caller)
+            .filter(|invite| invite.expires_at >= timestamp)
+            .cloned()
+    })
+}
+
+// Proposes removal; executes once enough admins have co-signed
+#[update(guard = "caller_is_not_blocked")]
+fn remove_admin(principal: Principal) -> Result<u64, String> {
+    if !caller_has_permission(Permission::ManageAdmins) {
+        return Err("Only super admin can remove admins".to_string());
+    }
+
+    STATE.with(|state| {
+        let state = state.borrow();
+        match state.admins.get(&principal) {
+            None => Err("Principal is not an admin".to_string()),
+            Some(Role::SuperAdmin) => Err("Cannot remove super admin".to_string()),
+            Some(_) => Ok(()),
+        }
+    })?;
+
+    create_admin_proposal(AdminAction::RemoveAdmin(principal))
+}
+
+// Config is changed through a co-signed proposal like other sensitive actions
+#[update(guard = "caller_is_not_blocked")]
+fn update_config(new_config: Config) -> Result<u64, String> {
+    if !caller_has_permission(Permission::ManageAdmins) {
+        return Err("Only super admin can update config".to_string());
+    }
+    new_config.validate()?;
+    create_admin_proposal(AdminAction::UpdateConfig(Box::new(new_config)))
+}
+
+#[query]
+fn get_config() -> Config {
+    config()
+}
+
+// Passing None removes the limit for that method
+#[update(guard = "caller_is_not_blocked")]
+fn set_rate_limit(method: RateLimitedMethod, limit: Option<RateLimit>) -> Result<(), String> {
+    if !caller_has_permission(Permission::ManageAdmins) {
+        return Err("Only super admin can set rate limits".to_string());
+    }
+    rate_limit::set_limit(method, limit)
+}
+
+#[query]
+fn get_rate_limits() -> Vec<(RateLimitedMethod, RateLimit)> {
+    rate_limit::limits()
+}
+
+// Threshold Approvals
+fn required_approvals(state: &State) -> usize {
+    (config().admin_approval_threshold as usize).min(state.admins.len()).max(1)
+}
+
+fn execute_admin_action(state: &mut State, action: &AdminAction) -> Result<(), String> {
+    match action {
+        AdminAction::RemoveAdmin(principal) => {
+            if state.admins.get(principal) == Some(&Role::SuperAdmin) {
+                return Err("Cannot remove super admin".to_string());
+            }
+            state.admins
+                .remove(principal)
+                .map(|_| ())
+                .ok_or("Principal is not an admin".to_string())
+        }
+        AdminAction::DeleteProject(project_id) => {
+            ensure_no_escrow(state, project_id)?;
+            revoke_bookings(state, project_id, ic_cdk::api::time());
+            remove_project(state, project_id)
+                .map(|_| ())
+                .ok_or("Project not found".to_string())
+        }
+        AdminAction::UpdateConfig(new_config) => {
+            new_config.validate()?;
+            CONFIG.with(|config| *config.borrow_mut() = (**new_config).clone());
+            Ok(())
+        }
+    }
+}
+
+// Executes the proposal if it has gathered enough approvals
+fn try_execute_proposal(state: &mut State, id: u64) {
+    let Some(proposal) = state.admin_proposals.get(&id) else {
+        return;
+    };
+    if proposal.status != ProposalStatus::Pending
+        || proposal.approvals.len() < proposal.required_approvals as usize {
+        return;
+    }
+
+    let action = proposal.action.clone();
+    let status = match execute_admin_action(state, &action) {
+        Ok(()) => ProposalStatus::Executed,
+        Err(e) => ProposalStatus::Failed(e),
+    };
+    if let Some(proposal) = state.admin_proposals.get_mut(&id) {
+        proposal.status = status;
+    }
+}
+
+fn create_admin_proposal(action: AdminAction) -> Result<u64, String> {
+    let caller = caller();
+    let timestamp = ic_cdk::api::time();
+
+    STATE.with(|state| {
+        let mut state = state.borrow_mut();
+
+        let duplicate = state.admin_proposals
+            .values()
+            .any(|p| p.status == ProposalStatus::Pending && p.action == action && p.expires_at >= timestamp);
+        if duplicate {
+            return Err("An identical proposal is already pending".to_string());
+        }
+
+        let id = state.next_proposal_id;
+        state.next_proposal_id += 1;
+        let required_approvals = required_approvals(&state) as u32;
+        state.admin_proposals.insert(id, AdminProposal {
+            id,
+            action,
+            proposer: caller,
+            approvals: vec![caller],
+            required_approvals,
+            created_at: timestamp,
+            expires_at: timestamp + ADMIN_PROPOSAL_EXPIRY_NANOS,
+            status: ProposalStatus::Pending,
+        });
+
+        try_execute_proposal(&mut state, id);
+        Ok(id)
+    })
+}
+
+#[update(guard = "caller_is_not_blocked")]
+fn approve_admin_proposal(id: u64) -> Result<ProposalStatus, String> {
+    let caller = caller();
+    if caller_role().is_none() {
+        return Err("Only admins can approve proposals".to_string());
+    }
+
+    let timestamp = ic_cdk::api::time();
+
+    STATE.with(|state| {
+        let mut state = state.borrow_mut();
+        let proposal = state.admin_proposals.get_mut(&id)
+            .ok_or("Proposal not found")?;
+
+        if proposal.status != ProposalStatus::Pending {
+            return Err("Proposal is no longer pending".to_string());
+        }
+        if timestamp > proposal.expires_at {
+            proposal.status = ProposalStatus::Expired;
+            return Err("Proposal has expired".to_string());
+        }
+        if proposal.approvals.contains(&caller) {
+            return Err("You have already approved this proposal".to_string());
+        }
+        proposal.approvals.push(caller);
+
+        try_execute_proposal(&mut state, id);
+        Ok(state.admin_proposals[&id].status.clone())
+    })
+}
+
+#[query]
+fn get_admin_proposals(pending_only: bool) -> Result<Vec<AdminProposal>, String> {
+    if caller_role().is_none() {
+        return Err("Only admins can view proposals".to_string());
+    }
+
+    let timestamp = ic_cdk::api::time();
+    STATE.with(|state| {
+        Ok(state.borrow()
+            .admin_proposals
+            .values()
+            .filter(|p| !pending_only || (p.status == ProposalStatus::Pending && p.expires_at >= timestamp))
+            .cloned()
+            .collect())
+    })
+}
+
+#[update(guard = "caller_is_not_blocked")]
+fn propose_super_admin_transfer(new_principal: Principal) -> Result<(), String> {
+    let caller = caller();
+    if caller_role() != Some(Role::SuperAdmin) {
+        return Err("Only super admin can transfer the role".to_string());
+    }
+    if new_principal == Principal::anonymous() {
+        return Err("Cannot transfer super admin to anonymous principal".to_string());
+    }
+    if new_principal == caller {
+        return Err("Caller is already super admin".to_string());
+    }
+
+    let timestamp = ic_cdk::api::time();
+    STATE.with(|state| {
+        state.borrow_mut().pending_super_admin_transfer = Some(SuperAdminTransfer {
+            from: caller,
+            to: new_principal,
+            proposed_at: timestamp,
+            expires_at: timestamp + SUPER_ADMIN_TRANSFER_EXPIRY_NANOS,
+        });
+        Ok(())
+    })
+}
+
+#[update(guard = "caller_is_not_blocked")]
+fn cancel_super_admin_transfer() -> Result<(), String> {
+    if caller_role() != Some(Role::SuperAdmin) {
+        return Err("Only super admin can cancel a transfer".to_string());
+    }
+
+    STATE.with(|state| {
+        state.borrow_mut()
+            .pending_super_admin_transfer
+            .take()
+            .map(|_| ())
+            .ok_or("No transfer pending".to_string())
+    })
+}
+
+#[update(guard = "caller_is_not_blocked")]
+fn accept_super_admin() -> Result<(), String> {
+    let caller = caller();
+    let timestamp = ic_cdk::api::time();
+
+    STATE.with(|state| {
+        let mut state = state.borrow_mut();
+        let transfer = match &state.pending_super_admin_transfer {
+            Some(transfer) if transfer.to == caller => transfer.clone(),
+            _ => return Err("No transfer pending for caller".to_string()),
+        };
+
+        if timestamp > transfer.expires_at {
+            state.pending_super_admin_transfer = None;
+            return Err("Transfer has expired".to_string());
+        }
+
+        // The previous super admin steps down entirely; they can be given a new role afterwards
+        state.admins.remove(&transfer.from);
+        state.admins.insert(caller, Role::SuperAdmin);
+        state.pending_super_admin_transfer = None;
+        Ok(())
+    })
+}
+
+#[query]
+fn get_pending_super_admin_transfer() -> Option<SuperAdminTransfer> {
+    STATE.with(|state| state.borrow().pending_super_admin_transfer.clone())
+}
+
+// Service Principals
+#[update(guard = "caller_is_not_blocked")]
+fn register_service_principal(principal: Principal, name: String, scopes: Vec<ServiceScope>) -> Result<(), String> {
+    if !caller_has_permission(Permission::ManageAdmins) {
+        return Err("Only super admin can register service principals".to_string());
+    }
+    if principal == Principal::anonymous() {
+        return Err("Cannot register anonymous principal".to_string());
+    }
+    if name.trim().is_empty() {
+        return Err("Service name is required".to_string());
+    }
+    if scopes.is_empty() {
+        return Err("At least one scope is required".to_string());
+    }
+
+    let caller = caller();
+    let timestamp = ic_cdk::api::time();
+
+    STATE.with(|state| {
+        let mut state = state.borrow_mut();
+        if state.admins.contains_key(&principal) {
+            return Err("Admins cannot also be service principals".to_string());
+        }
+
+        let scopes: Vec<ServiceScope> = scopes.into_iter().collect::<HashSet<_>>().into_iter().collect();
+        state.service_principals.insert(principal, ServicePrincipal {
+            name,
+            scopes: scopes.clone(),
+            registered_by: caller,
+            registered_at: timestamp,
+        });
+        audit(&mut state, caller, AuditEvent::ServiceRegistered { principal, scopes }, timestamp);
+        Ok(())
+    })
+}
+
+#[update(guard = "caller_is_not_blocked")]
+fn revoke_service_principal(principal: Principal) -> Result<(), String> {
+    if !caller_has_permission(Permission::ManageAdmins) {
+        return Err("Only super admin can revoke service principals".to_string());
+    }
+
+    let caller = caller();
+
+    STATE.with(|state| {
+        let mut state = state.borrow_mut();
+        state.service_principals.remove(&principal)
+            .ok_or("Service principal not found")?;
+        audit(&mut state, caller, AuditEvent::ServiceRevoked { principal }, ic_cdk::api::time());
+        Ok(())
+    })
+}
+
+#[query]
+fn get_service_principals() -> Result<Vec<(Principal, ServicePrincipal)>, String> {
+    if !caller_has_permission(Permission::ManageAdmins) {
+        return Err("Only super admin can view service principals".to_string());
+    }
+
+    STATE.with(|state| {
+        Ok(state.borrow()
+            .service_principals
+            .iter()
+            .map(|(principal, service)| (*principal, service.clone()))
+            .collect())
+    })
+}
+
+// Terms of Service
+#[update(guard = "caller_is_not_blocked")]
+fn accept_terms(version: u32) -> Result<(), String> {
+    let caller = caller();
+    if caller == Principal::anonymous() {
+        return Err("Anonymous principals cannot accept terms".to_string());
+    }
+
+    let terms_version = config().terms_version;
+    if terms_version == 0 {
+        return Err("No terms of service are published".to_string());
+    }
+    if version != terms_version {
+        return Err(format!("Current terms of service version is {}", terms_version));
+    }
+
+    STATE.with(|state| {
+        state.borrow_mut().terms_acceptances.insert(caller, TermsAcceptance {
+            version,
+            accepted_at: ic_cdk::api::time(),
+        });
+    });
+    Ok(())
+}
+
+#[query]
+fn get_terms_acceptance(principal: Principal) -> Option<TermsAcceptance> {
+    STATE.with(|state| state.borrow().terms_acceptances.get(&principal).cloned())
+}
+
+#[query]
+fn get_owners_without_latest_terms() -> Result<Vec<Principal>, String> {
+    if caller_role().is_none() {
+        return Err("Only admins can view terms acceptance".to_string());
+    }
+
+    let terms_version = config().terms_version;
+
+    STATE.with(|state| {
+        let state = state.borrow();
+        Ok(state.owner_projects
+            .iter()
+            .filter(|(_, ids)| !ids.is_empty())
+            .map(|(owner, _)| *owner)
+            .filter(|owner| {
+                state.terms_acceptances
+                    .get(owner)
+                    .is_none_or(|acceptance| acceptance.version < terms_version)
+            })
+            .collect())
+    })
+}
+
+// Profiles
+#[update(guard = "caller_is_not_blocked")]
+fn set_profile(display_name: String, avatar_url: Option<String>) -> Result<(), String> {
+    let caller = caller();
+    if caller == Principal::anonymous() {
+        return Err("Anonymous principals cannot have a profile".to_string());
+    }
+    screen_text(&[&display_name])?;
+
+    profiles::set(caller, Profile {
+        display_name,
+        avatar_url,
+        updated_at: ic_cdk::api::time(),
+    })
+}
+
+#[query]
+fn get_profile(principal: Principal) -> Option<Profile> {
+    profiles::get(&principal)
+}
+
+// External Identities
+// Returns the Sign-In with Ethereum message to sign with the wallet; valid for ten minutes
+#[update(guard = "caller_is_not_blocked")]
+fn request_identity_link(chain: ExternalChain, address: String) -> Result<String, String> {
+    let caller = caller();
+    if caller == Principal::anonymous() {
+        return Err("Anonymous principals cannot link identities".to_string());
+    }
+    let domain = config().siwe_domain
+        .unwrap_or_else(|| format!("{}.icp0.io", ic_cdk::api::id()));
+    identity::challenge(caller, chain, &address, &domain, ic_cdk::api::time())
+}
+
+// signature is the wallet's hex personal_sign signature over the requested message
+#[update(guard = "caller_is_not_blocked")]
+fn link_external_identity(chain: ExternalChain, address: String, signature: String) -> Result<ExternalIdentity, String> {
+    let caller = caller();
+    if caller == Principal::anonymous() {
+        return Err("Anonymous principals cannot link identities".to_string());
+    }
+    identity::link(caller, chain, &address, &signature, ic_cdk::api::time())
+}
+
+#[update(guard = "caller_is_not_blocked")]
+fn unlink_external_identity(chain: ExternalChain, address: String) -> Result<(), String> {
+    identity::unlink(caller(), chain, &address)
+}
+
+// The principal itself or admins with ViewPrivate
+#[query]
+fn get_external_identities(principal: Principal) -> Result<Vec<ExternalIdentity>, String> {
+    if caller() != principal && !caller_has_permission(Permission::ViewPrivate) {
+        return Err("Only the account holder or admins can view linked identities".to_string());
+    }
+    Ok(identity::identities(&principal))
+}
+
+// Project Management
+// Projects in a region served by a shard are created there, owned by the caller
+#[update(guard = "caller_is_not_blocked")]
+async fn create_project(project_data: ProjectData, allow_duplicate: Option<bool>) -> Result<String, String> {
+    let caller = caller();
+    ensure_may_create(caller)?;
+    let mut project_data = project_data;
+    set_geohash(&mut project_data.location)?;
+
+    // Routed on the geohash of the coordinates, not one the client supplied
+    let result = match shards::shard_for(&config().region_shards, &project_data.location.geohash) {
+        Some(shard) => shards::create_project(shard, caller, project_data, allow_duplicate).await,
+        None => create_local_project(caller, project_data, allow_duplicate),
+    };
+    if result.is_ok() {
+        rate_limit::record(caller, RateLimitedMethod::CreateProject, ic_cdk::api::time());
+    }
+    result
+}
+
+// Checks shared by every way a user creates a project, including drafts. Callers
+// record the creation with rate_limit::record once it has succeeded.
+fn ensure_may_create(caller: Principal) -> Result<(), String> {
+    if caller == Principal::anonymous() {
+        return Err("Anonymous principals cannot create projects".to_string());
+    }
+    ensure_enabled(Subsystem::ProjectCreation)?;
+    ensure_terms_accepted(caller)?;
+    rate_limit::check(caller, RateLimitedMethod::CreateProject, ic_cdk::api::time())
+}
+
+// Router only; the router has already applied the per-user checks
+#[update(guard = "caller_is_not_blocked")]
+fn create_project_for(owner: Principal, project_data: ProjectData, allow_duplicate: Option<bool>) -> Result<String, String> {
+    if config().router != Some(caller()) {
+        return Err("Only the router can create projects on behalf of users".to_string());
+    }
+    ensure_enabled(Subsystem::ProjectCreation)?;
+    create_local_project(owner, project_data, allow_duplicate)
+}
+
+fn create_local_project(caller: Principal, project_data: ProjectData, allow_duplicate: Option<bool>) -> Result<String, String> {
+    ensure_heap_headroom()?;
+    let mut project_data = project_data;
+    set_geohash(&mut project_data.location)?;
+    validate_tags(&project_data.tags)?;
+    validate_images(&project_data.images)?;
+    let flagged = screen_text(&[&project_data.name, &project_data.description, &project_data.tags.join(" ")])?;
+
+    // Soft-block likely duplicates unless the caller confirms the submission
+    if !allow_duplicate.unwrap_or(false) {
+        let duplicates = STATE.with(|state| {
+            find_duplicate_projects(&state.borrow(), &project_data.name, &project_data.location.geohash, None)
+        });
+        if !duplicates.is_empty() {
+            return Err(format!(
+                "Possible duplicate of existing project(s): {}. Resubmit with allow_duplicate to create anyway",
+                duplicates.join(", ")
+            ));
+        }
+    }
+
+    let timestamp = ic_cdk::api::time();
+
+    let project_id = STATE.with(|state| {
+        let mut state = state.borrow_mut();
+        let project = new_project(&state, project_data, caller, ProjectStatus::PendingReview, timestamp);
+        let project_id = insert_project(&mut state, project);
+        if !flagged.is_empty() {
+            flag_for_review(&mut state, &project_id, &flagged, timestamp);
+        }
+        project_id
+    });
+
+    Ok(project_id)
+}
+
+// Approved projects can only be deleted through a co-signed admin proposal
+#[update(guard = "caller_is_not_blocked")]
+fn delete_project(id: String) -> Result<Option<u64>, String> {
+    let caller = caller();
+    let is_moderator = caller_has_permission(Permission::ModerateContent);
+
+    let status = STATE.with(|state| {
+        let state = state.borrow();
+        let project = state.projects.get(&id)
+            .ok_or("Project not found")?;
+        if project.owner != caller && !is_moderator {
+            return Err("Only project owner or moderators can delete".to_string());
+        }
+        ensure_no_escrow(&state, &id)?;
+        Ok(project.status.clone())
+    })?;
+
+    if status == ProjectStatus::Approved {
+        if !is_moderator {
+            return Err("Approved projects can only be deleted by moderators".to_string());
+        }
+        return create_admin_proposal(AdminAction::DeleteProject(id)).map(Some);
+    }
+
+    STATE.with(|state| {
+        let mut state = state.borrow_mut();
+        revoke_bookings(&mut state, &id, ic_cdk::api::time());
+        remove_project(&mut state, &id);
+    });
+    Ok(None)
+}
+
+#[update(guard = "caller_is_not_blocked")]
+fn clone_project(id: String) -> Result<String, String> {
+    let caller = caller();
+    ensure_heap_headroom()?;
+    ensure_may_create(caller)?;
+
+    let timestamp = ic_cdk::api::time();
+
+    let result = STATE.with(|state| {
+        let mut state = state.borrow_mut();
+        let source = state.projects.get(&id)
+            .filter(|p| p.owner == caller || p.status != ProjectStatus::Draft)
+            .ok_or("Project not found")?;
+        if source.owner != caller && source.status != ProjectStatus::Approved {
+            return Err("Only approved projects can be cloned by other users".to_string());
+        }
+
+        // Private fields only carry over when cloning your own project
+        let mut project_data = ProjectData::from(source);
+        if source.owner != caller {
+            project_data.private_discord = String::new();
+        }
+
+        let project = new_project(&state, project_data, caller, ProjectStatus::Draft, timestamp);
+        Ok(insert_project(&mut state, project))
+    });
+    if result.is_ok() {
+        rate_limit::record(caller, RateLimitedMethod::CreateProject, ic_cdk::api::time());
+    }
+    result
+}
+
+#[update(guard = "caller_is_not_blocked")]
+fn submit_project(id: String, allow_duplicate: Option<bool>) -> Result<(), String> {
+    let caller = caller();
+
+    STATE.with(|state| {
+        let mut state = state.borrow_mut();
+        let project = state.projects.get(&id)
+            .ok_or("Project not found")?;
+
+        if project.owner != caller {
+            return Err("Only project owner can submit".to_string());
+        }
+        if project.status != ProjectStatus::Draft {
+            return Err("Only drafts can be submitted for review".to_string());
+        }
+
+        if !allow_duplicate.unwrap_or(false) {
+            let duplicates = find_duplicate_projects(&state, &project.name, &project.location.geohash, Some(&id));
+            if !duplicates.is_empty() {
+                return Err(format!(
+                    "Possible duplicate of existing project(s): {}. Resubmit with allow_duplicate to submit anyway",
+                    duplicates.join(", ")
+                ));
+            }
+        }
+
+        if let Some(project) = state.projects.get_mut(&id) {
+            project.status = ProjectStatus::PendingReview;
+        }
+        on_status_changed(&mut state, &id, ic_cdk::api::time());
+        Ok(())
+    })
+}
+
+#[update(guard = "caller_is_not_blocked")]
+fn update_project(id: String, project_data: ProjectData) -> Result<(), String> {
+    let caller = caller();
+    let mut project_data = project_data;
+    set_geohash(&mut project_data.location)?;
+    validate_tags(&project_data.tags)?;
+    validate_images(&project_data.images)?;
+    let flagged = screen_text(&[&project_data.name, &project_data.description, &project_data.tags.join(" ")])?;
+    
+    STATE.with(|state| {
+        let mut state = state.borrow_mut();
+        
+        let project = state.projects.get_mut(&id)
+            .ok_or("Project not found")?;
+        
+        if project.owner != caller {
+            return Err("Only project owner can update".to_string());
+        }
+
+        let previous_category = project.category.clone();
+        let previous_gateway_type = project.gateway_type.clone();
+        let previous_terms = project_search_terms(project);
+        let moved = (project.location.lat, project.location.lng) != (project_data.location.lat, project_data.location.lng);
+
+        // Update fields
+        project.name = project_data.name;
+        project.description = project_data.description;
+        project.category = project_data.category.clone();
+        project.gateway_type = project_data.gateway_type.clone();
+        let pins = retained_pins(&project.images, &project_data.images);
+        project.images = ProjectImages { pins, ..project_data.images };
+        project.location = project_data.location.clone();
+        project.project_discord = project_data.project_discord;
+        project.private_discord = project_data.private_discord;
+        project.sensors_required = project_data.sensors_required;
+        project.video = project_data.video;
+        let timestamp = ic_cdk::api::time();
+        on_sensor_count_changed(project, timestamp);
+        record_activity(project, timestamp);
+
+        // Update geohash index
+        geo_index::index(project_data.location.geohash, id.clone());
+
+        let project_name = project.name.clone();
+
+        // Move project between category buckets if the category changed
+        if previous_category != project_data.category {
+            if let Some(ids) = state.category_index.get_mut(&previous_category) {
+                ids.retain(|project_id| project_id != &id);
+            }
+            state.category_index
+                .entry(project_data.category)
+                .or_insert_with(Vec::new)
+                .push(id.clone());
+        }
+        if previous_gateway_type != project_data.gateway_type {
+            if let Some(ids) = state.gateway_index.get_mut(&previous_gateway_type) {
+                ids.retain(|project_id| project_id != &id);
+            }
+            state.gateway_index
+                .entry(project_data.gateway_type)
+                .or_default()
+                .push(id.clone());
+        }
+
+        set_tags(&mut state, &id, project_data.tags);
+        update_search_index(&mut state, &id, &previous_terms);
+        // A moved project is looked up again so its country follows it
+        if moved {
+            set_country(&mut state, &id, None);
+            state.geocode_retries.remove(&id);
+        }
+        if (moved || project_data.location.address.trim().is_empty()) && !state.geocode_queue.contains(&id) {
+            state.geocode_queue.push_back(id.clone());
+        }
+
+        if !flagged.is_empty() {
+            flag_for_review(&mut state, &id, &flagged, timestamp);
+        }
+
+        notify_followers(&mut state, &id, format!("Project \"{}\" was updated", project_name), timestamp);
+        
+        Ok(())
+    })
+}
+
+#[update(guard = "caller_is_not_blocked")]
+fn update_project_status(id: String, status: ProjectStatus) -> Result<(), String> {
+    if !caller_has_permission(Permission::ReviewProjects) {
+        return Err("Only reviewers can update project status".to_string());
+    }
+
+    let caller = caller();
+    let role = caller_role().ok_or("Only admins can update project status")?;
+    let timestamp = ic_cdk::api::time();
+
+    STATE.with(|state| set_project_status(&mut state.borrow_mut(), caller, role, &id, status, timestamp))
+}
+
+// Applies each update independently and returns the ones that failed with their reason
+#[update(guard = "caller_is_not_blocked")]
+fn bulk_update_project_status(updates: Vec<(String, ProjectStatus)>) -> Result<Vec<(String, String)>, String> {
+    if !caller_has_permission(Permission::ReviewProjects) {
+        return Err("Only reviewers can update project status".to_string());
+    }
+    if updates.len() > MAX_BULK_STATUS_UPDATES {
+        return Err(format!("At most {} updates per call", MAX_BULK_STATUS_UPDATES));
+    }
+
+    let caller = caller();
+    let role = caller_role().ok_or("Only admins can update project status")?;
+    let timestamp = ic_cdk::api::time();
+
+    STATE.with(|state| {
+        let mut state = state.borrow_mut();
+        Ok(updates
+            .into_iter()
+            .filter_map(|(id, status)| {
+                set_project_status(&mut state, caller, role, &id, status, timestamp)
+                    .err()
+                    .map(|error| (id, error))
+            })
+            .collect())
+    })
+}
+
+#[update(guard = "caller_is_not_blocked")]
+fn archive_project(id: String) -> Result<(), String> {
+    let caller = caller();
+    let can_override = caller_has_permission(Permission::ModerateContent);
+
+    STATE.with(|state| {
+        let mut state = state.borrow_mut();
+        
+        let project = state.projects.get_mut(&id)
+            .ok_or("Project not found")?;
+        
+        if project.owner != caller && !can_override {
+            return Err("Only project owner or admins can archive".to_string());
+        }
+        if project.status == ProjectStatus::Archived {
+            return Err("Project is already archived".to_string());
+        }
+
+        project.status = ProjectStatus::Archived;
+
+        // Archived projects can't stay on the homepage
+        let owner = project.owner;
+        state.feature_windows.remove(&id);
+        clear_featured(&mut state, &id);
+        if owner != caller {
+            revoke_bookings(&mut state, &id, ic_cdk::api::time());
+        }
+
+        on_status_changed(&mut state, &id, ic_cdk::api::time());
+        
+        Ok(())
+    })
+}
+
+// Features the project immediately, or schedules it when starts_at is in the future.
+// A feature with ends_at is expired automatically by the feature schedule timer.
+#[update(guard = "caller_is_not_blocked")]
+fn feature_project(project_id: String, starts_at: Option<u64>, ends_at: Option<u64>) -> Result<(), String> {
+    if !caller_has_permission(Permission::FeatureProjects) {
+        return Err("Only curators can feature projects".to_string());
+    }
+
+    let caller = caller();
+    let timestamp = ic_cdk::api::time();
+    STATE.with(|state| {
+        schedule_feature(&mut state.borrow_mut(), caller, project_id, starts_at, ends_at, timestamp)
+    })
+}
+
+#[update(guard = "caller_is_not_blocked")]
+fn unfeature_project(project_id: String) -> Result<(), String> {
+    if !caller_has_permission(Permission::FeatureProjects) {
+        return Err("Only curators can unfeature projects".to_string());
+    }
+
+    STATE.with(|state| remove_feature(&mut state.borrow_mut(), &project_id))
+}
+
+// ids must list every currently featured project exactly once, in the new display order
+#[update(guard = "caller_is_not_blocked")]
+fn reorder_featured(ids: Vec<String>) -> Result<(), String> {
+    if !caller_has_permission(Permission::FeatureProjects) {
+        return Err("Only curators can reorder featured projects".to_string());
+    }
+
+    STATE.with(|state| {
+        let mut state = state.borrow_mut();
+        let current: HashSet<&String> = state.featured_projects.iter().collect();
+        let requested: HashSet<&String> = ids.iter().collect();
+        if requested.len() != ids.len() || requested != current {
+            return Err("Order must contain each featured project exactly once".to_string());
+        }
+        state.featured_projects = ids;
+        Ok(())
+    })
+}
+
+#[query]
+fn get_feature_schedule() -> Result<Vec<FeatureWindow>, String> {
+    if !caller_has_permission(Permission::FeatureProjects) {
+        return Err("Only curators can view the feature schedule".to_string());
+    }
+
+    STATE.with(|state| {
+        let mut windows: Vec<FeatureWindow> = state.borrow().feature_windows.values().cloned().collect();
+        windows.sort_by_key(|window| window.starts_at);
+        Ok(windows)
+    })
+}
+
+// Voting System
+#[update(guard = "caller_is_not_blocked")]
+fn vote_for_project(project_id: String) -> Result<(), String> {
+    let caller = caller();
+    if caller == Principal::anonymous() {
+        return Err("Anonymous principals cannot vote".to_string());
+    }
+    ensure_enabled(Subsystem::Voting)?;
+    rate_limit::check(caller, RateLimitedMethod::Vote, ic_cdk::api::time())?;
+
+    let result = STATE.with(|state| {
+        let mut state = state.borrow_mut();
+        let timestamp = ic_cdk::api::time();
+        cast_vote(&mut state, caller, &project_id, timestamp)?;
+
+        let project = &state.projects[&project_id];
+        subscriptions::emit(EventPayload::VoteCast {
+            project_id: project_id.clone(),
+            voter: caller,
+            vote_count: project.vote_count,
+        }, timestamp);
+        owner_webhooks::dispatch(&project_id, WebhookEvent::VoteCast, serde_json::json!({
+            "voter": caller.to_text(),
+            "vote_count": project.vote_count,
+        }), timestamp);
+        if VOTE_MILESTONES.contains(&project.vote_count) {
+            let message = format!("Project \"{}\" reached {} votes", project.name, project.vote_count);
+            let values = [("project", project.name.as_str()), ("votes", &project.vote_count.to_string())];
+            relay_event(RelayEvent::VoteMilestone, &project_id, &values, timestamp);
+            post_to_discord(&state, &project_id, &message, timestamp);
+        }
+
+        Ok(())
+    });
+    if result.is_ok() {
+        rate_limit::record(caller, RateLimitedMethod::Vote, ic_cdk::api::time());
+    }
+    result
+}
+
+#[update(guard = "caller_is_not_blocked")]
+fn remove_vote(project_id: String) -> Result<(), String> {
+    let caller = caller();
+    ensure_enabled(Subsystem::Voting)?;
+
+    STATE.with(|state| {
+        let mut state = state.borrow_mut();
+        
+        // Remove vote from project_votes
+        if let Some(votes) = state.project_votes.get_mut(&project_id) {
+            if votes.remove(&caller).is_none() {
+                if has_voted(&state, &caller, &project_id) {
+                    return Err("Archived votes cannot be removed".to_string());
+                }
+                return Err("No vote found".to_string());
+            }
+        } else {
+            return Err("Project not found".to_string());
+        }
+
+        // Remove from vote index
+        if let Some(voted_projects) = state.vote_index.get_mut(&caller) {
+            voted_projects.retain(|id| id != &project_id);
+        }
+
+        // Update vote count
+        if let Some(vote_count) = state.projects.get(&project_id).map(|p| p.vote_count.saturating_sub(1)) {
+            set_vote_count(&mut state, &project_id, vote_count);
+        }
+
+        Ok(())
+    })
+}
+
+fn activate_feature(state: &mut State, project_id: &str, timestamp: u64) -> Result<(), String> {
+    if state.featured_projects.iter().any(|id| id == project_id) {
+        return Err("Project is already featured".to_string());
+    }
+    let featured_slots = config().featured_slots;
+    if state.featured_projects.len() >= featured_slots as usize {
+        return Err(format!("All {} featured slots are taken", featured_slots));
+    }
+
+    let project = state.projects.get_mut(project_id)
+        .ok_or("Project not found")?;
+    if project.status == ProjectStatus::Archived {
+        return Err("Archived projects cannot be featured".to_string());
+    }
+    project.featured = true;
+    project.featured_at = Some(timestamp);
+    state.featured_projects.push(project_id.to_string());
+    let message = format!("Project \"{}\" is now featured", project.name);
+    post_to_discord(state, project_id, &message, timestamp);
+    Ok(())
+}
+
+// Shared by scheduling and the governance proposal validator so the two agree
+fn featurable_project<'a>(
+    state: &'a State,
+    project_id: &str,
+    starts_at: u64,
+    ends_at: Option<u64>,
+) -> Result<&'a Project, String> {
+    if ends_at.is_some_and(|ends_at| ends_at <= starts_at) {
+        return Err("Feature must end after it starts".to_string());
+    }
+    let project = state.projects.get(project_id).ok_or("Project not found")?;
+    if project.featured || state.feature_windows.contains_key(project_id) {
+        return Err("Project is already featured or scheduled".to_string());
+    }
+    if project.status == ProjectStatus::Archived {
+        return Err("Archived projects cannot be featured".to_string());
+    }
+    Ok(project)
+}
+
+// Shared by curators and governance. Checks everything before changing state.
+fn schedule_feature(
+    state: &mut State,
+    caller: Principal,
+    project_id: String,
+    starts_at: Option<u64>,
+    ends_at: Option<u64>,
+    timestamp: u64,
+) -> Result<(), String> {
+    let starts_at = starts_at.unwrap_or(timestamp).max(timestamp);
+    featurable_project(state, &project_id, starts_at, ends_at)?;
+    // Keeps the slots of paid bookings free for their whole window
+    if !featuring_slot_free(state, starts_at, ends_at.unwrap_or(u64::MAX)) {
+        return Err("No featured slot is free for the whole range".to_string());
+    }
+
+    let active = starts_at <= timestamp;
+    if active {
+        activate_feature(state, &project_id, timestamp)?;
+    }
+    let event = AuditEvent::ProjectFeatured { project_id: project_id.clone(), starts_at, ends_at };
+    audit(state, caller, event, timestamp);
+    if !active || ends_at.is_some() {
+        state.feature_windows.insert(project_id.clone(), FeatureWindow {
+            project_id,
+            starts_at,
+            ends_at,
+            scheduled_by: caller,
+            active,
+        });
+    }
+    Ok(())
+}
+
+// Only reached by curators and governance, so paid bookings are refunded
+fn remove_feature(state: &mut State, project_id: &str) -> Result<(), String> {
+    let featured = state.projects.get(project_id)
+        .ok_or("Project not found")?
+        .featured;
+    let scheduled = state.feature_windows.remove(project_id).is_some();
+    if !featured && !scheduled {
+        return Err("Project is not featured".to_string());
+    }
+
+    clear_featured(state, project_id);
+    revoke_bookings(state, project_id, ic_cdk::api::time());
+    Ok(())
+}
+
+// Whether a slot stays free for the whole range. Conservative: every overlapping
+// window counts, and features without an end hold their slot forever.
+fn featuring_slot_free(state: &State, starts_at: u64, ends_at: u64) -> bool {
+    let permanent = state.featured_projects
+        .iter()
+        .filter(|id| !state.feature_windows.contains_key(*id))
+        .count();
+    let overlapping = state.feature_windows
+        .values()
+        .filter(|w| w.starts_at < ends_at && w.ends_at.is_none_or(|end| end > starts_at))
+        .count();
+    permanent + overlapping < config().featured_slots as usize
+}
+
+fn start_refund(state: &mut State, booking_id: u64, amount: Nat) {
+    let Some(booking) = state.featuring_bookings.get_mut(&booking_id) else {
+        return;
+    };
+    booking.status = BookingStatus::Refunding;
+    booking.refund_amount = Some(amount.clone());
+    let created_at = *booking.refund_created_at.get_or_insert(ic_cdk::api::time());
+    let (ledger, payer) = (booking.ledger, booking.payer);
+
+    // Runs in its own message, after the caller has released STATE
+    ic_cdk_timers::set_timer(std::time::Duration::ZERO, move || {
+        ic_cdk::spawn(refund_booking(booking_id, ledger, payer, amount, created_at));
+    });
+}
+
+async fn refund_booking(booking_id: u64, ledger: Principal, payer: Principal, amount: Nat, created_at: u64) {
+    let to = Account { owner: payer, subaccount: None };
+    let memo = ledger::memo(b"featuring-refund", booking_id);
+    let result = ledger::transfer(ledger, ledger::featuring_subaccount(), to, amount, memo, created_at).await;
+
+    STATE.with(|state| {
+        let mut state = state.borrow_mut();
+        let Some(booking) = state.featuring_bookings.get_mut(&booking_id) else {
+            return;
+        };
+        let project_id = booking.project_id.clone();
+        let message = match result {
+            Ok(block_index) => {
+                booking.status = BookingStatus::Refunded { block_index };
+                "Your featuring booking was revoked and refunded".to_string()
+            }
+            Err(error) => {
+                booking.status = BookingStatus::RefundFailed { error };
+                "Your featuring booking was revoked; the refund failed and will be retried by an admin".to_string()
+            }
+        };
+        notify(&mut state, payer, Some(project_id), message, ic_cdk::api::time());
+    });
+}
+
+// Deleting the project would drop the pledge records needed to pay these out
+fn ensure_no_escrow(state: &State, project_id: &str) -> Result<(), String> {
+    let unsettled = state.pledges.values()
+        .filter(|pledge| pledge.project_id == project_id)
+        .filter_map(|pledge| pledge.escrow.as_ref())
+        .any(|escrow| !matches!(escrow.status, EscrowStatus::Released { .. } | EscrowStatus::Refunded { .. }));
+    if unsettled {
+        return Err("Project has pledge payments in escrow; cancel or settle its pledges first".to_string());
+    }
+    Ok(())
+}
+
+// Pays a pledge's escrow out to the procurement principal, or back to the backer
+fn start_escrow_transfer(state: &mut State, pledge_id: u64, refund: bool) {
+    let Some(pledge) = state.pledges.get_mut(&pledge_id) else {
+        return;
+    };
+    let Some(escrow) = pledge.escrow.as_mut() else {
+        return;
+    };
+    let to = if refund {
+        escrow.status = EscrowStatus::Refunding;
+        pledge.backer
+    } else {
+        escrow.status = EscrowStatus::Releasing;
+        match config().procurement_principal {
+            Some(procurement) => procurement,
+            None => {
+                escrow.status = EscrowStatus::TransferFailed { refund, error: "No procurement principal is configured".to_string() };
+                return;
+            }
+        }
+    };
+    let (ledger, amount) = (escrow.ledger, escrow.amount.clone());
+    let created_at = *escrow.transfer_created_at.get_or_insert(ic_cdk::api::time());
+
+    // Runs in its own message, after the caller has released STATE
+    ic_cdk_timers::set_timer(std::time::Duration::ZERO, move || {
+        ic_cdk::spawn(settle_escrow(pledge_id, ledger, to, amount, refund, created_at));
+    });
+}
+
+async fn settle_escrow(pledge_id: u64, ledger: Principal, to: Principal, amount: Nat, refund: bool, created_at: u64) {
+    let to = Account { owner: to, subaccount: None };
+    let memo = ledger::memo(if refund { b"escrow-refund" } else { b"escrow-release" }, pledge_id);
+    let result = ledger::transfer(ledger, ledger::escrow_subaccount(pledge_id), to, amount, memo, created_at).await;
+
+    STATE.with(|state| {
+        let mut state = state.borrow_mut();
+        let Some(pledge) = state.pledges.get_mut(&pledge_id) else {
+            return;
+        };
+        let Some(escrow) = pledge.escrow.as_mut() else {
+            return;
+        };
+        escrow.status = match result {
+            Ok(block_index) if refund => EscrowStatus::Refunded { block_index },
+            Ok(block_index) => EscrowStatus::Released { block_index },
+            Err(error) => EscrowStatus::TransferFailed { refund, error },
+        };
+        if refund {
+            let (backer, project_id) = (pledge.backer, pledge.project_id.clone());
+            let message = if matches!(escrow.status, EscrowStatus::Refunded { .. }) {
+                "Your escrowed pledge payment was refunded"
+            } else {
+                "Your escrowed pledge payment could not be refunded yet; an admin will retry"
+            };
+            notify(&mut state, backer, Some(project_id), message.to_string(), ic_cdk::api::time());
+        }
+    });
+}
+
+// Refunds the unused share of every booking of the project that hasn't ended
+fn revoke_bookings(state: &mut State, project_id: &str, now: u64) {
+    let revoked: Vec<(u64, Nat)> = state.featuring_bookings
+        .values()
+        .filter(|b| b.project_id == project_id && b.status == BookingStatus::Booked && b.ends_at > now)
+        .map(|b| {
+            let unused = b.ends_at - b.starts_at.max(now);
+            (b.id, b.amount.clone() * Nat::from(unused) / Nat::from(b.ends_at - b.starts_at))
+        })
+        .collect();
+    for (booking_id, amount) in revoked {
+        start_refund(state, booking_id, amount);
+    }
+}
+
+// For bookings whose window could not be activated; returns whether there were any
+fn refund_unserved_bookings(state: &mut State, project_id: &str, now: u64) -> bool {
+    let unserved: Vec<(u64, Nat)> = state.featuring_bookings
+        .values()
+        .filter(|b| b.project_id == project_id && b.status == BookingStatus::Booked && b.ends_at > now)
+        .map(|b| (b.id, b.amount.clone()))
+        .collect();
+    let any = !unserved.is_empty();
+    for (booking_id, amount) in unserved {
+        start_refund(state, booking_id, amount);
+    }
+    any
+}
+
+fn clear_featured(state: &mut State, project_id: &str) {
+    if let Some(project) = state.projects.get_mut(project_id) {
+        project.featured = false;
+        project.featured_at = None;
+    }
+    state.featured_projects.retain(|id| id != project_id);
+}
+
+fn set_project_status(
+    state: &mut State,
+    caller: Principal,
+    role: Role,
+    id: &str,
+    status: ProjectStatus,
+    timestamp: u64,
+) -> Result<(), String> {
+    let current_status = state.projects.get(id)
+        .ok_or("Project not found")?
+        .status
+        .clone();
+    if !role_permits(role, status_change_permission(&current_status, &status)) {
+        return Err(format!("{:?} cannot change status from {:?} to {:?}", role, current_status, status));
+    }
+
+    if let Some(claim) = active_review_claim(state, id, timestamp) {
+        if claim.reviewer != caller {
+            return Err(format!("Project is being reviewed by {}", claim.reviewer));
+        }
+    }
+
+    // Reversing a rejection needs approvals from two different admins, also when the
+    // project was sent back to review in between
+    if status == ProjectStatus::Approved && state.previously_rejected.contains(id) {
+        let approvals = state.rejection_reversals.entry(id.to_string()).or_default();
+        approvals.insert(caller);
+        if approvals.len() < REJECTION_REVERSAL_APPROVALS {
+            return Err(format!(
+                "Approval recorded ({} of {}); another admin must also approve to reverse the rejection",
+                approvals.len(), REJECTION_REVERSAL_APPROVALS
+            ));
+        }
+    }
+
+    let project = state.projects.get_mut(id)
+        .ok_or("Project not found")?;
+    let was_pending = project.status == ProjectStatus::PendingReview;
+    let submitted_at = project.status_updated_at;
+    project.status = status.clone();
+
+    if was_pending && matches!(status, ProjectStatus::Approved | ProjectStatus::Rejected) {
+        state.review_decisions.push(ReviewDecision {
+            project_id: id.to_string(),
+            reviewer: caller,
+            status,
+            submitted_at,
+            decided_at: timestamp,
+        });
+    }
+
+    on_status_changed(state, id, timestamp);
+    Ok(())
+}
+
+fn csv_field(value: &str) -> String {
+    if value.contains([',', '"', '\n', '\r']) {
+        format!("\"{}\"", value.replace('"', "\"\""))
+    } else {
+        value.to_string()
+    }
+}
+
+// Escapes commas, equals signs and spaces in line protocol measurement names and tag values
+fn line_protocol_escape(value: &str) -> String {
+    value.replace('\\', "\\\\")
+        .replace(',', "\\,")
+        .replace('=', "\\=")
+        .replace(' ', "\\ ")
+}
+
+// Sensor Fulfillment
+fn adjust_sensors_assigned(project_id: &str, adjust: impl FnOnce(u32) -> Result<u32, String>) -> Result<u32, String> {
+    let caller = caller();
+    let can_override = caller_has_permission(Permission::ManageSensors);
+
+    STATE.with(|state| {
+        let mut state = state.borrow_mut();
+        // Registry sensors are only released through unassign_sensor
+        let registered = state.project_sensors.get(project_id).map_or(0, |s| s.len()) as u32;
+        let project = state.projects.get_mut(project_id)
+            .ok_or("Project not found")?;
+
+        if project.owner != caller && !can_override {
+            return Err("Only project owner or admins can assign sensors".to_string());
+        }
+
+        let assigned = adjust(project.sensors_assigned)?;
+        if assigned < registered {
+            return Err(format!("{} registered sensors are assigned to this project", registered));
+        }
+        project.sensors_assigned = assigned;
+        let timestamp = ic_cdk::api::time();
+        on_sensor_count_changed(project, timestamp);
+        record_activity(project, timestamp);
+        Ok(project.sensors_assigned)
+    })
+}
+
+#[update(guard = "caller_is_not_blocked")]
+fn assign_sensors(project_id: String, count: u32) -> Result<u32, String> {
+    adjust_sensors_assigned(&project_id, |assigned| {
+        assigned.checked_add(count).ok_or("Sensor count overflow".to_string())
+    })
+}
+
+#[update(guard = "caller_is_not_blocked")]
+fn unassign_sensors(project_id: String, count: u32) -> Result<u32, String> {
+    adjust_sensors_assigned(&project_id, |assigned| {
+        assigned.checked_sub(count).ok_or("Cannot unassign more sensors than are assigned".to_string())
+    })
+}
+
+// Only for sensors already registered by an import or a claim code, so serials can't be squatted
+#[update(guard = "caller_is_not_blocked")]
+fn assign_sensor(serial: String, project_id: String) -> Result<(), String> {
+    let caller = caller();
+    if serial.trim().is_empty() {
+        return Err("Sensor serial is required".to_string());
+    }
+    let can_override = caller_has_permission(Permission::ManageSensors);
+    let timestamp = ic_cdk::api::time();
+
+    STATE.with(|state| {
+        let mut state = state.borrow_mut();
+        let project = state.projects.get(&project_id)
+            .ok_or("Project not found")?;
+        if project.owner != caller && !can_override {
+            return Err("Only project owner or admins can assign sensors".to_string());
+        }
+
+        let sensor = state.sensors.get(&serial)
+            .ok_or("Sensor not found; it must be imported or claimed first")?;
+        if sensor.owner != caller && !can_override {
+            return Err("Sensor belongs to another principal".to_string());
+        }
+        if sensor.project_id.is_some() {
+            return Err("Sensor is already assigned to a project".to_string());
+        }
+
+        link_sensor(&mut state, &serial, &project_id, timestamp)
+    })
+}
+
+// Redeploys a sensor, closing its current assignment in the history
+#[update(guard = "caller_is_not_blocked")]
+fn transfer_sensor(serial: String, to_project: String) -> Result<(), String> {
+    let caller = caller();
+    let can_override = caller_has_permission(Permission::ManageSensors);
+    let timestamp = ic_cdk::api::time();
+
+    STATE.with(|state| {
+        let mut state = state.borrow_mut();
+        let sensor = state.sensors.get(&serial)
+            .ok_or("Sensor not found")?;
+        if sensor.owner != caller && !can_override {
+            return Err("Only sensor owner or admins can transfer sensors".to_string());
+        }
+        if sensor.project_id.as_deref() == Some(to_project.as_str()) {
+            return Err("Sensor is already assigned to this project".to_string());
+        }
+        let project = state.projects.get(&to_project)
+            .ok_or("Project not found")?;
+        if project.owner != caller && !can_override {
+            return Err("Only the destination project's owner or admins can receive sensors".to_string());
+        }
+
+        unlink_sensor(&mut state, &serial, timestamp);
+        link_sensor(&mut state, &serial, &to_project, timestamp)
+    })
+}
+
+#[query]
+fn get_sensor_history(serial: String) -> Vec<SensorAssignment> {
+    STATE.with(|state| state.borrow().sensor_history.get(&serial).cloned().unwrap_or_default())
+}
+
+// Returns the one-time code to ship with the hardware; only its hash is kept
+#[update(guard = "caller_is_not_blocked")]
+async fn create_claim_code(serial: String) -> Result<String, String> {
+    if !caller_has_permission(Permission::ManageSensors) {
+        return Err("Only sensor admins can create claim codes".to_string());
+    }
+    if serial.trim().is_empty() {
+        return Err("Sensor serial is required".to_string());
+    }
+
+    let (random,) = ic_cdk::api::management_canister::main::raw_rand()
+        .await
+        .map_err(|(_, e)| format!("Failed to generate claim code: {}", e))?;
+    let code: String = random.iter()
+        .take(CLAIM_CODE_BYTES)
+        .map(|b| format!("{:02X}", b))
+        .collect();
+
+    let caller = caller();
+    let timestamp = ic_cdk::api::time();
+
+    STATE.with(|state| {
+        let mut state = state.borrow_mut();
+        if state.sensors.get(&serial).is_some_and(|s| s.retired.is_some()) {
+            return Err("Sensor has been retired".to_string());
+        }
+        // Unknown serials are registered to the issuing admin until claimed
+        state.sensors.entry(serial.clone()).or_insert(new_sensor(&serial, caller, timestamp));
+        state.claim_codes.insert(hash_claim_code(&code), ClaimCode {
+            serial,
+            created_by: caller,
+            created_at: timestamp,
+            expires_at: timestamp + CLAIM_CODE_EXPIRY_NANOS,
+        });
+        Ok(code)
+    })
+}
+
+// Takes ownership of the sensor bound to the code and optionally assigns it to one of the caller's projects
+#[update(guard = "caller_is_not_blocked")]
+fn claim_sensor(code: String, project_id: Option<String>) -> Result<String, String> {
+    let caller = caller();
+    if caller == Principal::anonymous() {
+        return Err("Anonymous principals cannot claim sensors".to_string());
+    }
+    let timestamp = ic_cdk::api::time();
+
+    STATE.with(|state| {
+        let mut state = state.borrow_mut();
+        let key = hash_claim_code(&code);
+        let claim = state.claim_codes.get(&key)
+            .ok_or("Invalid claim code")?
+            .clone();
+        if timestamp > claim.expires_at {
+            state.claim_codes.remove(&key);
+            return Err("Claim code has expired".to_string());
+        }
+        if let Some(project_id) = &project_id {
+            let project = state.projects.get(project_id)
+                .ok_or("Project not found")?;
+            if project.owner != caller {
+                return Err("Only project owner can assign claimed sensors".to_string());
+            }
+        }
+
+        // Other codes issued for the same device must not be able to claim it back
+        state.claim_codes.retain(|_, c| c.serial != claim.serial);
+        unlink_sensor(&mut state, &claim.serial, timestamp);
+        if let Some(sensor) = state.sensors.get_mut(&claim.serial) {
+            sensor.owner = caller;
+        }
+        if let Some(project_id) = project_id {
+            link_sensor(&mut state, &claim.serial, &project_id, timestamp)?;
+        }
+        Ok(claim.serial)
+    })
+}
+
+#[query]
+fn get_claim_codes() -> Result<Vec<ClaimCode>, String> {
+    if !caller_has_permission(Permission::ManageSensors) {
+        return Err("Only sensor admins can view claim codes".to_string());
+    }
+
+    let timestamp = ic_cdk::api::time();
+    STATE.with(|state| {
+        Ok(state.borrow()
+            .claim_codes
+            .values()
+            .filter(|claim| claim.expires_at >= timestamp)
+            .cloned()
+            .collect())
+    })
+}
+
+#[update(guard = "caller_is_not_blocked")]
+fn unassign_sensor(serial: String) -> Result<(), String> {
+    let caller = caller();
+    let can_override = caller_has_permission(Permission::ManageSensors);
+
+    STATE.with(|state| {
+        let mut state = state.borrow_mut();
+        let sensor = state.sensors.get(&serial)
+            .ok_or("Sensor not found")?;
+        let project_id = sensor.project_id.clone()
+            .ok_or("Sensor is not assigned")?;
+        let project_owner = state.projects.get(&project_id).map(|p| p.owner);
+        if sensor.owner != caller && project_owner != Some(caller) && !can_override {
+            return Err("Only sensor owner, project owner or admins can unassign sensors".to_string());
+        }
+
+        unlink_sensor(&mut state, &serial, ic_cdk::api::time());
+        Ok(())
+    })
+}
+
+// Permanently takes a sensor out of service. It is unassigned and stops counting towards
+// its project, while its readings and assignment history are kept for reporting.
+#[update(guard = "caller_is_not_blocked")]
+fn retire_sensor(serial: String, reason: String) -> Result<(), String> {
+    if reason.trim().is_empty() {
+        return Err("A retirement reason is required".to_string());
+    }
+    if reason.len() > MAX_RETIREMENT_REASON_LENGTH {
+        return Err(format!("Reason cannot exceed {} characters", MAX_RETIREMENT_REASON_LENGTH));
+    }
+
+    let caller = caller();
+    let can_override = caller_has_permission(Permission::ManageSensors);
+    let timestamp = ic_cdk::api::time();
+
+    STATE.with(|state| {
+        let mut state = state.borrow_mut();
+        let sensor = state.sensors.get(&serial)
+            .ok_or("Sensor not found")?;
+        if sensor.owner != caller && !can_override {
+            return Err("Only sensor owner or admins can retire sensors".to_string());
+        }
+        if sensor.retired.is_some() {
+            return Err("Sensor is already retired".to_string());
+        }
+
+        unlink_sensor(&mut state, &serial, timestamp);
+        if let Some(gateway_id) = state.sensors.get(&serial).and_then(|s| s.relayed_by.clone()) {
+            if let Some(serials) = state.gateway_sensors.get_mut(&gateway_id) {
+                serials.remove(&serial);
+            }
+        }
+        state.claim_codes.retain(|_, claim| claim.serial != serial);
+        state.alert_trackers.retain(|(_, s), _| s != &serial);
+        state.offline_sensors.remove(&serial);
+        geo_index::remove(&sensor_geo_key(&serial));
+
+        if let Some(sensor) = state.sensors.get_mut(&serial) {
+            sensor.retired = Some(SensorRetirement {
+                reason,
+                retired_by: caller,
+                retired_at: timestamp,
+            });
+        }
+        Ok(())
+    })
+}
+
+// Sensor owners and admins only
+fn update_sensor(serial: &str, update: impl FnOnce(&mut Sensor)) -> Result<(), String> {
+    let caller = caller();
+    let can_override = caller_has_permission(Permission::ManageSensors);
+
+    STATE.with(|state| {
+        let mut state = state.borrow_mut();
+        let sensor = state.sensors.get_mut(serial)
+            .ok_or("Sensor not found")?;
+        if sensor.owner != caller && !can_override {
+            return Err("Only sensor owner or admins can update sensor metadata".to_string());
+        }
+        if sensor.retired.is_some() {
+            return Err("Sensor has been retired".to_string());
+        }
+        update(sensor);
+        Ok(())
+    })
+}
+
+#[update(guard = "caller_is_not_blocked")]
+fn set_sensor_firmware(serial: String, firmware_version: String) -> Result<(), String> {
+    if firmware_version.trim().is_empty() {
+        return Err("Firmware version is required".to_string());
+    }
+    update_sensor(&serial, |sensor| sensor.firmware_version = Some(firmware_version))
+}
+
+// None clears the type
+#[update(guard = "caller_is_not_blocked")]
+fn set_sensor_type(serial: String, type_id: Option<String>) -> Result<(), String> {
+    if let Some(type_id) = &type_id {
+        if !sensor_types::exists(type_id) {
+            return Err("Unknown sensor type".to_string());
+        }
+    }
+    update_sensor(&serial, |sensor| sensor.sensor_type = type_id)
+}
+
+#[update(guard = "caller_is_not_blocked")]
+fn set_sensor_calibration(serial: String, calibrated_at: u64, coefficients: Vec<f64>, interval_days: u32) -> Result<(), String> {
+    if calibrated_at > ic_cdk::api::time() {
+        return Err("Calibration date cannot be in the future".to_string());
+    }
+    if coefficients.len() > MAX_CALIBRATION_COEFFICIENTS || coefficients.iter().any(|c| !c.is_finite()) {
+        return Err(format!("Up to {} finite coefficients are allowed", MAX_CALIBRATION_COEFFICIENTS));
+    }
+    if interval_days == 0 || interval_days > MAX_CALIBRATION_INTERVAL_DAYS {
+        return Err(format!("Calibration interval must be 1 to {} days", MAX_CALIBRATION_INTERVAL_DAYS));
+    }
+    update_sensor(&serial, |sensor| {
+        sensor.calibration = Some(Calibration { calibrated_at, coefficients, interval_days });
+    })
+}
+
+// None clears the location
+#[update(guard = "caller_is_not_blocked")]
+fn set_sensor_location(serial: String, location: Option<Location>) -> Result<(), String> {
+    let location = match location {
+        Some(location) => {
+            let geohash = encode_geohash(location.lat, location.lng)?;
+            Some(Location { geohash, ..location })
+        }
+        None => None,
+    };
+    update_sensor(&serial, |sensor| {
+        geo_index::remove(&sensor_geo_key(&serial));
+        if let Some(location) = &location {
+            geo_index::index(location.geohash.clone(), sensor_geo_key(&serial));
+        }
+        sensor.location = location;
+    })
+}
+
+#[query]
+fn get_sensors_due_for_calibration(project_id: String) -> Vec<CalibrationDue> {
+    let now = ic_cdk::api::time();
+
+    STATE.with(|state| {
+        let state = state.borrow();
+        state.project_sensors
+            .get(&project_id)
+            .into_iter()
+            .flatten()
+            .filter_map(|serial| state.sensors.get(serial))
+            .filter_map(|sensor| {
+                let due_at = sensor.calibration.as_ref()
+                    .map(|c| c.calibrated_at.saturating_add((c.interval_days as u64).saturating_mul(NANOS_PER_DAY)));
+                if due_at.is_some_and(|due_at| due_at > now) {
+                    return None;
+                }
+                Some(CalibrationDue {
+                    serial: sensor.serial.clone(),
+                    calibrated_at: sensor.calibration.as_ref().map(|c| c.calibrated_at),
+                    due_at,
+                })
+            })
+            .collect()
+    })
+}
+
+// Adds a sensor type to the catalogue or replaces the one with the same id
+#[update(guard = "caller_is_not_blocked")]
+fn upsert_sensor_type(sensor_type: SensorType) -> Result<(), String> {
+    if !caller_has_permission(Permission::ManageSensors) {
+        return Err("Only sensor admins can manage sensor types".to_string());
+    }
+    sensor_types::upsert(sensor_type)
+}
+
+// Types still referenced by registered sensors cannot be removed
+#[update(guard = "caller_is_not_blocked")]
+fn remove_sensor_type(type_id: String) -> Result<(), String> {
+    if !caller_has_permission(Permission::ManageSensors) {
+        return Err("Only sensor admins can manage sensor types".to_string());
+    }
+    let in_use = STATE.with(|state| {
+        state.borrow()
+            .sensors
+            .values()
+            .filter(|s| s.sensor_type.as_deref() == Some(type_id.as_str()))
+            .count()
+    });
+    if in_use > 0 {
+        return Err(format!("{} sensors are registered with this type", in_use));
+    }
+    sensor_types::remove(&type_id)
+}
+
+#[query]
+fn get_sensor_types() -> Vec<SensorType> {
+    sensor_types::list()
+}
+
+// Listed projects with at least one assigned sensor of the given type
+#[query]
+fn get_projects_with_sensor_type(type_id: String, page: Option<u32>, limit: Option<u32>, offset: Option<u64>) -> Result<ProjectsResponse, String> {
+    STATE.with(|state| {
+        let state = state.borrow();
+        let mut projects: Vec<&Project> = state.project_sensors
+            .iter()
+            .filter(|(_, serials)| {
+                serials.iter()
+                    .filter_map(|serial| state.sensors.get(serial))
+                    .any(|s| s.sensor_type.as_deref() == Some(type_id.as_str()))
+            })
+            .filter_map(|(id, _)| state.projects.get(id))
+            .filter(|p| is_listed(p))
+            .collect();
+
+        projects.sort_by_key(|p| std::cmp::Reverse(p.created_at));
+
+        projects_page(projects, page, limit, offset)
+    })
+}
+
+#[query]
+fn get_sensor(serial: String) -> Option<SensorWithStatus> {
+    let now = ic_cdk::api::time();
+    STATE.with(|state| state.borrow().sensors.get(&serial).map(|s| sensor_with_status(s, now)))
+}
+
+#[query]
+fn get_project_sensors(project_id: String) -> Vec<SensorWithStatus> {
+    let now = ic_cdk::api::time();
+    STATE.with(|state| {
+        let state = state.borrow();
+        state.project_sensors
+            .get(&project_id)
+            .map(|serials| {
+                serials.iter()
+                    .filter_map(|s| state.sensors.get(s))
+                    .map(|s| sensor_with_status(s, now))
+                    .collect()
+            })
+            .unwrap_or_default()
+    })
+}
+
+#[query]
+fn get_underfunded_projects(page: Option<u32>, limit: Option<u32>, offset: Option<u64>) -> Result<ProjectsResponse, String> {
+    STATE.with(|state| {
+        let state = state.borrow();
+        let mut projects: Vec<&Project> = listed_projects(&state)
+            .filter(|p| p.sensors_assigned < p.sensors_required)
+            .collect();
+        
+        // Largest shortfall first
+        projects.sort_by_key(|p| std::cmp::Reverse(p.sensors_required - p.sensors_assigned));
+        
+        projects_page(projects, page, limit, offset)
+    })
+}
+
+// Badges
+// Supporters who voted for or sponsored a project can mint one badge for it
+#[update(guard = "caller_is_not_blocked")]
+async fn mint_supporter_badge(project_id: String) -> Result<Nat, String> {
+    let caller = caller();
+    if caller == Principal::anonymous() {
+        return Err("Anonymous principals cannot mint badges".to_string());
+    }
+    let collection = config().badge_collection
+        .ok_or("Supporter badges are not configured")?;
+    let key = (caller, project_id.clone());
+
+    let (unconfirmed, reason, project_name) = STATE.with(|state| {
+        let mut state = state.borrow_mut();
+        let project = state.projects.get(&project_id)
+            .ok_or("Project not found")?;
+        let project_name = project.name.clone();
+
+        let voted = has_voted(&state, &caller, &project_id);
+        let sponsored = state.sponsorships.values().any(|s| s.project_id == project_id && s.sponsor == caller);
+        let reason = if sponsored {
+            BadgeReason::Sponsored
+        } else if voted {
+            BadgeReason::Voted
+        } else {
+            return Err("Vote for or sponsor the project to earn its badge".to_string());
+        };
+        if state.badges.contains_key(&key) || !state.pending_badges.insert(key.clone()) {
+            return Err("You already have a badge for this project".to_string());
+        }
+        Ok((state.unconfirmed_badges.contains(&key), reason, project_name))
+    })?;
+
+    let token_id = badges::token_id(&caller, &project_id);
+    // A previous mint may have gone through even though its reply was lost
+    let existing_owner = match unconfirmed {
+        true => badges::owner_of(collection, token_id.clone()).await.map_err(badges::MintError::Unknown),
+        false => Ok(None),
+    };
+    let minted = match existing_owner {
+        Ok(Some(owner)) if owner.owner == caller && owner.subaccount.is_none() => Ok(token_id),
+        Ok(Some(_)) => Err(badges::MintError::Failed("Badge token is owned by another account".to_string())),
+        Ok(None) => {
+            let metadata = vec![
+                ("project_id".to_string(), badges::Value::Text(project_id.clone())),
+                ("project_name".to_string(), badges::Value::Text(project_name)),
+                ("reason".to_string(), badges::Value::Text(reason.metadata_value().to_string())),
+            ];
+            badges::mint(collection, token_id, caller, metadata).await
+        }
+        Err(e) => Err(e),
+    };
+
+    STATE.with(|state| {
+        let mut state = state.borrow_mut();
+        state.pending_badges.remove(&key);
+        let token_id = match minted {
+            Ok(token_id) => token_id,
+            Err(badges::MintError::Failed(e)) => {
+                state.unconfirmed_badges.remove(&key);
+                return Err(e);
+            }
+            Err(badges::MintError::Unknown(e)) => {
+                state.unconfirmed_badges.insert(key);
+                return Err(format!("{}; the badge may have been minted, try again to check", e));
+            }
+        };
+        state.unconfirmed_badges.remove(&key);
+        state.badges.insert(key, BadgeReceipt {
+            token_id: token_id.clone(),
+            project_id,
+            owner: caller,
+            reason,
+            minted_at: ic_cdk::api::time(),
+        });
+        Ok(token_id)
+    })
+}
+
+#[query]
+fn get_my_badges() -> Vec<BadgeReceipt> {
+    let caller = caller();
+    STATE.with(|state| {
+        state.borrow()
+            .badges
+            .values()
+            .filter(|b| b.owner == caller)
+            .cloned()
+            .collect()
+    })
+}
+
+// Donations
+// Donors transfer to this account on the configured ledger, then call notify_donation
+#[query]
+fn get_donation_account(project_id: String) -> Result<Account, String> {
+    STATE.with(|state| {
+        if !state.borrow().projects.contains_key(&project_id) {
+            return Err("Project not found".to_string());
+        }
+        Ok(Account {
+            owner: ic_cdk::id(),
+            subaccount: Some(ledger::project_subaccount(&project_id)),
+        })
+    })
+}
+
+// Credits a ledger transfer to the project's donation account; returns the project's new total
+#[update(guard = "caller_is_not_blocked")]
+async fn notify_donation(project_id: String, block_index: u64) -> Result<Nat, String> {
+    ensure_enabled(Subsystem::Donations)?;
+    let caller = caller();
+    let ledger = config().donation_ledger
+        .ok_or("Donations are not configured")?;
+
+    STATE.with(|state| {
+        let mut state = state.borrow_mut();
+        if !state.projects.contains_key(&project_id) {
+            return Err("Project not found".to_string());
+        }
+        if state.donations.contains_key(&(ledger, block_index)) || !state.pending_donation_blocks.insert((ledger, block_index)) {
+            return Err("Block has already been credited".to_string());
+        }
+        Ok(())
+    })?;
+
+    let transfer = ledger::get_transfer(ledger, block_index).await;
+    STATE.with(|state| {
+        let mut state = state.borrow_mut();
+        state.pending_donation_blocks.remove(&(ledger, block_index));
+        let transfer = transfer?;
+
+        let expected = Account {
+            owner: ic_cdk::id(),
+            subaccount: Some(ledger::project_subaccount(&project_id)),
+        };
+        if !transfer.is_to(&expected) {
+            return Err("Transfer was not made to this project's donation account".to_string());
+        }
+        // ICP blocks don't name the sender; credit the caller if it paid from its default account
+        let donor = transfer.sender()
+            .or_else(|| transfer.is_from(caller).then_some(caller))
+            .unwrap_or(Principal::anonymous());
+        let owner = state.projects.get(&project_id)
+            .map(|p| p.owner)
+            .ok_or("Project not found")?;
+
+        let timestamp = ic_cdk::api::time();
+        state.donations.insert((ledger, block_index), Donation {
+            ledger,
+            block_index,
+            project_id: project_id.clone(),
+            donor,
+            amount: transfer.amount.clone(),
+            donated_at: transfer.timestamp,
+            recorded_at: timestamp,
+        });
+        notify(&mut state, owner, Some(project_id.clone()), format!("Your project received a donation of {}", transfer.amount), timestamp);
+
+        Ok(recorded_donation_total(&state, &project_id))
+    })
+}
+
+fn recorded_donation_total(state: &State, project_id: &str) -> Nat {
+    state.donations
+        .values()
+        .filter(|d| d.project_id == project_id)
+        .fold(Nat::from(0u64), |total, d| total + d.amount.clone())
+}
+
+// What the donation account should hold: credited donations less completed withdrawals
+fn recorded_donation_balance(state: &State, project_id: &str) -> Nat {
+    let credited = recorded_donation_total(state, project_id);
+    let withdrawn = state.donation_withdrawals
+        .values()
+        .filter(|w| w.project_id == project_id && matches!(w.status, WithdrawalStatus::Completed { .. }))
+        .fold(Nat::from(0u64), |total, w| total + w.amount.clone());
+    if credited > withdrawn { credited - withdrawn } else { Nat::from(0u64) }
+}
+
+// The project's owner, or its last owner if it was removed with donations in its account
+fn donation_owner(state: &State, project_id: &str) -> Option<Principal> {
+    state.projects.get(project_id)
+        .map(|p| p.owner)
+        .or_else(|| state.removed_donation_owners.get(project_id).copied())
+}
+
+// Sends the whole balance of the project's donation account, less the ledger fee, to `to`
+#[update(guard = "caller_is_not_blocked")]
+async fn withdraw_donations(project_id: String, to: Account) -> Result<Nat, String> {
+    ensure_enabled(Subsystem::Donations)?;
+    let caller = caller();
+    let ledger = config().donation_ledger
+        .ok_or("Donations are not configured")?;
+
+    let (id, created_at) = STATE.with(|state| {
+        let mut state = state.borrow_mut();
+        if donation_owner(&state, &project_id) != Some(caller) {
+            return Err("Only the project owner can withdraw donations".to_string());
+        }
+        if state.donation_withdrawals.values().any(|w| w.project_id == project_id && w.status == WithdrawalStatus::Pending) {
+            return Err("A withdrawal is already in progress".to_string());
+        }
+        state.next_withdrawal_id += 1;
+        let id = state.next_withdrawal_id;
+        let created_at = ic_cdk::api::time();
+        state.donation_withdrawals.insert(id, DonationWithdrawal {
+            id,
+            project_id: project_id.clone(),
+            ledger,
+            to: to.clone(),
+            amount: Nat::from(0u64),
+            requested_by: caller,
+            created_at,
+            status: WithdrawalStatus::Pending,
+        });
+        Ok((id, created_at))
+    })?;
+
+    let subaccount = ledger::project_subaccount(&project_id);
+    let account = Account { owner: ic_cdk::id(), subaccount: Some(subaccount.clone()) };
+    let result = match ledger::balance_of(ledger, account).await {
+        Ok(balance) => {
+            let memo = ledger::memo(b"donation-withdrawal", id);
+            ledger::transfer(ledger, subaccount, to, balance.clone(), memo, created_at).await
+                .map(|block_index| (balance, block_index))
+        }
+        Err(e) => Err(e),
+    };
+
+    STATE.with(|state| {
+        let mut state = state.borrow_mut();
+        let withdrawal = state.donation_withdrawals.get_mut(&id)
+            .ok_or("Withdrawal not found")?;
+        match result {
+            Ok((amount, block_index)) => {
+                withdrawal.amount = amount.clone();
+                withdrawal.status = WithdrawalStatus::Completed { block_index };
+                Ok(amount)
+            }
+            Err(error) => {
+                withdrawal.status = WithdrawalStatus::Failed { error: error.clone() };
+                Err(error)
+            }
+        }
+    })
+}
+
+// Project owner or admins only, newest first
+#[query]
+fn get_donation_withdrawals(project_id: String) -> Result<Vec<DonationWithdrawal>, String> {
+    let caller = caller();
+    let is_admin = caller_has_permission(Permission::ViewPrivate);
+    STATE.with(|state| {
+        let state = state.borrow();
+        if donation_owner(&state, &project_id) != Some(caller) && !is_admin {
+            return Err("Only the project owner can view its withdrawals".to_string());
+        }
+        Ok(state.donation_withdrawals
+            .values()
+            .rev()
+            .filter(|w| w.project_id == project_id)
+            .cloned()
+            .collect())
+    })
+}
+
+#[query]
+fn get_project_donations(project_id: String) -> ProjectDonations {
+    STATE.with(|state| {
+        let state = state.borrow();
+        let mut total = Nat::from(0u64);
+        let mut donation_count = 0;
+        let mut by_donor: HashMap<Principal, Nat> = HashMap::new();
+        for donation in state.donations.values().filter(|d| d.project_id == project_id) {
+            total += donation.amount.clone();
+            donation_count += 1;
+            *by_donor.entry(donation.donor).or_insert_with(|| Nat::from(0u64)) += donation.amount.clone();
+        }
+
+        let mut top_donors: Vec<DonorTotal> = by_donor
+            .into_iter()
+            .map(|(donor, total)| DonorTotal {
+                donor_name: profiles::display_name(&donor),
+                donor,
+                total,
+            })
+            .collect();
+        top_donors.sort_by(|a, b| b.total.cmp(&a.total));
+        top_donors.truncate(TOP_DONORS);
+
+        ProjectDonations { project_id, total, donation_count, top_donors }
+    })
+}
+
+// Totals checked against the ledger by the reconcile_donations job
+#[query]
+fn get_verified_funding(project_id: String) -> Result<VerifiedFunding, String> {
+    STATE.with(|state| {
+        let state = state.borrow();
+        if !state.projects.contains_key(&project_id) {
+            return Err("Project not found".to_string());
+        }
+        let recorded_total = recorded_donation_balance(&state, &project_id);
+        let reconciliation = state.funding_reconciliations.get(&project_id);
+        Ok(VerifiedFunding {
+            verified_total: reconciliation.map(|r| r.ledger_balance.clone()),
+            recorded_total,
+            last_reconciled_at: reconciliation.map(|r| r.reconciled_at),
+            in_sync: reconciliation.map(|r| r.ledger_balance == r.recorded_total),
+            project_id,
+        })
+    })
+}
+
+#[query]
+fn get_my_donations() -> Vec<Donation> {
+    let caller = caller();
+    STATE.with(|state| {
+        state.borrow()
+            .donations
+            .values()
+            .filter(|d| d.donor == caller)
+            .cloned()
+            .collect()
+    })
+}
+
+// Paid Featuring
+// Owners transfer the quoted amount to this account, then call book_featuring with the block
+#[query]
+fn get_featuring_account() -> Account {
+    Account {
+        owner: ic_cdk::id(),
+        subaccount: Some(ledger::featuring_subaccount()),
+    }
+}
+
+#[query]
+fn get_featuring_quote(ledger: Principal, starts_at: u64, ends_at: u64) -> Result<Nat, String> {
+    featuring_quote(ledger, starts_at, ends_at)
+}
+
+fn featuring_quote(ledger: Principal, starts_at: u64, ends_at: u64) -> Result<Nat, String> {
+    if ends_at <= starts_at {
+        return Err("Booking must end after it starts".to_string());
+    }
+    let days = (ends_at - starts_at).div_ceil(NANOS_PER_DAY);
+    if days > MAX_FEATURING_BOOKING_DAYS {
+        return Err(format!("Bookings cannot exceed {} days", MAX_FEATURING_BOOKING_DAYS));
+    }
+    let price = config().featuring_prices
+        .into_iter()
+        .find(|p| p.ledger == ledger)
+        .ok_or("Featuring cannot be paid on this ledger")?;
+    Ok(price.price_per_day * Nat::from(days))
+}
+
+fn check_booking(state: &State, caller: Principal, project_id: &str, starts_at: u64, ends_at: u64) -> Result<(), String> {
+    let project = state.projects.get(project_id)
+        .ok_or("Project not found")?;
+    if project.owner != caller {
+        return Err("Only the project owner can book featuring".to_string());
+    }
+    if project.status != ProjectStatus::Approved {
+        return Err("Only approved projects can be featured".to_string());
+    }
+    if project.featured || state.feature_windows.contains_key(project_id) {
+        return Err("Project is already featured or scheduled".to_string());
+    }
+    if !featuring_slot_free(state, starts_at, ends_at) {
+        return Err("No featured slot is free for the whole range".to_string());
+    }
+    Ok(())
+}
+
+// Verifies the payment with the ledger and schedules the feature; the timer activates
+// and expires it. Returns the booking id.
+#[update(guard = "caller_is_not_blocked")]
+async fn book_featuring(project_id: String, ledger: Principal, block_index: u64, starts_at: u64, ends_at: u64) -> Result<u64, String> {
+    let caller = caller();
+    let starts_at = starts_at.max(ic_cdk::api::time());
+    let price = featuring_quote(ledger, starts_at, ends_at)?;
+
+    STATE.with(|state| {
+        let mut state = state.borrow_mut();
+        check_booking(&state, caller, &project_id, starts_at, ends_at)?;
+        if !state.featuring_blocks.insert((ledger, block_index)) {
+            return Err("Block has already been used".to_string());
+        }
+        Ok(())
+    })?;
+
+    let transfer = ledger::get_transfer(ledger, block_index).await;
+    STATE.with(|state| {
+        let mut state = state.borrow_mut();
+        let verified = transfer.and_then(|transfer| {
+            if !transfer.is_to(&get_featuring_account()) {
+                return Err("Transfer was not made to the featuring account".to_string());
+            }
+            if !transfer.is_from(caller) {
+                return Err("Transfer was not made by the caller".to_string());
+            }
+            if transfer.amount < price {
+                return Err(format!("Transfer of {} is below the price of {}", transfer.amount, price));
+            }
+            Ok(transfer)
+        });
+        let transfer = match verified {
+            Ok(transfer) => transfer,
+            Err(e) => {
+                state.featuring_blocks.remove(&(ledger, block_index));
+                return Err(e);
+            }
+        };
+
+        let now = ic_cdk::api::time();
+        let id = state.next_booking_id;
+        state.next_booking_id += 1;
+        state.featuring_bookings.insert(id, FeaturingBooking {
+            id,
+            project_id: project_id.clone(),
+            payer: caller,
+            ledger,
+            block_index,
+            amount: transfer.amount.clone(),
+            starts_at,
+            ends_at,
+            booked_at: now,
+            status: BookingStatus::Booked,
+            refund_amount: None,
+            refund_created_at: None,
+        });
+
+        // The slot may have been taken while the payment was verified
+        let scheduled = check_booking(&state, caller, &project_id, starts_at, ends_at)
+            .and_then(|_| schedule_feature(&mut state, caller, project_id, Some(starts_at), Some(ends_at), now));
+        if let Err(e) = scheduled {
+            start_refund(&mut state, id, transfer.amount);
+            return Err(format!("{}; the payment is being refunded", e));
+        }
+        Ok(id)
+    })
+}
+
+// Admins only, for refunds the ledger rejected
+#[update(guard = "caller_is_not_blocked")]
+fn retry_booking_refund(booking_id: u64) -> Result<(), String> {
+    if !caller_has_permission(Permission::ManageAdmins) {
+        return Err("Only super admins can retry refunds".to_string());
+    }
+    STATE.with(|state| {
+        let mut state = state.borrow_mut();
+        let booking = state.featuring_bookings.get(&booking_id)
+            .ok_or("Booking not found")?;
+        if !matches!(booking.status, BookingStatus::RefundFailed { .. }) {
+            return Err("Only failed refunds can be retried".to_string());
+        }
+        let amount = booking.refund_amount.clone().ok_or("Booking has no refund amount")?;
+        start_refund(&mut state, booking_id, amount);
+        Ok(())
+    })
+}
+
+// Project owner or admins only
+#[query]
+fn get_project_bookings(project_id: String) -> Result<Vec<FeaturingBooking>, String> {
+    let caller = caller();
+    let can_override = caller_has_permission(Permission::ViewPrivate);
+    STATE.with(|state| {
+        let state = state.borrow();
+        let project = state.projects.get(&project_id)
+            .ok_or("Project not found")?;
+        if project.owner != caller && !can_override {
+            return Err("Only project owner or admins can view bookings".to_string());
+        }
+        Ok(state.featuring_bookings
+            .values()
+            .filter(|b| b.project_id == project_id)
+            .cloned()
+            .collect())
+    })
+}
+
+#[query]
+fn get_my_bookings() -> Vec<FeaturingBooking> {
+    let caller = caller();
+    STATE.with(|state| {
+        state.borrow()
+            .featuring_bookings
+            .values()
+            .filter(|b| b.payer == caller)
+            .cloned()
+            .collect()
+    })
+}
+
+// Pledges
+#[update(guard = "caller_is_not_blocked")]
+fn pledge_sensors(project_id: String, count: u32) -> Result<u64, String> {
+    let caller = caller();
+    if caller == Principal::anonymous() {
+        return Err("Anonymous principals cannot pledge sensors".to_string());
+    }
+    if count == 0 {
+        return Err("Pledge at least one sensor".to_string());
+    }
+    let timestamp = ic_cdk::api::time();
+
+    STATE.with(|state| {
+        let mut state = state.borrow_mut();
+        let project = state.projects.get(&project_id)
+            .ok_or("Project not found")?;
+        if project.status != ProjectStatus::Approved {
+            return Err("Only approved projects accept pledges".to_string());
+        }
+        let open_demand = project_demand(&state, project).open_demand;
+        if count > open_demand {
+            return Err(format!("Only {} sensors are still needed", open_demand));
+        }
+        let owner = project.owner;
+
+        let open: Vec<&Pledge> = state.pledges.values()
+            .filter(|p| p.backer == caller && p.status == PledgeStatus::Open)
+            .collect();
+        if open.len() >= MAX_OPEN_PLEDGES {
+            return Err(format!("You have {} open pledges; resolve some before pledging again", MAX_OPEN_PLEDGES));
+        }
+        let pledged: u32 = open.iter().filter(|p| p.project_id == project_id).map(|p| p.count).sum();
+        if pledged.saturating_add(count) > MAX_OPEN_PLEDGED_SENSORS {
+            return Err(format!("You can have at most {} sensors pledged to a project", MAX_OPEN_PLEDGED_SENSORS));
+        }
+
+        let id = state.next_pledge_id;
+        state.next_pledge_id += 1;
+        state.pledges.insert(id, Pledge {
+            id,
+            project_id: project_id.clone(),
+            backer: caller,
+            count,
+            pledged_at: timestamp,
+            status: PledgeStatus::Open,
+            resolved_at: None,
+            resolved_by: None,
+            escrow: None,
+        });
+        shift_pledge_totals(&mut state, &project_id, PledgeStatus::Open, count, true);
+        notify(&mut state, owner, Some(project_id), format!("A backer pledged {} sensors to your project", count), timestamp);
+        Ok(id)
+    })
+}
+
+// Backers cancel their own open pledges; owners and admins confirm shipment
+#[update(guard = "caller_is_not_blocked")]
+fn resolve_pledge(pledge_id: u64, status: PledgeStatus) -> Result<(), String> {
+    let caller = caller();
+    let can_override = caller_has_permission(Permission::ManageSensors);
+    let timestamp = ic_cdk::api::time();
+
+    STATE.with(|state| {
+        let mut state = state.borrow_mut();
+        let pledge = state.pledges.get(&pledge_id)
+            .ok_or("Pledge not found")?;
+        if pledge.status != PledgeStatus::Open {
+            return Err("Pledge is already resolved".to_string());
+        }
+        let owner = state.projects.get(&pledge.project_id).map(|p| p.owner);
+        let funded = pledge.escrow.is_some();
+        let allowed = match status {
+            PledgeStatus::Open => return Err("Pledges can only be fulfilled or cancelled".to_string()),
+            // Escrow is released on the owner's confirmation of receipt alone
+            PledgeStatus::Fulfilled if funded => owner == Some(caller),
+            PledgeStatus::Fulfilled => owner == Some(caller) || can_override,
+            // A backer who has paid disputes instead, so the owner's side is heard
+            PledgeStatus::Cancelled if funded => owner == Some(caller),
+            PledgeStatus::Cancelled => pledge.backer == caller || owner == Some(caller) || can_override,
+        };
+        if !allowed {
+            return Err("Not authorized to resolve this pledge".to_string());
+        }
+        if pledge.escrow.as_ref().is_some_and(|e| e.status != EscrowStatus::Held) {
+            return Err("Escrow is disputed; an admin will resolve it".to_string());
+        }
+
+        let backer = pledge.backer;
+        let project_id = pledge.project_id.clone();
+        set_pledge_status(&mut state, pledge_id, status);
+        if let Some(pledge) = state.pledges.get_mut(&pledge_id) {
+            pledge.resolved_at = Some(timestamp);
+            pledge.resolved_by = Some(caller);
+        }
+        if funded {
+            start_escrow_transfer(&mut state, pledge_id, status == PledgeStatus::Cancelled);
+        }
+        if status == PledgeStatus::Fulfilled {
+            notify(&mut state, backer, Some(project_id), "Your pledged sensors were confirmed as shipped".to_string(), timestamp);
+        }
+        Ok(())
+    })
+}
+
+#[query]
+fn get_project_pledges(project_id: String) -> Vec<Pledge> {
+    STATE.with(|state| {
+        state.borrow()
+            .pledges
+            .values()
+            .filter(|p| p.project_id == project_id)
+            .cloned()
+            .collect()
+    })
+}
+
+#[query]
+fn get_my_pledges() -> Vec<Pledge> {
+    let caller = caller();
+    STATE.with(|state| {
+        state.borrow()
+            .pledges
+            .values()
+            .filter(|p| p.backer == caller)
+            .cloned()
+            .collect()
+    })
+}
+
+// Unmet sensor demand across approved projects, largest first, for procurement planning
+#[query]
+fn get_open_demand(page: Option<u32>, limit: Option<u32>) -> Result<DemandResponse, String> {
+    STATE.with(|state| {
+        let state = state.borrow();
+        let mut demand: Vec<ProjectDemand> = state.projects
+            .values()
+            .filter(|p| p.status == ProjectStatus::Approved)
+            .map(|p| project_demand(&state, p))
+            .filter(|d| d.open_demand > 0 || d.pledged_open > 0)
+            .collect();
+        demand.sort_by_key(|d| std::cmp::Reverse(d.open_demand));
+
+        let total_open_demand = demand.iter().map(|d| d.open_demand as u64).sum();
+        let total_pledged_open = demand.iter().map(|d| d.pledged_open as u64).sum();
+        let paged = paginate(demand, page, limit)?;
+
+        Ok(DemandResponse {
+            total_open_demand,
+            total_pledged_open,
+            projects: paged.items,
+            total: paged.total,
+            page: paged.page,
+            pages: paged.pages,
+            has_next: paged.has_next,
+            has_prev: paged.has_prev,
+        })
+    })
+}
+
+// Pledge Escrow
+// Backers transfer ckBTC to this account, then call fund_pledge with the block
+#[query]
+fn get_pledge_escrow_account(pledge_id: u64) -> Result<Account, String> {
+    config().escrow_ledger.ok_or("Pledge escrow is not enabled")?;
+    STATE.with(|state| {
+        state.borrow().pledges.get(&pledge_id).ok_or("Pledge not found")?;
+        Ok(Account {
+            owner: ic_cdk::id(),
+            subaccount: Some(ledger::escrow_subaccount(pledge_id)),
+        })
+    })
+}
+
+// Verifies the backer's transfer to the pledge's escrow account and holds it until the
+// owner confirms receipt (release) or cancels (refund)
+#[update(guard = "caller_is_not_blocked")]
+async fn fund_pledge(pledge_id: u64, block_index: u64) -> Result<(), String> {
+    let caller = caller();
+    let ledger = config().escrow_ledger.ok_or("Pledge escrow is not enabled")?;
+
+    STATE.with(|state| {
+        let mut state = state.borrow_mut();
+        let pledge = state.pledges.get(&pledge_id)
+            .ok_or("Pledge not found")?;
+        if pledge.backer != caller {
+            return Err("Only the backer can fund a pledge".to_string());
+        }
+        if !state.escrow_blocks.insert(block_index) {
+            return Err("Block has already been used".to_string());
+        }
+        Ok(())
+    })?;
+
+    let transfer = ledger::get_transfer(ledger, block_index).await;
+    let duplicate = STATE.with(|state| {
+        let mut state = state.borrow_mut();
+        let escrow_account = Account {
+            owner: ic_cdk::id(),
+            subaccount: Some(ledger::escrow_subaccount(pledge_id)),
+        };
+        let verified = transfer.and_then(|transfer| {
+            if !transfer.is_to(&escrow_account) {
+                return Err("Transfer was not made to the pledge's escrow account".to_string());
+            }
+            if !transfer.is_from(caller) {
+                return Err("Transfer was not made by the caller".to_string());
+            }
+            Ok(transfer)
+        });
+        let transfer = match verified {
+            Ok(transfer) => transfer,
+            Err(e) => {
+                state.escrow_blocks.remove(&block_index);
+                return Err(e);
+            }
+        };
+
+        let pledge = state.pledges.get_mut(&pledge_id)
+            .ok_or("Pledge not found")?;
+        if pledge.escrow.is_some() {
+            return Ok(Some(transfer.amount));
+        }
+        let still_open = pledge.status == PledgeStatus::Open;
+        pledge.escrow = Some(PledgeEscrow {
+            ledger,
+            block_index,
+            amount: transfer.amount,
+            funded_at: ic_cdk::api::time(),
+            status: EscrowStatus::Held,
+            transfer_created_at: None,
+        });
+
+        // The pledge may have been resolved while the payment was verified
+        if !still_open {
+            start_escrow_transfer(&mut state, pledge_id, true);
+            return Err("Pledge was resolved in the meantime; the payment is being refunded".to_string());
+        }
+        Ok(None)
+    })?;
+
+    // A second transfer into an already funded escrow is sent straight back. If that fails
+    // the block is released so the backer can call fund_pledge with it again to retry.
+    let Some(amount) = duplicate else {
+        return Ok(());
+    };
+    let to = Account { owner: caller, subaccount: None };
+    let memo = ledger::memo(b"escrow-return", block_index);
+    match ledger::transfer(ledger, ledger::escrow_subaccount(pledge_id), to, amount, memo, ic_cdk::api::time()).await {
+        Ok(refund_block) => Err(format!("Pledge was already funded; this transfer was refunded in block {}", refund_block)),
+        Err(e) => {
+            STATE.with(|state| state.borrow_mut().escrow_blocks.remove(&block_index));
+            Err(format!("Pledge was already funded and refunding this transfer failed ({}); call again to retry", e))
+        }
+    }
+}
+
+// Backer or project owner; holds the escrow until an admin resolves the dispute
+#[update(guard = "caller_is_not_blocked")]
+fn dispute_pledge(pledge_id: u64, reason: String) -> Result<(), String> {
+    let caller = caller();
+    if reason.trim().is_empty() || reason.chars().count() > MAX_DISPUTE_REASON_LENGTH {
+        return Err(format!("Reason must be 1 to {} characters", MAX_DISPUTE_REASON_LENGTH));
+    }
+    let timestamp = ic_cdk::api::time();
+
+    STATE.with(|state| {
+        let mut state = state.borrow_mut();
+        let pledge = state.pledges.get(&pledge_id)
+            .ok_or("Pledge not found")?;
+        let owner = state.projects.get(&pledge.project_id).map(|p| p.owner);
+        if pledge.backer != caller && owner != Some(caller) {
+            return Err("Only the backer or project owner can dispute a pledge".to_string());
+        }
+        if pledge.status != PledgeStatus::Open || pledge.escrow.as_ref().map(|e| &e.status) != Some(&EscrowStatus::Held) {
+            return Err("Only open pledges with escrow held can be disputed".to_string());
+        }
+
+        let (backer, project_id) = (pledge.backer, pledge.project_id.clone());
+        if let Some(escrow) = state.pledges.get_mut(&pledge_id).and_then(|p| p.escrow.as_mut()) {
+            escrow.status = EscrowStatus::Disputed { raised_by: caller, reason };
+        }
+        let other = if caller == backer { owner } else { Some(backer) };
+        if let Some(other) = other {
+            notify(&mut state, other, Some(project_id), "A pledge you are part of was disputed".to_string(), timestamp);
+        }
+        Ok(())
+    })
+}
+
+// Finance admins settle a disputed escrow: release fulfils the pledge and pays procurement,
+// otherwise the pledge is cancelled and the backer refunded
+#[update(guard = "caller_is_not_blocked")]
+fn resolve_pledge_dispute(pledge_id: u64, release: bool) -> Result<(), String> {
+    if !caller_has_permission(Permission::ManageEscrow) {
+        return Err("Only finance admins can resolve escrow disputes".to_string());
+    }
+    let caller = caller();
+    let timestamp = ic_cdk::api::time();
+
+    STATE.with(|state| {
+        let mut state = state.borrow_mut();
+        let pledge = state.pledges.get_mut(&pledge_id)
+            .ok_or("Pledge not found")?;
+        if !matches!(pledge.escrow.as_ref().map(|e| &e.status), Some(EscrowStatus::Disputed { .. })) {
+            return Err("Pledge is not disputed".to_string());
+        }
+        pledge.resolved_at = Some(timestamp);
+        pledge.resolved_by = Some(caller);
+        set_pledge_status(&mut state, pledge_id, if release { PledgeStatus::Fulfilled } else { PledgeStatus::Cancelled });
+        start_escrow_transfer(&mut state, pledge_id, !release);
+        Ok(())
+    })
+}
+
+// Finance admins only, for escrow transfers the ledger rejected
+#[update(guard = "caller_is_not_blocked")]
+fn retry_escrow_transfer(pledge_id: u64) -> Result<(), String> {
+    if !caller_has_permission(Permission::ManageEscrow) {
+        return Err("Only finance admins can retry escrow transfers".to_string());
+    }
+    STATE.with(|state| {
+        let mut state = state.borrow_mut();
+        let pledge = state.pledges.get(&pledge_id)
+            .ok_or("Pledge not found")?;
+        let Some(EscrowStatus::TransferFailed { refund, .. }) = pledge.escrow.as_ref().map(|e| &e.status) else {
+            return Err("Only failed escrow transfers can be retried".to_string());
+        };
+        let refund = *refund;
+        start_escrow_transfer(&mut state, pledge_id, refund);
+        Ok(())
+    })
+}
+
+// Sponsorship
+#[update(guard = "caller_is_not_blocked")]
+fn sponsor_sensor(project_id: String) -> Result<u64, String> {
+    let caller = caller();
+    if caller == Principal::anonymous() {
+        return Err("Anonymous principals cannot sponsor sensors".to_string());
+    }
+    let timestamp = ic_cdk::api::time();
+
+    STATE.with(|state| {
+        let mut state = state.borrow_mut();
+        let project = state.projects.get_mut(&project_id)
+            .ok_or("Project not found")?;
+        if project.status != ProjectStatus::Approved {
+            return Err("Only approved projects can be sponsored".to_string());
+        }
+        if project.sensors_sponsored >= project.sensors_required {
+            return Err("All of this project's sensors are already sponsored".to_string());
+        }
+        let owner = project.owner;
+
+        let mine: Vec<&Sponsorship> = state.sponsorships.values().filter(|s| s.sponsor == caller).collect();
+        if mine.iter().filter(|s| s.project_id == project_id).count() >= MAX_SPONSORSHIPS_PER_PROJECT {
+            return Err(format!("You can sponsor at most {} sensors per project", MAX_SPONSORSHIPS_PER_PROJECT));
+        }
+        if mine.iter().filter(|s| s.serial.is_none()).count() >= MAX_UNLINKED_SPONSORSHIPS {
+            return Err(format!("You have {} sponsorships awaiting deployment; wait for some to be linked", MAX_UNLINKED_SPONSORSHIPS));
+        }
+        if let Some(project) = state.projects.get_mut(&project_id) {
+            project.sensors_sponsored += 1;
+        }
+
+        let id = state.next_sponsorship_id;
+        state.next_sponsorship_id += 1;
+        state.sponsorships.insert(id, Sponsorship {
+            id,
+            project_id: project_id.clone(),
+            sponsor: caller,
+            created_at: timestamp,
+            serial: None,
+            linked_at: None,
+        });
+        notify(&mut state, owner, Some(project_id), "A supporter sponsored a sensor for your project".to_string(), timestamp);
+        Ok(id)
+    })
+}
+
+// Sponsors can withdraw until the sponsorship is linked to deployed hardware
+#[update(guard = "caller_is_not_blocked")]
+fn cancel_sponsorship(sponsorship_id: u64) -> Result<(), String> {
+    let caller = caller();
+
+    STATE.with(|state| {
+        let mut state = state.borrow_mut();
+        let sponsorship = state.sponsorships.get(&sponsorship_id)
+            .ok_or("Sponsorship not found")?;
+        if sponsorship.sponsor != caller {
+            return Err("Only the sponsor can cancel a sponsorship".to_string());
+        }
+        if sponsorship.serial.is_some() {
+            return Err("Sponsorship is already linked to a deployed sensor".to_string());
+        }
+
+        let project_id = sponsorship.project_id.clone();
+        state.sponsorships.remove(&sponsorship_id);
+        if let Some(project) = state.projects.get_mut(&project_id) {
+            project.sensors_sponsored = project.sensors_sponsored.saturating_sub(1);
+        }
+        Ok(())
+    })
+}
+
+// The serial must be assigned to the sponsored project and not already credited to another sponsor
+#[update(guard = "caller_is_not_blocked")]
+fn link_sponsorship(sponsorship_id: u64, serial: String) -> Result<(), String> {
+    let caller = caller();
+    let can_override = caller_has_permission(Permission::ManageSensors);
+    let timestamp = ic_cdk::api::time();
+
+    STATE.with(|state| {
+        let mut state = state.borrow_mut();
+        let sponsorship = state.sponsorships.get(&sponsorship_id)
+            .ok_or("Sponsorship not found")?;
+        let project = state.projects.get(&sponsorship.project_id)
+            .ok_or("Project not found")?;
+        if project.owner != caller && !can_override {
+            return Err("Only project owner or admins can link sponsorships".to_string());
+        }
+        if sponsorship.serial.is_some() {
+            return Err("Sponsorship is already linked to a sensor".to_string());
+        }
+        let sensor = state.sensors.get(&serial)
+            .ok_or("Sensor not found")?;
+        if sensor.project_id.as_deref() != Some(sponsorship.project_id.as_str()) {
+            return Err("Sensor is not assigned to the sponsored project".to_string());
+        }
+        if state.sponsorships.values().any(|s| s.serial.as_deref() == Some(serial.as_str())) {
+            return Err("Sensor is already linked to a sponsorship".to_string());
+        }
+
+        let sponsor = sponsorship.sponsor;
+        let project_id = sponsorship.project_id.clone();
+        if let Some(sponsorship) = state.sponsorships.get_mut(&sponsorship_id) {
+            sponsorship.serial = Some(serial.clone());
+            sponsorship.linked_at = Some(timestamp);
+        }
+        notify(&mut state, sponsor, Some(project_id), format!("Your sponsored sensor {} has been deployed", serial), timestamp);
+        Ok(())
+    })
+}
+
+#[query]
+fn get_project_sponsors(project_id: String) -> Vec<Sponsorship> {
+    STATE.with(|state| {
+        state.borrow()
+            .sponsorships
+            .values()
+            .filter(|s| s.project_id == project_id)
+            .cloned()
+            .collect()
+    })
+}
+
+#[query]
+fn get_my_sponsorships() -> Vec<Sponsorship> {
+    let caller = caller();
+    STATE.with(|state| {
+        state.borrow()
+            .sponsorships
+            .values()
+            .filter(|s| s.sponsor == caller)
+            .cloned()
+            .collect()
+    })
+}
+
+// Gateways
+#[update(guard = "caller_is_not_blocked")]
+fn register_gateway(data: GatewayData) -> Result<(), String> {
+    let caller = caller();
+    if caller == Principal::anonymous() {
+        return Err("Anonymous principals cannot register gateways".to_string());
+    }
+    let can_override = caller_has_permission(Permission::ManageSensors);
+    let timestamp = ic_cdk::api::time();
+
+    STATE.with(|state| add_gateway(&mut state.borrow_mut(), data, caller, can_override, timestamp))
+}
+
+// Registers a manufacturing batch to the importing admin, who can hand devices
+// over with claim codes. Rows are applied independently; the result for each row
+// is returned in input order.
+#[update(guard = "caller_is_not_blocked")]
+fn import_devices(devices: Vec<DeviceImport>) -> Result<Vec<Result<(), String>>, String> {
+    if !caller_has_permission(Permission::ManageSensors) {
+        return Err("Only sensor admins can import devices".to_string());
+    }
+    if devices.len() > MAX_DEVICE_IMPORT {
+        return Err(format!("At most {} devices per import", MAX_DEVICE_IMPORT));
+    }
+
+    let caller = caller();
+    let timestamp = ic_cdk::api::time();
+
+    STATE.with(|state| {
+        let mut state = state.borrow_mut();
+        Ok(devices.into_iter().map(|device| import_device(&mut state, device, caller, timestamp)).collect())
+    })
+}
+
+#[update(guard = "caller_is_not_blocked")]
+fn remove_gateway(gateway_id: String) -> Result<(), String> {
+    let caller = caller();
+    let can_override = caller_has_permission(Permission::ManageSensors);
+
+    STATE.with(|state| {
+        let mut state = state.borrow_mut();
+        let gateway = state.gateways.get(&gateway_id)
+            .ok_or("Gateway not found")?;
+        if gateway.owner != caller && !can_override {
+            return Err("Only gateway owner or admins can remove gateways".to_string());
+        }
+
+        if let Some(project_id) = gateway.project_id.clone() {
+            if let Some(ids) = state.project_gateways.get_mut(&project_id) {
+                ids.retain(|id| id != &gateway_id);
+            }
+        }
+        state.gateways.remove(&gateway_id);
+        state.gateway_online_intervals.remove(&gateway_id);
+        state.gateway_sensors.remove(&gateway_id);
+        if let Some(incident_id) = state.open_incidents.remove(&gateway_id) {
+            if let Some(incident) = state.incidents.get_mut(&incident_id) {
+                incident.closed_at = Some(ic_cdk::api::time());
+            }
+        }
+        geo_index::remove(&gateway_geo_key(&gateway_id));
+        Ok(())
+    })
+}
+
+#[query]
+fn get_gateway(gateway_id: String) -> Option<GatewayWithStatus> {
+    let now = ic_cdk::api::time();
+    STATE.with(|state| state.borrow().gateways.get(&gateway_id).map(|g| gateway_with_status(g, now)))
+}
+
+#[query]
+fn get_project_gateways(project_id: String) -> Vec<GatewayWithStatus> {
+    let now = ic_cdk::api::time();
+    STATE.with(|state| {
+        let state = state.borrow();
+        state.project_gateways
+            .get(&project_id)
+            .map(|ids| {
+                ids.iter()
+                    .filter_map(|id| state.gateways.get(id))
+                    .map(|g| gateway_with_status(g, now))
+                    .collect()
+            })
+            .unwrap_or_default()
+    })
+}
+
+#[update(guard = "caller_is_not_blocked")]
+fn set_gateway_capabilities(gateway_id: String, capabilities: GatewayCapabilities) -> Result<(), String> {
+    capabilities.validate()?;
+    let caller = caller();
+    let can_override = caller_has_permission(Permission::ManageSensors);
+
+    STATE.with(|state| {
+        let mut state = state.borrow_mut();
+        let gateway = state.gateways.get_mut(&gateway_id)
+            .ok_or("Gateway not found")?;
+        if gateway.owner != caller && !can_override {
+            return Err("Only gateway owner or admins can update gateways".to_string());
+        }
+        gateway.capabilities = capabilities;
+        Ok(())
+    })
+}
+
+// For matching hardware to deployments; gateways with unknown capabilities
+// don't match filters on those capabilities
+#[query]
+fn find_gateways(filter: GatewayFilter, page: Option<u32>, limit: Option<u32>) -> Result<GatewaysResponse, String> {
+    let now = ic_cdk::api::time();
+
+    STATE.with(|state| {
+        let state = state.borrow();
+        let mut gateways: Vec<&Gateway> = state.gateways
+            .values()
+            .filter(|g| filter.gateway_type.as_ref().is_none_or(|t| &g.gateway_type == t))
+            .filter(|g| filter.min_backhaul_kbps.is_none_or(|min| g.capabilities.backhaul_kbps.is_some_and(|b| b >= min)))
+            .filter(|g| filter.power_source.is_none_or(|p| g.capabilities.power_source == Some(p)))
+            .filter(|g| filter.lora_band.is_none_or(|b| g.capabilities.lora_band == Some(b)))
+            .filter(|g| filter.min_sensor_capacity.is_none_or(|min| g.capabilities.max_sensors.is_some_and(|m| m >= min)))
+            .filter(|g| filter.unassigned.is_none_or(|unassigned| g.project_id.is_none() == unassigned))
+            .collect();
+        gateways.sort_by(|a, b| a.id.cmp(&b.id));
+
+        let paged = paginate(gateways, page, limit)?.map(|g| gateway_with_status(g, now));
+
+        Ok(GatewaysResponse {
+            gateways: paged.items,
+            total: paged.total,
+            page: paged.page,
+            pages: paged.pages,
+            has_next: paged.has_next,
+            has_prev: paged.has_prev,
+        })
+    })
+}
+
+// Sensors whose latest readings came through this gateway
+#[query]
+fn get_gateway_sensors(gateway_id: String) -> Vec<SensorWithStatus> {
+    let now = ic_cdk::api::time();
+    STATE.with(|state| {
+        let state = state.borrow();
+        state.gateway_sensors
+            .get(&gateway_id)
+            .into_iter()
+            .flatten()
+            .filter_map(|serial| state.sensors.get(serial))
+            .map(|s| sensor_with_status(s, now))
+            .collect()
+    })
+}
+
+#[query]
+fn get_sensor_route(serial: String) -> Result<SensorRoute, String> {
+    let now = ic_cdk::api::time();
+    STATE.with(|state| {
+        let state = state.borrow();
+        let sensor = state.sensors.get(&serial)
+            .ok_or("Sensor not found")?;
+        Ok(SensorRoute {
+            serial: sensor.serial.clone(),
+            sensor_status: device_status(sensor.last_seen, now),
+            gateway_id: sensor.relayed_by.clone(),
+            gateway: sensor.relayed_by.as_ref()
+                .and_then(|id| state.gateways.get(id))
+                .map(|g| gateway_with_status(g, now)),
+            relayed_at: sensor.relayed_at,
+        })
+    })
+}
+
+// radius is in kilometers
+#[query]
+fn get_gateways_near(lat: f64, lng: f64, radius: f64) -> Result<Vec<GatewayWithStatus>, String> {
+    let geohash = encode_geohash(lat, lng)?;
+    let now = ic_cdk::api::time();
+    let prefix = gateway_geo_key("");
+
+    STATE.with(|state| {
+        let state = state.borrow();
+        Ok(geo_index::find(geohash, radius)
+            .iter()
+            .filter_map(|key| key.strip_prefix(prefix.as_str()))
+            .filter_map(|id| state.gateways.get(id))
+            .map(|g| gateway_with_status(g, now))
+            .collect())
+    })
+}
+
+// Linked gateways and located sensors, with the cells they cover, for comparing
+// the monitored area against the project's nominal location
+#[query]
+fn get_project_coverage(project_id: String) -> Result<ProjectCoverage, String> {
+    STATE.with(|state| {
+        let state = state.borrow();
+        let project = state.projects.get(&project_id)
+            .ok_or("Project not found")?;
+
+        let gateways = state.project_gateways
+            .get(&project_id)
+            .into_iter()
+            .flatten()
+            .filter_map(|id| state.gateways.get(id))
+            .map(|g| DevicePosition {
+                id: g.id.clone(),
+                kind: DeviceKind::Gateway,
+                location: g.location.clone(),
+            });
+        let sensors = state.project_sensors
+            .get(&project_id)
+            .into_iter()
+            .flatten()
+            .filter_map(|serial| state.sensors.get(serial))
+            .filter_map(|s| {
+                s.location.clone().map(|location| DevicePosition {
+                    id: s.serial.clone(),
+                    kind: DeviceKind::Sensor,
+                    location,
+                })
+            });
+        let devices: Vec<DevicePosition> = gateways.chain(sensors).collect();
+
+        let cells: BTreeSet<String> = devices
+            .iter()
+            .map(|d| d.location.geohash.chars().take(COVERAGE_CELL_PRECISION).collect())
+            .collect();
+
+        Ok(ProjectCoverage {
+            project_id,
+            nominal_location: project.location.clone(),
+            devices,
+            cells: cells.into_iter().collect(),
+        })
+    })
+}
+
+// Telemetry
+// A gateway relays its owner's sensors, its project's sensors and sensors it already relays
+fn gateway_may_relay(gateway: &Gateway, gateway_id: &str, sensor: &Sensor) -> bool {
+    sensor.owner == gateway.owner
+        || (gateway.project_id.is_some() && sensor.project_id == gateway.project_id)
+        || sensor.relayed_by.as_deref() == Some(gateway_id)
+}
+
+// Gateways push batches on behalf of their sensors; the whole batch is rejected if any reading is invalid.
+// Signed batches carry a sequence number that must increase, so a captured batch can't be replayed.
+#[update(guard = "caller_is_not_blocked")]
+fn submit_readings(gateway_id: String, readings: Vec<Reading>, signature: Option<ByteBuf>, sequence: Option<u64>) -> Result<u32, String> {
+    if readings.is_empty() {
+        return Err("No readings submitted".to_string());
+    }
+    if readings.len() > telemetry::MAX_BATCH_SIZE {
+        return Err(format!("At most {} readings per batch", telemetry::MAX_BATCH_SIZE));
+    }
+    ensure_heap_headroom()?;
+
+    let caller = caller();
+    let is_service = caller_has_permission(Permission::PostTelemetry);
+    let now = ic_cdk::api::time();
+    let require_signed = config().require_signed_telemetry;
+
+    // The gateway signs the batch in the order it was sent
+    let payload = telemetry::batch_payload(&gateway_id, sequence.unwrap_or(0), &readings);
+    let mut readings = readings;
+    readings.sort_by_key(|reading| reading.timestamp);
+
+    // Suspect readings are kept but marked, and don't trigger alerts
+    let sensor_types: HashMap<String, SensorType> = STATE.with(|state| {
+        let state = state.borrow();
+        readings.iter()
+            .filter_map(|r| state.sensors.get(&r.sensor))
+            .filter_map(|s| Some((s.serial.clone(), sensor_types::get(s.sensor_type.as_ref()?)?)))
+            .collect()
+    });
+    telemetry::flag_anomalies(&mut readings, |serial, metric| {
+        let sensor_type = sensor_types.get(serial).filter(|t| t.id == metric)?;
+        Some(telemetry::Limits {
+            min: sensor_type.min_value,
+            max: sensor_type.max_value,
+            max_jump: sensor_type.max_jump,
+        })
+    });
+
+    STATE.with(|state| {
+        let mut state = state.borrow_mut();
+        let gateway = state.gateways.get(&gateway_id)
+            .ok_or("Gateway not found")?;
+        if gateway.owner != caller && !is_service {
+            return Err("Only the gateway owner or telemetry services can submit readings".to_string());
+        }
+        let signed_sequence = match &gateway.public_key {
+            Some(key) => {
+                let sequence = sequence.ok_or("Signed batches need a sequence number")?;
+                if state.gateway_sequences.get(&gateway_id).is_some_and(|last| sequence <= *last) {
+                    return Err("Batch sequence number must increase; the batch was already submitted".to_string());
+                }
+                telemetry::verify_signature(key, &payload, signature.as_ref())
+                    .map_err(|e| format!("Batch: {}", e))?;
+                Some(sequence)
+            }
+            None if require_signed => {
+                return Err("Gateway has no public key; signed telemetry is required".to_string());
+            }
+            None => None,
+        };
+
+        let mut projects: HashSet<String> = HashSet::new();
+        for (i, reading) in readings.iter().enumerate() {
+            telemetry::validate(reading, now, MAX_READING_CLOCK_SKEW_NANOS)
+                .map_err(|e| format!("Reading {}: {}", i, e))?;
+            let sensor = state.sensors.get(&reading.sensor)
+                .ok_or(format!("Reading {}: unknown sensor {}", i, reading.sensor))?;
+            if sensor.retired.is_some() {
+                return Err(format!("Reading {}: sensor {} has been retired", i, reading.sensor));
+            }
+            if !gateway_may_relay(gateway, &gateway_id, sensor) {
+                return Err(format!("Reading {}: sensor {} is not relayed by this gateway", i, reading.sensor));
+            }
+            if let Some(key) = &sensor.public_key {
+                telemetry::verify_signature(key, &telemetry::reading_payload(reading), reading.signature.as_ref())
+                    .map_err(|e| format!("Reading {}: {}", i, e))?;
+            }
+            let project_id = sensor.project_id.clone()
+                .ok_or(format!("Reading {}: sensor {} is not assigned to a project", i, reading.sensor))?;
+            projects.insert(project_id);
+        }
+
+        if let Some(sequence) = signed_sequence {
+            state.gateway_sequences.insert(gateway_id.clone(), sequence);
+        }
+
+        // Data implies the devices are alive
+        mark_gateway_seen(&mut state, &gateway_id, now);
+        for reading in &readings {
+            state.offline_sensors.remove(&reading.sensor);
+            let Some(sensor) = state.sensors.get_mut(&reading.sensor) else {
+                continue;
+            };
+            sensor.last_seen = Some(now);
+            sensor.relayed_at = Some(now);
+            let previous = sensor.relayed_by.replace(gateway_id.clone());
+            if previous.as_deref() != Some(gateway_id.as_str()) {
+                if let Some(previous) = previous {
+                    if let Some(serials) = state.gateway_sensors.get_mut(&previous) {
+                        serials.remove(&reading.sensor);
+                    }
+                }
+                state.gateway_sensors.entry(gateway_id.clone()).or_default().insert(reading.sensor.clone());
+            }
+        }
+
+        for project_id in projects {
+            if let Some(project) = state.projects.get_mut(&project_id) {
+                record_activity(project, now);
+                project.last_data_at = Some(now);
+            }
+            refresh_active_sensors(&mut state, &project_id, now);
+        }
+
+        evaluate_alerts(&mut state, &readings, now);
+        Ok(())
+    })?;
+
+    let count = readings.len() as u32;
+    for reading in &mut readings {
+        reading.signature = None;
+    }
+    telemetry::append(readings);
+    Ok(count)
+}
+
+// bucket is in seconds; from/to are nanosecond timestamps
+#[query]
+fn get_sensor_aggregates(sensor_id: String, metric: String, from: u64, to: u64, bucket: u64) -> Result<Vec<Aggregate>, String> {
+    if from >= to {
+        return Err("from must be before to".to_string());
+    }
+    let bucket = bucket.checked_mul(1_000_000_000).filter(|b| *b > 0)
+        .ok_or("Invalid bucket size")?;
+    if (to - from).div_ceil(bucket) > telemetry::MAX_AGGREGATE_BUCKETS {
+        return Err(format!("At most {} buckets per query", telemetry::MAX_AGGREGATE_BUCKETS));
+    }
+    Ok(telemetry::aggregate(&sensor_id, &metric, from, to, bucket))
+}
+
+// Hourly min/max/avg kept beyond the raw retention window; from/to are nanosecond timestamps
+#[query]
+fn get_hourly_aggregates(sensor_id: String, metric: String, from: u64, to: u64) -> Result<Vec<Aggregate>, String> {
+    if from >= to {
+        return Err("from must be before to".to_string());
+    }
+    if (to - from).div_ceil(telemetry::NANOS_PER_HOUR) > telemetry::MAX_AGGREGATE_BUCKETS {
+        return Err(format!("At most {} buckets per query", telemetry::MAX_AGGREGATE_BUCKETS));
+    }
+    Ok(telemetry::hourly(&sensor_id, &metric, from, to))
+}
+
+// None reverts the project to the config default
+#[update(guard = "caller_is_not_blocked")]
+fn set_retention_policy(project_id: String, policy: Option<RetentionPolicy>) -> Result<(), String> {
+    if !caller_has_permission(Permission::ManageSensors) {
+        return Err("Only sensor admins can set retention policies".to_string());
+    }
+    if let Some(policy) = &policy {
+        policy.validate()?;
+    }
+
+    STATE.with(|state| {
+        let mut state = state.borrow_mut();
+        if !state.projects.contains_key(&project_id) {
+            return Err("Project not found".to_string());
+        }
+        match policy {
+            Some(policy) => state.retention_overrides.insert(project_id, policy),
+            None => state.retention_overrides.remove(&project_id),
+        };
+        Ok(())
+    })
+}
+
+// The policy currently applied to the project's sensors
+#[query]
+fn get_retention_policy(project_id: String) -> RetentionPolicy {
+    STATE.with(|state| state.borrow().retention_overrides.get(&project_id).cloned())
+        .unwrap_or_else(|| config().retention())
+}
+
+// Alerts
+#[update(guard = "caller_is_not_blocked")]
+fn add_alert_rule(project_id: String, rule: AlertRuleData) -> Result<u64, String> {
+    if rule.metric.is_empty() || rule.metric.len() > telemetry::MAX_METRIC_LENGTH {
+        return Err(format!("Metric must be 1 to {} characters", telemetry::MAX_METRIC_LENGTH));
+    }
+    if !rule.threshold.is_finite() {
+        return Err("Threshold must be a finite number".to_string());
+    }
+    if rule.duration_seconds > MAX_ALERT_WINDOW_SECONDS || rule.cooldown_seconds > MAX_ALERT_WINDOW_SECONDS {
+        return Err(format!("Duration and cooldown cannot exceed {} seconds", MAX_ALERT_WINDOW_SECONDS));
+    }
+
+    let caller = caller();
+    let can_override = caller_has_permission(Permission::ManageSensors);
+    let timestamp = ic_cdk::api::time();
+
+    STATE.with(|state| {
+        let mut state = state.borrow_mut();
+        let project = state.projects.get(&project_id)
+            .ok_or("Project not found")?;
+        if project.owner != caller && !can_override {
+            return Err("Only project owner or admins can manage alert rules".to_string());
+        }
+        if state.alert_rules.values().filter(|r| r.project_id == project_id).count() >= MAX_ALERT_RULES_PER_PROJECT {
+            return Err(format!("Projects can have at most {} alert rules", MAX_ALERT_RULES_PER_PROJECT));
+        }
+
+        let id = state.next_alert_rule_id;
+        state.next_alert_rule_id += 1;
+        state.alert_rule_index
+            .entry((project_id.clone(), rule.metric.clone()))
+            .or_default()
+            .push(id);
+        state.alert_rules.insert(id, AlertRule {
+            id,
+            project_id,
+            rule,
+            created_by: caller,
+            created_at: timestamp,
+        });
+        Ok(id)
+    })
+}
+
+#[update(guard = "caller_is_not_blocked")]
+fn remove_alert_rule(rule_id: u64) -> Result<(), String> {
+    let caller = caller();
+    let can_override = caller_has_permission(Permission::ManageSensors);
+
+    STATE.with(|state| {
+        let mut state = state.borrow_mut();
+        let rule = state.alert_rules.get(&rule_id)
+            .ok_or("Alert rule not found")?;
+        let owner = state.projects.get(&rule.project_id).map(|p| p.owner);
+        if owner != Some(caller) && !can_override {
+            return Err("Only project owner or admins can manage alert rules".to_string());
+        }
+        let key = (rule.project_id.clone(), rule.rule.metric.clone());
+        if let Some(ids) = state.alert_rule_index.get_mut(&key) {
+            ids.retain(|id| *id != rule_id);
+            if ids.is_empty() {
+                state.alert_rule_index.remove(&key);
+            }
+        }
+        state.alert_rules.remove(&rule_id);
+        state.alert_trackers.retain(|(id, _), _| *id != rule_id);
+        Ok(())
+    })
+}
+
+#[query]
+fn get_alert_rules(project_id: String) -> Vec<AlertRule> {
+    STATE.with(|state| {
+        state.borrow()
+            .alert_rules
+            .values()
+            .filter(|r| r.project_id == project_id)
+            .cloned()
+            .collect()
+    })
+}
+
+// Newest first
+#[query]
+fn get_alerts(project_id: String) -> Result<Vec<Alert>, String> {
+    let caller = caller();
+    let can_override = caller_has_permission(Permission::ViewPrivate);
+
+    STATE.with(|state| {
+        let state = state.borrow();
+        let project = state.projects.get(&project_id)
+            .ok_or("Project not found")?;
+        if project.owner != caller && !can_override {
+            return Err("Only project owner or admins can view alerts".to_string());
+        }
+        Ok(state.alerts
+            .get(&project_id)
+            .map(|alerts| alerts.iter().rev().cloned().collect())
+            .unwrap_or_default())
+    })
+}
+
+// device_id may be a gateway id or a sensor serial; None removes the key
+#[update(guard = "caller_is_not_blocked")]
+fn set_device_public_key(device_id: String, public_key: Option<ByteBuf>) -> Result<(), String> {
+    if let Some(key) = &public_key {
+        telemetry::validate_public_key(key)?;
+    }
+    let caller = caller();
+    let can_override = caller_has_permission(Permission::ManageSensors);
+
+    STATE.with(|state| {
+        let mut state = state.borrow_mut();
+        if let Some(gateway) = state.gateways.get_mut(&device_id) {
+            if gateway.owner != caller && !can_override {
+                return Err("Only the device owner or admins can set its public key".to_string());
+            }
+            gateway.public_key = public_key;
+            return Ok(());
+        }
+        let sensor = state.sensors.get_mut(&device_id)
+            .ok_or("Device not found")?;
+        if sensor.owner != caller && !can_override {
+            return Err("Only the device owner or admins can set its public key".to_string());
+        }
+        if sensor.retired.is_some() {
+            return Err("Sensor has been retired".to_string());
+        }
+        sensor.public_key = public_key;
+        Ok(())
+    })
+}
+
+// device_id may be a gateway id or a sensor serial
+#[update(guard = "caller_is_not_blocked")]
+fn heartbeat(device_id: String) -> Result<(), String> {
+    let caller = caller();
+    let is_service = caller_has_permission(Permission::PostTelemetry);
+    let now = ic_cdk::api::time();
+
+    STATE.with(|state| {
+        let mut state = state.borrow_mut();
+        let owner = state.gateways.get(&device_id).map(|g| g.owner)
+            .or_else(|| state.sensors.get(&device_id).map(|s| s.owner))
+            .ok_or("Device not found")?;
+        if owner != caller && !is_service {
+            return Err("Only the device owner or telemetry services can send heartbeats".to_string());
+        }
+        if state.sensors.get(&device_id).is_some_and(|s| s.retired.is_some()) {
+            return Err("Sensor has been retired".to_string());
+        }
+
+        if state.gateways.contains_key(&device_id) {
+            mark_gateway_seen(&mut state, &device_id, now);
+        } else if let Some(sensor) = state.sensors.get_mut(&device_id) {
+            sensor.last_seen = Some(now);
+            state.offline_sensors.remove(&device_id);
+        }
+        Ok(())
+    })
+}
+
+// Newest first
+#[query]
+fn get_incidents(scope: IncidentScope, page: Option<u32>, limit: Option<u32>) -> Result<IncidentsResponse, String> {
+    STATE.with(|state| {
+        let state = state.borrow();
+        let incidents: Vec<&Incident> = state.incidents
+            .values()
+            .rev()
+            .filter(|incident| match &scope {
+                IncidentScope::Gateway(gateway_id) => &incident.gateway_id == gateway_id,
+                IncidentScope::Project(project_id) => incident.project_id.as_ref() == Some(project_id),
+            })
+            .collect();
+
+        let paged = paginate(incidents, page, limit)?.map(Incident::clone);
+
+        Ok(IncidentsResponse {
+            incidents: paged.items,
+            total: paged.total,
+            page: paged.page,
+            pages: paged.pages,
+            has_next: paged.has_next,
+            has_prev: paged.has_prev,
+        })
+    })
+}
+
+#[query]
+fn get_gateway_uptime(gateway_id: String, window: UptimeWindow) -> Result<GatewayUptime, String> {
+    let now = ic_cdk::api::time();
+    STATE.with(|state| {
+        let state = state.borrow();
+        let gateway = state.gateways.get(&gateway_id)
+            .ok_or("Gateway not found")?;
+        Ok(gateway_uptime(&state, gateway, window, now))
+    })
+}
+
+#[query]
+fn get_project_uptime(project_id: String, window: UptimeWindow) -> ProjectUptime {
+    let now = ic_cdk::api::time();
+    STATE.with(|state| {
+        let state = state.borrow();
+        let gateways: Vec<GatewayUptime> = state.project_gateways
+            .get(&project_id)
+            .into_iter()
+            .flatten()
+            .filter_map(|id| state.gateways.get(id))
+            .map(|gateway| gateway_uptime(&state, gateway, window, now))
+            .collect();
+        let average_uptime_percentage = if gateways.is_empty() {
+            None
+        } else {
+            Some(gateways.iter().map(|g| g.uptime_percentage).sum::<f64>() / gateways.len() as f64)
+        };
+
+        ProjectUptime {
+            project_id,
+            average_uptime_percentage,
+            gateways,
+        }
+    })
+}
+
+// Stale and offline gateways and sensors linked to the project
+#[query]
+fn get_offline_devices(project_id: String) -> Vec<DeviceHealth> {
+    let now = ic_cdk::api::time();
+
+    STATE.with(|state| {
+        let state = state.borrow();
+        let gateways = state.project_gateways
+            .get(&project_id)
+            .into_iter()
+            .flatten()
+            .filter_map(|id| state.gateways.get(id))
+            .map(|g| DeviceHealth {
+                id: g.id.clone(),
+                kind: DeviceKind::Gateway,
+                last_seen: g.last_seen,
+                status: device_status(g.last_seen, now),
+            });
+        let sensors = state.project_sensors
+            .get(&project_id)
+            .into_iter()
+            .flatten()
+            .filter_map(|serial| state.sensors.get(serial))
+            .map(|s| DeviceHealth {
+                id: s.serial.clone(),
+                kind: DeviceKind::Sensor,
+                last_seen: s.last_seen,
+                status: device_status(s.last_seen, now),
+            });
+        gateways.chain(sensors)
+            .filter(|device| device.status != DeviceStatus::Online)
+            .collect()
+    })
+}
+
+#[query]
+fn get_latest_readings(serial: String, limit: Option<u32>) -> Result<Vec<Reading>, String> {
+    let limit = capped_limit(limit, config().default_page_limit, MAX_READINGS_QUERY)?;
+    Ok(telemetry::latest(&serial, limit))
+}
+
+// Readings flagged as suspect on ingestion across the project's sensors, newest first
+#[query]
+fn get_flagged_readings(project_id: String, limit: Option<u32>) -> Result<Vec<Reading>, String> {
+    let caller = caller();
+    let can_override = caller_has_permission(Permission::ViewPrivate);
+    let limit = capped_limit(limit, config().default_page_limit, MAX_READINGS_QUERY)?;
+
+    let serials = STATE.with(|state| {
+        let state = state.borrow();
+        let project = state.projects.get(&project_id)
+            .ok_or("Project not found")?;
+        if project.owner != caller && !can_override {
+            return Err("Only project owner or admins can review flagged readings".to_string());
+        }
+        Ok(state.project_sensors.get(&project_id).cloned().unwrap_or_default())
+    })?;
+
+    let mut flagged: Vec<Reading> = serials.iter()
+        .flat_map(|serial| telemetry::flagged(serial, limit))
+        .collect();
+    flagged.sort_by_key(|r| std::cmp::Reverse(r.timestamp));
+    flagged.truncate(limit);
+    Ok(flagged)
+}
+
+// Returns up to EXPORT_CHUNK_READINGS readings in [from, to). Chunks never split
+// readings that share a timestamp, so following next_from neither skips nor repeats rows.
+#[query]
+fn export_readings(sensor_id: String, from: u64, to: u64, format: ExportFormat) -> Result<ReadingsExport, String> {
+    if from >= to {
+        return Err("from must be before to".to_string());
+    }
+
+    let mut readings = telemetry::range(&sensor_id, from, to);
+    let mut next_from = None;
+    if readings.len() > EXPORT_CHUNK_READINGS {
+        let boundary = readings[EXPORT_CHUNK_READINGS].timestamp;
+        let mut end = readings.partition_point(|r| r.timestamp < boundary);
+        if end == 0 {
+            end = readings.partition_point(|r| r.timestamp <= boundary);
+        }
+        if end < readings.len() {
+            next_from = Some(readings[end].timestamp);
+            readings.truncate(end);
+        }
+    }
+
+    let mut data = match format {
+        ExportFormat::Csv => String::from("sensor,metric,timestamp,value\n"),
+        ExportFormat::LineProtocol => String::new(),
+    };
+    for reading in readings {
+        let line = match format {
+            ExportFormat::Csv => format!(
+                "{},{},{},{}",
+                csv_field(&reading.sensor), csv_field(&reading.metric), reading.timestamp, reading.value
+            ),
+            ExportFormat::LineProtocol => format!(
+                "{},sensor={} value={} {}",
+                line_protocol_escape(&reading.metric), line_protocol_escape(&reading.sensor), reading.value, reading.timestamp
+            ),
+        };
+        data.push_str(&line);
+        data.push('\n');
+    }
+
+    Ok(ReadingsExport { data, next_from })
+}
+
+// Discord
+// Posts status changes, features and vote milestones to the webhook URL stored in private_discord
+#[update(guard = "caller_is_not_blocked")]
+fn set_discord_notifications(project_id: String, enabled: bool) -> Result<(), String> {
+    let caller = caller();
+
+    STATE.with(|state| {
+        let mut state = state.borrow_mut();
+        let project = state.projects.get(&project_id)
+            .ok_or("Project not found")?;
+        if project.owner != caller {
+            return Err("Only project owner can change Discord notifications".to_string());
+        }
+        if enabled && !webhooks::is_webhook_url(&project.private_discord) {
+            return Err(format!("private_discord must be a webhook URL starting with {}", webhooks::WEBHOOK_URL_PREFIX));
+        }
+
+        if enabled {
+            state.discord_opt_in.insert(project_id);
+        } else {
+            state.discord_opt_in.remove(&project_id);
+        }
+        Ok(())
+    })
+}
+
+#[query]
+fn get_pending_webhooks() -> Result<u64, String> {
+    if !caller_has_permission(Permission::ManageAdmins) {
+        return Err("Only super admins can inspect the webhook queue".to_string());
+    }
+    Ok(webhooks::queue_length() as u64)
+}
+
+#[query]
+fn transform_webhook_response(
+    args: ic_cdk::api::management_canister::http_request::TransformArgs,
+) -> ic_cdk::api::management_canister::http_request::HttpResponse {
+    webhooks::transform(args)
+}
+
+// Notification Relay
+#[derive(CandidType, Serialize, Deserialize, Clone, Debug)]
+pub struct DeliveriesResponse {
+    deliveries: Vec<webhooks::DeliveryRecord>,
+    total: u64,
+    page: u32,
+    pages: u32,
+    has_next: bool,
+    has_prev: bool,
+}
+
+// Covers Discord and relay deliveries, newest first
+#[query]
+fn get_deliveries(page: Option<u32>, limit: Option<u32>) -> Result<DeliveriesResponse, String> {
+    if !caller_has_permission(Permission::ManageAdmins) {
+        return Err("Only super admins can inspect deliveries".to_string());
+    }
+    let paged = webhooks::with_records(|records| paginate(records, page, limit).map(|paged| paged.map(webhooks::DeliveryRecord::clone)))?;
+    Ok(DeliveriesResponse {
+        deliveries: paged.items,
+        total: paged.total,
+        page: paged.page,
+        pages: paged.pages,
+        has_next: paged.has_next,
+        has_prev: paged.has_prev,
+    })
+}
+
+#[query]
+fn get_delivery(id: u64) -> Result<webhooks::DeliveryRecord, String> {
+    if !caller_has_permission(Permission::ManageAdmins) {
+        return Err("Only super admins can inspect deliveries".to_string());
+    }
+    webhooks::get_record(id).ok_or("Delivery not found".to_string())
+}
+
+// Event Subscriptions
+// Canisters on the admins' allowlist subscribe themselves; admins can manage any subscription
+fn ensure_can_manage_subscription(callback_canister: &Principal) -> Result<(), String> {
+    let caller = caller();
+    if caller == Principal::anonymous() {
+        return Err("Anonymous principals cannot subscribe".to_string());
+    }
+    if caller != *callback_canister && !caller_has_permission(Permission::ManageAdmins) {
+        return Err("Only the subscribing canister or super admins can manage its subscription".to_string());
+    }
+    Ok(())
+}
+
+// Events are sent to the canister's on_earthstream_events method; see subscriptions.rs
+#[update(guard = "caller_is_not_blocked")]
+fn subscribe(callback_canister: Principal, event_types: Vec<EventType>) -> Result<(), String> {
+    ensure_can_manage_subscription(&callback_canister)?;
+    subscriptions::subscribe(callback_canister, event_types, ic_cdk::api::time())
+}
+
+#[update(guard = "caller_is_not_blocked")]
+fn unsubscribe(callback_canister: Principal) -> Result<(), String> {
+    ensure_can_manage_subscription(&callback_canister)?;
+    subscriptions::unsubscribe(&callback_canister)
+}
+
+// Called by subscribers with the highest seq they have processed; unacknowledged events are resent
+#[update(guard = "caller_is_not_blocked")]
+fn ack_events(seq: u64) -> Result<(), String> {
+    subscriptions::ack(&caller(), seq, ic_cdk::api::time())
+}
+
+#[update(guard = "caller_is_not_blocked")]
+fn allow_subscriber(callback_canister: Principal) -> Result<(), String> {
+    if !caller_has_permission(Permission::ManageAdmins) {
+        return Err("Only super admins can allow subscribers".to_string());
+    }
+    subscriptions::allow(callback_canister);
+    Ok(())
+}
+
+// Also ends the canister's subscription
+#[update(guard = "caller_is_not_blocked")]
+fn disallow_subscriber(callback_canister: Principal) -> Result<(), String> {
+    if !caller_has_permission(Permission::ManageAdmins) {
+        return Err("Only super admins can disallow subscribers".to_string());
+    }
+    subscriptions::disallow(&callback_canister)
+}
+
+#[query]
+fn get_allowed_subscribers() -> Result<Vec<Principal>, String> {
+    if !caller_has_permission(Permission::ManageAdmins) {
+        return Err("Only super admins can list allowed subscribers".to_string());
+    }
+    Ok(subscriptions::allowed())
+}
+
+#[query]
+fn get_subscriptions() -> Result<Vec<subscriptions::Subscription>, String> {
+    if !caller_has_permission(Permission::ManageAdmins) {
+        return Err("Only super admins can list subscriptions".to_string());
+    }
+    Ok(subscriptions::list())
+}
+
+// Owner Webhooks
+fn check_project_access(project_id: &str, allow_admins: bool) -> Result<(), String> {
+    let caller = caller();
+    let can_override = allow_admins && caller_has_permission(Permission::ViewPrivate);
+    STATE.with(|state| {
+        let state = state.borrow();
+        let project = state.projects.get(project_id)
+            .ok_or("Project not found")?;
+        if project.owner != caller && !can_override {
+            return Err("Only the project owner can manage its webhook".to_string());
+        }
+        Ok(())
+    })
+}
+
+// Registers or replaces the project's webhook and returns the new signing secret (hex);
+// it is not shown again
+#[update(guard = "caller_is_not_blocked")]
+async fn register_webhook(project_id: String, url: String, events: Vec<WebhookEvent>) -> Result<String, String> {
+    check_project_access(&project_id, false)?;
+    owner_webhooks::validate(&url, &events)?;
+
+    let (random,) = ic_cdk::api::management_canister::main::raw_rand()
+        .await
+        .map_err(|(_, e)| format!("Failed to generate webhook secret: {}", e))?;
+    let secret = random[..WEBHOOK_SECRET_BYTES].to_vec();
+
+    check_project_access(&project_id, false)?;
+    owner_webhooks::register(OwnerWebhook {
+        project_id,
+        url,
+        events,
+        registered_at: ic_cdk::api::time(),
+    }, secret.clone());
+    Ok(hex::encode(secret))
+}
+
+#[update(guard = "caller_is_not_blocked")]
+fn delete_webhook(project_id: String) -> Result<(), String> {
+    check_project_access(&project_id, false)?;
+    if !owner_webhooks::remove(&project_id) {
+        return Err("Project has no webhook".to_string());
+    }
+    Ok(())
+}
+
+// Project owner or admins only
+#[query]
+fn get_webhook(project_id: String) -> Result<Option<OwnerWebhook>, String> {
+    check_project_access(&project_id, true)?;
+    Ok(owner_webhooks::get(&project_id))
+}
+
+// Latest deliveries to the project's webhook, newest first; project owner or admins only
+#[query]
+fn list_webhook_deliveries(project_id: String) -> Result<Vec<webhooks::DeliveryRecord>, String> {
+    check_project_access(&project_id, true)?;
+    Ok(owner_webhooks::deliveries(&project_id))
+}
+
+// Notifications
+#[query]
+fn get_my_notifications(page: Option<u32>, limit: Option<u32>) -> Result<NotificationsResponse, String> {
+    let caller = caller();
+    STATE.with(|state| {
+        let state = state.borrow();
+        let notifications: Vec<&Notification> = state.notifications
+            .get(&caller)
+            .map(|inbox| inbox.iter().rev().collect())
+            .unwrap_or_default();
+        let paged = paginate(notifications, page, limit)?.map(|n| n.clone());
+
+        Ok(NotificationsResponse {
+            notifications: paged.items,
+            total: paged.total,
+            page: paged.page,
+            pages: paged.pages,
+            has_next: paged.has_next,
+            has_prev: paged.has_prev,
+        })
+    })
+}
+
+#[update(guard = "caller_is_not_blocked")]
+fn mark_notifications_read(ids: Vec<u64>) -> Result<(), String> {
+    let caller = caller();
+    STATE.with(|state| {
+        let mut state = state.borrow_mut();
+        if let Some(notifications) = state.notifications.get_mut(&caller) {
+            for notification in notifications.iter_mut().filter(|n| ids.contains(&n.id)) {
+                notification.read = true;
+            }
+        }
+        Ok(())
+    })
+}
+
+// Project Templates
+#[update(guard = "caller_is_not_blocked")]
+fn create_template(name: String, description: String, data: ProjectData) -> Result<u64, String> {
+    if !caller_has_permission(Permission::ManageTemplates) {
+        return Err("Only curators can create templates".to_string());
+    }
+
+    let caller = caller();
+    STATE.with(|state| {
+        let mut state = state.borrow_mut();
+        let id = state.next_template_id;
+        state.next_template_id += 1;
+        state.templates.insert(id, ProjectTemplate {
+            id,
+            name,
+            description,
+            data,
+            created_by: caller,
+            created_at: ic_cdk::api::time(),
+        });
+        Ok(id)
+    })
+}
+
+#[update(guard = "caller_is_not_blocked")]
+fn delete_template(id: u64) -> Result<(), String> {
+    if !caller_has_permission(Permission::ManageTemplates) {
+        return Err("Only curators can delete templates".to_string());
+    }
+
+    STATE.with(|state| {
+        state.borrow_mut()
+            .templates
+            .remove(&id)
+            .map(|_| ())
+            .ok_or("Template not found".to_string())
+    })
+}
+
+#[query]
+fn get_templates() -> Vec<ProjectTemplate> {
+    STATE.with(|state| state.borrow().templates.values().cloned().collect())
+}
+
+#[update(guard = "caller_is_not_blocked")]
+fn create_draft_from_template(template_id: u64) -> Result<String, String> {
+    let caller = caller();
+    ensure_heap_headroom()?;
+    ensure_may_create(caller)?;
+
+    let timestamp = ic_cdk::api::time();
+
+    let result = STATE.with(|state| {
+        let mut state = state.borrow_mut();
+        let template = state.templates.get(&template_id)
+            .ok_or("Template not found")?;
+        let project = new_project(&state, template.data.clone(), caller, ProjectStatus::Draft, timestamp);
+        Ok(insert_project(&mut state, project))
+    });
+    if result.is_ok() {
+        rate_limit::record(caller, RateLimitedMethod::CreateProject, ic_cdk::api::time());
+    }
+    result
+}
+
+// Verification
+#[update(guard = "caller_is_not_blocked")]
+fn request_verification(project_id: String, evidence: VerificationEvidence) -> Result<(), String> {
+    let caller = caller();
+
+    if evidence.documents.is_empty() && evidence.photos.is_empty() {
+        return Err("At least one document or photo is required".to_string());
+    }
+
+    STATE.with(|state| {
+        let mut state = state.borrow_mut();
+        let project = state.projects.get(&project_id)
+            .ok_or("Project not found")?;
+
+        if project.owner != caller {
+            return Err("Only project owner can request verification".to_string());
+        }
+        if project.verified {
+            return Err("Project is already verified".to_string());
+        }
+        if let Some(request) = state.verification_requests.get(&project_id) {
+            if request.status == VerificationStatus::Pending {
+                return Err("A verification request is already pending".to_string());
+            }
+        }
+
+        state.verification_requests.insert(project_id.clone(), VerificationRequest {
+            project_id,
+            requested_by: caller,
+            evidence,
+            submitted_at: ic_cdk::api::time(),
+            status: VerificationStatus::Pending,
+            reviewed_by: None,
+            reviewed_at: None,
+            reason: None,
+        });
+        Ok(())
+    })
+}
+
+fn review_verification(project_id: String, approve: bool, reason: Option<String>) -> Result<(), String> {
+    if !caller_has_permission(Permission::ReviewProjects) {
+        return Err("Only reviewers can review verification requests".to_string());
+    }
+
+    let caller = caller();
+    let timestamp = ic_cdk::api::time();
+
+    STATE.with(|state| {
+        let mut state = state.borrow_mut();
+        let request = state.verification_requests.get_mut(&project_id)
+            .ok_or("Verification request not found")?;
+
+        if request.status != VerificationStatus::Pending {
+            return Err("Verification request has already been reviewed".to_string());
+        }
+
+        request.status = if approve { VerificationStatus::Approved } else { VerificationStatus::Denied };
+        request.reviewed_by = Some(caller);
+        request.reviewed_at = Some(timestamp);
+        request.reason = reason.clone();
+
+        let project = state.projects.get_mut(&project_id)
+            .ok_or("Project not found")?;
+        if approve {
+            project.verified = true;
+            project.verified_by = Some(caller);
+            project.verified_at = Some(timestamp);
+        }
+
+        let owner = project.owner;
+        let message = if approve {
+            format!("Project \"{}\" has been verified", project.name)
+        } else {
+            format!(
+                "Verification of project \"{}\" was denied: {}",
+                project.name,
+                reason.unwrap_or_else(|| "no reason given".to_string())
+            )
+        };
+        notify(&mut state, owner, Some(project_id), message, timestamp);
+        Ok(())
+    })
+}
+
+#[update(guard = "caller_is_not_blocked")]
+fn approve_verification(project_id: String) -> Result<(), String> {
+    review_verification(project_id, true, None)
+}
+
+#[update(guard = "caller_is_not_blocked")]
+fn deny_verification(project_id: String, reason: String) -> Result<(), String> {
+    review_verification(project_id, false, Some(reason))
+}
+
+#[update(guard = "caller_is_not_blocked")]
+fn revoke_verification(project_id: String) -> Result<(), String> {
+    if !caller_has_permission(Permission::ReviewProjects) {
+        return Err("Only reviewers can revoke verification".to_string());
+    }
+
+    STATE.with(|state| {
+        let mut state = state.borrow_mut();
+        let project = state.projects.get_mut(&project_id)
+            .ok_or("Project not found")?;
+        if !project.verified {
+            return Err("Project is not verified".to_string());
+        }
+        project.verified = false;
+        project.verified_by = None;
+        project.verified_at = None;
+        Ok(())
+    })
+}
+
+#[query]
+fn get_verification_request(project_id: String) -> Result<Option<VerificationRequest>, String> {
+    let caller = caller();
+    let can_override = caller_has_permission(Permission::ViewPrivate);
+
+    STATE.with(|state| {
+        let state = state.borrow();
+        let project = state.projects.get(&project_id)
+            .ok_or("Project not found")?;
+        if project.owner != caller && !can_override {
+            return Err("Only project owner or admins can view verification requests".to_string());
+        }
+        Ok(state.verification_requests.get(&project_id).cloned())
+    })
+}
+
+#[query]
+fn get_pending_verifications() -> Result<Vec<VerificationRequest>, String> {
+    if !caller_has_permission(Permission::ReviewProjects) {
+        return Err("Only reviewers can view verification requests".to_string());
+    }
+
+    STATE.with(|state| {
+        let mut requests: Vec<VerificationRequest> = state.borrow()
+            .verification_requests
+            .values()
+            .filter(|r| r.status == VerificationStatus::Pending)
+            .cloned()
+            .collect();
+        // Oldest first
+        requests.sort_by_key(|r| r.submitted_at);
+        Ok(requests)
+    })
+}
+
+#[query]
+fn get_verified_projects(page: Option<u32>, limit: Option<u32>, offset: Option<u64>) -> Result<ProjectsResponse, String> {
+    STATE.with(|state| {
+        let state = state.borrow();
+        let projects: Vec<&Project> = state.projects
+            .values()
+            .filter(|p| p.verified && is_listed(p))
+            .collect();
+        
+        projects_page(projects, page, limit, offset)
+    })
+}
+
+// Following
+#[update(guard = "caller_is_not_blocked")]
+fn follow_project(project_id: String) -> Result<(), String> {
+    let caller = caller();
+    if caller == Principal::anonymous() {
+        return Err("Anonymous principals cannot follow projects".to_string());
+    }
+    rate_limit::check(caller, RateLimitedMethod::Follow, ic_cdk::api::time())?;
+
+    let result = STATE.with(|state| {
+        let mut state = state.borrow_mut();
+
+        if !state.projects.contains_key(&project_id) {
+            return Err("Project not found".to_string());
+        }
+
+        let newly_followed = state.follower_index
+            .entry(project_id.clone())
+            .or_default()
+            .insert(caller);
+        if !newly_followed {
+            return Err("Already following project".to_string());
+        }
+
+        state.followed_index
+            .entry(caller)
+            .or_default()
+            .push(project_id.clone());
+
+        if let Some(project) = state.projects.get_mut(&project_id) {
+            project.follower_count += 1;
+        }
+
+        Ok(())
+    });
+    if result.is_ok() {
+        rate_limit::record(caller, RateLimitedMethod::Follow, ic_cdk::api::time());
+    }
+    result
+}
+
+#[update(guard = "caller_is_not_blocked")]
+fn unfollow_project(project_id: String) -> Result<(), String> {
+    let caller = caller();
+
+    STATE.with(|state| {
+        let mut state = state.borrow_mut();
+
+        let removed = state.follower_index
+            .get_mut(&project_id)
+            .map(|followers| followers.remove(&caller))
+            .unwrap_or(false);
+        if !removed {
+            return Err("Not following project".to_string());
+        }
+
+        if let Some(followed) = state.followed_index.get_mut(&caller) {
+            followed.retain(|id| id != &project_id);
+        }
+
+        if let Some(project) = state.projects.get_mut(&project_id) {
+            project.follower_count = project.follower_count.saturating_sub(1);
+        }
+
+        Ok(())
+    })
+}
+
+#[query]
+fn get_followed_projects(user: Principal, page: Option<u32>, limit: Option<u32>, offset: Option<u64>) -> Result<ProjectsResponse, String> {
+    STATE.with(|state| {
+        let state = state.borrow();
+        let projects: Vec<&Project> = state.followed_index
+            .get(&user)
+            .map(|ids| {
+                ids.iter()
+                    .filter_map(|id| state.projects.get(id))
+                    .collect()
+            })
+            .unwrap_or_default();
+        
+        projects_page(projects, page, limit, offset)
+    })
+}
+
+#[query]
+fn is_following(project_id: String, user: Principal) -> bool {
+    STATE.with(|state| {
+        state.borrow()
+            .follower_index
+            .get(&project_id)
+            .map(|followers| followers.contains(&user))
+            .unwrap_or(false)
+    })
+}
+
+// Stale Projects
+fn check_stale_projects() {
+    let now = ic_cdk::api::time();
+    let config = config();
+    let stale_after = config.stale_after_days.saturating_mul(NANOS_PER_DAY);
+    let suspension_grace = config.stale_suspension_grace_days.saturating_mul(NANOS_PER_DAY);
+
+    STATE.with(|state| {
+        let mut state = state.borrow_mut();
+        let mut notices: Vec<(Principal, String, String)> = Vec::new();
+        let mut suspended: Vec<String> = Vec::new();
+
+        // Only the projects that change are borrowed mutably, so the rest stay certified
+        let due: Vec<String> = state.projects
+            .values()
+            .filter(|p| p.status == ProjectStatus::Approved)
+            .filter(|p| match p.stale_since {
+                None => now.saturating_sub(p.last_activity_at) > stale_after,
+                Some(since) => now.saturating_sub(since) > suspension_grace,
+            })
+            .map(|p| p.id.clone())
+            .collect();
+
+        for project_id in due {
+            let Some(project) = state.projects.get_mut(&project_id) else {
+                continue;
+            };
+            match project.stale_since {
+                None if now.saturating_sub(project.last_activity_at) > stale_after => {
+                    project.stale_since = Some(now);
+                    notices.push((
+                        project.owner,
+                        project.id.clone(),
+                        format!("Project \"{}\" has had no activity for {} days and will be suspended in {} days unless updated",
+                            project.name, config.stale_after_days, config.stale_suspension_grace_days),
+                    ));
+                }
+                Some(since) if now.saturating_sub(since) > suspension_grace => {
+                    project.status = ProjectStatus::Suspended;
+                    suspended.push(project.id.clone());
+                    notices.push((
+                        project.owner,
+                        project.id.clone(),
+                        format!("Project \"{}\" has been suspended due to inactivity", project.name),
+                    ));
+                }
+                _ => {}
+            }
+        }
+
+        for (owner, project_id, message) in notices {
+            notify(&mut state, owner, Some(project_id), message, now);
+        }
+        for project_id in suspended {
+            on_status_changed(&mut state, &project_id, now);
+        }
+    });
+}
+
+#[query]
+fn get_stale_projects(page: Option<u32>, limit: Option<u32>, offset: Option<u64>) -> Result<ProjectsResponse, String> {
+    if !caller_has_permission(Permission::ModerateContent) {
+        return Err("Only moderators can view stale projects".to_string());
+    }
+
+    STATE.with(|state| {
+        let state = state.borrow();
+        let mut projects: Vec<&Project> = state.projects
+            .values()
+            .filter(|p| p.stale_since.is_some())
+            .collect();
+        
+        // Longest inactive first
+        projects.sort_by_key(|p| p.last_activity_at);
+        
+        projects_page(projects, page, limit, offset)
+    })
+}
+
+// Review Queue
+fn active_review_claim<'a>(state: &'a State, project_id: &str, timestamp: u64) -> Option<&'a ReviewClaim> {
+    state.review_claims
+        .get(project_id)
+        .filter(|claim| {
+            claim.assigned_by.is_some()
+                || timestamp.saturating_sub(claim.claimed_at) <= REVIEW_CLAIM_EXPIRY_NANOS
+        })
+}
+
+fn audit(state: &mut State, actor: Principal, event: AuditEvent, timestamp: u64) {
+    let id = state.audit_log.len() as u64;
+    state.audit_log.push(AuditEntry { id, actor, event, timestamp });
+}
+
+fn review_queue_item(state: &State, project: &Project, timestamp: u64) -> ReviewQueueItem {
+    ReviewQueueItem {
+        project: PublicProject::from(project),
+        submitted_at: project.status_updated_at,
+        claim: active_review_claim(state, &project.id, timestamp).cloned(),
+    }
+}
+
+#[query]
+fn get_review_queue(page: Option<u32>, limit: Option<u32>) -> Result<ReviewQueueResponse, String> {
+    if !caller_has_permission(Permission::ReviewProjects) {
+        return Err("Only reviewers can view the review queue".to_string());
+    }
+
+    let timestamp = ic_cdk::api::time();
+
+    STATE.with(|state| {
+        let state = state.borrow();
+        let mut projects: Vec<&Project> = state.projects
+            .values()
+            .filter(|p| p.status == ProjectStatus::PendingReview)
+            .collect();
+
+        // Longest waiting first
+        projects.sort_by_key(|p| p.status_updated_at);
+
+        let paged = paginate(projects, page, limit)?;
+
+        Ok(ReviewQueueResponse {
+            items: paged.items.iter().map(|p| review_queue_item(&state, p, timestamp)).collect(),
+            total: paged.total,
+            page: paged.page,
+            pages: paged.pages,
+            has_next: paged.has_next,
+            has_prev: paged.has_prev,
+        })
+    })
+}
+
+// CSV with one row per pending project, longest waiting first, for offline triage
+#[query]
+fn export_review_queue() -> Result<String, String> {
+    if !caller_has_permission(Permission::ReviewProjects) {
+        return Err("Only reviewers can export the review queue".to_string());
+    }
+
+    let timestamp = ic_cdk::api::time();
+
+    STATE.with(|state| {
+        let state = state.borrow();
+        let mut projects: Vec<&Project> = state.projects
+            .values()
+            .filter(|p| p.status == ProjectStatus::PendingReview)
+            .collect();
+        projects.sort_by_key(|p| p.status_updated_at);
+
+        let mut csv = String::from(
+            "id,slug,name,category,owner,geohash,sensors_required,submitted_at,created_at,report_count,claimed_by\n"
+        );
+        for project in projects {
+            let report_count = state.project_reports.get(&project.id).map_or(0, |r| r.len());
+            let claimed_by = active_review_claim(&state, &project.id, timestamp)
+                .map(|claim| claim.reviewer.to_text())
+                .unwrap_or_default();
+            let row = [
+                csv_field(&project.id),
+                csv_field(&project.slug),
+                csv_field(&project.name),
+                format!("{:?}", project.category),
+                project.owner.to_text(),
+                csv_field(&project.location.geohash),
+                project.sensors_required.to_string(),
+                project.status_updated_at.to_string(),
+                project.created_at.to_string(),
+                report_count.to_string(),
+                claimed_by,
+            ];
+            csv.push_str(&row.join(","));
+            csv.push('\n');
+        }
+        Ok(csv)
+    })
+}
+
+#[update(guard = "caller_is_not_blocked")]
+fn claim_review(project_id: String) -> Result<(), String> {
+    if !caller_has_permission(Permission::ReviewProjects) {
+        return Err("Only reviewers can claim reviews".to_string());
+    }
+
+    let caller = caller();
+    let timestamp = ic_cdk::api::time();
+
+    STATE.with(|state| {
+        let mut state = state.borrow_mut();
+        let project = state.projects.get(&project_id)
+            .ok_or("Project not found")?;
+        if project.status != ProjectStatus::PendingReview {
+            return Err("Project is not pending review".to_string());
+        }
+        if let Some(claim) = active_review_claim(&state, &project_id, timestamp) {
+            if claim.reviewer != caller {
+                return Err(format!("Project is already claimed by {}", claim.reviewer));
+            }
+        }
+
+        state.review_claims.insert(project_id, ReviewClaim {
+            reviewer: caller,
+            claimed_at: timestamp,
+            assigned_by: None,
+        });
+        Ok(())
+    })
+}
+
+#[update(guard = "caller_is_not_blocked")]
+fn release_review(project_id: String) -> Result<(), String> {
+    let caller = caller();
+    let is_super_admin = caller_has_permission(Permission::ManageAdmins);
+
+    STATE.with(|state| {
+        let mut state = state.borrow_mut();
+        let claim = state.review_claims.get(&project_id)
+            .ok_or("Project has not been claimed")?;
+        if claim.reviewer != caller && !is_super_admin {
+            return Err("Only the claiming reviewer can release a review".to_string());
+        }
+        if let Some(claim) = state.review_claims.remove(&project_id) {
+            if claim.assigned_by.is_some() {
+                let event = AuditEvent::ReviewerUnassigned { project_id, reviewer: claim.reviewer };
+                audit(&mut state, caller, event, ic_cdk::api::time());
+            }
+        }
+        Ok(())
+    })
+}
+
+#[update(guard = "caller_is_not_blocked")]
+fn assign_reviewer(project_id: String, admin_principal: Principal) -> Result<(), String> {
+    if !caller_has_permission(Permission::ManageAdmins) {
+        return Err("Only super admin can assign reviewers".to_string());
+    }
+
+    let caller = caller();
+    let timestamp = ic_cdk::api::time();
+
+    STATE.with(|state| {
+        let mut state = state.borrow_mut();
+        let can_review = state.admins
+            .get(&admin_principal)
+            .is_some_and(|role| role_permits(*role, Permission::ReviewProjects));
+        if !can_review {
+            return Err("Assignee is not a reviewer".to_string());
+        }
+
+        let project = state.projects.get(&project_id)
+            .ok_or("Project not found")?;
+        if project.status != ProjectStatus::PendingReview {
+            return Err("Project is not pending review".to_string());
+        }
+
+        let previous = active_review_claim(&state, &project_id, timestamp).map(|claim| claim.reviewer);
+        if previous == Some(admin_principal) {
+            return Err("Project is already assigned to this reviewer".to_string());
+        }
+
+        state.review_claims.insert(project_id.clone(), ReviewClaim {
+            reviewer: admin_principal,
+            claimed_at: timestamp,
+            assigned_by: Some(caller),
+        });
+        let event = AuditEvent::ReviewerAssigned { project_id, reviewer: admin_principal, previous };
+        audit(&mut state, caller, event, timestamp);
+        Ok(())
+    })
+}
+
+#[query]
+fn get_my_assigned_reviews() -> Result<Vec<ReviewQueueItem>, String> {
+    if !caller_has_permission(Permission::ReviewProjects) {
+        return Err("Only reviewers can view assigned reviews".to_string());
+    }
+
+    let caller = caller();
+    let timestamp = ic_cdk::api::time();
+
+    STATE.with(|state| {
+        let state = state.borrow();
+        let mut items: Vec<ReviewQueueItem> = state.review_claims
+            .iter()
+            .filter(|(_, claim)| claim.reviewer == caller && claim.assigned_by.is_some())
+            .filter_map(|(id, _)| state.projects.get(id))
+            .map(|p| review_queue_item(&state, p, timestamp))
+            .collect();
+        items.sort_by_key(|item| item.submitted_at);
+        Ok(items)
+    })
+}
+
+#[query]
+fn get_reviewer_workload() -> Result<Vec<(Principal, u32)>, String> {
+    if !caller_has_permission(Permission::ReviewProjects) {
+        return Err("Only reviewers can view workloads".to_string());
+    }
+
+    let timestamp = ic_cdk::api::time();
+
+    STATE.with(|state| {
+        let state = state.borrow();
+        let mut workload: HashMap<Principal, u32> = HashMap::new();
+        for project_id in state.review_claims.keys() {
+            if let Some(claim) = active_review_claim(&state, project_id, timestamp) {
+                *workload.entry(claim.reviewer).or_insert(0) += 1;
+            }
+        }
+        Ok(workload.into_iter().collect())
+    })
+}
+
+#[query]
+fn get_reviews_claimed_by(reviewer: Principal) -> Result<Vec<ReviewQueueItem>, String> {
+    if !caller_has_permission(Permission::ReviewProjects) {
+        return Err("Only reviewers can view claimed reviews".to_string());
+    }
+
+    let timestamp = ic_cdk::api::time();
+
+    STATE.with(|state| {
+        let state = state.borrow();
+        let mut items: Vec<ReviewQueueItem> = state.review_claims
+            .iter()
+            .filter(|(_, claim)| claim.reviewer == reviewer)
+            .filter_map(|(id, _)| state.projects.get(id))
+            .map(|p| review_queue_item(&state, p, timestamp))
+            .filter(|item| item.claim.is_some())
+            .collect();
+        items.sort_by_key(|item| item.submitted_at);
+        Ok(items)
+    })
+}
+
+#[query]
+fn get_rejection_reversal_approvals(project_id: String) -> Result<Vec<Principal>, String> {
+    if !caller_has_permission(Permission::ReviewProjects) {
+        return Err("Only reviewers can view reversal approvals".to_string());
+    }
+
+    STATE.with(|state| {
+        Ok(state.borrow()
+            .rejection_reversals
+            .get(&project_id)
+            .map(|approvals| approvals.iter().copied().collect())
+            .unwrap_or_default())
+    })
+}
+
+// Audit Log
+#[query]
+fn get_audit_log(page: Option<u32>, limit: Option<u32>) -> Result<AuditLogResponse, String> {
+    if !caller_has_permission(Permission::ManageAdmins) {
+        return Err("Only super admin can view the audit log".to_string());
+    }
+
+    STATE.with(|state| {
+        let state = state.borrow();
+        // Newest first
+        let entries: Vec<&AuditEntry> = state.audit_log.iter().rev().collect();
+        let paged = paginate(entries, page, limit)?.map(AuditEntry::clone);
+
+        Ok(AuditLogResponse {
+            entries: paged.items,
+            total: paged.total,
+            page: paged.page,
+            pages: paged.pages,
+            has_next: paged.has_next,
+            has_prev: paged.has_prev,
+        })
+    })
+}
+
+// Admin Notes
+#[update(guard = "caller_is_not_blocked")]
+fn add_admin_note(project_id: String, note: String) -> Result<(), String> {
+    if caller_role().is_none() {
+        return Err("Only admins can add notes".to_string());
+    }
+    if note.trim().is_empty() {
+        return Err("Note cannot be empty".to_string());
+    }
+    if note.len() > MAX_ADMIN_NOTE_LENGTH {
+        return Err(format!("Note cannot exceed {} characters", MAX_ADMIN_NOTE_LENGTH));
+    }
+
+    let caller = caller();
+
+    STATE.with(|state| {
+        let mut state = state.borrow_mut();
+        if !state.projects.contains_key(&project_id) {
+            return Err("Project not found".to_string());
+        }
+        state.admin_notes
+            .entry(project_id)
+            .or_default()
+            .push(AdminNote {
+                author: caller,
+                note,
+                created_at: ic_cdk::api::time(),
+            });
+        Ok(())
+    })
+}
+
+#[query]
+fn get_admin_notes(project_id: String) -> Result<Vec<AdminNote>, String> {
+    if caller_role().is_none() {
+        return Err("Only admins can view notes".to_string());
+    }
+
+    STATE.with(|state| {
+        Ok(state.borrow()
+            .admin_notes
+            .get(&project_id)
+            .cloned()
+            .unwrap_or_default())
+    })
+}
+
+// Governance
+// Checks the action against current state without applying it and returns a
+// human-readable summary, as expected of a proposal validator
+fn describe_governance_action(state: &State, action: &GovernanceAction, now: u64) -> Result<String, String> {
+    match action {
+        GovernanceAction::FeatureProject { project_id, starts_at, ends_at } => {
+            let project = featurable_project(state, project_id, starts_at.unwrap_or(now).max(now), *ends_at)?;
+            Ok(format!(
+                "Feature project \"{}\" ({}) from {} {}",
+                project.name,
+                project_id,
+                starts_at.map_or("now".to_string(), rfc3339),
+                ends_at.map_or("indefinitely".to_string(), |ends_at| format!("until {}", rfc3339(ends_at))),
+            ))
+        }
+        GovernanceAction::UnfeatureProject { project_id } => {
+            let project = state.projects.get(project_id).ok_or("Project not found")?;
+            if !project.featured && !state.feature_windows.contains_key(project_id) {
                 return Err("Project is not featured".to_string());
             }
-            project.featured_at
+            Ok(format!("Stop featuring project \"{}\" ({})", project.name, project_id))
+        }
+        GovernanceAction::SetVoteCooldown { seconds } => {
+            Ok(format!("Set the vote cooldown to {} seconds", seconds))
+        }
+        GovernanceAction::SetFeaturedSlots { slots } => {
+            if *slots == 0 {
+                return Err("featured_slots must be at least 1".to_string());
+            }
+            Ok(format!("Set the number of featured slots to {}", slots))
+        }
+        GovernanceAction::SetVotingEnabled { enabled } => {
+            Ok(format!("{} voting", if *enabled { "Enable" } else { "Disable" }))
+        }
+    }
+}
+
+fn update_config_field(f: impl FnOnce(&mut Config)) -> Result<(), String> {
+    let mut new_config = config();
+    f(&mut new_config);
+    new_config.validate()?;
+    CONFIG.with(|config| *config.borrow_mut() = new_config);
+    Ok(())
+}
+
+// Validator for the governance canister's proposal type; anyone may call it
+#[query]
+fn validate_execute_proposal(action: GovernanceAction) -> Result<String, String> {
+    STATE.with(|state| describe_governance_action(&state.borrow(), &action, ic_cdk::api::time()))
+}
+
+#[update(guard = "caller_is_not_blocked")]
+fn execute_proposal(action: GovernanceAction) -> Result<(), String> {
+    let caller = caller();
+    if config().governance_canister != Some(caller) {
+        return Err("Only the governance canister can execute proposals".to_string());
+    }
+    let timestamp = ic_cdk::api::time();
+
+    STATE.with(|state| {
+        let mut state = state.borrow_mut();
+        describe_governance_action(&state, &action, timestamp)?;
+
+        match action.clone() {
+            GovernanceAction::FeatureProject { project_id, starts_at, ends_at } => {
+                schedule_feature(&mut state, caller, project_id, starts_at, ends_at, timestamp)?;
+            }
+            GovernanceAction::UnfeatureProject { project_id } => {
+                remove_feature(&mut state, &project_id)?;
+            }
+            GovernanceAction::SetVoteCooldown { seconds } => {
+                update_config_field(|config| config.vote_cooldown_seconds = seconds)?;
+            }
+            GovernanceAction::SetFeaturedSlots { slots } => {
+                update_config_field(|config| config.featured_slots = slots)?;
+            }
+            GovernanceAction::SetVotingEnabled { enabled } => {
+                if enabled {
+                    state.disabled_subsystems.remove(&Subsystem::Voting);
+                } else {
+                    state.disabled_subsystems.insert(Subsystem::Voting);
+                }
+            }
+        }
+        audit(&mut state, caller, AuditEvent::GovernanceExecuted { action }, timestamp);
+        Ok(())
+    })
+}
+
+// Circuit Breakers
+#[update(guard = "caller_is_not_blocked")]
+fn set_subsystem_enabled(subsystem: Subsystem, enabled: bool) -> Result<(), String> {
+    if !caller_has_permission(Permission::ManageAdmins) {
+        return Err("Only super admin can toggle subsystems".to_string());
+    }
+
+    STATE.with(|state| {
+        let mut state = state.borrow_mut();
+        if enabled {
+            state.disabled_subsystems.remove(&subsystem);
+        } else {
+            state.disabled_subsystems.insert(subsystem);
+        }
+    });
+    Ok(())
+}
+
+#[query]
+fn get_disabled_subsystems() -> Vec<Subsystem> {
+    STATE.with(|state| state.borrow().disabled_subsystems.iter().copied().collect())
+}
+
+// Analytics Push
+// Metrics are posted every ANALYTICS_PUSH_INTERVAL_SECS to analytics_endpoint while enabled
+#[update(guard = "caller_is_not_blocked")]
+fn set_analytics_push_enabled(enabled: bool) -> Result<(), String> {
+    if !caller_has_permission(Permission::ManageAdmins) {
+        return Err("Only super admin can toggle the analytics push".to_string());
+    }
+    STATE.with(|state| state.borrow_mut().analytics_push_enabled = enabled);
+    Ok(())
+}
+
+#[query]
+fn get_analytics_push_status() -> Result<AnalyticsPushStatus, String> {
+    if !caller_has_permission(Permission::ManageAdmins) {
+        return Err("Only super admin can view the analytics push".to_string());
+    }
+    STATE.with(|state| {
+        let state = state.borrow();
+        Ok(AnalyticsPushStatus {
+            enabled: state.analytics_push_enabled,
+            endpoint: config().analytics_endpoint,
+            last_pushed_at: state.last_analytics_push.map(|(at, _)| at),
+            last_delivery: state.last_analytics_push.and_then(|(_, id)| webhooks::get_record(id)),
+        })
+    })
+}
+
+// Blocklist
+#[update(guard = "caller_is_not_blocked")]
+fn block_principal(principal: Principal, reason: String) -> Result<(), String> {
+    if !caller_has_permission(Permission::ModerateContent) {
+        return Err("Only moderators can block principals".to_string());
+    }
+
+    let caller = caller();
+
+    STATE.with(|state| {
+        let mut state = state.borrow_mut();
+        if state.admins.contains_key(&principal) {
+            return Err("Admins cannot be blocked".to_string());
+        }
+        state.blocklist.insert(principal, BlockEntry {
+            reason,
+            blocked_by: caller,
+            blocked_at: ic_cdk::api::time(),
+        });
+        Ok(())
+    })
+}
+
+#[update(guard = "caller_is_not_blocked")]
+fn unblock_principal(principal: Principal) -> Result<(), String> {
+    if !caller_has_permission(Permission::ModerateContent) {
+        return Err("Only moderators can unblock principals".to_string());
+    }
+
+    STATE.with(|state| {
+        state.borrow_mut()
+            .blocklist
+            .remove(&principal)
+            .map(|_| ())
+            .ok_or("Principal is not blocked".to_string())
+    })
+}
+
+#[query]
+fn get_blocked_principals() -> Result<Vec<(Principal, BlockEntry)>, String> {
+    if !caller_has_permission(Permission::ModerateContent) {
+        return Err("Only moderators can view the blocklist".to_string());
+    }
+
+    STATE.with(|state| {
+        Ok(state.borrow()
+            .blocklist
+            .iter()
+            .map(|(principal, entry)| (*principal, entry.clone()))
+            .collect())
+    })
+}
+
+#[query]
+fn is_blocked(principal: Principal) -> bool {
+    STATE.with(|state| state.borrow().blocklist.contains_key(&principal))
+}
+
+// Word Filter
+#[update(guard = "caller_is_not_blocked")]
+fn set_filtered_term(term: String, action: Option<FilterAction>) -> Result<(), String> {
+    if !caller_has_permission(Permission::ModerateContent) {
+        return Err("Only moderators can manage the word filter".to_string());
+    }
+    word_filter::set_denied(&term, action)
+}
+
+#[update(guard = "caller_is_not_blocked")]
+fn set_allowed_word(word: String, allowed: bool) -> Result<(), String> {
+    if !caller_has_permission(Permission::ModerateContent) {
+        return Err("Only moderators can manage the word filter".to_string());
+    }
+    word_filter::set_allowed(&word, allowed)
+}
+
+#[query]
+fn get_word_filter() -> Result<WordFilter, String> {
+    if !caller_has_permission(Permission::ModerateContent) {
+        return Err("Only moderators can view the word filter".to_string());
+    }
+    Ok(word_filter::get())
+}
+
+// Redaction; an empty replacement removes optional fields and gallery images
+#[update(guard = "caller_is_not_blocked")]
+fn redact_project_field(project_id: String, field: RedactableField, replacement: String) -> Result<(), String> {
+    if !caller_has_permission(Permission::ModerateContent) {
+        return Err("Only moderators can redact content".to_string());
+    }
+
+    if matches!(field, RedactableField::BackgroundImage | RedactableField::GalleryImage(_)) {
+        image_store::validate_reference(&replacement)?;
+    }
+
+    let caller = caller();
+    let timestamp = ic_cdk::api::time();
+
+    STATE.with(|state| {
+        let mut state = state.borrow_mut();
+        let project = state.projects.get_mut(&project_id)
+            .ok_or("Project not found")?;
+        let previous_terms = project_search_terms(project);
+        let mut replaced_image = None;
+
+        match &field {
+            RedactableField::Name => {
+                if replacement.trim().is_empty() {
+                    return Err("Name cannot be empty".to_string());
+                }
+                project.name = replacement;
+            }
+            RedactableField::Description => project.description = replacement,
+            RedactableField::BackgroundImage => {
+                replaced_image = Some(std::mem::replace(&mut project.images.background, replacement));
+            }
+            RedactableField::GalleryImage(index) => {
+                let index = *index as usize;
+                if index >= project.images.gallery.len() {
+                    return Err("Gallery image not found".to_string());
+                }
+                if replacement.is_empty() {
+                    replaced_image = Some(project.images.gallery.remove(index));
+                } else {
+                    replaced_image = Some(std::mem::replace(&mut project.images.gallery[index], replacement));
+                }
+            }
+            RedactableField::ProjectDiscord => project.project_discord = Some(replacement).filter(|r| !r.is_empty()),
+            RedactableField::PrivateDiscord => project.private_discord = replacement,
+            RedactableField::Video => project.video = Some(replacement).filter(|r| !r.is_empty()),
+        }
+        project.images.pins = retained_pins(&project.images, &project.images);
+        // A redacted image stored here would otherwise still be served by hash
+        if let Some(hash) = replaced_image.as_deref().and_then(image_store::hash_of) {
+            if !stored_image_in_use(&project.images, hash) {
+                let _ = image_store::delete(hash, &project_id);
+            }
+        }
+
+        let owner = project.owner;
+        let message = format!("A moderator redacted the {:?} of project \"{}\"", field, project.name);
+        if matches!(field, RedactableField::Name | RedactableField::Description) {
+            update_search_index(&mut state, &project_id, &previous_terms);
+        }
+        notify(&mut state, owner, Some(project_id.clone()), message, timestamp);
+        audit(&mut state, caller, AuditEvent::FieldRedacted { project_id, field }, timestamp);
+        Ok(())
+    })
+}
+
+// Appeals
+#[update(guard = "caller_is_not_blocked")]
+fn file_appeal(project_id: String, statement: String) -> Result<u64, String> {
+    let caller = caller();
+    rate_limit::check(caller, RateLimitedMethod::Appeal, ic_cdk::api::time())?;
+    if statement.trim().is_empty() {
+        return Err("A statement is required".to_string());
+    }
+    if statement.len() > MAX_APPEAL_STATEMENT_LENGTH {
+        return Err(format!("Statement cannot exceed {} characters", MAX_APPEAL_STATEMENT_LENGTH));
+    }
+
+    let timestamp = ic_cdk::api::time();
+
+    let result = STATE.with(|state| {
+        let mut state = state.borrow_mut();
+        let project = state.projects.get(&project_id)
+            .ok_or("Project not found")?;
+        if project.owner != caller {
+            return Err("Only project owner can appeal".to_string());
+        }
+        if !matches!(project.status, ProjectStatus::Rejected | ProjectStatus::Suspended) {
+            return Err("Only rejected or suspended projects can be appealed".to_string());
+        }
+
+        // One appeal per decision
+        let decided_at = project.status_updated_at;
+        let appealed_status = project.status.clone();
+        if state.appeals.values().any(|a| a.project_id == project_id && a.decided_at == decided_at) {
+            return Err("This decision has already been appealed".to_string());
+        }
+
+        let id = state.next_appeal_id;
+        state.next_appeal_id += 1;
+        state.appeals.insert(id, Appeal {
+            id,
+            project_id,
+            owner: caller,
+            appealed_status,
+            decided_at,
+            statement,
+            filed_at: timestamp,
+            resolution: None,
+        });
+        Ok(id)
+    });
+    if result.is_ok() {
+        rate_limit::record(caller, RateLimitedMethod::Appeal, ic_cdk::api::time());
+    }
+    result
+}
+
+// Overturning a rejection sends the project back to review rather than approving it
+// outright, so the two-person rule for reversing rejections still applies
+#[update(guard = "caller_is_not_blocked")]
+fn resolve_appeal(appeal_id: u64, outcome: AppealOutcome, reason: String) -> Result<(), String> {
+    if !caller_has_permission(Permission::ReviewProjects) {
+        return Err("Only reviewers can resolve appeals".to_string());
+    }
+    if reason.trim().is_empty() {
+        return Err("A reason is required".to_string());
+    }
+
+    let caller = caller();
+    let can_suspend = caller_has_permission(Permission::SuspendProjects);
+    let timestamp = ic_cdk::api::time();
+
+    STATE.with(|state| {
+        let mut state = state.borrow_mut();
+        let appeal = state.appeals.get(&appeal_id)
+            .ok_or("Appeal not found")?
+            .clone();
+        if appeal.resolution.is_some() {
+            return Err("Appeal has already been resolved".to_string());
+        }
+
+        if outcome == AppealOutcome::Overturned {
+            if appeal.appealed_status == ProjectStatus::Suspended && !can_suspend {
+                return Err("Only super admin can lift a suspension".to_string());
+            }
+            let project = state.projects.get_mut(&appeal.project_id)
+                .ok_or("Project not found")?;
+            if project.status_updated_at != appeal.decided_at {
+                return Err("Project status has changed since the appeal was filed".to_string());
+            }
+            project.status = match appeal.appealed_status {
+                ProjectStatus::Suspended => ProjectStatus::Approved,
+                _ => ProjectStatus::PendingReview,
+            };
+            on_status_changed(&mut state, &appeal.project_id, timestamp);
+        }
+
+        let message = format!("Your appeal was {:?}: {}", outcome, reason);
+        if let Some(appeal) = state.appeals.get_mut(&appeal_id) {
+            appeal.resolution = Some(AppealResolution {
+                outcome,
+                reason,
+                resolved_by: caller,
+                resolved_at: timestamp,
+            });
+        }
+        notify(&mut state, appeal.owner, Some(appeal.project_id), message, timestamp);
+        Ok(())
+    })
+}
+
+#[query]
+fn get_appeals_queue(page: Option<u32>, limit: Option<u32>) -> Result<AppealsResponse, String> {
+    if !caller_has_permission(Permission::ReviewProjects) {
+        return Err("Only reviewers can view the appeals queue".to_string());
+    }
+
+    STATE.with(|state| {
+        let state = state.borrow();
+        // Oldest first
+        let appeals: Vec<&Appeal> = state.appeals
+            .values()
+            .filter(|appeal| appeal.resolution.is_none())
+            .collect();
+        let paged = paginate(appeals, page, limit)?.map(Appeal::clone);
+
+        Ok(AppealsResponse {
+            appeals: paged.items,
+            total: paged.total,
+            page: paged.page,
+            pages: paged.pages,
+            has_next: paged.has_next,
+            has_prev: paged.has_prev,
+        })
+    })
+}
+
+#[query]
+fn get_project_appeals(project_id: String) -> Result<Vec<Appeal>, String> {
+    let caller = caller();
+    let is_reviewer = caller_has_permission(Permission::ReviewProjects);
+
+    STATE.with(|state| {
+        let state = state.borrow();
+        let project = state.projects.get(&project_id)
+            .ok_or("Project not found")?;
+        if project.owner != caller && !is_reviewer {
+            return Err("Only project owner or reviewers can view appeals".to_string());
+        }
+        Ok(state.appeals
+            .values()
+            .filter(|appeal| appeal.project_id == project_id)
+            .cloned()
+            .collect())
+    })
+}
+
+// Reporting
+#[update(guard = "caller_is_not_blocked")]
+fn report_project(project_id: String, reason: String) -> Result<(), String> {
+    let caller = caller();
+    if caller == Principal::anonymous() {
+        return Err("Anonymous principals cannot report projects".to_string());
+    }
+    ensure_enabled(Subsystem::Reporting)?;
+    rate_limit::check(caller, RateLimitedMethod::Report, ic_cdk::api::time())?;
+    if reason.trim().is_empty() {
+        return Err("A reason is required".to_string());
+    }
+    if reason.len() > MAX_REPORT_REASON_LENGTH {
+        return Err(format!("Reason cannot exceed {} characters", MAX_REPORT_REASON_LENGTH));
+    }
+
+    let result = STATE.with(|state| {
+        let mut state = state.borrow_mut();
+
+        if !state.projects.contains_key(&project_id) {
+            return Err("Project not found".to_string());
+        }
+
+        let timestamp = ic_cdk::api::time();
+        let report = Report {
+            reporter: caller,
+            reason,
+            timestamp,
+        };
+
+        let reports = state.project_reports
+            .entry(project_id.clone())
+            .or_insert_with(HashMap::new);
+        if reports.contains_key(&caller) {
+            return Err("You have already reported this project".to_string());
+        }
+        reports.insert(caller, report);
+        let report_count = reports.len();
+
+        // Send approved projects back to review once enough distinct principals object
+        if report_count >= config().report_escalation_threshold as usize {
+            if let Some(project) = state.projects.get_mut(&project_id) {
+                if project.status == ProjectStatus::Approved {
+                    project.status = ProjectStatus::PendingReview;
+                    on_status_changed(&mut state, &project_id, timestamp);
+                }
+            }
+        }
+
+        Ok(())
+    });
+    if result.is_ok() {
+        rate_limit::record(caller, RateLimitedMethod::Report, ic_cdk::api::time());
+    }
+    result
+}
+
+#[update(guard = "caller_is_not_blocked")]
+fn dismiss_reports(project_id: String) -> Result<(), String> {
+    if !caller_has_permission(Permission::ModerateContent) {
+        return Err("Only moderators can dismiss reports".to_string());
+    }
+
+    STATE.with(|state| {
+        state.borrow_mut()
+            .project_reports
+            .remove(&project_id)
+            .map(|_| ())
+            .ok_or("No reports found for project".to_string())
+    })
+}
+
+#[query]
+fn get_reported_projects(page: Option<u32>, limit: Option<u32>) -> Result<ReportedProjectsResponse, String> {
+    if !caller_has_permission(Permission::ModerateContent) {
+        return Err("Only moderators can view reported projects".to_string());
+    }
+
+    STATE.with(|state| {
+        let state = state.borrow();
+        let mut reported: Vec<(&Project, &HashMap<Principal, Report>)> = state.project_reports
+            .iter()
+            .filter(|(_, reports)| !reports.is_empty())
+            .filter_map(|(id, reports)| Some((state.projects.get(id)?, reports)))
+            .collect();
+
+        // Most reported first
+        reported.sort_by_key(|(_, reports)| std::cmp::Reverse(reports.len()));
+
+        let paged = paginate(reported, page, limit)?.map(|(project, reports)| {
+            let mut reports: Vec<Report> = reports.values().cloned().collect();
+            reports.sort_by_key(|r| r.timestamp);
+            ReportedProject {
+                project: PublicProject::from(project),
+                report_count: reports.len() as u64,
+                reports,
+            }
+        });
+
+        Ok(ReportedProjectsResponse {
+            reported: paged.items,
+            total: paged.total,
+            page: paged.page,
+            pages: paged.pages,
+            has_next: paged.has_next,
+            has_prev: paged.has_prev,
+        })
+    })
+}
+
+#[query]
+fn get_project_report_count(project_id: String) -> u64 {
+    STATE.with(|state| {
+        state.borrow()
+            .project_reports
+            .get(&project_id)
+            .map(|reports| reports.len() as u64)
+            .unwrap_or(0)
+    })
+}
+
+// Image Storage
+// Whether the background or gallery still points at the stored image
+fn stored_image_in_use(images: &ProjectImages, hash: &str) -> bool {
+    std::iter::once(&images.background)
+        .chain(&images.gallery)
+        .any(|reference| image_store::hash_of(reference) == Some(hash))
+}
+
+fn caller_owns_project(project_id: &str) -> Result<(), String> {
+    let caller = caller();
+    STATE.with(|state| {
+        let state = state.borrow();
+        let project = state.projects.get(project_id)
+            .ok_or("Project not found")?;
+        if project.owner != caller {
+            return Err("Only project owner can manage images".to_string());
+        }
+        Ok(())
+    })
+}
+
+#[update(guard = "caller_is_not_blocked")]
+fn upload_image_chunk(project_id: String, upload_id: String, chunk_index: u32, chunk: serde_bytes::ByteBuf) -> Result<(), String> {
+    caller_owns_project(&project_id)?;
+    ensure_enabled(Subsystem::ImageUploads)?;
+    ensure_heap_headroom()?;
+    image_store::add_chunk(&project_id, &upload_id, chunk_index, chunk.into_vec(), ic_cdk::api::time())
+}
+
+#[update(guard = "caller_is_not_blocked")]
+fn commit_image_upload(project_id: String, upload_id: String, content_type: String) -> Result<String, String> {
+    caller_owns_project(&project_id)?;
+    ensure_enabled(Subsystem::ImageUploads)?;
+    image_store::commit(&project_id, &upload_id, content_type, ic_cdk::api::time())
+}
+
+#[update(guard = "caller_is_not_blocked")]
+fn delete_image(project_id: String, hash: String) -> Result<(), String> {
+    caller_owns_project(&project_id)?;
+    STATE.with(|state| {
+        let state = state.borrow();
+        let project = state.projects.get(&project_id).ok_or("Project not found")?;
+        if stored_image_in_use(&project.images, &hash) {
+            return Err("Image is still used by the project; remove it from the project first".to_string());
+        }
+        image_store::delete(&hash, &project_id)
+    })
+}
+
+// Authorizes the owner to upload under /projects/<id>/ on the asset canister for an hour
+#[update(guard = "caller_is_not_blocked")]
+async fn request_upload_token(project_id: String) -> Result<upload_grants::UploadGrant, String> {
+    caller_owns_project(&project_id)?;
+    ensure_enabled(Subsystem::ImageUploads)?;
+    config().asset_canister.ok_or("No asset canister is configured")?;
+
+    let (random,) = ic_cdk::api::management_canister::main::raw_rand()
+        .await
+        .map_err(|(_, e)| format!("Failed to generate upload token: {}", e))?;
+    let token = hex::encode(&random[..UPLOAD_TOKEN_BYTES]);
+
+    // Ownership may have changed while waiting for randomness
+    caller_owns_project(&project_id)?;
+    Ok(upload_grants::issue(token, &project_id, caller(), ic_cdk::api::time()))
+}
+
+// For the asset canister, to authorize an upload by uploader
+#[query]
+fn verify_upload_token(token: String, uploader: Principal) -> Result<upload_grants::UploadGrant, String> {
+    upload_grants::verify(&token, &uploader, ic_cdk::api::time())
+}
+
+// Asset canister only; adds the stored assets to the project's gallery
+#[update(guard = "caller_is_not_blocked")]
+fn record_uploaded_assets(token: String, paths: Vec<String>) -> Result<(), String> {
+    let asset_canister = config().asset_canister.ok_or("No asset canister is configured")?;
+    if caller() != asset_canister {
+        return Err("Only the asset canister can record uploads".to_string());
+    }
+    let room = |project_id: &str| STATE.with(|state| {
+        state.borrow().projects
+            .get(project_id)
+            .map_or(0, |p| MAX_GALLERY_IMAGES.saturating_sub(p.images.gallery.len()))
+    });
+    let (grant, new_paths) = upload_grants::record(&token, &paths, ic_cdk::api::time(), room)?;
+
+    STATE.with(|state| {
+        let mut state = state.borrow_mut();
+        let project = state.projects.get_mut(&grant.project_id)
+            .ok_or("Project not found")?;
+        for path in new_paths {
+            let url = format!("https://{}.icp0.io{}", asset_canister, path);
+            if !project.images.gallery.contains(&url) {
+                project.images.gallery.push(url);
+            }
+        }
+        Ok(())
+    })
+}
+
+// Called by the pinning tool as it pins the project's ipfs:// and ar:// references
+#[update(guard = "caller_is_not_blocked")]
+fn set_image_pin_status(project_id: String, reference: String, status: PinStatus) -> Result<(), String> {
+    if !caller_has_permission(Permission::ManageImagePins) {
+        return Err("Only moderators or the pinning service can set pin status".to_string());
+    }
+    if !image_store::is_content_addressed(&reference) {
+        return Err("Only ipfs:// and ar:// references are pinned".to_string());
+    }
+
+    STATE.with(|state| {
+        let mut state = state.borrow_mut();
+        let project = state.projects.get_mut(&project_id)
+            .ok_or("Project not found")?;
+        let images = &mut project.images;
+        if images.background != reference && !images.gallery.contains(&reference) {
+            return Err("The project does not use this image".to_string());
+        }
+
+        let pins = images.pins.get_or_insert_with(Vec::new);
+        let updated_at = ic_cdk::api::time();
+        match pins.iter_mut().find(|pin| pin.reference == reference) {
+            Some(pin) => {
+                pin.status = status;
+                pin.updated_at = updated_at;
+            }
+            None => pins.push(ImagePin { reference, status, updated_at }),
+        }
+        Ok(())
+    })
+}
+
+#[query]
+fn get_project_image_usage(project_id: String) -> u64 {
+    image_store::project_usage(&project_id)
+}
+
+// Query functions
+#[query]
+fn get_project(id: String) -> Option<PublicProject> {
+    let caller = caller();
+    let can_view_drafts = caller_has_permission(Permission::ViewPrivate);
+    STATE.with(|state| {
+        state.borrow().projects.get(&id)
+            .filter(|p| is_visible_to(p, &caller, can_view_drafts))
+            .map(PublicProject::from)
+    })
+}
+
+// Listed projects as of the last certify_projects run, verifiable against the subnet key.
+// Projects changed since then are refused until they are certified again.
+#[query]
+fn get_project_certified(id: String) -> Result<CertifiedProject, String> {
+    let certificate = ic_cdk::api::data_certificate()
+        .ok_or("Certificates are only available in query calls")?;
+    let project = STATE.with(|state| {
+        state.borrow().projects.get(&id)
+            .filter(|p| is_listed(p))
+            .map(PublicProject::from)
+    }).ok_or("Project not found")?;
+    let record = certification::record(&project).ok_or("Project could not be encoded")?;
+    let witness = certification::witness(&id, &record)
+        .ok_or(format!("Project has changed and will be certified within {} seconds", CERTIFICATION_INTERVAL_SECS))?;
+
+    Ok(CertifiedProject {
+        project,
+        record: ByteBuf::from(record),
+        certificate: ByteBuf::from(certificate),
+        witness: ByteBuf::from(witness),
+    })
+}
+
+#[query]
+fn get_project_by_slug(slug: String) -> Option<PublicProject> {
+    let caller = caller();
+    let can_view_drafts = caller_has_permission(Permission::ViewPrivate);
+    STATE.with(|state| {
+        let state = state.borrow();
+        state.slug_index
+            .get(&slug)
+            .and_then(|id| state.projects.get(id))
+            .filter(|p| is_visible_to(p, &caller, can_view_drafts))
+            .map(PublicProject::from)
+    })
+}
+
+#[query]
+fn get_project_private(id: String) -> Result<Project, String> {
+    let caller = caller();
+    let can_override = caller_has_permission(Permission::ViewPrivate);
+
+    STATE.with(|state| {
+        let state = state.borrow();
+        let project = state.projects.get(&id)
+            .ok_or("Project not found")?;
+        if project.owner != caller && !can_override {
+            return Err("Only project owner or admins can view private project details".to_string());
+        }
+        Ok(project.clone())
+    })
+}
+
+// One result per id, in the order given
+#[query]
+fn get_projects_bulk(ids: Vec<String>) -> Result<Vec<Result<ProjectSummary, NotFound>>, String> {
+    if ids.len() > MAX_BULK_GET_IDS {
+        return Err(format!("At most {} ids per call", MAX_BULK_GET_IDS));
+    }
+    let caller = caller();
+    let can_view_drafts = caller_has_permission(Permission::ViewPrivate);
+    STATE.with(|state| {
+        let state = state.borrow();
+        Ok(ids
+            .into_iter()
+            .map(|id| match state.projects.get(&id).filter(|p| is_visible_to(p, &caller, can_view_drafts)) {
+                Some(project) => Ok(ProjectSummary::from(project)),
+                None => Err(NotFound { id }),
+            })
+            .collect())
+    })
+}
+
+#[query]
+fn get_projects_by_ids(ids: Vec<String>, page: Option<u32>, limit: Option<u32>, offset: Option<u64>) -> Result<ProjectsResponse, String> {
+    let caller = caller();
+    let can_view_drafts = caller_has_permission(Permission::ViewPrivate);
+    STATE.with(|state| {
+        let state = state.borrow();
+        let projects: Vec<&Project> = ids.iter()
+            .filter_map(|id| state.projects.get(id))
+            .filter(|p| is_visible_to(p, &caller, can_view_drafts))
+            .collect();
+        
+        projects_page(projects, page, limit, offset)
+    })
+}
+
+#[query]
+fn get_projects_by_owner(owner: Principal, page: Option<u32>, limit: Option<u32>, offset: Option<u64>) -> Result<ProjectsResponse, String> {
+    let caller = caller();
+    let can_view_drafts = caller_has_permission(Permission::ViewPrivate);
+    STATE.with(|state| {
+        let state = state.borrow();
+        let projects: Vec<&Project> = state.owner_projects
+            .get(&owner)
+            .map(|ids| {
+                ids.iter()
+                    .filter_map(|id| state.projects.get(id))
+                    .filter(|p| is_visible_to(p, &caller, can_view_drafts))
+                    .collect()
+            })
+            .unwrap_or_default();
+        
+        projects_page(projects, page, limit, offset)
+    })
+}
+
+#[query]
+fn get_projects_by_date_range(start: u64, end: u64, page: Option<u32>, limit: Option<u32>, offset: Option<u64>) -> Result<ProjectsResponse, String> {
+    if start > end {
+        return projects_page(Vec::new(), page, limit, offset);
+    }
+    STATE.with(|state| {
+        let state = state.borrow();
+        let upper = match end.checked_add(1) {
+            Some(after) => Bound::Excluded((after, String::new())),
+            None => Bound::Unbounded,
+        };
+        let projects: Vec<&Project> = state.date_index
+            .range((Bound::Included((start, String::new())), upper))
+            .filter_map(|(_, id)| state.projects.get(id))
+            .filter(|p| is_listed(p))
+            .collect();
+        
+        projects_page(projects, page, limit, offset)
+    })
+}
+
+// Listed projects created after the cursor, oldest first. Pass next_cursor back for the
+// following page; new projects only ever land at the end, so earlier pages stay stable.
+#[query]
+fn list_projects_after(created_at_cursor: Option<(u64, String)>, limit: Option<u32>) -> Result<ProjectCursorResponse, String> {
+    let limit = page_limit(limit, config().default_page_limit)?;
+    let lower = match created_at_cursor {
+        Some(cursor) => Bound::Excluded(cursor),
+        None => Bound::Unbounded,
+    };
+
+    STATE.with(|state| {
+        let state = state.borrow();
+        let mut projects: Vec<&Project> = state.date_index
+            .range((lower, Bound::Unbounded))
+            .filter_map(|(_, id)| state.projects.get(id))
+            .filter(|p| is_listed(p))
+            .take(limit + 1)
+            .collect();
+        let has_more = projects.len() > limit;
+        projects.truncate(limit);
+        let truncated = fit_response(&mut projects);
+
+        Ok(ProjectCursorResponse {
+            next_cursor: projects.last().filter(|_| has_more || truncated).map(|p| (p.created_at, p.id.clone())),
+            projects: projects.into_iter().map(ProjectSummary::from).collect(),
+            truncated,
+        })
+    })
+}
+
+#[query]
+fn get_projects_by_location(lat: f64, lng: f64, radius: f64, limit: Option<u32>) -> Result<Vec<ProjectSummary>, String> {
+    STATE.with(|state| {
+        let state = state.borrow();
+        let project_ids = geo_index::find(format!("{},{}", lat, lng), radius);
+        let projects = project_ids.iter()
+            .filter_map(|id| state.projects.get(id))
+            .filter(|p| is_listed(p))
+            .collect();
+        capped_summaries(projects, limit)
+    })
+}
+
+#[query]
+fn get_project_votes(project_id: String) -> u64 {
+    STATE.with(|state| {
+        state.borrow()
+            .projects
+            .get(&project_id)
+            .map(|p| p.vote_count)
+            .unwrap_or(0)
+    })
+}
+
+#[query]
+fn get_user_vote_for_project(project_id: String, user: Principal) -> bool {
+    STATE.with(|state| has_voted(&state.borrow(), &user, &project_id))
+}
+
+#[derive(CandidType, Serialize, Deserialize, Clone, Debug)]
+pub struct VoteHistory {
+    votes: Vec<ProjectVote>,
+    total: u64,
+    page: u32,
+    pages: u32,
+    has_next: bool,
+    has_prev: bool,
+}
+
+// Newest first; pages past the local votes are read from the vote archive
+#[query(composite = true)]
+async fn get_project_vote_history(project_id: String, page: Option<u32>, limit: Option<u32>) -> Result<VoteHistory, String> {
+    let config = config();
+    let (page, limit) = page_window(page, limit)?;
+    let limit = limit as u32;
+    let offset = (page as u64 - 1) * limit as u64;
+
+    let (local, archived) = STATE.with(|state| {
+        let state = state.borrow();
+        if !state.projects.contains_key(&project_id) {
+            return Err("Project not found".to_string());
+        }
+        let mut local: Vec<ProjectVote> = state.project_votes
+            .get(&project_id)
+            .map(|votes| {
+                votes.values()
+                    .map(|v| ProjectVote { project_id: project_id.clone(), voter: v.voter, timestamp: v.timestamp })
+                    .collect()
+            })
+            .unwrap_or_default();
+        local.sort_by_key(|v| std::cmp::Reverse(v.timestamp));
+        Ok((local, state.archived_vote_counts.get(&project_id).copied().unwrap_or(0)))
+    })?;
+
+    let total = local.len() as u64 + archived;
+    let mut votes: Vec<ProjectVote> = local
+        .into_iter()
+        .skip(offset as usize)
+        .take(limit as usize)
+        .collect();
+    let remaining = limit - votes.len() as u32;
+    if remaining > 0 && offset + (votes.len() as u64) < total {
+        let archive = config.vote_archive.ok_or("Vote archive is not configured")?;
+        let start = (offset + votes.len() as u64).saturating_sub(total - archived);
+        votes.extend(vote_archive::get(archive, &project_id, start, remaining).await?);
+    }
+
+    let pages = total.div_ceil(limit as u64) as u32;
+    Ok(VoteHistory {
+        votes,
+        total,
+        page,
+        pages,
+        has_next: page < pages,
+        has_prev: page > 1 && pages > 0,
+    })
+}
+
+#[query]
+fn get_user_voted_projects(user: Principal, page: Option<u32>, limit: Option<u32>, offset: Option<u64>) -> Result<ProjectsResponse, String> {
+    STATE.with(|state| {
+        let state = state.borrow();
+        let projects: Vec<&Project> = state.vote_index
+            .get(&user)
+            .map(|ids| {
+                ids.iter()
+                    .filter_map(|id| state.projects.get(id))
+                    .collect()
+            })
+            .unwrap_or_default();
+        
+        projects_page(projects, page, limit, offset)
+    })
+}
+
+#[query]
+fn get_projects_by_gateway_type(gateway_type: GatewayType, page: Option<u32>, limit: Option<u32>, offset: Option<u64>) -> Result<ProjectsResponse, String> {
+    STATE.with(|state| {
+        let state = state.borrow();
+        let projects: Vec<&Project> = state.gateway_index
+            .get(&gateway_type)
+            .map(|ids| {
+                ids.iter()
+                    .filter_map(|id| state.projects.get(id))
+                    .filter(|p| is_listed(p))
+                    .collect()
+            })
+            .unwrap_or_default();
+        
+        projects_page(projects, page, limit, offset)
+    })
+}
+
+#[query]
+fn get_projects_by_country(country_code: String, page: Option<u32>, limit: Option<u32>, offset: Option<u64>) -> Result<ProjectsResponse, String> {
+    STATE.with(|state| {
+        let state = state.borrow();
+        let projects: Vec<&Project> = state.country_index
+            .get(&country_code.to_uppercase())
+            .map(|ids| {
+                ids.iter()
+                    .filter_map(|id| state.projects.get(id))
+                    .filter(|p| is_listed(p))
+                    .collect()
+            })
+            .unwrap_or_default();
+
+        projects_page(projects, page, limit, offset)
+    })
+}
+
+// Tags of listed projects with how many listed projects carry each, most used first
+#[query]
+fn list_all_tags(page: Option<u32>, limit: Option<u32>) -> Result<Vec<(String, u64)>, String> {
+    let (page, limit) = page_window(page, limit)?;
+    STATE.with(|state| {
+        Ok(state.borrow().tag_rank
+            .iter()
+            .skip((page as usize - 1).saturating_mul(limit))
+            .take(limit)
+            .map(|(count, tag)| (tag.clone(), count.0))
+            .collect())
+    })
+}
+
+#[query]
+fn get_projects_by_category(category: ProjectCategory, page: Option<u32>, limit: Option<u32>, offset: Option<u64>) -> Result<ProjectsResponse, String> {
+    STATE.with(|state| {
+        let state = state.borrow();
+        let projects: Vec<&Project> = state.category_index
+            .get(&category)
+            .map(|ids| {
+                ids.iter()
+                    .filter_map(|id| state.projects.get(id))
+                    .filter(|p| is_listed(p))
+                    .collect()
+            })
+            .unwrap_or_default();
+        
+        projects_page(projects, page, limit, offset)
+    })
+}
+
+#[query]
+fn get_projects_by_votes(min_votes: Option<u64>, max_votes: Option<u64>, page: Option<u32>, limit: Option<u32>, offset: Option<u64>) -> Result<ProjectsResponse, String> {
+    STATE.with(|state| {
+        let state = state.borrow();
+        // The homepage's unfiltered first pages come straight off the leaderboard
+        if min_votes.is_none() && max_votes.is_none() {
+            let listed = listed_count(&state);
+            let (page_number, page_limit) = page_window(page, limit)?;
+            let board = &state.leaderboard;
+            let end = match offset {
+                Some(offset) => usize::try_from(offset).unwrap_or(usize::MAX).saturating_add(page_limit),
+                None => (page_number as usize).saturating_mul(page_limit),
+            };
+            if board.len() == listed || end <= board.len() {
+                let top: Vec<&Project> = board.iter().filter_map(|(_, id)| state.projects.get(id)).collect();
+                return Ok(projects_response(paginate_prefix(top, listed, page, limit, offset)?));
+            }
+        }
+
+        // An inverted range matches nothing, and would panic in BTreeSet::range
+        if let (Some(min), Some(max)) = (min_votes, max_votes) {
+            if min > max {
+                return projects_page(Vec::new(), page, limit, offset);
+            }
+        }
+        let lower = Bound::Included((min_votes.unwrap_or(0), String::new()));
+        let upper = match max_votes.and_then(|max| max.checked_add(1)) {
+            Some(above) => Bound::Excluded((above, String::new())),
+            None => Bound::Unbounded,
+        };
+        // Vote count descending
+        let projects: Vec<&Project> = state.vote_rank
+            .range((lower, upper))
+            .rev()
+            .filter_map(|(_, id)| state.projects.get(id))
+            .filter(|p| is_listed(p))
+            .collect();
+        
+        projects_page(projects, page, limit, offset)
+    })
+}
+
+#[query]
+fn get_featured_projects(page: Option<u32>, limit: Option<u32>, offset: Option<u64>) -> Result<ProjectsResponse, String> {
+    STATE.with(|state| {
+        let state = state.borrow();
+        let projects: Vec<&Project> = state.featured_projects
+            .iter()
+            .filter_map(|id| state.projects.get(id))
+            .collect();
+        
+        projects_page(projects, page, limit, offset)
+    })
+}
+
+// Implement search functionality using index_text:
+#[query]
+fn search_projects(query: String, page: Option<u32>, limit: Option<u32>, offset: Option<u64>) -> Result<ProjectsResponse, String> {
+    STATE.with(|state| {
+        let state = state.borrow();
+        
+        // Get search terms
+        let search_terms = index_text(&query);
+        
+        // Projects with any search term in their name or description, in id order
+        let matching: BTreeSet<&String> = search_terms
+            .iter()
+            .filter_map(|term| state.search_index.get(term))
+            .flatten()
+            .collect();
+        let mut projects: Vec<&Project> = matching
+            .into_iter()
+            .filter_map(|id| state.projects.get(id))
+            .filter(|project| is_listed(project))
+            .collect();
+        
+        // Sort by relevance: most search terms matched in the name first.
+        // Cached so each name is tokenized once rather than on every comparison.
+        projects.sort_by_cached_key(|project| {
+            let name_terms = index_text(&project.name);
+            std::cmp::Reverse(search_terms.iter().filter(|term| name_terms.contains(term)).count())
+        });
+        
+        projects_page(projects, page, limit, offset)
+    })
+}
+
+// All listed projects in the requested order
+#[query]
+fn get_projects_sorted(sort: ProjectSort, page: Option<u32>, limit: Option<u32>, offset: Option<u64>) -> Result<ProjectsResponse, String> {
+    STATE.with(|state| {
+        let state = state.borrow();
+        let projects: Vec<&Project> = match sort {
+            ProjectSort::Newest => state.date_index
+                .iter()
+                .rev()
+                .filter_map(|(_, id)| state.projects.get(id))
+                .filter(|p| is_listed(p))
+                .collect(),
+            ProjectSort::MostVotes => state.vote_rank
+                .iter()
+                .rev()
+                .filter_map(|(_, id)| state.projects.get(id))
+                .filter(|p| is_listed(p))
+                .collect(),
+            ProjectSort::MostActive => {
+                let mut projects: Vec<&Project> = listed_projects(&state).collect();
+                projects.sort_by_key(|p| std::cmp::Reverse((p.active_sensor_count, p.last_data_at, p.vote_count)));
+                projects
+            }
+        };
+
+        projects_page(projects, page, limit, offset)
+    })
+}
+
+// Listed projects ranked by votes in the last week, as of the last refresh_trending run
+#[query]
+fn get_trending_projects(page: Option<u32>, limit: Option<u32>, offset: Option<u64>) -> Result<ProjectsResponse, String> {
+    STATE.with(|state| {
+        let state = state.borrow();
+        let projects: Vec<&Project> = state.trending_projects
+            .iter()
+            .filter_map(|id| state.projects.get(id))
+            .filter(|p| is_listed(p))
+            .collect();
+
+        projects_page(projects, page, limit, offset)
+    })
+}
+
+// Add this query function to project.rs
+
+#[query]
+fn get_projects_by_status(status: ProjectStatus, page: Option<u32>, limit: Option<u32>, offset: Option<u64>) -> Result<ProjectsResponse, String> {
+    STATE.with(|state| {
+        let state = state.borrow();
+        
+        // Newest first
+        let projects: Vec<&Project> = state.status_index
+            .get(&status)
+            .into_iter()
+            .flat_map(|ids| ids.iter().rev())
+            .filter_map(|(_, id)| state.projects.get(id))
+            .collect();
+        
+        projects_page(projects, page, limit, offset)
+    })
+}
+
+#[query]
+fn find_similar_projects(name: String, geohash: String) -> Vec<ProjectSummary> {
+    STATE.with(|state| {
+        let state = state.borrow();
+        let projects = find_duplicate_projects(&state, &name, &geohash, None)
+            .iter()
+            .filter_map(|id| state.projects.get(id))
+            .collect();
+        capped_summaries(projects, None).unwrap_or_default()
+    })
+}
+
+#[query]
+fn get_archived_projects(page: Option<u32>, limit: Option<u32>, offset: Option<u64>) -> Result<ProjectsResponse, String> {
+    get_projects_by_status(ProjectStatus::Archived, page, limit, offset)
+}
+
+// Add functionality using get_distance_from_geohash:
+#[query]
+fn get_nearest_projects(geohash: String, limit: Option<u32>) -> Result<Vec<(ProjectSummary, f64)>, String> {
+    let limit = page_limit(limit, 10)?;
+    STATE.with(|state| {
+        let state = state.borrow();
+        let mut projects_with_distance: Vec<(&Project, f64)> = state.projects
+            .values()
+            .filter(|project| is_listed(project))
+            .map(|project| {
+                let distance = geo_index::get_distance_from_geohash(
+                    geohash.clone(),
+                    project.location.geohash.clone()
+                );
+                (project, distance)
+            })
+            .collect();
+        
+        // Sort by distance
+        projects_with_distance.sort_by(|a, b| 
+            a.1.partial_cmp(&b.1).unwrap_or(std::cmp::Ordering::Equal)
+        );
+        
+        // Take limited number of results
+        projects_with_distance.truncate(limit);
+        let mut projects: Vec<&Project> = projects_with_distance.iter().map(|(project, _)| *project).collect();
+        fit_response(&mut projects);
+        projects_with_distance.truncate(projects.len());
+        
+        Ok(projects_with_distance
+            .into_iter()
+            .map(|(project, distance)| (ProjectSummary::from(project), distance))
+            .collect())
+    })
+}
+
+// Buckets timestamps into `periods` consecutive windows ending at `now`, oldest first
+fn count_per_period(timestamps: impl Iterator<Item = u64>, now: u64, period: u64, periods: u64) -> Vec<PeriodCount> {
+    let first_start = (now / period).saturating_sub(periods - 1) * period;
+    let mut counts = vec![0u64; periods as usize];
+    for timestamp in timestamps.filter(|t| *t >= first_start) {
+        let index = ((timestamp - first_start) / period) as usize;
+        if let Some(count) = counts.get_mut(index) {
+            *count += 1;
+        }
+    }
+    counts.into_iter()
+        .enumerate()
+        .map(|(i, count)| PeriodCount {
+            period_start: first_start + i as u64 * period,
+            count,
+        })
+        .collect()
+}
+
+#[query]
+fn get_admin_dashboard() -> Result<AdminDashboard, String> {
+    if caller_role().is_none() {
+        return Err("Only admins can view the dashboard".to_string());
+    }
+
+    let now = ic_cdk::api::time();
+    let week = 7 * NANOS_PER_DAY;
+
+    STATE.with(|state| {
+        let state = state.borrow();
+
+        let pending: Vec<&Project> = state.projects
+            .values()
+            .filter(|p| p.status == ProjectStatus::PendingReview)
+            .collect();
+        let unclaimed_reviews = pending.iter()
+            .filter(|p| active_review_claim(&state, &p.id, now).is_none())
+            .count();
+
+        let turnarounds: Vec<u64> = state.review_decisions
+            .iter()
+            .map(|d| d.decided_at.saturating_sub(d.submitted_at))
+            .collect();
+        let average_review_turnaround = if turnarounds.is_empty() {
+            None
         } else {
-            return Err("Project not found".to_string());
+            Some(turnarounds.iter().sum::<u64>() / turnarounds.len() as u64)
+        };
+
+        let decided_at = |status: ProjectStatus| {
+            state.review_decisions
+                .iter()
+                .filter(move |d| d.status == status)
+                .map(|d| d.decided_at)
+        };
+        let approvals = count_per_period(decided_at(ProjectStatus::Approved), now, week, DASHBOARD_WEEKS);
+        let rejections = count_per_period(decided_at(ProjectStatus::Rejected), now, week, DASHBOARD_WEEKS);
+        let decisions_per_week = approvals.into_iter()
+            .zip(rejections)
+            .map(|(a, r)| WeeklyDecisions {
+                week_start: a.period_start,
+                approvals: a.count,
+                rejections: r.count,
+            })
+            .collect();
+
+        Ok(AdminDashboard {
+            pending_review: pending.len() as u64,
+            unclaimed_reviews: unclaimed_reviews as u64,
+            pending_verifications: state.verification_requests
+                .values()
+                .filter(|r| r.status == VerificationStatus::Pending)
+                .count() as u64,
+            reported_projects: state.project_reports
+                .values()
+                .filter(|reports| !reports.is_empty())
+                .count() as u64,
+            stale_projects: state.projects
+                .values()
+                .filter(|p| p.stale_since.is_some())
+                .count() as u64,
+            average_review_turnaround,
+            decisions_per_week,
+            new_projects_per_day: count_per_period(
+                state.projects.values().map(|p| p.created_at),
+                now,
+                NANOS_PER_DAY,
+                DASHBOARD_DAYS,
+            ),
+            votes_per_day: count_per_period(
+                state.project_votes.values().flat_map(|votes| votes.values()).map(|v| v.timestamp),
+                now,
+                NANOS_PER_DAY,
+                DASHBOARD_DAYS,
+            ),
+        })
+    })
+}
+
+// window_days limits the metrics to recent activity; None covers all time
+#[query]
+fn get_admin_activity(principal: Principal, window_days: Option<u64>) -> Result<AdminActivity, String> {
+    if !caller_has_permission(Permission::ManageAdmins) {
+        return Err("Only super admin can view admin activity".to_string());
+    }
+
+    let now = ic_cdk::api::time();
+    let since = window_days.map_or(0, |days| now.saturating_sub(days.saturating_mul(NANOS_PER_DAY)));
+
+    STATE.with(|state| {
+        let state = state.borrow();
+
+        let decisions: Vec<&ReviewDecision> = state.review_decisions
+            .iter()
+            .filter(|d| d.reviewer == principal && d.decided_at >= since)
+            .collect();
+        let average_decision_time = if decisions.is_empty() {
+            None
+        } else {
+            let total: u64 = decisions.iter().map(|d| d.decided_at.saturating_sub(d.submitted_at)).sum();
+            Some(total / decisions.len() as u64)
         };
+
+        let features_granted = state.audit_log
+            .iter()
+            .filter(|entry| entry.actor == principal && entry.timestamp >= since)
+            .filter(|entry| matches!(entry.event, AuditEvent::ProjectFeatured { .. }))
+            .count();
+        let notes_written = state.admin_notes
+            .values()
+            .flatten()
+            .filter(|note| note.author == principal && note.created_at >= since)
+            .count();
+
+        Ok(AdminActivity {
+            principal,
+            role: state.admins.get(&principal).copied(),
+            window_start: since,
+            reviews_completed: decisions.len() as u64,
+            approvals: decisions.iter().filter(|d| d.status == ProjectStatus::Approved).count() as u64,
+            rejections: decisions.iter().filter(|d| d.status == ProjectStatus::Rejected).count() as u64,
+            average_decision_time,
+            features_granted: features_granted as u64,
+            notes_written: notes_written as u64,
+        })
+    })
+}
+
+// Stats and utility queries
+#[query]
+fn get_total_projects() -> u64 {
+    STATE.with(|state| state.borrow().projects.len() as u64)
+}
+
+#[query]
+fn get_total_votes() -> u64 {
+    STATE.with(|state| {
+        state.borrow()
+            .projects
+            .values()
+            .map(|p| p.vote_count)
+            .sum()
+    })
+}
+
+#[query]
+fn get_index_stats() -> HashMap<String, usize> {
+    let mut stats = HashMap::new();
+    
+    STATE.with(|state| {
+        let state = state.borrow();
+        let indexed_projects = geo_index::view_index();
         
-        // Remove from featured_projects if we have a timestamp
-        if let Some(timestamp) = featured_at {
-            state.featured_projects.remove(&timestamp);
-        }
+        stats.insert("total_indexed".to_string(), indexed_projects.len());
+        stats.insert("total_projects".to_string(), state.projects.len());
         
-        // Update the project
-        if let Some(project) = state.projects.get_mut(&project_id) {
-            project.featured = false;
-            project.featured_at = None;
+        // Count projects by status
+        for project in state.projects.values() {
+            let status_key = format!("status_{:?}", project.status);
+            *stats.entry(status_key).or_insert(0) += 1;
         }
-        
-        Ok(())
-    })
+    });
+    
+    stats
 }
 
-// Voting System
-#[update]
-fn vote_for_project(project_id: String) -> Result<(), String> {
-    let caller = caller();
-    if caller == Principal::anonymous() {
-        return Err("Anonymous principals cannot vote".to_string());
+#[query]
+fn is_admin(principal: Principal) -> bool {
+    STATE.with(|state| state.borrow().admins.contains_key(&principal))
+}
+
+#[query]
+fn is_super_admin(principal: Principal) -> bool {
+    get_role(principal) == Some(Role::SuperAdmin)
+}
+
+#[query]
+fn get_role(principal: Principal) -> Option<Role> {
+    STATE.with(|state| state.borrow().admins.get(&principal).copied())
+}
+
+#[query]
+fn get_admins() -> Result<Vec<(Principal, Role)>, String> {
+    if caller_role().is_none() {
+        return Err("Only admins can list admins".to_string());
     }
 
     STATE.with(|state| {
-        let mut state = state.borrow_mut();
-        
-        // Verify project exists
-        if !state.projects.contains_key(&project_id) {
-            return Err("Project not found".to_string());
-        }
+        Ok(state.borrow()
+            .admins
+            .iter()
+            .map(|(principal, role)| (*principal, *role))
+            .collect())
+    })
+}
 
-        let vote = Vote {
-            voter: caller,
-            timestamp: ic_cdk::api::time(),
-        };
+// HTTP interface
+fn http_bad_request(message: String) -> HttpResponse {
+    HttpResponse {
+        status_code: 400,
+        headers: vec![("Content-Type".to_string(), "text/plain".to_string())],
+        body: message.into_bytes(),
+    }
+}
 
-        // Add vote
-        state.project_votes
-            .entry(project_id.clone())
-            .or_insert_with(HashMap::new)
-            .insert(caller, vote);
+fn http_not_found() -> HttpResponse {
+    HttpResponse {
+        status_code: 404,
+        headers: vec![("Content-Type".to_string(), "text/plain".to_string())],
+        body: b"Not found".to_vec(),
+    }
+}
 
-        // Update vote index
-        state.vote_index
-            .entry(caller)
-            .or_insert_with(Vec::new)
-            .push(project_id.clone());
+fn http_json<T: Serialize>(value: &T) -> HttpResponse {
+    match serde_json::to_vec(value) {
+        Ok(body) => HttpResponse {
+            status_code: 200,
+            headers: vec![
+                ("Content-Type".to_string(), "application/json".to_string()),
+                ("Cache-Control".to_string(), format!("public, max-age={}", HTTP_JSON_MAX_AGE_SECS)),
+                ("Access-Control-Allow-Origin".to_string(), "*".to_string()),
+            ],
+            body,
+        },
+        Err(e) => HttpResponse {
+            status_code: 500,
+            headers: vec![("Content-Type".to_string(), "text/plain".to_string())],
+            body: e.to_string().into_bytes(),
+        },
+    }
+}
 
-        // Update vote count
-        if let Some(project) = state.projects.get_mut(&project_id) {
-            project.vote_count += 1;
+// Decodes %XX escapes and '+' in a query string component; malformed escapes are kept as is
+fn percent_decode(value: &str) -> String {
+    let bytes = value.as_bytes();
+    let mut decoded = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        match bytes[i] {
+            b'+' => decoded.push(b' '),
+            b'%' if i + 2 < bytes.len() => {
+                let hex = std::str::from_utf8(&bytes[i + 1..i + 3]).ok();
+                match hex.and_then(|hex| u8::from_str_radix(hex, 16).ok()) {
+                    Some(byte) => {
+                        decoded.push(byte);
+                        i += 2;
+                    }
+                    None => decoded.push(b'%'),
+                }
+            }
+            byte => decoded.push(byte),
         }
+        i += 1;
+    }
+    String::from_utf8_lossy(&decoded).into_owned()
+}
 
-        Ok(())
+fn query_param(url: &str, name: &str) -> Option<String> {
+    let (_, query) = url.split_once('?')?;
+    query.split('&')
+        .filter_map(|pair| pair.split_once('='))
+        .find(|(key, _)| *key == name)
+        .map(|(_, value)| percent_decode(value))
+}
+
+// GET /projects/<slug> and GET /projects?tag=&page=&limit=, listed projects only
+fn http_projects(url: &str, path: &str) -> HttpResponse {
+    STATE.with(|state| {
+        let state = state.borrow();
+
+        if let Some(slug) = path.strip_prefix(HTTP_PROJECTS_PATH).and_then(|rest| rest.strip_prefix('/')) {
+            return state.slug_index
+                .get(&percent_decode(slug))
+                .and_then(|id| state.projects.get(id))
+                .filter(|p| is_listed(p))
+                .map(|p| http_json(&PublicProject::from(p)))
+                .unwrap_or_else(http_not_found);
+        }
+
+        let mut projects: Vec<&Project> = match query_param(url, "tag") {
+            Some(tag) => state.tag_index
+                .get(&tag.to_lowercase())
+                .map(|ids| ids.iter().filter_map(|id| state.projects.get(id)).collect())
+                .unwrap_or_default(),
+            None => state.projects.values().collect(),
+        };
+        projects.retain(|p| is_listed(p));
+        projects.sort_by_key(|p| std::cmp::Reverse(p.created_at));
+
+        let page = query_param(url, "page").and_then(|p| p.parse().ok());
+        let limit = query_param(url, "limit").and_then(|l| l.parse().ok());
+        let offset = query_param(url, "offset").and_then(|o| o.parse().ok());
+        match projects_page(projects, page, limit, offset) {
+            Ok(response) => http_json(&response),
+            Err(e) => http_bad_request(e),
+        }
     })
 }
 
-#[update]
-fn remove_vote(project_id: String) -> Result<(), String> {
-    let caller = caller();
+fn canister_url(path: &str) -> String {
+    format!("https://{}.icp0.io{}", ic_cdk::id(), path)
+}
 
-    STATE.with(|state| {
-        let mut state = state.borrow_mut();
-        
-        // Remove vote from project_votes
-        if let Some(votes) = state.project_votes.get_mut(&project_id) {
-            if votes.remove(&caller).is_none() {
-                return Err("No vote found".to_string());
+// GET /registry/v1/projects?cursor=&limit=, /registry/v1/projects/<id> and /registry/v1/count
+fn http_registry(url: &str, path: &str) -> HttpResponse {
+    let Some(rest) = path.strip_prefix(registry::HTTP_PATH) else {
+        return http_not_found();
+    };
+    match rest {
+        "/count" => http_json(&serde_json::json!({ "count": registry_count() })),
+        "/projects" => {
+            let limit = query_param(url, "limit").and_then(|l| l.parse().ok());
+            match registry_list(query_param(url, "cursor").as_deref(), limit) {
+                Ok(page) => http_json(&page),
+                Err(e) => http_bad_request(e),
             }
-        } else {
-            return Err("Project not found".to_string());
         }
+        _ => rest.strip_prefix("/projects/")
+            .and_then(|id| registry_get(&percent_decode(id)))
+            .map(|record| http_json(&record))
+            .unwrap_or_else(http_not_found),
+    }
+}
 
-        // Remove from vote index
-        if let Some(voted_projects) = state.vote_index.get_mut(&caller) {
-            voted_projects.retain(|id| id != &project_id);
+// GET /feeds/new-projects.xml and GET /feeds/project/<slug>/updates.xml as Atom
+fn http_feeds(path: &str) -> HttpResponse {
+    let feed = STATE.with(|state| {
+        let state = state.borrow();
+        let now = ic_cdk::api::time();
+        let project_entry = |p: &Project| feeds::Entry {
+            id: canister_url(&format!("{}/{}", HTTP_PROJECTS_PATH, p.slug)),
+            title: p.name.clone(),
+            link: canister_url(&format!("{}/{}", HTTP_PROJECTS_PATH, p.slug)),
+            updated: p.created_at,
+            summary: p.description.clone(),
+        };
+
+        let rest = path.strip_prefix(HTTP_FEEDS_PATH)?;
+        if rest == "/new-projects.xml" {
+            let entries: Vec<feeds::Entry> = state.date_index
+                .iter()
+                .rev()
+                .filter_map(|(_, id)| state.projects.get(id))
+                .filter(|p| is_listed(p))
+                .take(MAX_FEED_ENTRIES)
+                .map(project_entry)
+                .collect();
+            return Some(feeds::atom(&canister_url(path), "New Earthstream projects", &canister_url(path), &entries, now));
         }
 
-        // Update vote count
-        if let Some(project) = state.projects.get_mut(&project_id) {
-            project.vote_count = project.vote_count.saturating_sub(1);
+        let slug = rest.strip_prefix("/project/")?.strip_suffix("/updates.xml")?;
+        let project = state.slug_index
+            .get(&percent_decode(slug))
+            .and_then(|id| state.projects.get(id))
+            .filter(|p| is_listed(p))?;
+        let mut entries: Vec<feeds::Entry> = vec![project_entry(project)];
+        for update in state.project_updates.get(&project.id).into_iter().flatten() {
+            entries.push(feeds::Entry {
+                id: format!("{}#update-{}", canister_url(&format!("{}/{}", HTTP_PROJECTS_PATH, project.slug)), update.sequence),
+                title: update.message.clone(),
+                link: canister_url(&format!("{}/{}", HTTP_PROJECTS_PATH, project.slug)),
+                updated: update.timestamp,
+                summary: update.message.clone(),
+            });
         }
+        entries.reverse();  // Newest first
+        let title = format!("Updates from {}", project.name);
+        Some(feeds::atom(&canister_url(path), &title, &canister_url(path), &entries, project.created_at))
+    });
 
-        Ok(())
-    })
+    match feed {
+        Some(xml) => HttpResponse {
+            status_code: 200,
+            headers: vec![
+                ("Content-Type".to_string(), feeds::CONTENT_TYPE.to_string()),
+                ("Cache-Control".to_string(), format!("public, max-age={}", HTTP_JSON_MAX_AGE_SECS)),
+                ("Access-Control-Allow-Origin".to_string(), "*".to_string()),
+            ],
+            body: xml.into_bytes(),
+        },
+        None => http_not_found(),
+    }
+}
+
+#[query]
+fn http_request(request: HttpRequest) -> HttpResponse {
+    if request.method != "GET" {
+        return HttpResponse {
+            status_code: 405,
+            headers: vec![("Content-Type".to_string(), "text/plain".to_string())],
+            body: b"Method not allowed".to_vec(),
+        };
+    }
+
+    let path = request.url.split('?').next().unwrap_or_default();
+    if let Some(hash) = image_store::hash_of(path) {
+        return match image_store::get(hash) {
+            Some(image) => HttpResponse {
+                status_code: 200,
+                headers: vec![
+                    ("Content-Type".to_string(), image.content_type),
+                    ("X-Content-Type-Options".to_string(), "nosniff".to_string()),
+                    // Content-addressed, so the response never changes
+                    ("Cache-Control".to_string(), "public, max-age=31536000, immutable".to_string()),
+                ],
+                body: image.data,
+            },
+            None => http_not_found(),
+        };
+    }
+    if path.strip_prefix(HTTP_PROJECTS_PATH).is_some_and(|rest| rest.is_empty() || rest.starts_with('/')) {
+        return http_projects(&request.url, path);
+    }
+    if path.starts_with(HTTP_FEEDS_PATH) {
+        return http_feeds(path);
+    }
+    if path.starts_with(registry::HTTP_PATH) {
+        return http_registry(&request.url, path);
+    }
+
+    http_not_found()
+}
+
+// Climate
+// Latest weather at the project's location, refreshed by the refresh_climate job
+#[query]
+fn get_project_climate(id: String) -> Option<climate::Climate> {
+    let geohash = STATE.with(|state| {
+        state.borrow().projects.get(&id).map(|p| p.location.geohash.clone())
+    })?;
+    climate::get(&climate::cell(&geohash))
+}
+
+#[query]
+fn transform_climate_response(
+    args: ic_cdk::api::management_canister::http_request::TransformArgs,
+) -> ic_cdk::api::management_canister::http_request::HttpResponse {
+    climate::transform(args)
+}
+
+// Region Shards
+#[derive(CandidType, Serialize, Deserialize, Clone, Debug)]
+pub struct RegionSearchResponse {
+    projects: Vec<ProjectSummary>,
+    total: u64,  // Matches across every reachable region
+    unreachable: Vec<Principal>,  // Shards that didn't answer
+}
+
+#[derive(CandidType, Serialize, Deserialize, Clone, Debug)]
+pub struct RegionProjectResponse {
+    project: Option<PublicProject>,
+    unreachable: Vec<Principal>,  // Shards that didn't answer; the project may be on one of them
+}
+
+// Looks the project up here, then on each shard
+#[query(composite = true)]
+async fn get_project_any_region(id: String) -> RegionProjectResponse {
+    let mut unreachable = vec![];
+    if let Some(project) = get_project(id.clone()) {
+        return RegionProjectResponse { project: Some(project), unreachable };
+    }
+    for shard in config().region_shards {
+        match shards::get_project(shard.canister, &id).await {
+            Ok(Some(project)) => return RegionProjectResponse { project: Some(project), unreachable },
+            Ok(None) => {}
+            Err(_) => unreachable.push(shard.canister),
+        }
+    }
+    RegionProjectResponse { project: None, unreachable }
+}
+
+// Best `limit` matches across this canister and every shard
+#[query(composite = true)]
+async fn search_projects_all_regions(query: String, limit: Option<u32>) -> Result<RegionSearchResponse, String> {
+    let config = config();
+    let limit = page_limit(limit, config.default_page_limit)? as u32;
+
+    let local = search_projects(query.clone(), Some(1), Some(limit), None)?;
+    let mut total = local.total;
+    let mut projects = local.projects;
+    let mut unreachable = vec![];
+    for shard in config.region_shards {
+        match shards::search_projects(shard.canister, &query, limit).await {
+            Ok(response) => {
+                total += response.total;
+                projects.extend(response.projects);
+            }
+            Err(_) => unreachable.push(shard.canister),
+        }
+    }
+
+    // Same ranking as search_projects: name matches first, then votes
+    let search_terms = index_text(&query);
+    projects.sort_by_cached_key(|p| {
+        let name_terms = index_text(&p.name);
+        let matches = search_terms.iter().filter(|term| name_terms.contains(term)).count();
+        std::cmp::Reverse((matches, p.vote_count))
+    });
+    projects.truncate(limit as usize);
+
+    Ok(RegionSearchResponse { projects, total, unreachable })
+}
+
+// Geocoding
+#[query]
+fn transform_geocoding_response(
+    args: ic_cdk::api::management_canister::http_request::TransformArgs,
+) -> ic_cdk::api::management_canister::http_request::HttpResponse {
+    geocoding::transform(args)
+}
+
+// Partner API
+#[query]
+fn supported_interfaces() -> Vec<partner::SupportedInterface> {
+    vec![
+        partner::SupportedInterface {
+            name: partner::INTERFACE_NAME.to_string(),
+            version: partner::INTERFACE_VERSION.to_string(),
+        },
+        partner::SupportedInterface {
+            name: registry::INTERFACE_NAME.to_string(),
+            version: registry::INTERFACE_VERSION.to_string(),
+        },
+    ]
 }
 
-// Query functions
 #[query]
-fn get_project(id: String) -> Option<Project> {
+fn partner_v1_get_project(id: String) -> Option<partner::ProjectV1> {
     STATE.with(|state| {
-        state.borrow().projects.get(&id).cloned()
+        state.borrow().projects
+            .get(&id)
+            .filter(|p| is_listed(p))
+            .map(partner::ProjectV1::from)
     })
 }
 
+// Oldest first, so pages stay stable as projects are added
 #[query]
-fn get_projects_by_ids(ids: Vec<String>, page: Option<u32>, limit: Option<u32>) -> ProjectsResponse {
+fn partner_v1_query_projects(query: partner::ProjectQueryV1, page: Option<u32>, limit: Option<u32>) -> partner::ProjectPageV1 {
     STATE.with(|state| {
         let state = state.borrow();
-        let projects: Vec<Project> = ids.iter()
-            .filter_map(|id| state.projects.get(id))
-            .cloned()
-            .collect();
-        
-        let (paginated_projects, total, pages) = paginate(projects, page, limit);
-        
-        ProjectsResponse {
-            projects: paginated_projects,
-            total,  // Now this is u64
-            page: page.unwrap_or(1),
-            pages,
+        // status_index and date_index are both in (created_at, id) order
+        let projects: Vec<&Project> = match query.resolve() {
+            Some(filter) => {
+                let ids = match &filter.status {
+                    Some(status) => state.status_index.get(status),
+                    None => Some(&state.date_index),
+                };
+                ids.into_iter()
+                    .flatten()
+                    .filter_map(|(_, id)| state.projects.get(id))
+                    .filter(|p| is_listed(p) && filter.matches(p))
+                    .collect()
+            }
+            None => vec![],
+        };
+
+        // v1 can't grow an error variant, so a zero limit rejects the call
+        let paged = paginate(projects, page, limit).unwrap_or_else(|e| ic_cdk::trap(&e));
+
+        partner::ProjectPageV1 {
+            projects: paged.items.into_iter().map(partner::ProjectV1::from).collect(),
+            total: paged.total,
+            page: paged.page,
+            pages: paged.pages,
         }
     })
 }
 
+// Unknown or unlisted ids are left out
 #[query]
-fn get_projects_by_owner(owner: Principal, page: Option<u32>, limit: Option<u32>) -> ProjectsResponse {
+fn partner_v1_get_vote_totals(project_ids: Vec<String>) -> Result<Vec<partner::VoteTotalV1>, String> {
+    if project_ids.len() > partner::MAX_VOTE_TOTALS_QUERY {
+        return Err(format!("At most {} projects per call", partner::MAX_VOTE_TOTALS_QUERY));
+    }
     STATE.with(|state| {
         let state = state.borrow();
-        let projects: Vec<Project> = state.owner_projects
-            .get(&owner)
-            .map(|ids| {
-                ids.iter()
-                    .filter_map(|id| state.projects.get(id))
-                    .cloned()
-                    .collect()
+        Ok(project_ids
+            .into_iter()
+            .filter_map(|id| {
+                let project = state.projects.get(&id).filter(|p| is_listed(p))?;
+                Some(partner::VoteTotalV1 { project_id: id, vote_count: project.vote_count })
             })
-            .unwrap_or_default();
-        
-        let (paginated_projects, total, pages) = paginate(projects, page, limit);
-        
-        ProjectsResponse {
-            projects: paginated_projects,
-            total,
-            page: page.unwrap_or(1),
-            pages,
+            .collect())
+    })
+}
+
+// Registry Interface
+fn registry_list(cursor: Option<&str>, limit: Option<u32>) -> Result<registry::RegistryPageV1, String> {
+    let limit = page_limit(limit, config().default_page_limit)?;
+    let start = match cursor {
+        Some(cursor) => Bound::Excluded(registry::parse_cursor(cursor)?),
+        None => Bound::Unbounded,
+    };
+    STATE.with(|state| {
+        let state = state.borrow();
+        // date_index is in (created_at, id) order, as the registry cursors expect
+        let projects = state.date_index
+            .range((start, Bound::Unbounded))
+            .filter_map(|(_, id)| state.projects.get(id))
+            .filter(|p| is_listed(p));
+        Ok(registry::page(projects, limit))
+    })
+}
+
+fn registry_get(id: &str) -> Option<registry::RegistryRecordV1> {
+    STATE.with(|state| {
+        state.borrow().projects
+            .get(id)
+            .filter(|p| is_listed(p))
+            .map(registry::RegistryRecordV1::from)
+    })
+}
+
+fn registry_count() -> u64 {
+    STATE.with(|state| listed_count(&state.borrow()) as u64)
+}
+
+// Pass next_cursor back to continue; None starts from the oldest project
+#[query]
+fn registry_v1_list(cursor: Option<String>, limit: Option<u32>) -> Result<registry::RegistryPageV1, String> {
+    registry_list(cursor.as_deref(), limit)
+}
+
+#[query]
+fn registry_v1_get(id: String) -> Option<registry::RegistryRecordV1> {
+    registry_get(&id)
+}
+
+#[query]
+fn registry_v1_count() -> u64 {
+    registry_count()
+}
+
+// Timers
+#[query]
+fn get_scheduled_jobs() -> Result<Vec<jobs::ScheduledJob>, String> {
+    if !caller_has_permission(Permission::ManageAdmins) {
+        return Err("Only super admins can inspect scheduled jobs".to_string());
+    }
+    Ok(jobs::list())
+}
+
+fn refresh_trending() {
+    let cutoff = ic_cdk::api::time().saturating_sub(TRENDING_WINDOW_NANOS);
+    STATE.with(|state| {
+        let mut state = state.borrow_mut();
+        let mut scored: Vec<(usize, u64, &String)> = state.projects
+            .values()
+            .filter(|p| is_listed(p))
+            .filter_map(|p| {
+                let recent = state.project_votes
+                    .get(&p.id)?
+                    .values()
+                    .filter(|vote| vote.timestamp >= cutoff)
+                    .count();
+                (recent > 0).then_some((recent, p.vote_count, &p.id))
+            })
+            .collect();
+        scored.sort_by(|a, b| b.cmp(a));
+        state.trending_projects = scored.into_iter().map(|(_, _, id)| id.clone()).collect();
+    });
+}
+
+// Re-hashes the projects changed since the last run; unlisted ones leave the tree
+fn certify_projects() {
+    let hashes = STATE.with(|state| {
+        let mut state = state.borrow_mut();
+        let changed = state.projects.take_changed();
+        changed.into_iter()
+            .map(|id| {
+                let hash = state.projects.get(&id)
+                    .filter(|p| is_listed(p))
+                    .and_then(|p| certification::record(&PublicProject::from(p)))
+                    .map(|record| certification::hash(&record));
+                (id, hash)
+            })
+            .collect()
+    });
+    certification::update(hashes);
+}
+
+// Aggregate platform metrics, as JSON for the analytics endpoint
+fn platform_metrics(state: &State, now: u64) -> serde_json::Value {
+    let mut projects_by_status: BTreeMap<String, u64> = BTreeMap::new();
+    for project in state.projects.values() {
+        *projects_by_status.entry(partner::status_name(&project.status).to_string()).or_insert(0) += 1;
+    }
+    let votes_per_day: Vec<serde_json::Value> = count_per_period(
+        state.project_votes.values().flat_map(|votes| votes.values()).map(|v| v.timestamp),
+        now,
+        NANOS_PER_DAY,
+        DASHBOARD_DAYS,
+    )
+    .into_iter()
+    .map(|day| serde_json::json!({ "day_start": day.period_start, "votes": day.count }))
+    .collect();
+
+    serde_json::json!({
+        "timestamp": now,
+        "total_projects": state.projects.len(),
+        "projects_by_status": projects_by_status,
+        "votes_per_day": votes_per_day,
+        "active_sensors": state.projects.values().map(|p| p.active_sensor_count as u64).sum::<u64>(),
+        "registered_sensors": state.sensors.len(),
+    })
+}
+
+fn push_analytics() {
+    let Some(url) = config().analytics_endpoint else {
+        return;
+    };
+    let now = ic_cdk::api::time();
+    STATE.with(|state| {
+        let mut state = state.borrow_mut();
+        if !state.analytics_push_enabled {
+            return;
+        }
+        let body = platform_metrics(&state, now).to_string();
+        let id = webhooks::enqueue(&url, ANALYTICS_EVENT, body, now);
+        state.last_analytics_push = Some((now, id));
+    });
+}
+
+// Reads the donation account balance of projects that have received donations,
+// least recently reconciled first
+fn reconcile_donations() {
+    let Some(ledger) = config().donation_ledger else {
+        return;
+    };
+    let mut due: Vec<(Option<u64>, String)> = STATE.with(|state| {
+        let state = state.borrow();
+        let funded: BTreeSet<&String> = state.donations.values().map(|d| &d.project_id).collect();
+        funded.into_iter()
+            .filter(|id| state.projects.contains_key(*id))
+            .map(|id| (state.funding_reconciliations.get(id).map(|r| r.reconciled_at), id.clone()))
+            .collect()
+    });
+    due.sort();
+    due.truncate(MAX_RECONCILIATIONS_PER_RUN);
+
+    ic_cdk::spawn(async move {
+        for (_, project_id) in due {
+            let account = Account {
+                owner: ic_cdk::id(),
+                subaccount: Some(ledger::project_subaccount(&project_id)),
+            };
+            // Failures are retried on the next run
+            let Ok(balance) = ledger::balance_of(ledger, account).await else {
+                continue;
+            };
+            STATE.with(|state| {
+                let mut state = state.borrow_mut();
+                let recorded_total = recorded_donation_balance(&state, &project_id);
+                state.funding_reconciliations.insert(project_id, FundingReconciliation {
+                    ledger_balance: balance,
+                    recorded_total,
+                    reconciled_at: ic_cdk::api::time(),
+                });
+            });
+        }
+    });
+}
+
+// Fetches climate for approved projects' cells, stalest first
+fn refresh_climate() {
+    let Some(template) = config().climate_api_url else {
+        return;
+    };
+    let now = ic_cdk::api::time();
+
+    let mut cells: HashMap<String, (f64, f64)> = HashMap::new();
+    STATE.with(|state| {
+        for project in state.borrow().projects.values() {
+            if project.status == ProjectStatus::Approved {
+                cells.entry(climate::cell(&project.location.geohash))
+                    .or_insert((project.location.lat, project.location.lng));
+            }
+        }
+    });
+    let mut due: Vec<(Option<u64>, String, (f64, f64))> = cells
+        .into_iter()
+        .map(|(cell, coordinates)| (climate::fetched_at(&cell), cell, coordinates))
+        .filter(|(fetched_at, _, _)| fetched_at.is_none_or(|at| now.saturating_sub(at) >= CLIMATE_MAX_AGE_NANOS))
+        .collect();
+    due.sort_by_key(|(fetched_at, _, _)| *fetched_at);
+    due.truncate(MAX_CLIMATE_FETCHES_PER_RUN);
+
+    ic_cdk::spawn(async move {
+        for (_, cell, (lat, lng)) in due {
+            // Failures are retried on the next run
+            let _ = climate::refresh(&template, cell, lat, lng, now).await;
+        }
+    });
+}
+
+// Moves a batch of votes older than vote_archive_after_rounds to the archive canister.
+// The votes leave local state while in flight and are put back only if the archive
+// definitely refused them; a batch without a reply is sent again on the next run.
+fn archive_old_votes() {
+    let config = config();
+    let Some(archive) = config.vote_archive else {
+        return;
+    };
+    let round = VOTE_ROUND_DAYS * NANOS_PER_DAY;
+    let cutoff = (ic_cdk::api::time() / round).saturating_sub(config.vote_archive_after_rounds as u64) * round;
+
+    let batch = STATE.with(|state| take_archive_batch(&mut state.borrow_mut(), cutoff));
+    if batch.is_empty() {
+        return;
+    }
+
+    ic_cdk::spawn(async move {
+        let result = vote_archive::append(archive, batch.clone()).await;
+        STATE.with(|state| finish_archive_batch(&mut state.borrow_mut(), batch, result));
+    });
+}
+
+// Takes the votes to send and drops them from local state, leaving archive keys behind
+fn take_archive_batch(state: &mut State, cutoff: u64) -> Vec<ProjectVote> {
+    if state.archiving_votes {
+        return vec![];
+    }
+    if !state.unconfirmed_vote_batch.is_empty() {
+        state.archiving_votes = true;
+        return std::mem::take(&mut state.unconfirmed_vote_batch);
+    }
+    let mut batch = vec![];
+    for (project_id, votes) in state.project_votes.iter_mut() {
+        let old: Vec<Principal> = votes.values()
+            .filter(|v| v.timestamp < cutoff)
+            .map(|v| v.voter)
+            .take(MAX_VOTES_PER_ARCHIVE_BATCH - batch.len())
+            .collect();
+        for voter in old {
+            if let Some(vote) = votes.remove(&voter) {
+                batch.push(ProjectVote { project_id: project_id.clone(), voter, timestamp: vote.timestamp });
+            }
+        }
+        if batch.len() >= MAX_VOTES_PER_ARCHIVE_BATCH {
+            break;
+        }
+    }
+    for vote in &batch {
+        state.archived_votes.insert(vote_archive::vote_key(&vote.voter, &vote.project_id));
+        if let Some(ids) = state.vote_index.get_mut(&vote.voter) {
+            ids.retain(|id| id != &vote.project_id);
+            if ids.is_empty() {
+                state.vote_index.remove(&vote.voter);
+            }
+        }
+    }
+    state.archiving_votes = !batch.is_empty();
+    batch
+}
+
+fn finish_archive_batch(state: &mut State, batch: Vec<ProjectVote>, result: Result<(), AppendError>) {
+    state.archiving_votes = false;
+    match result {
+        Ok(()) => {
+            for vote in batch {
+                *state.archived_vote_counts.entry(vote.project_id).or_default() += 1;
+            }
         }
-    })
+        Err(AppendError::Unknown) => state.unconfirmed_vote_batch = batch,
+        Err(AppendError::Failed) => {
+            for vote in batch {
+                state.archived_votes.remove(&vote_archive::vote_key(&vote.voter, &vote.project_id));
+                if !state.projects.contains_key(&vote.project_id) {
+                    continue;
+                }
+                state.vote_index.entry(vote.voter).or_default().push(vote.project_id.clone());
+                state.project_votes
+                    .entry(vote.project_id)
+                    .or_default()
+                    .entry(vote.voter)
+                    .or_insert(Vote { voter: vote.voter, timestamp: vote.timestamp });
+            }
+        }
+    }
 }
 
-#[query]
-fn get_projects_by_date_range(start: u64, end: u64, page: Option<u32>, limit: Option<u32>) -> ProjectsResponse {
-    STATE.with(|state| {
-        let state = state.borrow();
-        let projects: Vec<Project> = state.date_index
-            .range(start..=end)
-            .filter_map(|(_, id)| state.projects.get(id))
-            .cloned()
-            .collect();
-        
-        let (paginated_projects, total, pages) = paginate(projects, page, limit);
-        
-        ProjectsResponse {
-            projects: paginated_projects,
-            total,
-            page: page.unwrap_or(1),
-            pages,
+// Fills in empty addresses and the country of queued projects, a few per run
+fn geocode_projects() {
+    let Some(template) = config().geocoding_api_url else {
+        return;
+    };
+    let now = ic_cdk::api::time();
+    let batch: Vec<String> = STATE.with(|state| {
+        let mut state = state.borrow_mut();
+        let mut batch = Vec::new();
+        let mut waiting = VecDeque::new();
+        while let Some(project_id) = state.geocode_queue.pop_front() {
+            let backing_off = matches!(state.geocode_retries.get(&project_id), Some((_, retry_at)) if *retry_at > now);
+            if backing_off || batch.len() >= MAX_GEOCODES_PER_RUN {
+                waiting.push_back(project_id);
+            } else {
+                batch.push(project_id);
+            }
         }
-    })
+        state.geocode_queue = waiting;
+        batch
+    });
+    if batch.is_empty() {
+        return;
+    }
+
+    ic_cdk::spawn(async move {
+        for project_id in batch {
+            let Some(location) = STATE.with(|state| state.borrow().projects.get(&project_id).map(|p| p.location.clone())) else {
+                STATE.with(|state| state.borrow_mut().geocode_retries.remove(&project_id));
+                continue;
+            };
+            // The cache is shared by every project in a cell, so key it on the coordinates
+            let Ok(geohash) = encode_geohash(location.lat, location.lng) else {
+                continue;
+            };
+            let place = match geocoding::cached(&geohash) {
+                Some(place) => place,
+                None => match geocoding::reverse(&template, &geohash, location.lat, location.lng).await {
+                    Ok(place) => place,
+                    Err(_) => {
+                        retry_geocode(&project_id, now);
+                        continue;
+                    }
+                },
+            };
+
+            STATE.with(|state| {
+                let mut state = state.borrow_mut();
+                state.geocode_retries.remove(&project_id);
+                // The owner may have moved the project or typed an address meanwhile
+                let Some(project) = state.projects.get_mut(&project_id) else {
+                    return;
+                };
+                if project.location.geohash != location.geohash {
+                    return;
+                }
+                if project.location.address.trim().is_empty() {
+                    project.location.address = place.address;
+                }
+                set_country(&mut state, &project_id, place.country_code);
+            });
+        }
+    });
 }
 
-#[query]
-fn get_projects_by_location(lat: f64, lng: f64, radius: f64) -> Vec<Project> {
+// Re-queues a failed lookup with exponential backoff, giving up after MAX_GEOCODE_ATTEMPTS
+fn retry_geocode(project_id: &str, now: u64) {
     STATE.with(|state| {
-        let state = state.borrow();
-        let project_ids = geo_index::find(format!("{},{}", lat, lng), radius);
-        project_ids.iter()
-            .filter_map(|id| state.projects.get(id))
-            .cloned()
-            .collect()
-    })
+        let mut state = state.borrow_mut();
+        let attempts = state.geocode_retries.get(project_id).map_or(0, |(attempts, _)| *attempts) + 1;
+        if attempts >= MAX_GEOCODE_ATTEMPTS {
+            state.geocode_retries.remove(project_id);
+            return;
+        }
+        let delay = (GEOCODE_INTERVAL_SECS << attempts).saturating_mul(1_000_000_000);
+        state.geocode_retries.insert(project_id.to_string(), (attempts, now.saturating_add(delay)));
+        if !state.geocode_queue.iter().any(|id| id == project_id) {
+            state.geocode_queue.push_back(project_id.to_string());
+        }
+    });
 }
 
-#[query]
-fn get_project_votes(project_id: String) -> u64 {
-    STATE.with(|state| {
-        state.borrow()
-            .projects
-            .get(&project_id)
-            .map(|p| p.vote_count)
-            .unwrap_or(0)
-    })
+fn prune_rate_limits() {
+    rate_limit::prune(ic_cdk::api::time());
 }
 
-#[query]
-fn get_user_vote_for_project(project_id: String, user: Principal) -> bool {
-    STATE.with(|state| {
-        state.borrow()
-            .project_votes
-            .get(&project_id)
-            .map(|votes| votes.contains_key(&user))
-            .unwrap_or(false)
-    })
+fn prune_claim_codes() {
+    let now = ic_cdk::api::time();
+    STATE.with(|state| state.borrow_mut().claim_codes.retain(|_, claim| claim.expires_at >= now));
 }
 
-#[query]
-fn get_user_voted_projects(user: Principal, page: Option<u32>, limit: Option<u32>) -> ProjectsResponse {
-    STATE.with(|state| {
-        let state = state.borrow();
-        let projects: Vec<Project> = state.vote_index
-            .get(&user)
-            .map(|ids| {
-                ids.iter()
-                    .filter_map(|id| state.projects.get(id))
-                    .cloned()
-                    .collect()
-            })
-            .unwrap_or_default();
-        
-        let (paginated_projects, total, pages) = paginate(projects, page, limit);
-        
-        ProjectsResponse {
-            projects: paginated_projects,
-            total,
-            page: page.unwrap_or(1),
-            pages,
-        }
-    })
+fn deliver_webhooks() {
+    ic_cdk::spawn(webhooks::deliver_due(ic_cdk::api::time()));
 }
 
-#[query]
-fn get_projects_by_gateway_type(gateway_type: GatewayType, page: Option<u32>, limit: Option<u32>) -> ProjectsResponse {
-    STATE.with(|state| {
-        let state = state.borrow();
-        let projects: Vec<Project> = state.projects
-            .values()
-            .filter(|p| p.gateway_type == gateway_type)
-            .cloned()
-            .collect();
-        
-        let (paginated_projects, total, pages) = paginate(projects, page, limit);
-        
-        ProjectsResponse {
-            projects: paginated_projects,
-            total,
-            page: page.unwrap_or(1),
-            pages,
-        }
-    })
+fn deliver_events() {
+    subscriptions::deliver_due(ic_cdk::api::time());
 }
 
-#[query]
-fn get_projects_by_votes(min_votes: Option<u64>, max_votes: Option<u64>, page: Option<u32>, limit: Option<u32>) -> ProjectsResponse {
+// Opens an incident for every gateway that has gone silent; mark_gateway_seen closes them
+fn detect_gateway_incidents() {
+    let now = ic_cdk::api::time();
+    let threshold = config().incident_after_minutes.saturating_mul(60 * 1_000_000_000);
+
     STATE.with(|state| {
-        let state = state.borrow();
-        let mut projects: Vec<Project> = state.projects
+        let mut state = state.borrow_mut();
+        let silent: Vec<Gateway> = state.gateways
             .values()
-            .filter(|p| {
-                let meets_min = min_votes.map(|min| p.vote_count >= min).unwrap_or(true);
-                let meets_max = max_votes.map(|max| p.vote_count <= max).unwrap_or(true);
-                meets_min && meets_max
-            })
+            .filter(|g| !state.open_incidents.contains_key(&g.id))
+            .filter(|g| now.saturating_sub(g.last_seen.unwrap_or(g.registered_at)) >= threshold)
             .cloned()
             .collect();
-        
-        // Sort by vote count descending
-        projects.sort_by(|a, b| b.vote_count.cmp(&a.vote_count));
-        
-        let (paginated_projects, total, pages) = paginate(projects, page, limit);
-        
-        ProjectsResponse {
-            projects: paginated_projects,
-            total,
-            page: page.unwrap_or(1),
-            pages,
+
+        for gateway in silent {
+            let id = state.next_incident_id;
+            state.next_incident_id += 1;
+            state.incidents.insert(id, Incident {
+                id,
+                gateway_id: gateway.id.clone(),
+                project_id: gateway.project_id.clone(),
+                last_seen_at: gateway.last_seen,
+                opened_at: now,
+                closed_at: None,
+            });
+            state.open_incidents.insert(gateway.id.clone(), id);
+
+            let message = format!("Gateway {} has stopped reporting", gateway.id);
+            notify(&mut state, gateway.owner, gateway.project_id.clone(), message, now);
         }
-    })
+    });
 }
 
-#[query]
-fn get_featured_projects(page: Option<u32>, limit: Option<u32>) -> ProjectsResponse {
+// Reports assigned sensors that have gone silent once, until they are seen again
+fn detect_offline_sensors() {
+    let now = ic_cdk::api::time();
+    let threshold = config().incident_after_minutes.saturating_mul(60 * 1_000_000_000);
+
     STATE.with(|state| {
-        let state = state.borrow();
-        let projects: Vec<Project> = state.featured_projects
+        let mut state = state.borrow_mut();
+        let silent: Vec<(Sensor, String)> = state.sensors
             .values()
-            .filter_map(|id| state.projects.get(id))
-            .cloned()
+            .filter(|s| s.retired.is_none() && !state.offline_sensors.contains(&s.serial))
+            .filter(|s| s.last_seen.is_some_and(|seen| now.saturating_sub(seen) >= threshold))
+            .filter_map(|s| Some((s.clone(), s.project_id.clone()?)))
             .collect();
-        
-        let (paginated_projects, total, pages) = paginate(projects, page, limit);
-        
-        ProjectsResponse {
-            projects: paginated_projects,
-            total,
-            page: page.unwrap_or(1),
-            pages,
-        }
-    })
-}
 
-// Implement search functionality using index_text:
-#[query]
-fn search_projects(query: String, page: Option<u32>, limit: Option<u32>) -> ProjectsResponse {
-    STATE.with(|state| {
-        let state = state.borrow();
-        
-        // Get search terms
-        let search_terms = index_text(&query);
-        
-        // Search through projects
-        let mut projects: Vec<Project> = state.projects
-            .values()
-            .filter(|project| {
-                let project_terms = index_text(&project.name);
-                let desc_terms = index_text(&project.description);
-                
-                // Check if any search term matches project terms
-                search_terms.iter().any(|term| 
-                    project_terms.contains(term) || desc_terms.contains(term)
-                )
-            })
-            .cloned()
-            .collect();
-        
-        // Sort by relevance (simple implementation - could be improved)
-        projects.sort_by(|a, b| {
-            let a_name_terms = index_text(&a.name);
-            let b_name_terms = index_text(&b.name);
-            
-            // Count matching terms in name
-            let a_matches = search_terms.iter()
-                .filter(|term| a_name_terms.contains(term))
-                .count();
-            let b_matches = search_terms.iter()
-                .filter(|term| b_name_terms.contains(term))
-                .count();
-                
-            b_matches.cmp(&a_matches)
-        });
-        
-        let (paginated_projects, total, pages) = paginate(projects, page, limit);
-        
-        ProjectsResponse {
-            projects: paginated_projects,
-            total,
-            page: page.unwrap_or(1),
-            pages,
+        for (sensor, project_id) in silent {
+            state.offline_sensors.insert(sensor.serial.clone());
+            let Some(project_name) = state.projects.get(&project_id).map(|p| p.name.clone()) else {
+                continue;
+            };
+            let values = [("project", project_name.as_str()), ("serial", sensor.serial.as_str())];
+            relay_event(RelayEvent::SensorOffline, &project_id, &values, now);
+
+            let message = format!("Sensor {} has stopped reporting", sensor.serial);
+            notify(&mut state, sensor.owner, Some(project_id), message, now);
         }
-    })
+    });
 }
 
-// Add this query function to project.rs
-
-#[query]
-fn get_projects_by_status(status: ProjectStatus, page: Option<u32>, limit: Option<u32>) -> ProjectsResponse {
+// Active sensor counts decay as sensors go quiet, so they are recomputed periodically
+fn refresh_deployment_counts() {
+    let now = ic_cdk::api::time();
     STATE.with(|state| {
-        let state = state.borrow();
-        
-        // Collect projects with matching status and sort by created_at (newest first)
-        let mut projects: Vec<Project> = state.projects
-            .values()
-            .filter(|p| p.status == status)
-            .cloned()
-            .collect();
-        
-        // Sort by created_at timestamp in descending order (newest first)
-        projects.sort_by(|a, b| b.created_at.cmp(&a.created_at));
-        
-        let (paginated_projects, total, pages) = paginate(projects, page, limit);
-        
-        ProjectsResponse {
-            projects: paginated_projects,
-            total,
-            page: page.unwrap_or(1),
-            pages,
+        let mut state = state.borrow_mut();
+        let project_ids: Vec<String> = state.project_sensors.keys().cloned().collect();
+        for project_id in project_ids {
+            refresh_active_sensors(&mut state, &project_id, now);
         }
-    })
+    });
 }
 
-// Add functionality using get_distance_from_geohash:
-#[query]
-fn get_nearest_projects(geohash: String, limit: Option<u32>) -> Vec<(Project, f64)> {
+// Applies each sensor's retention policy; unassigned sensors use the config default
+fn prune_telemetry() {
+    let now = ic_cdk::api::time();
+    let default_policy = config().retention();
+    let cutoff = |days: u32| now.saturating_sub((days as u64).saturating_mul(NANOS_PER_DAY));
+
     STATE.with(|state| {
         let state = state.borrow();
-        let mut projects_with_distance: Vec<(Project, f64)> = state.projects
-            .values()
-            .map(|project| {
-                let distance = geo_index::get_distance_from_geohash(
-                    geohash.clone(),
-                    project.location.geohash.clone()
-                );
-                (project.clone(), distance)
-            })
-            .collect();
-        
-        // Sort by distance
-        projects_with_distance.sort_by(|a, b| 
-            a.1.partial_cmp(&b.1).unwrap_or(std::cmp::Ordering::Equal)
-        );
-        
-        // Take limited number of results
-        let limit = limit.unwrap_or(10) as usize;
-        projects_with_distance.truncate(limit);
-        
-        projects_with_distance
-    })
+        telemetry::prune(|serial| {
+            let policy = state.sensors
+                .get(serial)
+                .and_then(|s| s.project_id.as_ref())
+                .and_then(|project_id| state.retention_overrides.get(project_id))
+                .unwrap_or(&default_policy);
+            (cutoff(policy.raw_days), policy.hourly_days.map(cutoff))
+        });
+    });
 }
 
-// Stats and utility queries
-#[query]
-fn get_total_projects() -> u64 {
-    STATE.with(|state| state.borrow().projects.len() as u64)
-}
+// Activates scheduled features whose window has started and expires those that have ended
+fn process_feature_schedules() {
+    let now = ic_cdk::api::time();
 
-#[query]
-fn get_total_votes() -> u64 {
     STATE.with(|state| {
-        state.borrow()
-            .projects
-            .values()
-            .map(|p| p.vote_count)
-            .sum()
-    })
-}
+        let mut state = state.borrow_mut();
+        let mut windows: Vec<FeatureWindow> = state.feature_windows.values().cloned().collect();
+        windows.sort_by_key(|window| window.starts_at);
 
-#[query]
-fn get_index_stats() -> HashMap<String, usize> {
-    let mut stats = HashMap::new();
-    
-    STATE.with(|state| {
-        let state = state.borrow();
-        let indexed_projects = geo_index::view_index();
-        
-        stats.insert("total_indexed".to_string(), indexed_projects.len());
-        stats.insert("total_projects".to_string(), state.projects.len());
-        
-        // Count projects by status
-        for project in state.projects.values() {
-            let status_key = format!("status_{:?}", project.status);
-            *stats.entry(status_key).or_insert(0) += 1;
+        for window in windows {
+            if window.ends_at.is_some_and(|ends_at| ends_at <= now) {
+                state.feature_windows.remove(&window.project_id);
+                if window.active {
+                    clear_featured(&mut state, &window.project_id);
+                }
+            } else if !window.active && window.starts_at <= now {
+                // Stays scheduled and is retried next run if all slots are taken, unless
+                // it was paid for: then the booking is refunded in full
+                if activate_feature(&mut state, &window.project_id, now).is_ok() {
+                    if window.ends_at.is_some() {
+                        if let Some(window) = state.feature_windows.get_mut(&window.project_id) {
+                            window.active = true;
+                        }
+                    } else {
+                        state.feature_windows.remove(&window.project_id);
+                    }
+                } else if refund_unserved_bookings(&mut state, &window.project_id, now) {
+                    state.feature_windows.remove(&window.project_id);
+                }
+            }
         }
     });
-    
-    stats
 }
 
-#[query]
-fn is_admin(principal: Principal) -> bool {
-    STATE.with(|state| state.borrow().admins.contains_key(&principal))
+fn start_timers() {
+    jobs::schedule("check_stale_projects", STALE_CHECK_INTERVAL_SECS, check_stale_projects);
+    jobs::schedule("process_feature_schedules", FEATURE_SCHEDULE_INTERVAL_SECS, process_feature_schedules);
+    jobs::schedule("prune_telemetry", TELEMETRY_PRUNE_INTERVAL_SECS, prune_telemetry);
+    jobs::schedule("refresh_deployment_counts", DEPLOYMENT_REFRESH_INTERVAL_SECS, refresh_deployment_counts);
+    jobs::schedule("refresh_trending", TRENDING_REFRESH_INTERVAL_SECS, refresh_trending);
+    jobs::schedule("detect_gateway_incidents", INCIDENT_CHECK_INTERVAL_SECS, detect_gateway_incidents);
+    jobs::schedule("detect_offline_sensors", INCIDENT_CHECK_INTERVAL_SECS, detect_offline_sensors);
+    jobs::schedule("certify_projects", CERTIFICATION_INTERVAL_SECS, certify_projects);
+    jobs::schedule("refresh_climate", CLIMATE_REFRESH_INTERVAL_SECS, refresh_climate);
+    jobs::schedule("reconcile_donations", DONATION_RECONCILE_INTERVAL_SECS, reconcile_donations);
+    jobs::schedule("push_analytics", ANALYTICS_PUSH_INTERVAL_SECS, push_analytics);
+    jobs::schedule("archive_old_votes", VOTE_ARCHIVE_INTERVAL_SECS, archive_old_votes);
+    jobs::schedule("geocode_projects", GEOCODE_INTERVAL_SECS, geocode_projects);
+    jobs::schedule("prune_rate_limits", RATE_LIMIT_PRUNE_INTERVAL_SECS, prune_rate_limits);
+    jobs::schedule("prune_claim_codes", CLAIM_CODE_PRUNE_INTERVAL_SECS, prune_claim_codes);
+    jobs::schedule("deliver_webhooks", WEBHOOK_DELIVERY_INTERVAL_SECS, deliver_webhooks);
+    jobs::schedule("deliver_events", EVENT_DELIVERY_INTERVAL_SECS, deliver_events);
+    jobs::schedule("deliver_follower_notifications", FOLLOWER_FANOUT_INTERVAL_SECS, deliver_follower_notifications);
 }
 
-#[query]
-fn is_super_admin(principal: Principal) -> bool {
-    STATE.with(|state| {
-        state.borrow()
-            .admins
-            .get(&principal)
-            .copied()
-            .unwrap_or(false)
-    })
+#[init]
+fn init() {
+    start_timers();
 }
 
 // Pre-upgrade and post-upgrade hooks for stable storage
@@ -835,4 +9952,245 @@ fn pre_upgrade() {
 #[post_upgrade]
 fn post_upgrade() {
     // TODO: Implement stable storage
-}
\ No newline at end of file
+    start_timers();
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn items(n: u32) -> Vec<u32> {
+        (1..=n).collect()
+    }
+
+    #[test]
+    fn paginate_returns_requested_page() {
+        let page = paginate(items(25), Some(2), Some(10)).unwrap();
+        assert_eq!(page.items, (11..=20).collect::<Vec<_>>());
+        assert_eq!((page.total, page.page, page.pages), (25, 2, 3));
+        assert!(page.has_next && page.has_prev);
+    }
+
+    #[test]
+    fn paginate_last_page_is_partial() {
+        let page = paginate(items(25), Some(3), Some(10)).unwrap();
+        assert_eq!(page.items, (21..=25).collect::<Vec<_>>());
+        assert!(!page.has_next && page.has_prev);
+    }
+
+    #[test]
+    fn paginate_past_the_end_is_empty() {
+        let page = paginate(items(25), Some(4), Some(10)).unwrap();
+        assert!(page.items.is_empty());
+        assert_eq!((page.total, page.page, page.pages), (25, 4, 3));
+        assert!(!page.has_next && page.has_prev);
+
+        let page = paginate(items(25), Some(u32::MAX), Some(MAX_PAGE_LIMIT)).unwrap();
+        assert!(page.items.is_empty());
+    }
+
+    #[test]
+    fn paginate_page_zero_reads_as_first_page() {
+        let page = paginate(items(5), Some(0), Some(2)).unwrap();
+        assert_eq!(page.items, vec![1, 2]);
+        assert_eq!(page.page, 1);
+        assert!(page.has_next && !page.has_prev);
+    }
+
+    #[test]
+    fn paginate_rejects_zero_limit() {
+        assert!(paginate(items(5), Some(1), Some(0)).is_err());
+        assert!(paginate_prefix(items(5), 5, None, Some(0), Some(2)).is_err());
+    }
+
+    #[test]
+    fn paginate_caps_limit_and_defaults() {
+        let page = paginate(items(MAX_PAGE_LIMIT + 1), None, Some(MAX_PAGE_LIMIT + 50)).unwrap();
+        assert_eq!(page.items.len(), MAX_PAGE_LIMIT as usize);
+        assert_eq!(page.pages, 2);
+
+        let page = paginate(items(DEFAULT_PAGE_LIMIT + 1), None, None).unwrap();
+        assert_eq!(page.items.len(), DEFAULT_PAGE_LIMIT as usize);
+        assert_eq!(page.page, 1);
+    }
+
+    #[test]
+    fn paginate_resumes_from_offset() {
+        let page = paginate_prefix(items(25), 25, Some(1), Some(10), Some(7)).unwrap();
+        assert_eq!(page.items, (8..=17).collect::<Vec<_>>());
+        assert_eq!((page.start, page.page), (7, 1));
+        assert!(page.has_next && page.has_prev);
+
+        let page = paginate_prefix(items(25), 25, None, Some(10), Some(u64::MAX)).unwrap();
+        assert!(page.items.is_empty() && !page.has_next);
+    }
+
+    #[test]
+    fn paginate_empty_list() {
+        let page = paginate(Vec::<u32>::new(), Some(1), Some(10)).unwrap();
+        assert!(page.items.is_empty());
+        assert_eq!((page.total, page.pages), (0, 0));
+        assert!(!page.has_next && !page.has_prev);
+    }
+
+    fn add_project(state: &mut State, name: &str, description: &str, tags: &[&str], created_at: u64) -> String {
+        let data = ProjectData {
+            name: name.to_string(),
+            description: description.to_string(),
+            category: ProjectCategory::Water,
+            gateway_type: GatewayType::Wifi,
+            images: ProjectImages {
+                background: "https://example.org/background.jpg".to_string(),
+                gallery: vec![],
+                pins: None,
+            },
+            location: Location {
+                lat: 51.5,
+                lng: -0.12,
+                address: "London".to_string(),
+                geohash: encode_geohash(51.5, -0.12).unwrap(),
+            },
+            project_discord: None,
+            private_discord: String::new(),
+            sensors_required: 1,
+            video: None,
+            tags: tags.iter().map(|t| t.to_string()).collect(),
+        };
+        let project = new_project(state, data, Principal::anonymous(), ProjectStatus::Approved, created_at);
+        insert_project(state, project)
+    }
+
+    // What the leaderboard should hold, straight from vote_rank
+    fn expected_leaderboard(state: &State) -> Vec<(u64, String)> {
+        state.vote_rank.iter()
+            .rev()
+            .filter(|(_, id)| is_listed(&state.projects[id]))
+            .take(LEADERBOARD_SIZE)
+            .cloned()
+            .collect()
+    }
+
+    #[test]
+    fn vote_rank_follows_vote_count() {
+        let mut state = State::default();
+        let id = add_project(&mut state, "Pond", "", &[], 1);
+        assert!(state.vote_rank.contains(&(0, id.clone())));
+
+        set_vote_count(&mut state, &id, 7);
+        assert!(state.vote_rank.contains(&(7, id.clone())));
+        assert!(!state.vote_rank.contains(&(0, id)));
+        assert_eq!(state.vote_rank.len(), 1);
+    }
+
+    #[test]
+    fn leaderboard_refills_when_last_entry_drops() {
+        let mut state = State::default();
+        let ids: Vec<String> = (0..=LEADERBOARD_SIZE as u64)
+            .map(|i| add_project(&mut state, &format!("Project {}", i), "", &[], i))
+            .collect();
+        for (i, id) in ids.iter().enumerate() {
+            set_vote_count(&mut state, id, 10 + i as u64);
+        }
+        // ids[0] is off the board; ids[1] holds the last slot
+        set_vote_count(&mut state, &ids[0], 9);
+        set_vote_count(&mut state, &ids[1], 8);
+        assert!(state.leaderboard.iter().any(|(_, id)| *id == ids[0]));
+        assert!(!state.leaderboard.iter().any(|(_, id)| *id == ids[1]));
+        assert_eq!(state.leaderboard, expected_leaderboard(&state));
+
+        set_vote_count(&mut state, &ids[1], 1_000);
+        assert_eq!(state.leaderboard[0], (1_000, ids[1].clone()));
+        assert_eq!(state.leaderboard, expected_leaderboard(&state));
+    }
+
+    #[test]
+    fn leaderboard_skips_unlisted_projects() {
+        let mut state = State::default();
+        let listed = add_project(&mut state, "Listed", "", &[], 1);
+        let draft = add_project(&mut state, "Draft", "", &[], 2);
+        set_vote_count(&mut state, &listed, 3);
+        set_vote_count(&mut state, &draft, 5);
+
+        state.projects.get_mut(&draft).unwrap().status = ProjectStatus::Draft;
+        update_leaderboard(&mut state, &draft);
+        assert_eq!(state.leaderboard, vec![(3, listed)]);
+        assert_eq!(state.leaderboard, expected_leaderboard(&state));
+    }
+
+    #[test]
+    fn set_tags_moves_tag_index_entries() {
+        let mut state = State::default();
+        let id = add_project(&mut state, "Reef", "", &["Coral", "fish"], 1);
+        assert_eq!(state.tag_index["coral"], vec![id.clone()]);
+
+        set_tags(&mut state, &id, vec!["fish".to_string(), "kelp".to_string()]);
+        assert!(!state.tag_index.contains_key("coral"));
+        assert_eq!(state.tag_index["fish"], vec![id.clone()]);
+        assert_eq!(state.tag_index["kelp"], vec![id]);
+        assert!(!state.listed_tag_counts.contains_key("coral"));
+        assert_eq!(state.listed_tag_counts["fish"], 1);
+        assert_eq!(state.tag_rank.len(), 2);
+    }
+
+    #[test]
+    fn search_index_follows_edits() {
+        let mut state = State::default();
+        let id = add_project(&mut state, "River watch", "Water quality", &[], 1);
+        let other = add_project(&mut state, "River birds", "", &[], 2);
+        assert_eq!(state.search_index["river"].len(), 2);
+
+        let previous = project_search_terms(&state.projects[&id]);
+        state.projects.get_mut(&id).unwrap().name = "Lake watch".to_string();
+        update_search_index(&mut state, &id, &previous);
+        assert_eq!(state.search_index["river"], HashSet::from([other]));
+        assert!(state.search_index["lake"].contains(&id));
+        assert!(state.search_index["quality"].contains(&id));
+
+        let previous = project_search_terms(&state.projects[&id]);
+        state.projects.remove(&id);
+        update_search_index(&mut state, &id, &previous);
+        assert!(!state.search_index.contains_key("lake"));
+        assert!(!state.search_index.contains_key("quality"));
+    }
+
+    #[test]
+    fn archived_vote_cannot_be_cast_again() {
+        let mut state = State::default();
+        let id = add_project(&mut state, "Pond", "", &[], 1);
+        let voter = Principal::from_slice(&[1]);
+
+        cast_vote(&mut state, voter, &id, NANOS_PER_DAY).unwrap();
+        assert_eq!(cast_vote(&mut state, voter, &id, 2 * NANOS_PER_DAY), Err("Already voted".to_string()));
+
+        let batch = take_archive_batch(&mut state, u64::MAX);
+        assert_eq!(batch.len(), 1);
+        finish_archive_batch(&mut state, batch, Ok(()));
+        assert!(!state.project_votes[&id].contains_key(&voter));
+        assert!(!state.vote_index.contains_key(&voter));
+
+        assert_eq!(cast_vote(&mut state, voter, &id, 30 * NANOS_PER_DAY), Err("Already voted".to_string()));
+        assert_eq!(state.projects[&id].vote_count, 1);
+        assert_eq!(state.archived_vote_counts[&id], 1);
+    }
+
+    #[test]
+    fn notifications_are_capped_and_fanned_out() {
+        let mut state = State::default();
+        let id = add_project(&mut state, "Pond", "", &[], 1);
+        let reader = Principal::from_slice(&[1]);
+        for i in 0..MAX_NOTIFICATIONS as u64 + 5 {
+            notify(&mut state, reader, None, format!("Message {}", i), i);
+        }
+        let inbox = &state.notifications[&reader];
+        assert_eq!(inbox.len(), MAX_NOTIFICATIONS);
+        assert_eq!(inbox.front().unwrap().message, "Message 5");
+
+        let followers: HashSet<Principal> = (0..MAX_FOLLOWER_NOTIFICATIONS_PER_MESSAGE as u32 + 10)
+            .map(|i| Principal::from_slice(&i.to_be_bytes()))
+            .collect();
+        state.follower_index.insert(id.clone(), followers);
+        notify_followers(&mut state, &id, "Update".to_string(), 1);
+        assert_eq!(state.notifications.len(), MAX_FOLLOWER_NOTIFICATIONS_PER_MESSAGE + 1);
+        assert_eq!(state.follower_fanout[0].recipients.len(), 10);
+    }
+}
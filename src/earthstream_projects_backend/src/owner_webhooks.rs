@@ -0,0 +1,129 @@
+use candid::CandidType;
+use hmac::{Hmac, Mac};
+use serde::{Deserialize, Serialize};
+use sha2::Sha256;
+use std::cell::RefCell;
+use std::collections::{BTreeMap, VecDeque};
+
+use crate::webhooks;
+
+// Owners receive JSON of the form
+//   { "event": "StatusChanged", "project_id": "...", "timestamp": <nanos>, "data": { ... } }
+// with header X-Earthstream-Signature: sha256=<hex HMAC-SHA256 of the body under the
+// secret returned at registration>.
+
+pub const SIGNATURE_HEADER: &str = "X-Earthstream-Signature";
+const MAX_URL_LENGTH: usize = 500;
+const MAX_TRACKED_DELIVERIES: usize = 100;  // Per project, for list_webhook_deliveries
+const MAX_PENDING_DELIVERIES: usize = 20;  // Per project; further events are skipped until some are delivered or given up
+
+#[derive(CandidType, Serialize, Deserialize, Clone, Copy, Debug, PartialEq)]
+pub enum WebhookEvent {
+    StatusChanged,
+    VoteCast,
+    SensorAlert,
+}
+
+impl WebhookEvent {
+    fn name(&self) -> &'static str {
+        match self {
+            WebhookEvent::StatusChanged => "StatusChanged",
+            WebhookEvent::VoteCast => "VoteCast",
+            WebhookEvent::SensorAlert => "SensorAlert",
+        }
+    }
+}
+
+#[derive(CandidType, Serialize, Deserialize, Clone, Debug)]
+pub struct OwnerWebhook {
+    pub project_id: String,
+    pub url: String,
+    pub events: Vec<WebhookEvent>,
+    pub registered_at: u64,
+}
+
+struct Registration {
+    webhook: OwnerWebhook,
+    secret: Vec<u8>,
+    delivery_ids: VecDeque<u64>,  // Newest last
+}
+
+thread_local! {
+    static WEBHOOKS: RefCell<BTreeMap<String, Registration>> = RefCell::default();  // project_id -> registration
+}
+
+pub fn validate(url: &str, events: &[WebhookEvent]) -> Result<(), String> {
+    if !url.starts_with("https://") || url.len() > MAX_URL_LENGTH || url.chars().any(|c| c.is_whitespace()) {
+        return Err(format!("Webhook URL must be an https URL of at most {} characters", MAX_URL_LENGTH));
+    }
+    if events.is_empty() {
+        return Err("Select at least one event".to_string());
+    }
+    Ok(())
+}
+
+/// Registers or replaces the project's webhook. Replacing it also replaces the secret.
+pub fn register(webhook: OwnerWebhook, secret: Vec<u8>) {
+    WEBHOOKS.with(|webhooks| {
+        webhooks.borrow_mut().insert(webhook.project_id.clone(), Registration {
+            webhook,
+            secret,
+            delivery_ids: VecDeque::new(),
+        })
+    });
+}
+
+pub fn remove(project_id: &str) -> bool {
+    WEBHOOKS.with(|webhooks| webhooks.borrow_mut().remove(project_id).is_some())
+}
+
+pub fn get(project_id: &str) -> Option<OwnerWebhook> {
+    WEBHOOKS.with(|webhooks| webhooks.borrow().get(project_id).map(|r| r.webhook.clone()))
+}
+
+/// Newest first.
+pub fn deliveries(project_id: &str) -> Vec<webhooks::DeliveryRecord> {
+    WEBHOOKS.with(|webhooks| {
+        webhooks.borrow()
+            .get(project_id)
+            .map(|r| r.delivery_ids.iter().rev().filter_map(|id| webhooks::get_record(*id)).collect())
+            .unwrap_or_default()
+    })
+}
+
+fn sign(secret: &[u8], body: &str) -> String {
+    let mut mac = Hmac::<Sha256>::new_from_slice(secret).expect("HMAC accepts keys of any length");
+    mac.update(body.as_bytes());
+    format!("sha256={}", hex::encode(mac.finalize().into_bytes()))
+}
+
+/// Queues the event for the project's webhook if it subscribed to it.
+pub fn dispatch(project_id: &str, event: WebhookEvent, data: serde_json::Value, now: u64) {
+    WEBHOOKS.with(|webhooks| {
+        let mut webhooks = webhooks.borrow_mut();
+        let Some(registration) = webhooks.get_mut(project_id) else {
+            return;
+        };
+        if !registration.webhook.events.contains(&event) {
+            return;
+        }
+        let pending = registration.delivery_ids.iter().filter(|id| webhooks::is_pending(**id)).count();
+        if pending >= MAX_PENDING_DELIVERIES {
+            return;
+        }
+
+        let body = serde_json::json!({
+            "event": event.name(),
+            "project_id": project_id,
+            "timestamp": now,
+            "data": data,
+        }).to_string();
+        let headers = vec![(SIGNATURE_HEADER.to_string(), sign(&registration.secret, &body))];
+        let id = webhooks::enqueue_owner(&registration.webhook.url, event.name(), body, headers, now);
+
+        if registration.delivery_ids.len() >= MAX_TRACKED_DELIVERIES {
+            registration.delivery_ids.pop_front();
+        }
+        registration.delivery_ids.push_back(id);
+    });
+}
@@ -0,0 +1,161 @@
+use candid::{CandidType, Principal};
+use serde::{Deserialize, Serialize};
+
+use crate::{Project, ProjectCategory, ProjectStatus};
+
+// Types exposed to partner canisters through the partner_v1_* endpoints. They are
+// decoupled from the internal types and follow semver:
+//   - minor: new optional fields or new endpoints
+//   - major: anything else, shipped as partner_v2_* next to v1
+// Enums are exposed as text so new variants don't break existing decoders.
+pub const INTERFACE_NAME: &str = "earthstream:partner";
+pub const INTERFACE_VERSION: &str = "1.0.0";
+pub const MAX_VOTE_TOTALS_QUERY: usize = 100;
+
+#[derive(CandidType, Serialize, Deserialize, Clone, Debug)]
+pub struct SupportedInterface {
+    pub name: String,
+    pub version: String,
+}
+
+#[derive(CandidType, Serialize, Deserialize, Clone, Debug)]
+pub struct ProjectV1 {
+    pub id: String,
+    pub slug: String,
+    pub name: String,
+    pub category: String,
+    pub status: String,
+    pub owner: Principal,
+    pub lat: f64,
+    pub lng: f64,
+    pub tags: Vec<String>,
+    pub vote_count: u64,
+    pub sensors_required: u32,
+    pub sensors_assigned: u32,
+    pub created_at: u64,
+}
+
+impl From<&Project> for ProjectV1 {
+    fn from(project: &Project) -> Self {
+        Self {
+            id: project.id.clone(),
+            slug: project.slug.clone(),
+            name: project.name.clone(),
+            category: category_name(&project.category).to_string(),
+            status: status_name(&project.status).to_string(),
+            owner: project.owner,
+            lat: project.location.lat,
+            lng: project.location.lng,
+            tags: project.tags.clone(),
+            vote_count: project.vote_count,
+            sensors_required: project.sensors_required,
+            sensors_assigned: project.sensors_assigned,
+            created_at: project.created_at,
+        }
+    }
+}
+
+// Every set field must match; names are compared as returned in ProjectV1
+#[derive(CandidType, Serialize, Deserialize, Clone, Debug, Default)]
+pub struct ProjectQueryV1 {
+    pub category: Option<String>,
+    pub status: Option<String>,
+    pub tag: Option<String>,
+}
+
+impl ProjectQueryV1 {
+    /// The query with its names resolved; None when a name matches no variant,
+    /// so no project can match.
+    pub fn resolve(&self) -> Option<ProjectFilter> {
+        let category = match &self.category {
+            Some(name) => Some(parse_category(name)?),
+            None => None,
+        };
+        let status = match &self.status {
+            Some(name) => Some(parse_status(name)?),
+            None => None,
+        };
+        Some(ProjectFilter { category, status, tag: self.tag.clone() })
+    }
+}
+
+pub struct ProjectFilter {
+    pub category: Option<ProjectCategory>,
+    pub status: Option<ProjectStatus>,
+    pub tag: Option<String>,
+}
+
+impl ProjectFilter {
+    pub fn matches(&self, project: &Project) -> bool {
+        self.category.as_ref().is_none_or(|c| *c == project.category)
+            && self.status.as_ref().is_none_or(|s| *s == project.status)
+            && self.tag.as_ref().is_none_or(|t| project.tags.contains(t))
+    }
+}
+
+// Wire names are spelled out so renaming a variant can't change the frozen format
+pub fn category_name(category: &ProjectCategory) -> &'static str {
+    match category {
+        ProjectCategory::Reforestation => "Reforestation",
+        ProjectCategory::Bioacoustics => "Bioacoustics",
+        ProjectCategory::Water => "Water",
+        ProjectCategory::Wildlife => "Wildlife",
+        ProjectCategory::Soil => "Soil",
+        ProjectCategory::AirQuality => "AirQuality",
+        ProjectCategory::Marine => "Marine",
+        ProjectCategory::Agriculture => "Agriculture",
+        ProjectCategory::Other => "Other",
+    }
+}
+
+pub fn status_name(status: &ProjectStatus) -> &'static str {
+    match status {
+        ProjectStatus::PendingReview => "PendingReview",
+        ProjectStatus::Approved => "Approved",
+        ProjectStatus::Rejected => "Rejected",
+        ProjectStatus::Suspended => "Suspended",
+        ProjectStatus::Archived => "Archived",
+        ProjectStatus::Draft => "Draft",
+    }
+}
+
+fn parse_category(name: &str) -> Option<ProjectCategory> {
+    Some(match name {
+        "Reforestation" => ProjectCategory::Reforestation,
+        "Bioacoustics" => ProjectCategory::Bioacoustics,
+        "Water" => ProjectCategory::Water,
+        "Wildlife" => ProjectCategory::Wildlife,
+        "Soil" => ProjectCategory::Soil,
+        "AirQuality" => ProjectCategory::AirQuality,
+        "Marine" => ProjectCategory::Marine,
+        "Agriculture" => ProjectCategory::Agriculture,
+        "Other" => ProjectCategory::Other,
+        _ => return None,
+    })
+}
+
+fn parse_status(name: &str) -> Option<ProjectStatus> {
+    Some(match name {
+        "PendingReview" => ProjectStatus::PendingReview,
+        "Approved" => ProjectStatus::Approved,
+        "Rejected" => ProjectStatus::Rejected,
+        "Suspended" => ProjectStatus::Suspended,
+        "Archived" => ProjectStatus::Archived,
+        "Draft" => ProjectStatus::Draft,
+        _ => return None,
+    })
+}
+
+#[derive(CandidType, Serialize, Deserialize, Clone, Debug)]
+pub struct ProjectPageV1 {
+    pub projects: Vec<ProjectV1>,
+    pub total: u64,
+    pub page: u32,
+    pub pages: u32,
+}
+
+#[derive(CandidType, Serialize, Deserialize, Clone, Debug)]
+pub struct VoteTotalV1 {
+    pub project_id: String,
+    pub vote_count: u64,
+}
@@ -0,0 +1,71 @@
+use candid::{CandidType, Principal};
+use serde::{Deserialize, Serialize};
+use std::cell::RefCell;
+use std::collections::HashMap;
+
+const MAX_DISPLAY_NAME_LENGTH: usize = 50;
+const MAX_AVATAR_URL_LENGTH: usize = 500;
+const MAX_PROFILES: usize = 100_000;
+
+#[derive(CandidType, Serialize, Deserialize, Clone, Debug)]
+pub struct Profile {
+    pub display_name: String,
+    pub avatar_url: Option<String>,
+    pub updated_at: u64,
+}
+
+thread_local! {
+    static PROFILES: RefCell<HashMap<Principal, Profile>> = RefCell::default();
+    static NAMES: RefCell<HashMap<String, Principal>> = RefCell::default();  // Case-folded display name -> owner
+}
+
+fn validate(profile: &Profile) -> Result<(), String> {
+    let name = &profile.display_name;
+    if name.trim().is_empty() || name.chars().count() > MAX_DISPLAY_NAME_LENGTH {
+        return Err(format!("Display name must be 1 to {} characters", MAX_DISPLAY_NAME_LENGTH));
+    }
+    if name.trim() != name || name.chars().any(char::is_control) {
+        return Err("Display name cannot have surrounding whitespace or control characters".to_string());
+    }
+    if let Some(url) = &profile.avatar_url {
+        if !url.starts_with("https://") || url.len() > MAX_AVATAR_URL_LENGTH {
+            return Err(format!("Avatar URL must be an https URL of at most {} characters", MAX_AVATAR_URL_LENGTH));
+        }
+    }
+    Ok(())
+}
+
+// Names differing only in case would let one user pass for another
+fn name_key(display_name: &str) -> String {
+    display_name.to_lowercase()
+}
+
+pub fn set(principal: Principal, profile: Profile) -> Result<(), String> {
+    validate(&profile)?;
+    let key = name_key(&profile.display_name);
+    PROFILES.with(|profiles| {
+        NAMES.with(|names| {
+            let mut profiles = profiles.borrow_mut();
+            let mut names = names.borrow_mut();
+            if names.get(&key).is_some_and(|owner| *owner != principal) {
+                return Err("Display name is already taken".to_string());
+            }
+            if !profiles.contains_key(&principal) && profiles.len() >= MAX_PROFILES {
+                return Err("Profile limit reached".to_string());
+            }
+            if let Some(previous) = profiles.insert(principal, profile) {
+                names.remove(&name_key(&previous.display_name));
+            }
+            names.insert(key, principal);
+            Ok(())
+        })
+    })
+}
+
+pub fn get(principal: &Principal) -> Option<Profile> {
+    PROFILES.with(|profiles| profiles.borrow().get(principal).cloned())
+}
+
+pub fn display_name(principal: &Principal) -> Option<String> {
+    PROFILES.with(|profiles| profiles.borrow().get(principal).map(|p| p.display_name.clone()))
+}
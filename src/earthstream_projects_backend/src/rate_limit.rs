@@ -0,0 +1,138 @@
+use candid::{CandidType, Principal};
+use serde::{Deserialize, Serialize};
+use std::cell::RefCell;
+use std::collections::{BTreeMap, HashMap};
+
+const NANOS_PER_SECOND: u64 = 1_000_000_000;
+const MAX_CAPACITY: u32 = 10_000;
+const MAX_REFILL_INTERVAL_SECONDS: u64 = 30 * 24 * 60 * 60;
+
+#[derive(CandidType, Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub enum RateLimitedMethod {
+    CreateProject,
+    Vote,
+    Report,
+    Follow,
+    Appeal,
+}
+
+// Token bucket: up to `capacity` calls in a burst, one token regained every `refill_interval_seconds`
+#[derive(CandidType, Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct RateLimit {
+    pub capacity: u32,
+    pub refill_interval_seconds: u64,
+}
+
+impl RateLimit {
+    fn interval(&self) -> u64 {
+        self.refill_interval_seconds.saturating_mul(NANOS_PER_SECOND)
+    }
+}
+
+struct Bucket {
+    tokens: u32,
+    updated_at: u64,
+}
+
+type Limits = BTreeMap<RateLimitedMethod, RateLimit>;
+type Buckets = HashMap<(Principal, RateLimitedMethod), Bucket>;
+
+thread_local! {
+    static LIMITS: RefCell<Limits> = RefCell::new(default_limits());
+    static BUCKETS: RefCell<Buckets> = RefCell::default();  // Only callers who have spent tokens recently
+}
+
+fn default_limits() -> Limits {
+    BTreeMap::from([
+        (RateLimitedMethod::CreateProject, RateLimit { capacity: 5, refill_interval_seconds: 720 }),
+        (RateLimitedMethod::Vote, RateLimit { capacity: 30, refill_interval_seconds: 10 }),
+        (RateLimitedMethod::Report, RateLimit { capacity: 10, refill_interval_seconds: 360 }),
+        (RateLimitedMethod::Follow, RateLimit { capacity: 30, refill_interval_seconds: 60 }),
+        (RateLimitedMethod::Appeal, RateLimit { capacity: 3, refill_interval_seconds: 3600 }),
+    ])
+}
+
+pub fn set_limit(method: RateLimitedMethod, limit: Option<RateLimit>) -> Result<(), String> {
+    if let Some(limit) = &limit {
+        if limit.capacity == 0 || limit.refill_interval_seconds == 0 {
+            return Err("Capacity and refill interval must be positive".to_string());
+        }
+        if limit.capacity > MAX_CAPACITY || limit.refill_interval_seconds > MAX_REFILL_INTERVAL_SECONDS {
+            return Err(format!(
+                "Capacity cannot exceed {} and the refill interval {} seconds",
+                MAX_CAPACITY, MAX_REFILL_INTERVAL_SECONDS
+            ));
+        }
+    }
+    LIMITS.with(|limits| match limit {
+        Some(limit) => limits.borrow_mut().insert(method, limit),
+        None => limits.borrow_mut().remove(&method),
+    });
+    // Drop existing buckets so the new limit applies from a full bucket
+    BUCKETS.with(|buckets| buckets.borrow_mut().retain(|(_, m), _| *m != method));
+    Ok(())
+}
+
+pub fn limits() -> Vec<(RateLimitedMethod, RateLimit)> {
+    LIMITS.with(|limits| limits.borrow().iter().map(|(m, l)| (*m, l.clone())).collect())
+}
+
+fn limit(method: RateLimitedMethod) -> Option<RateLimit> {
+    LIMITS.with(|limits| limits.borrow().get(&method).cloned())
+}
+
+// Tokens in the bucket at `now`; a missing bucket is full
+fn available(bucket: Option<&Bucket>, limit: &RateLimit, now: u64) -> (u32, u64) {
+    let Some(bucket) = bucket else {
+        return (limit.capacity, now);
+    };
+    let interval = limit.interval();
+    let refilled = now.saturating_sub(bucket.updated_at) / interval;
+    let tokens = (bucket.tokens as u64).saturating_add(refilled).min(limit.capacity as u64) as u32;
+    if tokens == limit.capacity {
+        return (tokens, now);
+    }
+    (tokens, bucket.updated_at + refilled * interval)
+}
+
+/// Fails when the caller's bucket for `method` is empty, without taking a token.
+/// Methods without a configured limit are unrestricted.
+pub fn check(caller: Principal, method: RateLimitedMethod, now: u64) -> Result<(), String> {
+    let Some(limit) = limit(method) else {
+        return Ok(());
+    };
+    let (tokens, updated_at) = BUCKETS.with(|buckets| available(buckets.borrow().get(&(caller, method)), &limit, now));
+    if tokens == 0 {
+        let wait = updated_at.saturating_add(limit.interval()).saturating_sub(now) / NANOS_PER_SECOND;
+        return Err(format!("Rate limit exceeded, try again in {} seconds", wait.max(1)));
+    }
+    Ok(())
+}
+
+/// Takes a token for a call that went through. Call `check` first; a call that
+/// failed validation doesn't cost the caller anything.
+pub fn record(caller: Principal, method: RateLimitedMethod, now: u64) {
+    let Some(limit) = limit(method) else {
+        return;
+    };
+    BUCKETS.with(|buckets| {
+        let mut buckets = buckets.borrow_mut();
+        let (tokens, updated_at) = available(buckets.get(&(caller, method)), &limit, now);
+        buckets.insert((caller, method), Bucket {
+            tokens: tokens.saturating_sub(1),
+            updated_at,
+        });
+    });
+}
+
+/// Drops buckets that have refilled, which behave the same as no bucket.
+pub fn prune(now: u64) {
+    LIMITS.with(|limits| {
+        let limits = limits.borrow();
+        BUCKETS.with(|buckets| {
+            buckets.borrow_mut().retain(|(_, method), bucket| {
+                limits.get(method).is_some_and(|limit| available(Some(bucket), limit, now).0 < limit.capacity)
+            });
+        });
+    });
+}
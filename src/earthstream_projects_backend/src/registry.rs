@@ -0,0 +1,82 @@
+use candid::CandidType;
+use serde::{Deserialize, Serialize};
+
+use crate::partner::{category_name, status_name};
+use crate::Project;
+
+// Registry-neutral view of projects for aggregators indexing several registries.
+// The same records are served through registry_v1_* and, as JSON, under
+// HTTP_PATH (see http_registry). Records are listed oldest first by (created_at, id),
+// so a cursor stays valid as projects are added.
+pub const INTERFACE_NAME: &str = "registry";
+pub const INTERFACE_VERSION: &str = "1.0.0";
+pub const HTTP_PATH: &str = "/registry/v1";
+pub const REGISTRY_NAME: &str = "earthstream";
+
+#[derive(CandidType, Serialize, Deserialize, Clone, Debug)]
+pub struct RegistryRecordV1 {
+    pub id: String,  // Stable for the life of the project
+    pub registry: String,
+    pub name: String,
+    pub description: String,
+    pub category: String,
+    pub status: String,
+    pub country_code: Option<String>,
+    pub lat: f64,
+    pub lng: f64,
+    pub verified: bool,
+    pub created_at: u64,
+    pub updated_at: u64,
+}
+
+impl From<&Project> for RegistryRecordV1 {
+    fn from(project: &Project) -> Self {
+        Self {
+            id: project.id.clone(),
+            registry: REGISTRY_NAME.to_string(),
+            name: project.name.clone(),
+            description: project.description.clone(),
+            category: category_name(&project.category).to_string(),
+            status: status_name(&project.status).to_string(),
+            country_code: project.country_code.clone(),
+            lat: project.location.lat,
+            lng: project.location.lng,
+            verified: project.verified,
+            created_at: project.created_at,
+            updated_at: project.last_activity_at.max(project.status_updated_at),
+        }
+    }
+}
+
+#[derive(CandidType, Serialize, Deserialize, Clone, Debug)]
+pub struct RegistryPageV1 {
+    pub records: Vec<RegistryRecordV1>,
+    pub next_cursor: Option<String>,  // None on the last page
+}
+
+/// Opaque to clients; encodes the position after the given record.
+fn cursor_after(record: &RegistryRecordV1) -> String {
+    hex::encode(format!("{}:{}", record.created_at, record.id))
+}
+
+pub fn parse_cursor(cursor: &str) -> Result<(u64, String), String> {
+    let invalid = || "Invalid cursor".to_string();
+    let decoded = hex::decode(cursor).map_err(|_| invalid())?;
+    let decoded = String::from_utf8(decoded).map_err(|_| invalid())?;
+    let (created_at, id) = decoded.split_once(':').ok_or_else(invalid)?;
+    Ok((created_at.parse().map_err(|_| invalid())?, id.to_string()))
+}
+
+/// One page from `projects`, the listed projects after the cursor in (created_at, id) order.
+pub fn page<'a>(projects: impl Iterator<Item = &'a Project>, limit: usize) -> RegistryPageV1 {
+    let mut projects = projects.peekable();
+    let records: Vec<RegistryRecordV1> = projects
+        .by_ref()
+        .take(limit)
+        .map(RegistryRecordV1::from)
+        .collect();
+    let next_cursor = records.last()
+        .filter(|_| projects.peek().is_some())
+        .map(cursor_after);
+    RegistryPageV1 { records, next_cursor }
+}
@@ -0,0 +1,74 @@
+use candid::CandidType;
+use serde::{Deserialize, Serialize};
+
+// Events forwarded to the configured notification relay, which turns them into
+// emails, chat messages, etc. Each is posted as
+//   { "event": "project_approved", "project_id": ..., "message": ..., "timestamp": ... }
+// where message is the event's template with its {placeholders} filled in.
+
+const MAX_TEMPLATE_LENGTH: usize = 500;
+
+#[derive(CandidType, Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum RelayEvent {
+    ProjectApproved,  // {project}
+    VoteMilestone,  // {project}, {votes}
+    SensorOffline,  // {project}, {serial}
+}
+
+#[derive(CandidType, Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct RelayTemplate {
+    pub event: RelayEvent,
+    pub template: String,
+}
+
+impl RelayEvent {
+    pub fn name(self) -> &'static str {
+        match self {
+            RelayEvent::ProjectApproved => "project_approved",
+            RelayEvent::VoteMilestone => "vote_milestone",
+            RelayEvent::SensorOffline => "sensor_offline",
+        }
+    }
+
+    fn default_template(self) -> &'static str {
+        match self {
+            RelayEvent::ProjectApproved => "Project \"{project}\" has been approved",
+            RelayEvent::VoteMilestone => "Project \"{project}\" reached {votes} votes",
+            RelayEvent::SensorOffline => "Sensor {serial} on project \"{project}\" has stopped reporting",
+        }
+    }
+}
+
+pub fn validate_templates(templates: &[RelayTemplate]) -> Result<(), String> {
+    for (i, template) in templates.iter().enumerate() {
+        if template.template.trim().is_empty() || template.template.len() > MAX_TEMPLATE_LENGTH {
+            return Err(format!("Relay templates must be 1 to {} characters", MAX_TEMPLATE_LENGTH));
+        }
+        if templates[..i].iter().any(|t| t.event == template.event) {
+            return Err(format!("Duplicate relay template for {}", template.event.name()));
+        }
+    }
+    Ok(())
+}
+
+/// Renders the event with the configured template, falling back to the default.
+pub fn render(event: RelayEvent, templates: &[RelayTemplate], values: &[(&str, &str)]) -> String {
+    let template = templates
+        .iter()
+        .find(|t| t.event == event)
+        .map(|t| t.template.as_str())
+        .unwrap_or(event.default_template());
+    values.iter().fold(template.to_string(), |message, (name, value)| {
+        message.replace(&format!("{{{}}}", name), value)
+    })
+}
+
+pub fn body(event: RelayEvent, project_id: &str, message: &str, timestamp: u64) -> String {
+    serde_json::json!({
+        "event": event.name(),
+        "project_id": project_id,
+        "message": message,
+        "timestamp": timestamp,
+    })
+    .to_string()
+}
@@ -0,0 +1,102 @@
+use candid::CandidType;
+use serde::{Deserialize, Serialize};
+use std::cell::RefCell;
+use std::collections::BTreeMap;
+
+const MAX_ID_LENGTH: usize = 32;
+const MAX_NAME_LENGTH: usize = 64;
+const MAX_UNIT_LENGTH: usize = 16;
+
+#[derive(CandidType, Serialize, Deserialize, Clone, Debug)]
+pub struct SensorType {
+    pub id: String,  // Stable key referenced by sensors, e.g. "soil_moisture"
+    pub name: String,
+    pub unit: String,  // Unit of the primary metric, e.g. "%" or "°C"; empty for cameras
+    pub description: Option<String>,
+    // Plausibility limits for readings whose metric equals `id`; values outside them are flagged
+    pub min_value: Option<f64>,
+    pub max_value: Option<f64>,
+    pub max_jump: Option<f64>,  // Largest believable change between consecutive readings
+}
+
+type Catalogue = BTreeMap<String, SensorType>; // id -> type
+
+thread_local! {
+    static CATALOGUE: RefCell<Catalogue> = RefCell::new(default_catalogue());
+}
+
+fn default_catalogue() -> Catalogue {
+    [
+        ("temperature", "Temperature", "°C", Some((-60.0, 70.0))),
+        ("humidity", "Relative Humidity", "%", Some((0.0, 100.0))),
+        ("acoustic", "Acoustic", "dB", Some((0.0, 194.0))),
+        ("soil_moisture", "Soil Moisture", "%", Some((0.0, 100.0))),
+        ("camera_trap", "Camera Trap", "", None),
+    ]
+    .into_iter()
+    .map(|(id, name, unit, range)| {
+        (id.to_string(), SensorType {
+            id: id.to_string(),
+            name: name.to_string(),
+            unit: unit.to_string(),
+            description: None,
+            min_value: range.map(|(min, _)| min),
+            max_value: range.map(|(_, max)| max),
+            max_jump: None,
+        })
+    })
+    .collect()
+}
+
+fn validate(sensor_type: &SensorType) -> Result<(), String> {
+    let id = &sensor_type.id;
+    if id.is_empty() || id.len() > MAX_ID_LENGTH {
+        return Err(format!("Id must be 1 to {} characters", MAX_ID_LENGTH));
+    }
+    if !id.chars().all(|c| c.is_ascii_lowercase() || c.is_ascii_digit() || c == '_') {
+        return Err("Id may only contain lowercase letters, digits and underscores".to_string());
+    }
+    if sensor_type.name.trim().is_empty() || sensor_type.name.len() > MAX_NAME_LENGTH {
+        return Err(format!("Name must be 1 to {} characters", MAX_NAME_LENGTH));
+    }
+    if sensor_type.unit.len() > MAX_UNIT_LENGTH {
+        return Err(format!("Unit cannot exceed {} characters", MAX_UNIT_LENGTH));
+    }
+    let limits = [sensor_type.min_value, sensor_type.max_value, sensor_type.max_jump];
+    if limits.iter().flatten().any(|v| !v.is_finite()) {
+        return Err("Limits must be finite numbers".to_string());
+    }
+    if let (Some(min), Some(max)) = (sensor_type.min_value, sensor_type.max_value) {
+        if min > max {
+            return Err("min_value cannot exceed max_value".to_string());
+        }
+    }
+    if sensor_type.max_jump.is_some_and(|jump| jump <= 0.0) {
+        return Err("max_jump must be positive".to_string());
+    }
+    Ok(())
+}
+
+pub fn upsert(sensor_type: SensorType) -> Result<(), String> {
+    validate(&sensor_type)?;
+    CATALOGUE.with(|catalogue| catalogue.borrow_mut().insert(sensor_type.id.clone(), sensor_type));
+    Ok(())
+}
+
+pub fn remove(id: &str) -> Result<(), String> {
+    CATALOGUE.with(|catalogue| catalogue.borrow_mut().remove(id))
+        .map(|_| ())
+        .ok_or("Sensor type not found".to_string())
+}
+
+pub fn get(id: &str) -> Option<SensorType> {
+    CATALOGUE.with(|catalogue| catalogue.borrow().get(id).cloned())
+}
+
+pub fn exists(id: &str) -> bool {
+    CATALOGUE.with(|catalogue| catalogue.borrow().contains_key(id))
+}
+
+pub fn list() -> Vec<SensorType> {
+    CATALOGUE.with(|catalogue| catalogue.borrow().values().cloned().collect())
+}
@@ -0,0 +1,62 @@
+use candid::{CandidType, Principal};
+use serde::{Deserialize, Serialize};
+
+use crate::{ProjectData, ProjectsResponse, PublicProject};
+
+// Router mode: projects whose geohash starts with a shard's prefix are stored on
+// that sibling canister, which runs this same code with `router` set to us.
+// Fan-out reads use composite queries, so shards must live on the router's subnet.
+
+const GEOHASH_ALPHABET: &str = "0123456789bcdefghjkmnpqrstuvwxyz";
+const MAX_PREFIX_LENGTH: usize = 6;
+
+#[derive(CandidType, Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct RegionShard {
+    pub geohash_prefix: String,
+    pub canister: Principal,
+}
+
+pub fn validate(shards: &[RegionShard]) -> Result<(), String> {
+    for (i, shard) in shards.iter().enumerate() {
+        let prefix = &shard.geohash_prefix;
+        if prefix.is_empty() || prefix.len() > MAX_PREFIX_LENGTH {
+            return Err(format!("Shard prefixes must be 1 to {} characters", MAX_PREFIX_LENGTH));
+        }
+        if !prefix.chars().all(|c| GEOHASH_ALPHABET.contains(c)) {
+            return Err(format!("Shard prefix {} is not a geohash", prefix));
+        }
+        if shards[..i].iter().any(|s| s.geohash_prefix == *prefix) {
+            return Err(format!("Duplicate shard prefix {}", prefix));
+        }
+    }
+    Ok(())
+}
+
+/// The shard owning the geohash, by longest matching prefix.
+pub fn shard_for(shards: &[RegionShard], geohash: &str) -> Option<Principal> {
+    shards.iter()
+        .filter(|s| geohash.starts_with(&s.geohash_prefix))
+        .max_by_key(|s| s.geohash_prefix.len())
+        .map(|s| s.canister)
+}
+
+pub async fn create_project(shard: Principal, owner: Principal, data: ProjectData, allow_duplicate: Option<bool>) -> Result<String, String> {
+    let (result,): (Result<String, String>,) = ic_cdk::call(shard, "create_project_for", (owner, data, allow_duplicate))
+        .await
+        .map_err(|(_, e)| format!("Region shard call failed: {}", e))?;
+    result
+}
+
+pub async fn get_project(shard: Principal, id: &str) -> Result<Option<PublicProject>, String> {
+    let (project,): (Option<PublicProject>,) = ic_cdk::call(shard, "get_project", (id,))
+        .await
+        .map_err(|(_, e)| format!("Region shard call failed: {}", e))?;
+    Ok(project)
+}
+
+pub async fn search_projects(shard: Principal, query: &str, limit: u32) -> Result<ProjectsResponse, String> {
+    let (response,): (Result<ProjectsResponse, String>,) = ic_cdk::call(shard, "search_projects", (query, Some(1u32), Some(limit)))
+        .await
+        .map_err(|(_, e)| format!("Region shard call failed: {}", e))?;
+    response
+}
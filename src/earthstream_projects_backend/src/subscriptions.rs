@@ -0,0 +1,232 @@
+use candid::{CandidType, Principal};
+use serde::{Deserialize, Serialize};
+use std::cell::RefCell;
+use std::collections::{BTreeMap, BTreeSet, VecDeque};
+
+use crate::ProjectStatus;
+
+// Subscriber canisters are expected to provide
+//   on_earthstream_events : (vec Event) -> ()
+// and to call ack_events with the highest seq they have processed. Events are sent
+// with one-way calls, so anything not acknowledged is sent again with backoff:
+// subscribers must tolerate duplicates and skip seqs they have already seen.
+
+pub const CALLBACK_METHOD: &str = "on_earthstream_events";
+const MAX_SUBSCRIBERS: usize = 50;
+const MAX_EVENTS: usize = 10_000;  // Oldest dropped first; a subscriber this far behind sees a gap in seq
+const MAX_BATCH: usize = 100;
+const RETRY_BASE_NANOS: u64 = 30 * 1_000_000_000;  // Doubles after every unacknowledged send
+const MAX_RETRY_NANOS: u64 = 60 * 60 * 1_000_000_000;
+
+#[derive(CandidType, Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum EventType {
+    ProjectCreated,
+    StatusChanged,
+    VoteCast,
+}
+
+#[derive(CandidType, Serialize, Deserialize, Clone, Debug)]
+pub enum EventPayload {
+    ProjectCreated { project_id: String, owner: Principal, status: ProjectStatus },
+    StatusChanged { project_id: String, status: ProjectStatus },
+    VoteCast { project_id: String, voter: Principal, vote_count: u64 },
+}
+
+impl EventPayload {
+    fn event_type(&self) -> EventType {
+        match self {
+            EventPayload::ProjectCreated { .. } => EventType::ProjectCreated,
+            EventPayload::StatusChanged { .. } => EventType::StatusChanged,
+            EventPayload::VoteCast { .. } => EventType::VoteCast,
+        }
+    }
+}
+
+#[derive(CandidType, Serialize, Deserialize, Clone, Debug)]
+pub struct Event {
+    pub seq: u64,
+    pub timestamp: u64,
+    pub payload: EventPayload,
+}
+
+#[derive(CandidType, Serialize, Deserialize, Clone, Debug)]
+pub struct Subscription {
+    pub callback_canister: Principal,
+    pub event_types: Vec<EventType>,
+    pub acked_seq: u64,  // Every event up to and including this seq has been processed
+    pub subscribed_at: u64,
+    pub attempts: u32,  // Sends since the last acknowledgement
+    pub next_attempt_at: u64,
+    pub last_error: Option<String>,
+}
+
+thread_local! {
+    static SUBSCRIPTIONS: RefCell<BTreeMap<Principal, Subscription>> = RefCell::default();
+    static ALLOWED: RefCell<BTreeSet<Principal>> = RefCell::default();  // Canisters admins have let subscribe
+    static EVENTS: RefCell<VecDeque<Event>> = RefCell::default();
+    static NEXT_SEQ: RefCell<u64> = const { RefCell::new(1) };
+}
+
+/// Adds the subscriber, or replaces the event types of an existing one. New
+/// subscribers only receive events emitted from now on.
+pub fn subscribe(callback_canister: Principal, event_types: Vec<EventType>, now: u64) -> Result<(), String> {
+    if event_types.is_empty() {
+        return Err("Subscribe to at least one event type".to_string());
+    }
+    if !ALLOWED.with(|allowed| allowed.borrow().contains(&callback_canister)) {
+        return Err("Canister is not allowed to subscribe; ask an admin to allow it".to_string());
+    }
+    let acked_seq = NEXT_SEQ.with(|next| *next.borrow()) - 1;
+    SUBSCRIPTIONS.with(|subscriptions| {
+        let mut subscriptions = subscriptions.borrow_mut();
+        if let Some(subscription) = subscriptions.get_mut(&callback_canister) {
+            subscription.event_types = event_types;
+            return Ok(());
+        }
+        if subscriptions.len() >= MAX_SUBSCRIBERS {
+            return Err(format!("At most {} subscribers are supported", MAX_SUBSCRIBERS));
+        }
+        subscriptions.insert(callback_canister, Subscription {
+            callback_canister,
+            event_types,
+            acked_seq,
+            subscribed_at: now,
+            attempts: 0,
+            next_attempt_at: now,
+            last_error: None,
+        });
+        Ok(())
+    })
+}
+
+pub fn unsubscribe(callback_canister: &Principal) -> Result<(), String> {
+    SUBSCRIPTIONS.with(|subscriptions| subscriptions.borrow_mut().remove(callback_canister))
+        .ok_or("Not subscribed".to_string())?;
+    prune();
+    Ok(())
+}
+
+pub fn allow(callback_canister: Principal) {
+    ALLOWED.with(|allowed| allowed.borrow_mut().insert(callback_canister));
+}
+
+/// Removes the canister from the allowlist along with any subscription it holds.
+pub fn disallow(callback_canister: &Principal) -> Result<(), String> {
+    if !ALLOWED.with(|allowed| allowed.borrow_mut().remove(callback_canister)) {
+        return Err("Canister is not allowed".to_string());
+    }
+    let _ = unsubscribe(callback_canister);
+    Ok(())
+}
+
+pub fn allowed() -> Vec<Principal> {
+    ALLOWED.with(|allowed| allowed.borrow().iter().copied().collect())
+}
+
+pub fn list() -> Vec<Subscription> {
+    SUBSCRIPTIONS.with(|subscriptions| subscriptions.borrow().values().cloned().collect())
+}
+
+/// Records the event for every subscriber of its type; nothing is kept when no one
+/// is listening.
+pub fn emit(payload: EventPayload, now: u64) {
+    let event_type = payload.event_type();
+    let listening = SUBSCRIPTIONS.with(|subscriptions| {
+        subscriptions.borrow().values().any(|s| s.event_types.contains(&event_type))
+    });
+    if !listening {
+        return;
+    }
+
+    let seq = NEXT_SEQ.with(|next| {
+        let mut next = next.borrow_mut();
+        let seq = *next;
+        *next += 1;
+        seq
+    });
+    EVENTS.with(|events| {
+        let mut events = events.borrow_mut();
+        if events.len() >= MAX_EVENTS {
+            events.pop_front();
+        }
+        events.push_back(Event { seq, timestamp: now, payload });
+    });
+}
+
+/// Marks everything up to seq as processed, so the subscriber's next batch is sent
+/// on the next delivery run.
+pub fn ack(callback_canister: &Principal, seq: u64, now: u64) -> Result<(), String> {
+    let last_seq = NEXT_SEQ.with(|next| *next.borrow()) - 1;
+    if seq > last_seq {
+        return Err(format!("No event with seq {} has been emitted", seq));
+    }
+    SUBSCRIPTIONS.with(|subscriptions| -> Result<(), String> {
+        let mut subscriptions = subscriptions.borrow_mut();
+        let subscription = subscriptions.get_mut(callback_canister)
+            .ok_or("Not subscribed".to_string())?;
+        if seq > subscription.acked_seq {
+            subscription.acked_seq = seq;
+            subscription.attempts = 0;
+            subscription.next_attempt_at = now;
+            subscription.last_error = None;
+        }
+        Ok(())
+    })?;
+    prune();
+    Ok(())
+}
+
+// Drops events every subscriber has acknowledged
+fn prune() {
+    let Some(min_acked) = SUBSCRIPTIONS.with(|subscriptions| {
+        subscriptions.borrow().values().map(|s| s.acked_seq).min()
+    }) else {
+        EVENTS.with(|events| events.borrow_mut().clear());
+        return;
+    };
+    EVENTS.with(|events| {
+        let mut events = events.borrow_mut();
+        while events.front().is_some_and(|e| e.seq <= min_acked) {
+            events.pop_front();
+        }
+    });
+}
+
+/// Sends each due subscriber its oldest unacknowledged events and schedules a
+/// resend in case no acknowledgement arrives.
+pub fn deliver_due(now: u64) {
+    let last_seq = NEXT_SEQ.with(|next| *next.borrow()) - 1;
+    let due: Vec<(Principal, Vec<Event>)> = SUBSCRIPTIONS.with(|subscriptions| {
+        EVENTS.with(|events| {
+            let events = events.borrow();
+            let mut due = Vec::new();
+            for s in subscriptions.borrow_mut().values_mut().filter(|s| s.next_attempt_at <= now) {
+                let batch: Vec<Event> = events.iter()
+                    .filter(|e| e.seq > s.acked_seq && s.event_types.contains(&e.payload.event_type()))
+                    .take(MAX_BATCH)
+                    .cloned()
+                    .collect();
+                if batch.is_empty() {
+                    // Nothing of its types is pending, so other events needn't be kept for it
+                    s.acked_seq = last_seq;
+                } else {
+                    due.push((s.callback_canister, batch));
+                }
+            }
+            due
+        })
+    });
+    prune();
+
+    for (callback_canister, batch) in due {
+        let result = ic_cdk::notify(callback_canister, CALLBACK_METHOD, (batch,));
+        SUBSCRIPTIONS.with(|subscriptions| {
+            if let Some(subscription) = subscriptions.borrow_mut().get_mut(&callback_canister) {
+                let backoff = RETRY_BASE_NANOS.saturating_mul(2u64.saturating_pow(subscription.attempts));
+                subscription.attempts += 1;
+                subscription.next_attempt_at = now + backoff.min(MAX_RETRY_NANOS);
+                subscription.last_error = result.err().map(|code| format!("Notify failed: {:?}", code));
+            }
+        });
+    }
+}
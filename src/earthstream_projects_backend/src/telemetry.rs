@@ -0,0 +1,351 @@
+use candid::CandidType;
+use ed25519_dalek::{Signature, VerifyingKey};
+use serde::{Deserialize, Serialize};
+use serde_bytes::ByteBuf;
+use std::cell::RefCell;
+use std::collections::{BTreeMap, VecDeque};
+
+pub const MAX_READINGS_PER_SENSOR: usize = 2048;  // Oldest readings are dropped beyond this
+pub const MAX_BATCH_SIZE: usize = 500;
+pub const MAX_METRIC_LENGTH: usize = 32;
+pub const MAX_AGGREGATE_BUCKETS: u64 = 1000;
+pub const NANOS_PER_HOUR: u64 = 3_600_000_000_000;
+
+#[derive(CandidType, Serialize, Deserialize, Clone, Debug)]
+pub struct Reading {
+    pub sensor: String,  // Sensor serial
+    pub timestamp: u64,
+    pub metric: String,
+    pub value: f64,
+    pub signature: Option<ByteBuf>,  // Required from sensors with a public key; not stored
+    pub anomaly: Option<Anomaly>,  // Set on ingestion; anything submitted here is ignored
+}
+
+#[derive(CandidType, Serialize, Deserialize, Clone, Copy, Debug, PartialEq)]
+pub enum Anomaly {
+    OutOfRange,
+    ImpossibleJump,  // Changed more than max_jump since the previous plausible reading
+}
+
+#[derive(Clone, Copy)]
+pub struct Limits {
+    pub min: Option<f64>,
+    pub max: Option<f64>,
+    pub max_jump: Option<f64>,
+}
+
+#[derive(CandidType, Serialize, Deserialize, Clone, Debug)]
+pub struct Aggregate {
+    pub bucket_start: u64,
+    pub min: f64,
+    pub max: f64,
+    pub avg: f64,
+    pub count: u64,
+}
+
+// Hourly summary kept after the raw readings behind it are pruned
+#[derive(Clone, Copy)]
+struct Rollup {
+    min: f64,
+    max: f64,
+    sum: f64,
+    count: u64,
+}
+
+type Readings = BTreeMap<String, VecDeque<Reading>>; // serial -> ring buffer, oldest first
+type HourlyRollups = BTreeMap<(String, String), BTreeMap<u64, Rollup>>; // (serial, metric) -> hour start -> rollup
+
+thread_local! {
+    static READINGS: RefCell<Readings> = RefCell::default();
+    static HOURLY: RefCell<HourlyRollups> = RefCell::default();
+}
+
+pub fn validate(reading: &Reading, now: u64, max_clock_skew: u64) -> Result<(), String> {
+    if reading.metric.is_empty() || reading.metric.len() > MAX_METRIC_LENGTH {
+        return Err(format!("Metric must be 1 to {} characters", MAX_METRIC_LENGTH));
+    }
+    if !reading.value.is_finite() {
+        return Err("Value must be a finite number".to_string());
+    }
+    if reading.timestamp == 0 || reading.timestamp > now + max_clock_skew {
+        return Err("Timestamp is invalid or in the future".to_string());
+    }
+    Ok(())
+}
+
+pub fn validate_public_key(public_key: &[u8]) -> Result<(), String> {
+    let bytes: &[u8; 32] = public_key.try_into()
+        .map_err(|_| "Public key must be 32 bytes".to_string())?;
+    VerifyingKey::from_bytes(bytes)
+        .map(|_| ())
+        .map_err(|_| "Invalid ed25519 public key".to_string())
+}
+
+pub fn verify_signature(public_key: &[u8], message: &[u8], signature: Option<&ByteBuf>) -> Result<(), String> {
+    let signature = signature.ok_or("Signature is required")?;
+    let key: &[u8; 32] = public_key.try_into()
+        .map_err(|_| "Invalid public key".to_string())?;
+    let key = VerifyingKey::from_bytes(key)
+        .map_err(|_| "Invalid public key".to_string())?;
+    let signature = Signature::from_slice(signature)
+        .map_err(|_| "Malformed signature".to_string())?;
+    key.verify_strict(message, &signature)
+        .map_err(|_| "Signature does not match".to_string())
+}
+
+/// Marks readings that fall outside `limits(sensor, metric)` or jump too far from the
+/// previous plausible reading of the same metric. `readings` must be in timestamp order.
+pub fn flag_anomalies(readings: &mut [Reading], limits: impl Fn(&str, &str) -> Option<Limits>) {
+    let mut previous: BTreeMap<(String, String), f64> = BTreeMap::new();
+    READINGS.with(|store| {
+        let store = store.borrow();
+        for reading in readings.iter_mut() {
+            reading.anomaly = None;
+            let Some(limits) = limits(&reading.sensor, &reading.metric) else {
+                continue;
+            };
+
+            let key = (reading.sensor.clone(), reading.metric.clone());
+            let last = previous.get(&key).copied().or_else(|| {
+                store.get(&reading.sensor)?
+                    .iter()
+                    .rev()
+                    .find(|r| r.metric == reading.metric && r.anomaly.is_none())
+                    .map(|r| r.value)
+            });
+
+            if limits.min.is_some_and(|min| reading.value < min) || limits.max.is_some_and(|max| reading.value > max) {
+                reading.anomaly = Some(Anomaly::OutOfRange);
+            } else if limits.max_jump.zip(last).is_some_and(|(jump, last)| (reading.value - last).abs() > jump) {
+                reading.anomaly = Some(Anomaly::ImpossibleJump);
+            } else {
+                previous.insert(key, reading.value);
+            }
+        }
+    });
+}
+
+/// Flagged readings for a sensor, newest first.
+pub fn flagged(serial: &str, limit: usize) -> Vec<Reading> {
+    READINGS.with(|store| {
+        store.borrow()
+            .get(serial)
+            .map(|buffer| buffer.iter().rev().filter(|r| r.anomaly.is_some()).take(limit).cloned().collect())
+            .unwrap_or_default()
+    })
+}
+
+/// Bytes a sensor signs: the Candid encoding of (sensor, timestamp, metric, value).
+pub fn reading_payload(reading: &Reading) -> Vec<u8> {
+    candid::encode_args((&reading.sensor, reading.timestamp, &reading.metric, reading.value))
+        .unwrap_or_default()
+}
+
+const BATCH_DOMAIN: &[u8] = b"earthstream-telemetry-batch-v1";
+
+fn push_field(bytes: &mut Vec<u8>, field: &[u8]) {
+    bytes.extend_from_slice(&(field.len() as u32).to_be_bytes());
+    bytes.extend_from_slice(field);
+}
+
+/// Bytes a gateway signs, fixed regardless of how `Reading` is encoded on the wire:
+/// BATCH_DOMAIN, the sequence number, the gateway id, then per reading as submitted its
+/// sensor, timestamp, metric, value and sensor signature (empty if none). Numbers are
+/// big-endian, the value as IEEE 754 bits; strings and signatures are prefixed with
+/// their length as a big-endian u32.
+pub fn batch_payload(gateway_id: &str, sequence: u64, readings: &[Reading]) -> Vec<u8> {
+    let mut bytes = BATCH_DOMAIN.to_vec();
+    bytes.extend_from_slice(&sequence.to_be_bytes());
+    push_field(&mut bytes, gateway_id.as_bytes());
+    for reading in readings {
+        push_field(&mut bytes, reading.sensor.as_bytes());
+        bytes.extend_from_slice(&reading.timestamp.to_be_bytes());
+        push_field(&mut bytes, reading.metric.as_bytes());
+        bytes.extend_from_slice(&reading.value.to_bits().to_be_bytes());
+        push_field(&mut bytes, reading.signature.as_ref().map_or(&[][..], |s| s.as_slice()));
+    }
+    bytes
+}
+
+pub fn append(readings: Vec<Reading>) {
+    HOURLY.with(|hourly| {
+        let mut hourly = hourly.borrow_mut();
+        // Suspect readings stay in the raw buffer for review but don't skew the rollups
+        for reading in readings.iter().filter(|r| r.anomaly.is_none()) {
+            let hour = reading.timestamp / NANOS_PER_HOUR * NANOS_PER_HOUR;
+            let rollup = hourly
+                .entry((reading.sensor.clone(), reading.metric.clone()))
+                .or_default()
+                .entry(hour)
+                .or_insert(Rollup { min: f64::MAX, max: f64::MIN, sum: 0.0, count: 0 });
+            rollup.min = rollup.min.min(reading.value);
+            rollup.max = rollup.max.max(reading.value);
+            rollup.sum += reading.value;
+            rollup.count += 1;
+        }
+    });
+    READINGS.with(|store| {
+        let mut store = store.borrow_mut();
+        for reading in readings {
+            let buffer = store.entry(reading.sensor.clone()).or_default();
+            if buffer.len() >= MAX_READINGS_PER_SENSOR {
+                buffer.pop_front();
+            }
+            buffer.push_back(reading);
+        }
+    });
+}
+
+/// Most recent readings for a sensor, newest first.
+pub fn latest(serial: &str, limit: usize) -> Vec<Reading> {
+    READINGS.with(|store| {
+        store.borrow()
+            .get(serial)
+            .map(|buffer| buffer.iter().rev().take(limit).cloned().collect())
+            .unwrap_or_default()
+    })
+}
+
+/// Readings in [from, to), oldest first.
+pub fn range(serial: &str, from: u64, to: u64) -> Vec<Reading> {
+    READINGS.with(|store| {
+        let mut readings: Vec<Reading> = store.borrow()
+            .get(serial)
+            .into_iter()
+            .flatten()
+            .filter(|r| r.timestamp >= from && r.timestamp < to)
+            .cloned()
+            .collect();
+        // Batches may arrive out of order, so the buffer is only roughly sorted
+        readings.sort_by_key(|r| r.timestamp);
+        readings
+    })
+}
+
+/// Min/max/avg/count of `metric` per `bucket` nanoseconds in [from, to), leaving out
+/// flagged readings. Buckets without readings are omitted.
+pub fn aggregate(serial: &str, metric: &str, from: u64, to: u64, bucket: u64) -> Vec<Aggregate> {
+    READINGS.with(|store| {
+        let store = store.borrow();
+        let mut buckets: BTreeMap<u64, Aggregate> = BTreeMap::new();
+        let readings = store.get(serial).into_iter().flatten()
+            .filter(|r| r.metric == metric && r.timestamp >= from && r.timestamp < to && r.anomaly.is_none());
+
+        for reading in readings {
+            let bucket_start = from + (reading.timestamp - from) / bucket * bucket;
+            let entry = buckets.entry(bucket_start).or_insert(Aggregate {
+                bucket_start,
+                min: f64::MAX,
+                max: f64::MIN,
+                avg: 0.0,
+                count: 0,
+            });
+            entry.min = entry.min.min(reading.value);
+            entry.max = entry.max.max(reading.value);
+            entry.avg += reading.value;  // Running sum until the final pass
+            entry.count += 1;
+        }
+
+        buckets.into_values()
+            .map(|mut aggregate| {
+                aggregate.avg /= aggregate.count as f64;
+                aggregate
+            })
+            .collect()
+    })
+}
+
+/// Hourly rollups of `metric` in [from, to), including hours whose raw readings were pruned.
+pub fn hourly(serial: &str, metric: &str, from: u64, to: u64) -> Vec<Aggregate> {
+    HOURLY.with(|hourly| {
+        hourly.borrow()
+            .get(&(serial.to_string(), metric.to_string()))
+            .map(|hours| {
+                hours.range(from / NANOS_PER_HOUR * NANOS_PER_HOUR..to)
+                    .map(|(hour, rollup)| Aggregate {
+                        bucket_start: *hour,
+                        min: rollup.min,
+                        max: rollup.max,
+                        avg: rollup.sum / rollup.count as f64,
+                        count: rollup.count,
+                    })
+                    .collect()
+            })
+            .unwrap_or_default()
+    })
+}
+
+/// Drops raw readings and hourly rollups older than the cutoffs returned by
+/// `cutoffs(serial)` as (raw, hourly); a None hourly cutoff keeps rollups forever.
+/// Returns the number of raw readings removed.
+pub fn prune(cutoffs: impl Fn(&str) -> (u64, Option<u64>)) -> u64 {
+    let removed = READINGS.with(|store| {
+        let mut store = store.borrow_mut();
+        let mut removed = 0;
+        for (serial, buffer) in store.iter_mut() {
+            let (raw_cutoff, _) = cutoffs(serial);
+            let before = buffer.len();
+            buffer.retain(|r| r.timestamp >= raw_cutoff);
+            removed += (before - buffer.len()) as u64;
+        }
+        store.retain(|_, buffer| !buffer.is_empty());
+        removed
+    });
+    HOURLY.with(|hourly| {
+        let mut hourly = hourly.borrow_mut();
+        for ((serial, _), hours) in hourly.iter_mut() {
+            if let (_, Some(hourly_cutoff)) = cutoffs(serial) {
+                hours.retain(|hour, _| hour + NANOS_PER_HOUR > hourly_cutoff);
+            }
+        }
+        hourly.retain(|_, hours| !hours.is_empty());
+    });
+    removed
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ed25519_dalek::{Signer, SigningKey};
+
+    fn sample_batch() -> Vec<Reading> {
+        vec![Reading {
+            sensor: "s1".to_string(),
+            timestamp: 1_700_000_000_000_000_000,
+            metric: "pm25".to_string(),
+            value: 12.5,
+            signature: Some(ByteBuf::from(vec![0xaa, 0xbb])),
+            anomaly: None,
+        }]
+    }
+
+    #[test]
+    fn batch_payload_layout_is_fixed() {
+        let fields = [
+            "0000000000000007",  // sequence
+            "00000003", "677731",  // gateway id
+            "00000002", "7331",  // sensor
+            "17979cfe362a0000",  // timestamp
+            "00000004", "706d3235",  // metric
+            "4029000000000000",  // value
+            "00000002", "aabb",  // sensor signature
+        ];
+        let expected = [BATCH_DOMAIN, &hex::decode(fields.concat()).unwrap()].concat();
+        assert_eq!(batch_payload("gw1", 7, &sample_batch()), expected);
+    }
+
+    #[test]
+    fn signed_batch_verifies_and_tampering_is_rejected() {
+        let key = SigningKey::from_bytes(&[7; 32]);
+        let public_key = key.verifying_key().to_bytes();
+        let readings = sample_batch();
+        let signature = ByteBuf::from(key.sign(&batch_payload("gw1", 7, &readings)).to_bytes().to_vec());
+
+        assert!(verify_signature(&public_key, &batch_payload("gw1", 7, &readings), Some(&signature)).is_ok());
+        assert!(verify_signature(&public_key, &batch_payload("gw1", 8, &readings), Some(&signature)).is_err());
+        let mut tampered = readings.clone();
+        tampered[0].value = 13.5;
+        assert!(verify_signature(&public_key, &batch_payload("gw1", 7, &tampered), Some(&signature)).is_err());
+        assert!(verify_signature(&public_key, &batch_payload("gw1", 7, &readings), None).is_err());
+    }
+}
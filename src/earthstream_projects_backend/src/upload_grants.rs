@@ -0,0 +1,99 @@
+use candid::{CandidType, Principal};
+use serde::{Deserialize, Serialize};
+use std::cell::RefCell;
+use std::collections::BTreeMap;
+
+// Grants let a project owner upload to the configured asset canister. That canister
+// is expected to check each upload with verify_upload_token(token, uploader) and
+// report what was stored with record_uploaded_assets(token, paths).
+
+const GRANT_TTL_NANOS: u64 = 60 * 60 * 1_000_000_000;
+const MAX_ASSETS_PER_GRANT: usize = 20;
+const MAX_PATH_LENGTH: usize = 200;
+
+#[derive(CandidType, Serialize, Deserialize, Clone, Debug)]
+pub struct UploadGrant {
+    pub token: String,
+    pub project_id: String,
+    pub owner: Principal,  // Only uploads made by this principal are covered
+    pub path_prefix: String,  // Uploads must stay under it
+    pub issued_at: u64,
+    pub expires_at: u64,
+    pub recorded_paths: Vec<String>,
+}
+
+thread_local! {
+    static GRANTS: RefCell<BTreeMap<String, UploadGrant>> = RefCell::default();  // token -> grant
+}
+
+pub fn path_prefix(project_id: &str) -> String {
+    format!("/projects/{}/", project_id)
+}
+
+/// Stores a grant for the token and drops expired ones.
+pub fn issue(token: String, project_id: &str, owner: Principal, now: u64) -> UploadGrant {
+    let grant = UploadGrant {
+        token: token.clone(),
+        project_id: project_id.to_string(),
+        owner,
+        path_prefix: path_prefix(project_id),
+        issued_at: now,
+        expires_at: now + GRANT_TTL_NANOS,
+        recorded_paths: vec![],
+    };
+    GRANTS.with(|grants| {
+        let mut grants = grants.borrow_mut();
+        grants.retain(|_, g| g.expires_at > now);
+        grants.insert(token, grant.clone());
+    });
+    grant
+}
+
+/// The grant behind the token, if it is unexpired and was issued to the uploader.
+pub fn verify(token: &str, uploader: &Principal, now: u64) -> Result<UploadGrant, String> {
+    let grant = GRANTS.with(|grants| grants.borrow().get(token).cloned())
+        .ok_or("Unknown upload token")?;
+    if grant.expires_at <= now {
+        return Err("Upload token has expired".to_string());
+    }
+    if grant.owner != *uploader {
+        return Err("Upload token was issued to another principal".to_string());
+    }
+    Ok(grant)
+}
+
+/// Checks the paths against the grant and remembers them; returns the paths not
+/// recorded before. `room(project_id)` is how many more assets the project takes.
+pub fn record(token: &str, paths: &[String], now: u64, room: impl FnOnce(&str) -> usize) -> Result<(UploadGrant, Vec<String>), String> {
+    GRANTS.with(|grants| {
+        let mut grants = grants.borrow_mut();
+        let grant = grants.get_mut(token)
+            .ok_or("Unknown upload token")?;
+        if grant.expires_at <= now {
+            return Err("Upload token has expired".to_string());
+        }
+        for path in paths {
+            let name = path.strip_prefix(&grant.path_prefix)
+                .ok_or(format!("{} is outside {}", path, grant.path_prefix))?;
+            if name.is_empty() || path.len() > MAX_PATH_LENGTH || path.contains("..")
+                || path.chars().any(|c| c.is_whitespace() || c.is_control()) {
+                return Err(format!("{} is not a valid asset path", path));
+            }
+        }
+
+        let mut new_paths: Vec<String> = Vec::new();
+        for path in paths {
+            if !grant.recorded_paths.contains(path) && !new_paths.contains(path) {
+                new_paths.push(path.clone());
+            }
+        }
+        if grant.recorded_paths.len() + new_paths.len() > MAX_ASSETS_PER_GRANT {
+            return Err(format!("A grant covers at most {} assets", MAX_ASSETS_PER_GRANT));
+        }
+        if new_paths.len() > room(&grant.project_id) {
+            return Err("The project's gallery is full".to_string());
+        }
+        grant.recorded_paths.extend(new_paths.iter().cloned());
+        Ok((grant.clone(), new_paths))
+    })
+}
@@ -0,0 +1,54 @@
+use candid::{CandidType, Principal};
+use ic_cdk::api::call::RejectionCode;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+
+// Interface of the vote archive canister, deployed separately and configured as
+// vote_archive. It is expected to provide
+//   append_votes : (vec ProjectVote) -> ()                          // callable by this canister only
+//   get_votes : (project_id : text, start : nat64, length : nat32) -> (vec ProjectVote) query
+// where get_votes returns a project's archived votes newest first, and append_votes
+// skips votes it already holds (same project_id and voter), so a batch whose reply
+// was lost can be sent again.
+
+#[derive(CandidType, Serialize, Deserialize, Clone, Debug)]
+pub struct ProjectVote {
+    pub project_id: String,
+    pub voter: Principal,
+    pub timestamp: u64,
+}
+
+pub enum AppendError {
+    Failed,  // Nothing was appended
+    Unknown,  // The votes may or may not have been appended
+}
+
+/// Compact stand-in for an archived (voter, project) pair: the first 8 bytes of a
+/// SHA-256 over both.
+pub fn vote_key(voter: &Principal, project_id: &str) -> u64 {
+    let mut hasher = Sha256::new();
+    hasher.update(b"earthstream-vote:");
+    hasher.update(voter.as_slice());
+    hasher.update(project_id.as_bytes());
+    let hash = hasher.finalize();
+    let mut bytes = [0u8; 8];
+    bytes.copy_from_slice(&hash[..8]);
+    u64::from_be_bytes(bytes)
+}
+
+pub async fn append(archive: Principal, votes: Vec<ProjectVote>) -> Result<(), AppendError> {
+    ic_cdk::call::<_, ()>(archive, "append_votes", (votes,))
+        .await
+        .map_err(|(code, _)| match code {
+            // Only these say the call was never executed or rolled back
+            RejectionCode::DestinationInvalid | RejectionCode::CanisterReject | RejectionCode::CanisterError => AppendError::Failed,
+            _ => AppendError::Unknown,
+        })
+}
+
+pub async fn get(archive: Principal, project_id: &str, start: u64, length: u32) -> Result<Vec<ProjectVote>, String> {
+    let (votes,): (Vec<ProjectVote>,) = ic_cdk::call(archive, "get_votes", (project_id, start, length))
+        .await
+        .map_err(|(_, e)| format!("Vote archive call failed: {}", e))?;
+    Ok(votes)
+}
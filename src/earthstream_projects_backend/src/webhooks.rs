@@ -0,0 +1,235 @@
+use candid::CandidType;
+use ic_cdk::api::management_canister::http_request::{
+    http_request, CanisterHttpRequestArgument, HttpHeader, HttpMethod, HttpResponse, TransformArgs,
+    TransformContext,
+};
+use serde::{Deserialize, Serialize};
+use std::cell::RefCell;
+use std::collections::{BTreeMap, VecDeque};
+use std::thread::LocalKey;
+
+pub const WEBHOOK_URL_PREFIX: &str = "https://discord.com/api/webhooks/";
+pub const DISCORD_EVENT: &str = "discord";
+const MAX_ATTEMPTS: u32 = 5;
+const RETRY_BASE_NANOS: u64 = 60 * 1_000_000_000;  // Doubles after every failed attempt
+const MAX_QUEUE_LENGTH: usize = 1000;  // Per queue; project owners' webhooks have their own
+const MAX_RECORDS: usize = 2000;  // Delivery statuses kept for admins, oldest dropped first
+const MAX_CONTENT_LENGTH: usize = 2000;  // Discord's message limit
+const MAX_RESPONSE_BYTES: u64 = 4 * 1024;
+const OUTCALL_CYCLES: u128 = 50_000_000_000;
+
+#[derive(CandidType, Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub enum DeliveryStatus {
+    Pending,
+    Delivered,
+    Failed,  // Gave up after MAX_ATTEMPTS, or dropped from a full queue
+}
+
+#[derive(CandidType, Serialize, Deserialize, Clone, Debug)]
+pub struct DeliveryRecord {
+    pub id: u64,
+    pub event: String,
+    pub status: DeliveryStatus,
+    pub attempts: u32,
+    pub created_at: u64,
+    pub last_attempt_at: Option<u64>,
+    pub last_error: Option<String>,
+}
+
+struct Delivery {
+    id: u64,
+    url: String,
+    body: String,  // JSON posted as is
+    headers: Vec<(String, String)>,  // Sent in addition to Content-Type
+    attempts: u32,
+    next_attempt_at: u64,
+    owner: bool,  // Queued on OWNER_QUEUE
+}
+
+type Queue = RefCell<VecDeque<Delivery>>;
+
+thread_local! {
+    static QUEUE: Queue = RefCell::default();
+    static OWNER_QUEUE: Queue = RefCell::default();  // Failing owner endpoints can't crowd out admin, relay or analytics deliveries
+    static RECORDS: RefCell<BTreeMap<u64, DeliveryRecord>> = RefCell::default();
+    static NEXT_ID: RefCell<u64> = const { RefCell::new(0) };
+}
+
+pub fn is_webhook_url(url: &str) -> bool {
+    url.starts_with(WEBHOOK_URL_PREFIX)
+}
+
+fn update_record(id: u64, f: impl FnOnce(&mut DeliveryRecord)) {
+    RECORDS.with(|records| {
+        if let Some(record) = records.borrow_mut().get_mut(&id) {
+            f(record);
+        }
+    });
+}
+
+/// Queues a JSON body for delivery by the next `deliver_due` run and returns its
+/// delivery id. The oldest message is dropped when the queue is full.
+pub fn enqueue(url: &str, event: &str, body: String, now: u64) -> u64 {
+    enqueue_with_headers(url, event, body, vec![], now)
+}
+
+pub fn enqueue_with_headers(url: &str, event: &str, body: String, headers: Vec<(String, String)>, now: u64) -> u64 {
+    push(false, url, event, body, headers, now)
+}
+
+/// As `enqueue_with_headers`, for project owners' webhooks.
+pub fn enqueue_owner(url: &str, event: &str, body: String, headers: Vec<(String, String)>, now: u64) -> u64 {
+    push(true, url, event, body, headers, now)
+}
+
+fn queue(owner: bool) -> &'static LocalKey<Queue> {
+    if owner { &OWNER_QUEUE } else { &QUEUE }
+}
+
+fn push(owner: bool, url: &str, event: &str, body: String, headers: Vec<(String, String)>, now: u64) -> u64 {
+    let id = NEXT_ID.with(|next| {
+        let mut next = next.borrow_mut();
+        *next += 1;
+        *next
+    });
+    RECORDS.with(|records| {
+        let mut records = records.borrow_mut();
+        if records.len() >= MAX_RECORDS {
+            records.pop_first();
+        }
+        records.insert(id, DeliveryRecord {
+            id,
+            event: event.to_string(),
+            status: DeliveryStatus::Pending,
+            attempts: 0,
+            created_at: now,
+            last_attempt_at: None,
+            last_error: None,
+        });
+    });
+
+    let dropped = queue(owner).with(|queue| {
+        let mut queue = queue.borrow_mut();
+        let dropped = if queue.len() >= MAX_QUEUE_LENGTH { queue.pop_front() } else { None };
+        queue.push_back(Delivery {
+            id,
+            url: url.to_string(),
+            body,
+            headers,
+            attempts: 0,
+            next_attempt_at: now,
+            owner,
+        });
+        dropped
+    });
+    if let Some(dropped) = dropped {
+        update_record(dropped.id, |record| {
+            record.status = DeliveryStatus::Failed;
+            record.last_error = Some("Dropped from a full queue".to_string());
+        });
+    }
+    id
+}
+
+/// Queues a Discord message, truncated to Discord's length limit.
+/// Mentions are never parsed, since the content carries user-chosen names.
+pub fn enqueue_discord(url: &str, content: &str, now: u64) -> u64 {
+    let content: String = content.chars().take(MAX_CONTENT_LENGTH).collect();
+    let body = serde_json::json!({
+        "content": content,
+        "allowed_mentions": { "parse": [] },
+    }).to_string();
+    enqueue(url, DISCORD_EVENT, body, now)
+}
+
+pub fn queue_length() -> usize {
+    QUEUE.with(|queue| queue.borrow().len()) + OWNER_QUEUE.with(|queue| queue.borrow().len())
+}
+
+pub fn is_pending(id: u64) -> bool {
+    RECORDS.with(|records| records.borrow().get(&id).is_some_and(|r| r.status == DeliveryStatus::Pending))
+}
+
+pub fn get_record(id: u64) -> Option<DeliveryRecord> {
+    RECORDS.with(|records| records.borrow().get(&id).cloned())
+}
+
+/// Runs `f` over the records, newest first, without copying them.
+pub fn with_records<R>(f: impl FnOnce(Vec<&DeliveryRecord>) -> R) -> R {
+    RECORDS.with(|records| f(records.borrow().values().rev().collect()))
+}
+
+/// Sends every message that is due. Failed deliveries are retried with
+/// exponential backoff and given up after MAX_ATTEMPTS.
+pub async fn deliver_due(now: u64) {
+    let take_due = |queue: &Queue| {
+        let mut queue = queue.borrow_mut();
+        let (due, waiting): (VecDeque<Delivery>, VecDeque<Delivery>) =
+            queue.drain(..).partition(|d| d.next_attempt_at <= now);
+        *queue = waiting;
+        due
+    };
+    let mut due = QUEUE.with(take_due);
+    due.extend(OWNER_QUEUE.with(take_due));
+
+    for mut delivery in due {
+        delivery.attempts += 1;
+        let result = post(&delivery.url, &delivery.body, &delivery.headers).await;
+        let exhausted = delivery.attempts >= MAX_ATTEMPTS;
+        update_record(delivery.id, |record| {
+            record.attempts = delivery.attempts;
+            record.last_attempt_at = Some(now);
+            match &result {
+                Ok(()) => record.status = DeliveryStatus::Delivered,
+                Err(e) => {
+                    record.last_error = Some(e.clone());
+                    if exhausted {
+                        record.status = DeliveryStatus::Failed;
+                    }
+                }
+            }
+        });
+        if result.is_err() && !exhausted {
+            delivery.next_attempt_at = now + RETRY_BASE_NANOS * 2u64.pow(delivery.attempts - 1);
+            queue(delivery.owner).with(|queue| queue.borrow_mut().push_back(delivery));
+        }
+    }
+}
+
+// Every replica performs the outcall, so the receiver may see the message more than once
+async fn post(url: &str, body: &str, headers: &[(String, String)]) -> Result<(), String> {
+    let mut all_headers = vec![HttpHeader {
+        name: "Content-Type".to_string(),
+        value: "application/json".to_string(),
+    }];
+    all_headers.extend(headers.iter().map(|(name, value)| HttpHeader {
+        name: name.clone(),
+        value: value.clone(),
+    }));
+    let request = CanisterHttpRequestArgument {
+        url: url.to_string(),
+        method: HttpMethod::POST,
+        body: Some(body.as_bytes().to_vec()),
+        max_response_bytes: Some(MAX_RESPONSE_BYTES),
+        transform: Some(TransformContext::from_name("transform_webhook_response".to_string(), vec![])),
+        headers: all_headers,
+    };
+
+    let (response,) = http_request(request, OUTCALL_CYCLES)
+        .await
+        .map_err(|(_, e)| e)?;
+    if response.status >= 200u64 && response.status < 300u64 {
+        Ok(())
+    } else {
+        Err(format!("Webhook returned {}", response.status))
+    }
+}
+
+/// Keeps only the status so replicas agree on the response.
+pub fn transform(args: TransformArgs) -> HttpResponse {
+    HttpResponse {
+        status: args.response.status,
+        headers: vec![],
+        body: vec![],
+    }
+}
@@ -0,0 +1,101 @@
+use candid::CandidType;
+use serde::{Deserialize, Serialize};
+use std::cell::RefCell;
+use std::collections::{BTreeMap, BTreeSet};
+
+const MAX_TERM_LENGTH: usize = 64;
+
+#[derive(CandidType, Serialize, Deserialize, Clone, Copy, Debug, PartialEq)]
+pub enum FilterAction {
+    Block,  // Reject the submission outright
+    Flag,   // Accept it but queue it for moderator attention
+}
+
+#[derive(CandidType, Serialize, Deserialize, Clone, Debug, Default)]
+pub struct WordFilter {
+    pub denied: BTreeMap<String, FilterAction>,
+    pub allowed: BTreeSet<String>,  // Words that contain a denied term but are fine, e.g. place names
+}
+
+#[derive(Default)]
+pub struct Matches {
+    pub blocked: Vec<String>,
+    pub flagged: Vec<String>,
+}
+
+thread_local! {
+    static FILTER: RefCell<WordFilter> = RefCell::default();
+}
+
+fn normalize_term(term: &str) -> Result<String, String> {
+    let term = term.trim().to_lowercase();
+    if term.is_empty() {
+        return Err("Term cannot be empty".to_string());
+    }
+    if !term.chars().all(char::is_alphanumeric) {
+        return Err("Term must be a single word".to_string());
+    }
+    if term.len() > MAX_TERM_LENGTH {
+        return Err(format!("Term cannot exceed {} characters", MAX_TERM_LENGTH));
+    }
+    Ok(term)
+}
+
+pub fn set_denied(term: &str, action: Option<FilterAction>) -> Result<(), String> {
+    let term = normalize_term(term)?;
+    FILTER.with(|filter| {
+        let mut filter = filter.borrow_mut();
+        match action {
+            Some(action) => filter.denied.insert(term, action),
+            None => filter.denied.remove(&term),
+        };
+    });
+    Ok(())
+}
+
+pub fn set_allowed(word: &str, allowed: bool) -> Result<(), String> {
+    let word = normalize_term(word)?;
+    FILTER.with(|filter| {
+        let mut filter = filter.borrow_mut();
+        if allowed {
+            filter.allowed.insert(word);
+        } else {
+            filter.allowed.remove(&word);
+        }
+    });
+    Ok(())
+}
+
+pub fn get() -> WordFilter {
+    FILTER.with(|filter| filter.borrow().clone())
+}
+
+/// Returns the denied terms found in `texts`. A word matches when it contains
+/// a denied term, unless the whole word is on the allow-list.
+pub fn scan(texts: &[&str]) -> Matches {
+    FILTER.with(|filter| {
+        let filter = filter.borrow();
+        let mut matches = Matches::default();
+        if filter.denied.is_empty() {
+            return matches;
+        }
+
+        let words: BTreeSet<String> = texts
+            .iter()
+            .flat_map(|text| text.split(|c: char| !c.is_alphanumeric()))
+            .filter(|word| !word.is_empty())
+            .map(|word| word.to_lowercase())
+            .filter(|word| !filter.allowed.contains(word))
+            .collect();
+
+        for (term, action) in &filter.denied {
+            if words.iter().any(|word| word.contains(term.as_str())) {
+                match action {
+                    FilterAction::Block => matches.blocked.push(term.clone()),
+                    FilterAction::Flag => matches.flagged.push(term.clone()),
+                }
+            }
+        }
+        matches
+    })
+}